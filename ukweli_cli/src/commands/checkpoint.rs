@@ -0,0 +1,121 @@
+use anyhow::{Context, Result, bail};
+use std::path::PathBuf;
+use ukweli_db::core::{Checkpoint, OperatorDesignation};
+
+use crate::{ledger_manager::LedgerManager, user_store::UserStore};
+
+pub fn create(operator_id: String) -> Result<()> {
+    let ledger_mgr = LedgerManager::load()?;
+
+    let operator = UserStore::open()?
+        .load_user(&operator_id)
+        .with_context(|| format!("Failed to load operator '{}'", operator_id))?;
+
+    match ledger_mgr.ledger()?.operator_id() {
+        None => bail!(
+            "This ledger names no operator yet, name one with: ukweli checkpoint designate --operator <user>"
+        ),
+        Some(id) if id != operator_id => {
+            bail!("Checkpoints are signed by the operator '{}'", id)
+        }
+        Some(_) => {}
+    }
+
+    let checkpoint = ledger_mgr.create_checkpoint(&operator)?;
+
+    println!("\nCheckpoint created");
+    print_checkpoint(&checkpoint);
+
+    Ok(())
+}
+
+/// Names the operator of a ledger created without one, in a record at its tip.
+pub fn designate(operator_id: String) -> Result<()> {
+    let ledger_mgr = LedgerManager::load()?;
+
+    let operator = UserStore::open()?
+        .load_user(&operator_id)
+        .with_context(|| format!("Failed to load operator '{}'", operator_id))?;
+
+    let registered = {
+        let ledger = ledger_mgr.ledger()?;
+        if let Some(id) = ledger.operator_id() {
+            bail!("This ledger already names '{}' as its operator", id);
+        }
+        ledger.verify_registry.contains_key(&operator_id)
+    };
+    if !registered {
+        ledger_mgr.register_user(operator.clone())?;
+    }
+
+    ledger_mgr.append_record(
+        OperatorDesignation::new(&operator_id).to_payload(),
+        vec![operator],
+    )?;
+
+    println!("'{}' is the ledger operator", operator_id);
+
+    Ok(())
+}
+
+pub fn cosign(witness_id: String, size: Option<usize>) -> Result<()> {
+    let ledger_mgr = LedgerManager::load()?;
+
+    let witness = UserStore::open()?
+        .load_user(&witness_id)
+        .with_context(|| format!("Failed to load witness '{}'", witness_id))?;
+
+    let size = match size {
+        Some(size) => size,
+        None => match ledger_mgr.ledger()?.latest_checkpoint() {
+            Some(checkpoint) => checkpoint.tree_size(),
+            None => bail!("No checkpoints to countersign"),
+        },
+    };
+
+    let checkpoint = ledger_mgr.cosign_checkpoint(size, &witness)?;
+
+    println!("\nCheckpoint countersigned by '{}'", witness_id);
+    print_checkpoint(&checkpoint);
+
+    Ok(())
+}
+
+pub fn show(out: Option<PathBuf>) -> Result<()> {
+    let ledger_mgr = LedgerManager::load_read_only()?;
+
+    let ledger = ledger_mgr.ledger()?;
+    let Some(checkpoint) = ledger.latest_checkpoint() else {
+        println!("No checkpoints yet.");
+        println!("Create one with: ukweli checkpoint create --operator <user>");
+        return Ok(());
+    };
+
+    print_checkpoint(checkpoint);
+
+    if let Some(path) = out {
+        let json =
+            serde_json::to_string_pretty(checkpoint).context("Failed to serialize checkpoint")?;
+        std::fs::write(&path, json).context("Failed to write checkpoint file")?;
+        println!("\nCheckpoint written to: {}", path.display());
+    }
+
+    Ok(())
+}
+
+fn print_checkpoint(checkpoint: &Checkpoint) {
+    println!("─────────────────────────────────────");
+    println!("Size:      {}", checkpoint.tree_size());
+    println!("Root hash: {}", checkpoint.root_hash());
+    println!("Timestamp: {}", checkpoint.head.tree_head.timestamp);
+    println!("Operator:  {}", checkpoint.operator_id());
+
+    if checkpoint.witnesses.is_empty() {
+        println!("Witnesses: none");
+    } else {
+        println!("Witnesses:");
+        for witness in &checkpoint.witnesses {
+            println!("  • {}", witness.signer_id);
+        }
+    }
+}
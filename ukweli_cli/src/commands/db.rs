@@ -0,0 +1,300 @@
+use anyhow::{Context, Result, bail};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Instant;
+use ukweli_db::UkweliDb;
+use ukweli_db::core::User;
+use ukweli_db::storage::append::AppendLog;
+use ukweli_db::storage::compaction::CompactionPolicy;
+use ukweli_db::storage::durability::Durability;
+use ukweli_db::storage::reader::DatabaseReader;
+use ukweli_db::storage::recovery::RecoveryManager;
+use ukweli_db::storage::segment::Manifest;
+
+use crate::config::Config;
+
+fn resolve_path(path: Option<PathBuf>) -> Result<PathBuf> {
+    let db_path = match path {
+        Some(path) => path,
+        None => Config::load_or_default()?.db_path,
+    };
+
+    if !db_path.exists() {
+        bail!("Database not found at: {}", db_path.display());
+    }
+
+    Ok(db_path)
+}
+
+pub fn inspect(path: Option<PathBuf>) -> Result<()> {
+    let db_path = resolve_path(path)?;
+
+    let mut all_valid = true;
+
+    match Manifest::load(&db_path).context("Failed to read manifest")? {
+        Some(manifest) => {
+            println!("Manifest: {}", Manifest::path_for(&db_path).display());
+            println!("Segments: {}", manifest.segments.len());
+            println!("Records:  {}", manifest.next_index());
+
+            for segment in &manifest.segments {
+                println!(
+                    "\nSegment {} (records {}..{})",
+                    segment.file,
+                    segment.first_index,
+                    segment.end_index()
+                );
+                let path = manifest.segment_path(&db_path, segment);
+                if let Some(key) = &segment.object
+                    && !path.exists()
+                {
+                    // checked against the manifest again when it is fetched
+                    println!("Archived as {}, not cached locally", key);
+                    continue;
+                }
+                all_valid &= inspect_file(&path)?;
+            }
+        }
+        None => {
+            all_valid &= inspect_file(&db_path)?;
+        }
+    }
+
+    // finding where the WAL starts reads the last segment, fetched if archived
+    let backend = Config::load_or_default()?.backend()?;
+    if let Some(mut wal) =
+        AppendLog::open_existing_in(&backend, &db_path).context("Failed to open WAL")?
+    {
+        let stats = wal.stats().context("Failed to read WAL")?;
+        println!(
+            "\nWAL: {} entries, {} bytes, oldest {}s ago{}",
+            stats.entries,
+            stats.bytes,
+            stats.age().as_secs(),
+            if CompactionPolicy::default().is_due(&stats) {
+                " (compaction due on the next write)"
+            } else {
+                ""
+            }
+        );
+    }
+
+    if !all_valid {
+        bail!("Database failed verification");
+    }
+
+    Ok(())
+}
+
+pub fn repair(path: Option<PathBuf>, discard: bool) -> Result<()> {
+    let db_path = resolve_path(path)?;
+
+    println!("Repairing database at: {}", db_path.display());
+
+    let (repair, ledger) =
+        RecoveryManager::repair(&db_path, !discard).context("Failed to repair database")?;
+
+    match &repair.damage {
+        Some(damage) => {
+            println!(
+                "WAL entry {} at offset {} is damaged: {}",
+                damage.entry, damage.offset, damage.kind
+            );
+            println!(
+                "Kept the {} entries before it, cut {} bytes",
+                damage.entry, damage.tail_bytes
+            );
+            match &repair.quarantined {
+                Some(path) => println!("Damaged tail moved to: {}", path.display()),
+                None => println!("Damaged tail discarded"),
+            }
+        }
+        None => println!("WAL is intact"),
+    }
+
+    if repair.uncommitted_discarded {
+        println!("Discarded a batch that was never committed");
+    }
+
+    println!("Recovered {} records, chain is valid", ledger.length());
+
+    Ok(())
+}
+
+pub fn archive(path: Option<PathBuf>, store: Option<String>, evict: bool) -> Result<()> {
+    let db_path = resolve_path(path)?;
+    let Some(archive) = Config::load_or_default()?.archive_backend(store.as_deref())? else {
+        bail!("No object store given, pass --store or set \"archive\" in config.json");
+    };
+
+    println!("Pushing sealed segments of {}", db_path.display());
+    let report = archive.push(&db_path).context("Failed to push segments")?;
+    println!(
+        "Uploaded {} segments ({} bytes), {} were already stored",
+        report.uploaded, report.bytes, report.already_stored
+    );
+
+    if evict {
+        let evicted = archive
+            .evict(&db_path)
+            .context("Failed to drop local copies")?;
+        println!(
+            "Dropped {} local copies, they are fetched again when read",
+            evicted
+        );
+    }
+
+    Ok(())
+}
+
+pub fn bench(records: usize, threads: usize, durability: Option<Durability>) -> Result<()> {
+    if records == 0 || threads == 0 {
+        bail!("Need at least one record and one thread");
+    }
+
+    let modes = match durability {
+        Some(mode) => vec![mode],
+        None => vec![
+            Durability::None,
+            Durability::Flush,
+            Durability::Fsync,
+            Durability::group_commit(),
+        ],
+    };
+
+    // never the real ledger, the records would stay in it for good
+    let scratch = std::env::temp_dir().join(format!("ukweli-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&scratch).context("Failed to create scratch directory")?;
+
+    println!(
+        "Appending {} records from {} threads per mode, scratch files in {}",
+        records,
+        threads,
+        scratch.display()
+    );
+    println!(
+        "\n{:<26} {:>10} {:>12} {:>12} {:>8}",
+        "Durability", "records/s", "mean", "max", "fsyncs"
+    );
+
+    let result = modes.iter().enumerate().try_for_each(|(i, mode)| {
+        bench_mode(
+            &scratch.join(format!("bench-{}.ukweli", i)),
+            *mode,
+            records,
+            threads,
+        )
+    });
+
+    let _ = std::fs::remove_dir_all(&scratch);
+    result
+}
+
+fn bench_mode(path: &Path, mode: Durability, records: usize, threads: usize) -> Result<()> {
+    let db = UkweliDb::create(path)
+        .context("Failed to create scratch database")?
+        .with_durability(mode);
+    let user = User::new("bench");
+    db.register_user(user.clone())?;
+
+    let started = Instant::now();
+    thread::scope(|scope| {
+        let writers: Vec<_> = (0..threads)
+            .map(|t| {
+                let (db, user) = (&db, &user);
+                // the first `records % threads` writers take one extra
+                let count = records / threads + usize::from(t < records % threads);
+                scope.spawn(move || -> Result<()> {
+                    for i in 0..count {
+                        db.append_record(
+                            format!("bench {}-{}", t, i).as_str(),
+                            vec![user.clone()],
+                        )?;
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+
+        writers
+            .into_iter()
+            .try_for_each(|writer| match writer.join() {
+                Ok(result) => result,
+                Err(_) => bail!("Bench writer panicked"),
+            })
+    })?;
+    // buffered records only count once they are written
+    db.flush()?;
+    let elapsed = started.elapsed();
+
+    let stats = db.stats()?;
+    println!(
+        "{:<26} {:>10.0} {:>12} {:>12} {:>8}",
+        mode.to_string(),
+        records as f64 / elapsed.as_secs_f64(),
+        format!("{:.2?}", stats.mean_latency()),
+        format!("{:.2?}", stats.max_latency),
+        stats.fsyncs
+    );
+
+    Ok(())
+}
+
+fn inspect_file(path: &Path) -> Result<bool> {
+    let reader =
+        DatabaseReader::new(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let inspection = reader
+        .inspect()
+        .with_context(|| format!("Unreadable header in {}", path.display()))?;
+    let header = &inspection.header;
+
+    println!("─────────────────────────────────────");
+    println!("File:          {}", path.display());
+    println!("Size:          {} bytes", inspection.file_size);
+
+    println!("\nHeader");
+    println!(
+        "  Version:     {}.{}",
+        header.version_major, header.version_minor
+    );
+    println!("  Records:     {}", header.record_count);
+    println!("  First index: {}", header.first_record_index);
+    println!("  Created:     {}", header.created_timestamp);
+    println!("  Modified:    {}", header.last_modified);
+    println!("  Body offset: {}", header.body_offset);
+    println!("  Footer at:   {}", header.footer_offset);
+    println!("  Checksum:    {}", hex::encode(header.checksum));
+
+    println!("\nBody");
+    match &inspection.body {
+        Some(body) => {
+            println!("  Records:     {}", body.records);
+            if let (Some(first), Some(last)) = (body.first_index, body.last_index) {
+                println!("  Indexes:     {}..={}", first, last);
+            }
+            println!("  Users:       {}", body.users);
+            println!("  Checkpoints: {}", body.checkpoints);
+        }
+        None => println!("  unreadable"),
+    }
+
+    println!("\nFooter");
+    match &inspection.footer {
+        Some(footer) => {
+            println!("  Integrity:   {}", hex::encode(footer.integrity_hash));
+            println!("  File size:   {} bytes", footer.total_file_size);
+        }
+        None => println!("  missing"),
+    }
+
+    match &inspection.verification {
+        Ok(()) => {
+            println!("\nVerification: OK");
+            Ok(true)
+        }
+        Err(e) => {
+            println!("\nVerification: FAILED - {}", e);
+            Ok(false)
+        }
+    }
+}
@@ -1,10 +1,12 @@
 use anyhow::{Context, Result};
 use std::path::PathBuf;
-use ukweli_db::{Ledger, storage::writer::DatabaseWriter};
+use ukweli_db::UkweliDb;
+use ukweli_db::core::OperatorDesignation;
 
 use crate::config::Config;
+use crate::user_store::UserStore;
 
-pub fn run(db_path: Option<PathBuf>) -> Result<()> {
+pub fn run(db_path: Option<PathBuf>, operator_id: Option<String>) -> Result<()> {
     println!("Initialising Ukweli database...\n");
     println!("Path {:?}", db_path);
 
@@ -22,20 +24,40 @@ pub fn run(db_path: Option<PathBuf>) -> Result<()> {
     create_directory_structure()?;
 
     println!("Setting up genesis ledger...");
-    let ledger = Ledger::new();
-
     println!("Writing up ledger to: {}", config.db_path.display());
 
     if let Some(parent) = config.db_path.parent() {
         std::fs::create_dir_all(parent).context("Failed to create database directory")?;
     }
 
-    let mut writer =
-        DatabaseWriter::new(&config.db_path).context("Failed to create database writer")?;
-
-    writer
-        .write_ledger(&ledger)
-        .context("Failed to write initial ledger")?;
+    // unlocked before the ledger is written, a wrong passphrase leaves no ledger behind
+    let users = UserStore::open()?;
+    let operator = match &operator_id {
+        Some(id) if users.user_exists(id) => Some(users.load_user(id)?),
+        Some(id) => Some(users.create_user(id)?),
+        None => None,
+    };
+
+    let db = UkweliDb::create(&config.db_path).context("Failed to write initial ledger")?;
+
+    match operator {
+        Some(operator) => {
+            // named right after genesis, before anyone else can claim it
+            db.register_user(operator.clone())
+                .context("Failed to register operator")?;
+            db.append_record(
+                OperatorDesignation::new(&operator.user_id).to_payload(),
+                vec![operator.clone()],
+            )
+            .context("Failed to name the operator")?;
+            println!("'{}' is the ledger operator", operator.user_id);
+        }
+        None => {
+            println!("Warning: no operator named, this ledger can't be checkpointed");
+            println!("and no roles can be granted, the operator grants the first admin");
+            println!("Name one with: ukweli checkpoint designate --operator <user>");
+        }
+    }
 
     println!("Note: GENESIS user is in the ledger but cannot sign new records from CLI");
     println!("Create new users with: ukweli user create <username>");
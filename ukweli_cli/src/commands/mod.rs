@@ -1,3 +1,7 @@
+pub mod checkpoint;
+pub mod db;
 pub mod init;
+pub mod proof;
 pub mod record;
+pub mod state;
 pub mod workflow;
@@ -0,0 +1,136 @@
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use std::path::PathBuf;
+use ukweli_db::core::merkle::{self, ConsistencyProof, InclusionProof};
+
+use crate::{ledger_manager::LedgerManager, user_store::UserStore};
+
+pub fn inclusion(index: usize, out: Option<PathBuf>, signer: Option<String>) -> Result<()> {
+    let ledger_mgr = LedgerManager::load_read_only()?;
+    let ledger = ledger_mgr.ledger()?;
+
+    let mut proof = ledger
+        .inclusion_proof(index)
+        .with_context(|| format!("Cannot build proof for record #{}", index))?;
+
+    if let Some(signer_id) = signer {
+        let user = UserStore::open()?
+            .load_user(&signer_id)
+            .with_context(|| format!("Failed to load signer '{}'", signer_id))?;
+        proof.signed_tree_head = Some(ledger.tree_head().sign(&user));
+    }
+
+    let json = serde_json::to_string_pretty(&proof).context("Failed to serialize proof")?;
+
+    match out {
+        Some(path) => {
+            std::fs::write(&path, json).context("Failed to write proof file")?;
+            println!(
+                "Inclusion proof for record #{} written to: {}",
+                index,
+                path.display()
+            );
+        }
+        None => println!("{}", json),
+    }
+
+    println!("Tree size: {}", proof.tree_size);
+    println!("Root hash: {}", proof.root_hash);
+    println!("Path:      {} hashes", proof.path.len());
+
+    Ok(())
+}
+
+pub fn consistency(from: usize, to: Option<usize>, out: Option<PathBuf>) -> Result<()> {
+    let ledger_mgr = LedgerManager::load_read_only()?;
+    let ledger = ledger_mgr.ledger()?;
+    let to = to.unwrap_or(ledger.length());
+
+    let proof = ledger
+        .consistency_proof(from, to)
+        .with_context(|| format!("Cannot build consistency proof from {} to {}", from, to))?;
+
+    write_proof(&proof, out.as_ref())?;
+
+    println!("Old size: {} (root {})", proof.old_size, proof.old_root);
+    println!("New size: {} (root {})", proof.new_size, proof.new_root);
+    println!("Path:     {} hashes", proof.path.len());
+
+    Ok(())
+}
+
+fn write_proof<T: serde::Serialize>(proof: &T, out: Option<&PathBuf>) -> Result<()> {
+    let json = serde_json::to_string_pretty(proof).context("Failed to serialize proof")?;
+
+    match out {
+        Some(path) => {
+            std::fs::write(path, json).context("Failed to write proof file")?;
+            println!("Proof written to: {}", path.display());
+        }
+        None => println!("{}", json),
+    }
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ProofFile {
+    Inclusion(InclusionProof),
+    Consistency(ConsistencyProof),
+}
+
+pub fn verify(file: PathBuf, old_root: Option<String>) -> Result<()> {
+    let content = std::fs::read_to_string(&file).context("Failed to read proof file")?;
+    let proof: ProofFile = serde_json::from_str(&content).context("Failed to parse proof file")?;
+
+    match proof {
+        ProofFile::Inclusion(proof) => {
+            if old_root.is_some() {
+                bail!("--old-root only applies to consistency proofs");
+            }
+            verify_inclusion(&proof)
+        }
+        ProofFile::Consistency(proof) => verify_consistency(&proof, old_root.as_deref()),
+    }
+}
+
+fn verify_inclusion(proof: &InclusionProof) -> Result<()> {
+    merkle::verify_inclusion(proof).context("Proof verification failed")?;
+
+    println!("Proof is valid");
+    println!("Record #{} ({})", proof.index, proof.record_hash);
+    println!(
+        "is included in the ledger of size {} with root {}",
+        proof.tree_size, proof.root_hash
+    );
+
+    if let Some(sth) = &proof.signed_tree_head {
+        println!(
+            "Tree head signed by '{}' (key {})",
+            sth.signer_id, sth.verifying_key
+        );
+    }
+
+    Ok(())
+}
+
+fn verify_consistency(proof: &ConsistencyProof, old_root: Option<&str>) -> Result<()> {
+    merkle::verify_consistency(proof, old_root).context("Proof verification failed")?;
+
+    println!("Proof is valid");
+    println!(
+        "Ledger of size {} (root {})",
+        proof.new_size, proof.new_root
+    );
+    println!(
+        "is an append-only extension of size {} (root {})",
+        proof.old_size, proof.old_root
+    );
+
+    if old_root.is_none() {
+        println!("Note: compare the old root with one you recorded earlier, or pass --old-root");
+    }
+
+    Ok(())
+}
@@ -1,29 +1,77 @@
 use anyhow::Context;
 use anyhow::{Result, bail};
-
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use ukweli_db::Payload;
+use ukweli_db::workflow::TransitionMetadata;
+
+use ukweli_db::core::roles::RoleAction;
+use ukweli_db::core::{Deactivation, KeyRevocation, KeyRotation, Record, RoleChange, User};
+use ukweli_db::storage::durability::Durability;
+use ukweli_db::storage::segment;
+
+use crate::commands::workflow::load_engine;
+use crate::config::Config;
 use crate::{ledger_manager::LedgerManager, user_store::UserStore};
 
-pub fn append(payload: String, signer_ids: Vec<String>) -> Result<()> {
-    if payload.is_empty() {
-        bail!("Payload cannot be empty");
-    }
+#[allow(clippy::too_many_arguments)]
+pub fn append(
+    payload: Option<String>,
+    signer_ids: Vec<String>,
+    entity: Option<String>,
+    workflow: Option<String>,
+    transition: Option<String>,
+    tags: Vec<String>,
+    json: bool,
+    durability: Option<Durability>,
+) -> Result<()> {
+    let raw = payload.unwrap_or_default();
+
+    let body = if raw.is_empty() {
+        None
+    } else if json {
+        Some(serde_json::from_str(&raw).context("Payload is not valid JSON")?)
+    } else {
+        Some(Value::String(raw))
+    };
+
+    let tags = tags
+        .iter()
+        .map(|tag| match tag.split_once('=') {
+            Some((key, value)) => Ok((key.to_string(), value.to_string())),
+            None => bail!("Tags must look like key=value, got '{}'", tag),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let payload = build_payload(body, json, entity, workflow, transition, tags)?;
 
     if signer_ids.is_empty() {
         bail!("At least one signer is required");
     }
 
     println!("Appending record...");
+    let metadata = TransitionMetadata::from_payload(&payload);
+    if let Some(metadata) = &metadata {
+        println!(
+            "Entity: {} ({}: {})",
+            metadata.entity_id, metadata.workflow_id, metadata.transition
+        );
+    }
     println!("Payload: {}", payload);
     println!("Signers: {}", signer_ids.join(", "));
 
-    let mut ledger_mgr = LedgerManager::load()?;
+    let ledger_mgr = LedgerManager::load()?.with_durability(durability.unwrap_or_default());
 
+    let users = UserStore::open()?;
     let mut signers = Vec::new();
     for signer_id in &signer_ids {
-        let user = UserStore::load_user(signer_id)
+        let user = users
+            .load_user(signer_id)
             .with_context(|| format!("Failed to load signer '{}'", signer_id))?;
 
-        if !ledger_mgr.ledger().verify_registry.contains_key(signer_id) {
+        if !ledger_mgr.ledger()?.verify_registry.contains_key(signer_id) {
             println!(
                 "User '{}' not registered in ledger, attempting to register...",
                 signer_id
@@ -34,19 +82,153 @@ pub fn append(payload: String, signer_ids: Vec<String>) -> Result<()> {
         signers.push(user);
     }
 
-    let index = ledger_mgr.append_record(&payload, signers)?;
+    let index = if metadata.is_some() {
+        let engine = load_engine()?;
+        ledger_mgr.append_transition(&engine, payload, signers)?
+    } else {
+        ledger_mgr.append_record(payload, signers)?
+    };
 
     println!("\n Record appended successfully!");
     println!("   Index: {}", index);
     println!(
         "   Hash: {}",
         ledger_mgr
-            .ledger()
+            .ledger()?
             .records
             .get(index)
             .map(|r| r.record_hash.as_str())
             .unwrap_or("unknown")
     );
+    ledger_mgr.print_stats()?;
+
+    Ok(())
+}
+
+fn build_payload(
+    body: Option<Value>,
+    json: bool,
+    entity: Option<String>,
+    workflow: Option<String>,
+    transition: Option<String>,
+    tags: Vec<(String, String)>,
+) -> Result<Payload> {
+    let mut payload = match (entity, workflow, transition) {
+        (Some(entity), Some(workflow), Some(transition)) => {
+            let metadata = TransitionMetadata::new(&entity, &workflow, &transition);
+            match body {
+                Some(body) => metadata.with_data(body).to_payload(),
+                None => metadata.to_payload(),
+            }
+        }
+        (None, None, None) => match body {
+            Some(value) if json => Payload::json(value),
+            Some(Value::String(text)) => Payload::text(&text),
+            Some(value) => Payload::text(&value.to_string()),
+            None => bail!("Payload cannot be empty"),
+        },
+        _ => bail!("--entity, --workflow and --transition must be used together"),
+    };
+
+    for (key, value) in tags {
+        payload = payload.with_tag(&key, &value);
+    }
+
+    Ok(payload)
+}
+
+/// One line of an `append-batch` file. A string payload is stored as text,
+/// anything else as JSON.
+#[derive(Deserialize)]
+struct BatchLine {
+    #[serde(default)]
+    payload: Option<Value>,
+    signers: Vec<String>,
+    entity: Option<String>,
+    workflow: Option<String>,
+    transition: Option<String>,
+    #[serde(default)]
+    tags: BTreeMap<String, String>,
+}
+
+pub fn append_batch(file: PathBuf, durability: Option<Durability>) -> Result<()> {
+    let content = std::fs::read_to_string(&file)
+        .with_context(|| format!("Failed to read batch file {}", file.display()))?;
+
+    let ledger_mgr = LedgerManager::load()?.with_durability(durability.unwrap_or_default());
+    let user_store = UserStore::open()?;
+    let mut users: HashMap<String, User> = HashMap::new();
+    let mut records = Vec::new();
+    let mut has_transitions = false;
+
+    for (number, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let line: BatchLine = serde_json::from_str(line)
+            .with_context(|| format!("Line {} is not a valid batch entry", number + 1))?;
+
+        if line.signers.is_empty() {
+            bail!("Line {} has no signers", number + 1);
+        }
+
+        let json = !matches!(line.payload, Some(Value::String(_)));
+        has_transitions |= line.entity.is_some();
+        let payload = build_payload(
+            line.payload,
+            json,
+            line.entity,
+            line.workflow,
+            line.transition,
+            line.tags.into_iter().collect(),
+        )
+        .with_context(|| format!("Line {}", number + 1))?;
+
+        let mut signers = Vec::new();
+        for signer_id in &line.signers {
+            if !users.contains_key(signer_id) {
+                let user = user_store
+                    .load_user(signer_id)
+                    .with_context(|| format!("Failed to load signer '{}'", signer_id))?;
+
+                if !ledger_mgr.ledger()?.verify_registry.contains_key(signer_id) {
+                    println!(
+                        "User '{}' not registered in ledger, attempting to register...",
+                        signer_id
+                    );
+                    ledger_mgr.register_user(user.clone())?;
+                }
+
+                users.insert(signer_id.clone(), user);
+            }
+
+            if let Some(user) = users.get(signer_id) {
+                signers.push(user.clone());
+            }
+        }
+
+        records.push((payload, signers));
+    }
+
+    if records.is_empty() {
+        bail!("Batch file {} has no records", file.display());
+    }
+
+    println!("Appending {} records as one batch...", records.len());
+
+    let engine = if has_transitions {
+        Some(load_engine()?)
+    } else {
+        None
+    };
+    let indices = ledger_mgr.append_batch(engine.as_ref(), records)?;
+
+    println!("\n Batch appended successfully!");
+    for index in indices {
+        println!("   #{}", index);
+    }
+    ledger_mgr.print_stats()?;
 
     Ok(())
 }
@@ -57,9 +239,10 @@ pub fn list(
     to: Option<usize>,
     limit: Option<usize>,
 ) -> Result<()> {
-    let ledger_mgr = LedgerManager::load()?;
+    let ledger_mgr = LedgerManager::load_read_only()?;
 
-    let all_records: Vec<_> = ledger_mgr.ledger().all_records().collect();
+    let ledger = ledger_mgr.ledger()?;
+    let all_records: Vec<_> = ledger.all_records().collect();
 
     if all_records.is_empty() {
         println!("No records in ledger.");
@@ -128,13 +311,11 @@ pub fn list(
             .collect::<Vec<_>>()
             .join(", ");
 
-        let display_payload = if record.payload.len() > 60 {
-            format!(
-                "{}...",
-                &record.payload.chars().take(57).collect::<String>()
-            )
+        let payload = record.payload.to_string();
+        let display_payload = if payload.chars().count() > 60 {
+            format!("{}...", &payload.chars().take(57).collect::<String>())
         } else {
-            record.payload.clone()
+            payload
         };
 
         println!(
@@ -146,16 +327,67 @@ pub fn list(
     Ok(())
 }
 pub fn show(index: usize) -> Result<()> {
-    let ledger_mgr = LedgerManager::load()?;
-
-    let record = ledger_mgr
-        .ledger()
-        .records
-        .get(index)
-        .with_context(|| format!("Record #{} not found", index))?;
+    let record = match read_sealed_record(index) {
+        Some(record) => record,
+        None => {
+            // not compacted yet, or the file needs recovery
+            let ledger_mgr = LedgerManager::load_read_only()?;
+            ledger_mgr
+                .ledger()?
+                .records
+                .get(index)
+                .cloned()
+                .with_context(|| format!("Record #{} not found", index))?
+        }
+    };
 
     println!("Record #{}", record.index);
     println!("─────────────────────────────────────");
+    println!("Content Type: {}", record.payload.content_type);
+    if let Some(metadata) = TransitionMetadata::from_payload(&record.payload) {
+        println!("Entity:       {}", metadata.entity_id);
+        println!("Workflow:     {}", metadata.workflow_id);
+        println!("Transition:   {}", metadata.transition);
+    }
+    if let Some(rotation) = KeyRotation::from_payload(&record.payload) {
+        println!(
+            "Key:          {} moves to {}",
+            rotation.user_id, rotation.new_key
+        );
+    }
+    if let Some(revocation) = KeyRevocation::from_payload(&record.payload) {
+        println!(
+            "Key:          {} of {} revoked, compromised from {}",
+            revocation.key, revocation.user_id, revocation.since
+        );
+    }
+    if let Some(deactivation) = Deactivation::from_payload(&record.payload) {
+        println!(
+            "Deactivates:  {} from record #{} on ({})",
+            deactivation.user_id, deactivation.effective_index, deactivation.reason
+        );
+    }
+    if let Some(change) = RoleChange::from_payload(&record.payload) {
+        match change.action {
+            RoleAction::Grant => {
+                println!("Role:         grants {} to {}", change.role, change.user_id)
+            }
+            RoleAction::Revoke => println!(
+                "Role:         revokes {} from {}",
+                change.role, change.user_id
+            ),
+        }
+    }
+    if !record.payload.tags.is_empty() {
+        let tags = record
+            .payload
+            .tags
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<_>>()
+            .join(", ");
+        println!("Tags:         {}", tags);
+    }
     println!("Payload:      {}", record.payload);
     println!("Payload Hash: {}", record.payload_hash);
     println!("Record Hash:  {}", record.record_hash);
@@ -164,20 +396,7 @@ pub fn show(index: usize) -> Result<()> {
     println!("Nonce:        {}", record.nonce);
     println!("\nSigners:");
     for signer in &record.signers {
-        println!(
-            "  • {} (roles: {})",
-            signer.user_id,
-            if signer.roles.is_empty() {
-                "none".to_string()
-            } else {
-                signer
-                    .roles
-                    .iter()
-                    .map(|s| s.as_str())
-                    .collect::<Vec<_>>()
-                    .join(", ")
-            }
-        );
+        println!("  • {}", signer.user_id);
     }
     println!("\nSignatures:");
     for (user_id, sig) in &record.signatures {
@@ -193,7 +412,55 @@ pub fn compact() -> Result<()> {
 }
 
 pub fn verify() -> Result<()> {
-    let ledger_mgr = LedgerManager::load()?;
+    let ledger_mgr = LedgerManager::load_read_only()?;
     ledger_mgr.verify_chain()?;
+
+    let compromised = ledger_mgr.ledger()?.compromised_records();
+    if !compromised.is_empty() {
+        println!(
+            "Warning: {} signatures come from keys revoked as compromised, see: ukweli record compromised",
+            compromised.len()
+        );
+    }
     Ok(())
 }
+
+pub fn compromised() -> Result<()> {
+    let ledger_mgr = LedgerManager::load_read_only()?;
+    let ledger = ledger_mgr.ledger()?;
+    let compromised = ledger.compromised_records();
+
+    if compromised.is_empty() {
+        println!("No records were signed with a revoked key");
+        return Ok(());
+    }
+
+    println!("Records signed with revoked keys:");
+    for affected in &compromised {
+        let payload = ledger
+            .records
+            .get(affected.index)
+            .map(|record| record.payload.to_string())
+            .unwrap_or_default();
+        let display_payload = if payload.chars().count() > 40 {
+            format!("{}...", &payload.chars().take(37).collect::<String>())
+        } else {
+            payload
+        };
+
+        println!(
+            "#{:<4} | {} | Signer: {} (compromised from {})",
+            affected.index, display_payload, affected.user_id, affected.since
+        );
+    }
+    println!("\n{} affected signatures", compromised.len());
+
+    Ok(())
+}
+
+// sealed records never change, so they can be served from the mapped segment
+// without replaying the WAL or loading the rest of the ledger
+fn read_sealed_record(index: usize) -> Option<Record> {
+    let config = Config::load_or_default().ok()?;
+    segment::read_sealed_record(&config.db_path, index).ok()?
+}
@@ -0,0 +1,84 @@
+use anyhow::{Result, bail};
+use ukweli_db::workflow::{EntityState, StateProjector};
+
+use crate::{commands::workflow::load_engine, ledger_manager::LedgerManager};
+
+fn load_entity(entity_id: &str) -> Result<EntityState> {
+    let ledger_mgr = LedgerManager::load_read_only()?;
+    let engine = load_engine()?;
+
+    let ledger = ledger_mgr.ledger()?;
+    let projector = StateProjector::new(&engine);
+    match projector.entity(&ledger, entity_id) {
+        Some(state) => Ok(state),
+        None => bail!("No records found for entity '{}'", entity_id),
+    }
+}
+
+pub fn current(entity_id: String) -> Result<()> {
+    let state = load_entity(&entity_id)?;
+
+    println!("Entity:   {}", state.entity_id);
+    println!("Workflow: {}", state.workflow_id);
+    if state.current_state.is_empty() {
+        println!(
+            "State:    unknown, workflow '{}' is not loaded",
+            state.workflow_id
+        );
+    } else {
+        println!("State:    {}", state.current_state);
+    }
+
+    if let Some(last) = state.history.last() {
+        println!(
+            "Since:    record #{} ({})",
+            last.record_index, last.transition
+        );
+    }
+
+    if !state.rejected.is_empty() {
+        println!(
+            "\n{} record(s) ignored, see: ukweli state history {}",
+            state.rejected.len(),
+            state.entity_id
+        );
+    }
+
+    Ok(())
+}
+
+pub fn history(entity_id: String) -> Result<()> {
+    let state = load_entity(&entity_id)?;
+
+    println!("Entity: {} ({})", state.entity_id, state.workflow_id);
+    println!("─────────────────────────────────────");
+
+    if state.history.is_empty() {
+        println!("No transitions applied yet.");
+    }
+
+    for change in &state.history {
+        println!(
+            "#{:<4} | {} → {} | {} | Signers: {}",
+            change.record_index,
+            change.from_state,
+            change.to_state,
+            change.transition,
+            change.signers.join(", ")
+        );
+    }
+
+    if !state.rejected.is_empty() {
+        println!("\nRejected:");
+        for rejected in &state.rejected {
+            println!(
+                "#{:<4} | {} | {}",
+                rejected.record_index, rejected.transition, rejected.reason
+            );
+        }
+    }
+
+    println!("\nCurrent state: {}", state.current_state);
+
+    Ok(())
+}
@@ -152,6 +152,33 @@ pub fn delete(workflow_id: String) -> Result<()> {
     Ok(())
 }
 
+// all saved workflows in one engine, used by anything that replays entity state
+pub fn load_engine() -> Result<Engine> {
+    let workflows_dir = Config::workflows_dir()?;
+    let mut engine = Engine::new();
+
+    if !workflows_dir.exists() {
+        return Ok(engine);
+    }
+
+    for entry in std::fs::read_dir(&workflows_dir)? {
+        let path = entry?.path();
+
+        if path.extension().and_then(|s| s.to_str()) == Some("json") {
+            let content = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let workflow_json: Value = serde_json::from_str(&content)
+                .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+            engine
+                .load_workflow_from_json(workflow_json)
+                .with_context(|| format!("Failed to load {}", path.display()))?;
+        }
+    }
+
+    Ok(engine)
+}
+
 fn load_workflow_from_file<P: AsRef<Path>>(path: P) -> Result<Workflow> {
     let content = std::fs::read_to_string(path.as_ref()).context("Failed to read workflow file")?;
 
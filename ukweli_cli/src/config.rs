@@ -1,10 +1,18 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::sync::Arc;
+use ukweli_db::storage::archive::ArchiveBackend;
+use ukweli_db::storage::backend::{self, Backend};
+use ukweli_db::storage::object_store;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
     pub db_path: PathBuf,
+
+    /// object store sealed segments are pushed to, `s3://bucket/prefix` or a directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive: Option<String>,
 }
 
 impl Config {
@@ -42,10 +50,33 @@ impl Config {
         } else {
             Ok(Config {
                 db_path: Self::default_db_path()?,
+                archive: None,
             })
         }
     }
 
+    /// The archive at `url`, or the configured one, on top of the filesystem.
+    pub fn archive_backend(&self, url: Option<&str>) -> Result<Option<Arc<ArchiveBackend>>> {
+        let Some(url) = url.or(self.archive.as_deref()) else {
+            return Ok(None);
+        };
+
+        let store = object_store::open(url)
+            .with_context(|| format!("Failed to open object store {}", url))?;
+        Ok(Some(Arc::new(ArchiveBackend::new(
+            backend::filesystem(),
+            store,
+        ))))
+    }
+
+    /// Where the database is read from, archived segments are fetched as needed.
+    pub fn backend(&self) -> Result<Backend> {
+        Ok(match self.archive_backend(None)? {
+            Some(archive) => archive,
+            None => backend::filesystem(),
+        })
+    }
+
     // pub fn save(&self) -> Result<()> {
     //     let config_path = Self::config_file()?;
 
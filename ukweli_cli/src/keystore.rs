@@ -0,0 +1,240 @@
+use anyhow::{Context, Result, anyhow, bail};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+use zeroize::Zeroizing;
+
+/// Current keystore format, files without a version are the old plaintext ones.
+pub const KEYSTORE_VERSION: u32 = 2;
+
+/// Passphrase for every user, for scripts and CI.
+pub const PASSPHRASE_ENV: &str = "UKWELI_PASSPHRASE";
+/// Passphrase `change-passphrase` switches to, when not prompting.
+pub const NEW_PASSPHRASE_ENV: &str = "UKWELI_NEW_PASSPHRASE";
+/// Shell command printing the passphrase, run with `UKWELI_USER` set, so a
+/// password manager or agent can hand it over.
+pub const PASSPHRASE_COMMAND_ENV: &str = "UKWELI_PASSPHRASE_COMMAND";
+
+// argon2id, 64 MiB and three passes
+const M_COST: u32 = 64 * 1024;
+const T_COST: u32 = 3;
+const P_COST: u32 = 1;
+
+const KDF: &str = "argon2id";
+const CIPHER: &str = "xchacha20poly1305";
+
+/// A signing key sealed under a passphrase. The KDF parameters are kept with
+/// it, so they can be raised later without breaking older files.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptedKey {
+    kdf: String,
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    salt: String,
+    cipher: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+impl EncryptedKey {
+    /// `context` is bound into the ciphertext, a key moved to another user's
+    /// file doesn't decrypt.
+    pub fn seal(signing_key: &[u8; 32], passphrase: &str, context: &str) -> Result<Self> {
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+        let key = derive_key(passphrase, &salt, M_COST, T_COST, P_COST)?;
+        let ciphertext = XChaCha20Poly1305::new(Key::from_slice(key.as_slice()))
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: signing_key,
+                    aad: context.as_bytes(),
+                },
+            )
+            .map_err(|_| anyhow!("Failed to encrypt signing key"))?;
+
+        Ok(Self {
+            kdf: KDF.to_string(),
+            m_cost: M_COST,
+            t_cost: T_COST,
+            p_cost: P_COST,
+            salt: hex::encode(salt),
+            cipher: CIPHER.to_string(),
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(ciphertext),
+        })
+    }
+
+    pub fn open(&self, passphrase: &str, context: &str) -> Result<Zeroizing<[u8; 32]>> {
+        if self.kdf != KDF || self.cipher != CIPHER {
+            bail!("Unsupported keystore ({} / {})", self.kdf, self.cipher);
+        }
+
+        let salt = hex::decode(&self.salt).context("Corrupt keystore salt")?;
+        let nonce = hex::decode(&self.nonce).context("Corrupt keystore nonce")?;
+        let ciphertext = hex::decode(&self.ciphertext).context("Corrupt keystore key")?;
+        if nonce.len() != 24 {
+            bail!("Corrupt keystore nonce");
+        }
+
+        let key = derive_key(passphrase, &salt, self.m_cost, self.t_cost, self.p_cost)?;
+        let plaintext = Zeroizing::new(
+            XChaCha20Poly1305::new(Key::from_slice(key.as_slice()))
+                .decrypt(
+                    XNonce::from_slice(&nonce),
+                    Payload {
+                        msg: &ciphertext,
+                        aad: context.as_bytes(),
+                    },
+                )
+                .map_err(|_| anyhow!("Wrong passphrase, or the key file was tampered with"))?,
+        );
+
+        let mut signing_key = Zeroizing::new([0u8; 32]);
+        if plaintext.len() != signing_key.len() {
+            bail!("Invalid signing key length");
+        }
+        signing_key.copy_from_slice(&plaintext);
+        Ok(signing_key)
+    }
+}
+
+fn derive_key(
+    passphrase: &str,
+    salt: &[u8],
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+) -> Result<Zeroizing<[u8; 32]>> {
+    let params = Params::new(m_cost, t_cost, p_cost, Some(32))
+        .map_err(|e| anyhow!("Invalid key derivation parameters: {}", e))?;
+
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, key.as_mut_slice())
+        .map_err(|e| anyhow!("Key derivation failed: {}", e))?;
+    Ok(key)
+}
+
+/// Where `UserStore` gets passphrases from, `Interactive` unless a test
+/// hands in its own.
+pub trait PassphraseSource {
+    /// The passphrase protecting `user_id`'s key.
+    fn passphrase(&self, user_id: &str) -> Result<Zeroizing<String>>;
+
+    /// A passphrase to seal `user_id`'s key under, `env` may hold it.
+    fn new_passphrase(&self, user_id: &str, env: &str) -> Result<Zeroizing<String>>;
+}
+
+/// The environment, the passphrase agent or the terminal, see `passphrase`
+/// and `new_passphrase`.
+pub struct Interactive;
+
+impl PassphraseSource for Interactive {
+    fn passphrase(&self, user_id: &str) -> Result<Zeroizing<String>> {
+        passphrase(user_id)
+    }
+
+    fn new_passphrase(&self, user_id: &str, env: &str) -> Result<Zeroizing<String>> {
+        new_passphrase(user_id, env)
+    }
+}
+
+/// The passphrase protecting `user_id`'s key, from `UKWELI_PASSPHRASE`, the
+/// `UKWELI_PASSPHRASE_COMMAND` agent, or a prompt, in that order.
+pub fn passphrase(user_id: &str) -> Result<Zeroizing<String>> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        return Ok(Zeroizing::new(passphrase));
+    }
+
+    if let Ok(command) = std::env::var(PASSPHRASE_COMMAND_ENV) {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .env("UKWELI_USER", user_id)
+            .output()
+            .with_context(|| format!("Failed to run {}", PASSPHRASE_COMMAND_ENV))?;
+        if !output.status.success() {
+            bail!("{} failed with {}", PASSPHRASE_COMMAND_ENV, output.status);
+        }
+
+        let stdout = Zeroizing::new(output.stdout);
+        let passphrase = String::from_utf8_lossy(&stdout);
+        return Ok(Zeroizing::new(
+            passphrase.trim_end_matches(['\r', '\n']).to_string(),
+        ));
+    }
+
+    prompt(&format!("Passphrase for '{}': ", user_id))
+}
+
+/// A passphrase for a key being sealed: `env` if set, otherwise asked for twice.
+pub fn new_passphrase(user_id: &str, env: &str) -> Result<Zeroizing<String>> {
+    if let Ok(passphrase) = std::env::var(env) {
+        if passphrase.is_empty() {
+            bail!("{} is empty", env);
+        }
+        return Ok(Zeroizing::new(passphrase));
+    }
+
+    let passphrase = prompt(&format!("New passphrase for '{}': ", user_id))?;
+    if passphrase.is_empty() {
+        bail!("Passphrase can't be empty");
+    }
+    if *prompt("Repeat it: ")? != *passphrase {
+        bail!("Passphrases don't match");
+    }
+    Ok(passphrase)
+}
+
+// asks on the terminal even when stdin is piped
+fn prompt(message: &str) -> Result<Zeroizing<String>> {
+    rpassword::prompt_password(message)
+        .map(Zeroizing::new)
+        .with_context(|| {
+            format!(
+                "Failed to read a passphrase, without a terminal set {} or {}",
+                PASSPHRASE_ENV, PASSPHRASE_COMMAND_ENV
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTEXT: &str = "ukweli-keystore:v2:amina:00";
+
+    #[test]
+    fn test_seal_and_open() {
+        let signing_key = [7u8; 32];
+        let sealed = EncryptedKey::seal(&signing_key, "correct horse", CONTEXT).unwrap();
+
+        assert_eq!(*sealed.open("correct horse", CONTEXT).unwrap(), signing_key);
+        assert_ne!(sealed.ciphertext, hex::encode(signing_key));
+    }
+
+    #[test]
+    fn test_wrong_passphrase_rejected() {
+        let sealed = EncryptedKey::seal(&[7u8; 32], "correct horse", CONTEXT).unwrap();
+
+        assert!(sealed.open("battery staple", CONTEXT).is_err());
+    }
+
+    #[test]
+    fn test_other_context_rejected() {
+        let sealed = EncryptedKey::seal(&[7u8; 32], "correct horse", CONTEXT).unwrap();
+
+        assert!(
+            sealed
+                .open("correct horse", "ukweli-keystore:v2:zuri:00")
+                .is_err()
+        );
+    }
+}
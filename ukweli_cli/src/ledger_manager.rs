@@ -1,25 +1,42 @@
 use anyhow::{Result, bail};
 use std::path::Path;
+use std::sync::RwLockReadGuard;
 
 use crate::config::Config;
 use anyhow::Context;
-use ukweli_db::{Ledger, core::User};
-use ukweli_db::{storage::append::AppendLog, storage::recovery::RecoveryManager};
+use ukweli_db::storage::backend::Backend;
+use ukweli_db::storage::durability::Durability;
+use ukweli_db::workflow::{Engine, TransitionMetadata};
+use ukweli_db::{
+    Ledger, Payload, UkweliDb,
+    core::{Checkpoint, User},
+};
 
 pub struct LedgerManager {
-    pub ledger: Ledger,
-    db_path: std::path::PathBuf,
+    db: UkweliDb,
 }
 
 impl LedgerManager {
     pub fn load() -> Result<Self> {
         let config = Config::load_or_default()?;
-        Self::load_from_path(&config.db_path)
+        Self::load_from_path(&config.db_path, &config.backend()?)
     }
 
-    pub fn load_from_path<P: AsRef<Path>>(db_path: P) -> Result<Self> {
-        let db_path = db_path.as_ref();
+    /// For commands that only look at the ledger, nothing on disk is changed.
+    pub fn load_read_only() -> Result<Self> {
+        let config = Config::load_or_default()?;
+        Self::load_read_only_from_path(&config.db_path, &config.backend()?)
+    }
+
+    pub fn load_from_path<P: AsRef<Path>>(db_path: P, backend: &Backend) -> Result<Self> {
+        Self::open_with(db_path.as_ref(), backend, false)
+    }
 
+    pub fn load_read_only_from_path<P: AsRef<Path>>(db_path: P, backend: &Backend) -> Result<Self> {
+        Self::open_with(db_path.as_ref(), backend, true)
+    }
+
+    fn open_with(db_path: &Path, backend: &Backend, read_only: bool) -> Result<Self> {
         if !db_path.exists() {
             bail!(
                 "Database not found at: {}\nRun 'ukweli init' first.",
@@ -29,69 +46,143 @@ impl LedgerManager {
 
         println!("Loading ledger from: {}", db_path.display());
 
-        let ledger = RecoveryManager::recover_ledger(db_path).context("Failed to load ledger")?;
+        let db = if read_only {
+            UkweliDb::open_read_only_in(backend, db_path)
+        } else {
+            UkweliDb::open_in(backend, db_path)
+        }
+        .context("Failed to load ledger")?;
 
-        println!("Loaded {} records", ledger.length());
+        println!("Loaded {} records", db.length()?);
 
-        Ok(Self {
-            ledger,
-            db_path: db_path.to_path_buf(),
-        })
+        Ok(Self { db })
     }
 
-    pub fn register_user(&mut self, user: User) -> Result<()> {
-        if self.ledger.verify_registry.contains_key(&user.user_id) {
-            bail!(
-                "User '{}' is already registered in the ledger",
-                user.user_id
-            );
+    pub fn with_durability(self, durability: Durability) -> Self {
+        Self {
+            db: self.db.with_durability(durability),
         }
+    }
 
-        self.ledger.register_user(user.clone());
-
-        let mut append_log = AppendLog::new(&self.db_path).context("Failed to open append log")?;
+    /// What the writes of this run cost, under the durability they were made with.
+    pub fn print_stats(&self) -> Result<()> {
+        let stats = self.db.stats()?;
+        println!(
+            "Durability: {} | {} entries in {} appends, mean {:?}, max {:?}, {} fsyncs",
+            self.db.durability(),
+            stats.entries,
+            stats.appends,
+            stats.mean_latency(),
+            stats.max_latency,
+            stats.fsyncs
+        );
+        Ok(())
+    }
 
-        append_log
-            .append_user(&user)
-            .context("Failed to write user to WAL")?;
+    pub fn register_user(&self, user: User) -> Result<()> {
+        self.db
+            .register_user(user.clone())
+            .context("Failed to register user")?;
 
         println!("User '{}' registered in ledger", user.user_id);
 
         Ok(())
     }
-    pub fn ledger(&self) -> &Ledger {
-        &self.ledger
+
+    pub fn ledger(&self) -> Result<RwLockReadGuard<'_, Ledger>> {
+        Ok(self.db.read()?)
     }
 
-    pub fn append_record(&mut self, payload: &str, signers: Vec<User>) -> Result<usize> {
+    pub fn append_record(&self, payload: Payload, signers: Vec<User>) -> Result<usize> {
         let index = self
-            .ledger
-            .add_record(payload, signers.clone())
-            .context("Failed to add record to ledger")?;
+            .db
+            .append_record(payload, signers)
+            .context("Failed to append record")?;
 
-        let record = self
-            .ledger
-            .records
-            .get(index)
-            .context("Record was added but cannot be retrieved")?;
+        println!("Record #{} appended to WAL", index);
 
-        let mut append_log = AppendLog::new(&self.db_path).context("Failed to open append log")?;
+        Ok(index)
+    }
 
-        append_log
-            .append_record(record)
-            .context("Failed to write record to WAL")?;
+    pub fn append_transition(
+        &self,
+        engine: &Engine,
+        payload: Payload,
+        signers: Vec<User>,
+    ) -> Result<usize> {
+        let index = self
+            .db
+            .append_transition(engine, payload, signers)
+            .context("Failed to append record")?;
 
         println!("Record #{} appended to WAL", index);
 
         Ok(index)
     }
 
+    /// Appends every record or none of them. Records carrying transition
+    /// metadata are checked against `engine`.
+    pub fn append_batch(
+        &self,
+        engine: Option<&Engine>,
+        records: Vec<(Payload, Vec<User>)>,
+    ) -> Result<Vec<usize>> {
+        let mut batch = self.db.begin();
+        for (payload, signers) in records {
+            if TransitionMetadata::from_payload(&payload).is_none() {
+                batch.append(payload, signers);
+                continue;
+            }
+
+            let Some(engine) = engine else {
+                bail!("Batch contains workflow transitions but no workflows were loaded");
+            };
+            batch.append_transition(engine, payload, signers);
+        }
+
+        let indices = batch.commit().context("Failed to append batch")?;
+
+        println!(
+            "Records #{}..#{} appended to WAL",
+            indices.first().copied().unwrap_or_default(),
+            indices.last().copied().unwrap_or_default()
+        );
+
+        Ok(indices)
+    }
+
+    pub fn create_checkpoint(&self, operator: &User) -> Result<Checkpoint> {
+        let checkpoint = self
+            .db
+            .create_checkpoint(operator)
+            .context("Failed to create checkpoint")?;
+
+        Self::print_checkpoint_written(&checkpoint);
+        Ok(checkpoint)
+    }
+
+    pub fn cosign_checkpoint(&self, tree_size: usize, witness: &User) -> Result<Checkpoint> {
+        let checkpoint = self
+            .db
+            .cosign_checkpoint(tree_size, witness)
+            .context("Failed to countersign checkpoint")?;
+
+        Self::print_checkpoint_written(&checkpoint);
+        Ok(checkpoint)
+    }
+
+    fn print_checkpoint_written(checkpoint: &Checkpoint) {
+        println!(
+            "Checkpoint at size {} appended to WAL",
+            checkpoint.tree_size()
+        );
+    }
+
     pub fn compact(&self) -> Result<()> {
-        // from wal to main db
-        // TODO: automate this
+        // writes compact on their own once the WAL is big or old enough,
+        // this seals it right away
         println!("Compacting database...");
-        RecoveryManager::compact(&self.db_path, &self.ledger)
-            .context("Failed to compact database")?;
+        self.db.compact().context("Failed to compact database")?;
 
         println!("Database compacted");
 
@@ -101,7 +192,7 @@ impl LedgerManager {
     pub fn verify_chain(&self) -> Result<bool> {
         println!("Verifying chain integrity...");
 
-        self.ledger
+        self.db
             .verify_chain()
             .context("Chain verification failed")?;
 
@@ -1,11 +1,16 @@
 mod commands;
 mod config;
+mod keystore;
 mod ledger_manager;
 mod user_store;
 
 use anyhow::Result;
 use clap::{Parser, Subcommand};
 use std::path::PathBuf;
+use ukweli_db::core::keys::Compromised;
+use ukweli_db::core::roles::RoleAction;
+use ukweli_db::core::{Deactivation, KeyRevocation, KeyRotation, Keys, RoleChange};
+use ukweli_db::storage::durability::Durability;
 // use ukweli_db::Workflow;
 
 // TODO
@@ -15,12 +20,17 @@ use std::path::PathBuf;
 // workflow load – load JSON/YAML workflow definitions #13 done
 // workflow list – list available workflows #14 done
 // record show – inspect records by entity or range #15 done
-// state current – compute current entity state by replay #16
+// state current – compute current entity state by replay #16 done
 
 #[derive(Parser)]
 #[command(name = "ukweli")]
 #[command(author, version, about, long_about = None)]
 struct Cli {
+    /// only read: no database, key or workflow file is created, truncated or
+    /// rewritten, so copies on read-only media work. Commands that write are refused
+    #[arg(long, global = true)]
+    read_only: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -31,6 +41,11 @@ enum Commands {
         /// custom database path (default: ~/.ukweli/default.ukweli)
         #[arg(short, long)]
         db_path: Option<PathBuf>,
+
+        /// user who signs checkpoints, created if they don't exist yet.
+        /// Only a new ledger can name one
+        #[arg(long)]
+        operator: Option<String>,
     },
 
     /// user management comms
@@ -42,27 +57,180 @@ enum Commands {
     Record(RecordCommands),
     #[command(subcommand)]
     Workflow(WorkflowCommands),
-    // #[command(subcommand)]
-    // State(StateCommands),
+    #[command(subcommand)]
+    State(StateCommands),
+    #[command(subcommand)]
+    Proof(ProofCommands),
+    #[command(subcommand)]
+    Checkpoint(CheckpointCommands),
+    #[command(subcommand)]
+    Db(DbCommands),
+}
+
+impl Commands {
+    // read commands always open the database read-only, these can't
+    fn writes(&self) -> bool {
+        match self {
+            Commands::Init { .. } => true,
+            Commands::User { command } => {
+                matches!(
+                    command,
+                    UserCommands::Create { .. }
+                        | UserCommands::Delete { .. }
+                        | UserCommands::ChangePassphrase { .. }
+                        | UserCommands::RotateKey { .. }
+                        | UserCommands::RevokeKey { .. }
+                        | UserCommands::Deactivate { .. }
+                        | UserCommands::AddRole { .. }
+                        | UserCommands::RemoveRole { .. }
+                )
+            }
+            Commands::Record(command) => matches!(
+                command,
+                RecordCommands::Append { .. }
+                    | RecordCommands::AppendBatch { .. }
+                    | RecordCommands::Compact
+            ),
+            Commands::Workflow(command) => matches!(
+                command,
+                WorkflowCommands::Load { .. } | WorkflowCommands::Delete { .. }
+            ),
+            Commands::State(_) | Commands::Proof(_) => false,
+            Commands::Checkpoint(command) => !matches!(command, CheckpointCommands::Show { .. }),
+            Commands::Db(command) => !matches!(command, DbCommands::Inspect { .. }),
+        }
+    }
 }
 
 #[derive(Subcommand)]
 enum UserCommands {
-    Create { user_id: String },
+    Create {
+        user_id: String,
+    },
     List,
-    Delete { user_id: String },
-    Show { user_id: String },
+    Delete {
+        user_id: String,
+    },
+    Show {
+        user_id: String,
+    },
+    /// re-encrypt a user's signing key under a new passphrase
+    ChangePassphrase {
+        user_id: String,
+    },
+    /// move a user to a new key, endorsed by their old key or, when it is
+    /// lost, by more than half of the admins signing instead
+    RotateKey {
+        user_id: String,
+
+        #[arg(short, long, value_delimiter = ',')]
+        signers: Vec<String>,
+    },
+    /// mark one of a user's keys compromised: it signs nothing new, and the
+    /// records it signed from that point on are reported
+    RevokeKey {
+        user_id: String,
+
+        /// hex verifying key to revoke (default: the user's current key)
+        #[arg(long)]
+        key: Option<String>,
+
+        /// compromised from this record index on
+        #[arg(
+            long,
+            conflicts_with = "from_time",
+            required_unless_present = "from_time"
+        )]
+        from_index: Option<usize>,
+
+        /// compromised from this unix time on, in seconds. Record times are
+        /// set by the signer, so the leaked key can backdate past it
+        #[arg(long)]
+        from_time: Option<u64>,
+
+        /// the user, or more than half of the admins (default: the user)
+        #[arg(short, long, value_delimiter = ',')]
+        signers: Vec<String>,
+    },
+    /// stop a user signing from some record on, with the reason on the
+    /// ledger. What they signed before stays valid
+    Deactivate {
+        user_id: String,
+
+        #[arg(long)]
+        reason: String,
+
+        /// first record they can't sign (default: the next one)
+        #[arg(long)]
+        from_index: Option<usize>,
+
+        /// the user or an admin (default: the user)
+        #[arg(short, long, value_delimiter = ',')]
+        signers: Vec<String>,
+    },
+    /// grant a role with a record signed by an admin. The ledger operator
+    /// grants the first "admin"
+    AddRole {
+        user_id: String,
+        role: String,
+
+        #[arg(short, long, value_delimiter = ',')]
+        signers: Vec<String>,
+    },
+    /// revoke a role with a record signed by an admin
+    RemoveRole {
+        user_id: String,
+        role: String,
+
+        #[arg(short, long, value_delimiter = ',')]
+        signers: Vec<String>,
+    },
 }
 
 #[derive(Subcommand)]
 enum RecordCommands {
     Append {
-        payload: String,
+        /// record payload, optional data when appending a workflow transition
+        payload: Option<String>,
 
         #[arg(short, long, value_delimiter = ',')]
         signers: Vec<String>,
+
+        /// entity moved by this record (with --workflow and --transition)
+        #[arg(long, requires_all = ["workflow", "transition"])]
+        entity: Option<String>,
+
+        #[arg(long, requires = "entity")]
+        workflow: Option<String>,
+
+        #[arg(long, requires = "entity")]
+        transition: Option<String>,
+
+        /// key=value tag stored with the record, can be repeated
+        #[arg(short, long)]
+        tag: Vec<String>,
+
+        /// parse the payload as JSON instead of plain text
+        #[arg(long)]
+        json: bool,
+
+        /// none, flush, fsync or group (default: flush)
+        #[arg(long)]
+        durability: Option<Durability>,
+    },
+    /// append every record in a JSONL file as one batch, all or nothing
+    AppendBatch {
+        /// one JSON object per line: {"payload": ..., "signers": [...], "tags": {...}}
+        /// plus "entity", "workflow" and "transition" for workflow records
+        file: PathBuf,
+
+        /// none, flush, fsync or group (default: flush)
+        #[arg(long)]
+        durability: Option<Durability>,
     },
     Verify,
+    /// list records signed with keys revoked as compromised
+    Compromised,
     Show {
         index: usize,
     },
@@ -90,21 +258,158 @@ enum WorkflowCommands {
     Delete { workflow_id: String },
 }
 
+#[derive(Subcommand)]
+enum StateCommands {
+    Current { entity_id: String },
+    History { entity_id: String },
+}
+
+#[derive(Subcommand)]
+enum ProofCommands {
+    Inclusion {
+        index: usize,
+
+        /// write the proof to a file instead of stdout
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+
+        /// sign the tree head with this user's key
+        #[arg(short, long)]
+        signer: Option<String>,
+    },
+    Consistency {
+        /// ledger size the auditor saw before
+        #[arg(long)]
+        from: usize,
+
+        /// newer ledger size (default: current size)
+        #[arg(long)]
+        to: Option<usize>,
+
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+    },
+    /// check a proof file without the database
+    Verify {
+        file: PathBuf,
+
+        /// root you recorded for the old size, checked against a consistency proof
+        #[arg(long)]
+        old_root: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum CheckpointCommands {
+    /// sign the current ledger state as the operator
+    Create {
+        #[arg(long)]
+        operator: String,
+    },
+    /// name the operator of a ledger created without one
+    Designate {
+        #[arg(long)]
+        operator: String,
+    },
+    /// countersign a checkpoint as an external witness
+    Cosign {
+        #[arg(long)]
+        witness: String,
+
+        /// checkpoint to countersign (default: latest)
+        #[arg(long)]
+        size: Option<usize>,
+    },
+    Show {
+        /// export the checkpoint as JSON
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum DbCommands {
+    /// dump header, body and footer details and verify every file
+    Inspect {
+        /// database file to inspect (default: configured database)
+        path: Option<PathBuf>,
+    },
+    /// cut a damaged WAL back to its last good entry and recover
+    Repair {
+        /// database file to repair (default: configured database)
+        path: Option<PathBuf>,
+
+        /// drop the damaged tail instead of keeping it in a .wal.corrupt file
+        #[arg(long)]
+        discard: bool,
+    },
+    /// push sealed segments to an object store
+    Archive {
+        /// database file to archive (default: configured database)
+        path: Option<PathBuf>,
+
+        /// s3://bucket/prefix or a directory (default: "archive" in config.json)
+        #[arg(long)]
+        store: Option<String>,
+
+        /// then drop the local copies, reads fetch them back as needed
+        #[arg(long)]
+        evict: bool,
+    },
+    /// time appends under each durability mode on a scratch database
+    Bench {
+        #[arg(long, default_value_t = 1000)]
+        records: usize,
+
+        /// concurrent writers sharing one handle
+        #[arg(long, default_value_t = 4)]
+        threads: usize,
+
+        /// only this mode (default: all of them)
+        #[arg(long)]
+        durability: Option<Durability>,
+    },
+}
+
 fn main() -> Result<()> {
     let cli = Cli::parse();
 
+    if cli.read_only && cli.command.writes() {
+        anyhow::bail!(
+            "This command writes to the ledger or its files, it can't run with --read-only"
+        );
+    }
+    user_store::UserStore::set_read_only(cli.read_only);
+
     match cli.command {
-        Commands::Init { db_path } => {
-            commands::init::run(db_path)?;
+        Commands::Init { db_path, operator } => {
+            commands::init::run(db_path, operator)?;
         }
 
         Commands::Record(command) => match command {
-            RecordCommands::Append { payload, signers } => {
-                commands::record::append(payload, signers)?;
+            RecordCommands::Append {
+                payload,
+                signers,
+                entity,
+                workflow,
+                transition,
+                tag,
+                json,
+                durability,
+            } => {
+                commands::record::append(
+                    payload, signers, entity, workflow, transition, tag, json, durability,
+                )?;
+            }
+            RecordCommands::AppendBatch { file, durability } => {
+                commands::record::append_batch(file, durability)?;
             }
             RecordCommands::Verify => {
                 commands::record::verify()?;
             }
+            RecordCommands::Compromised => {
+                commands::record::compromised()?;
+            }
             RecordCommands::Show { index } => {
                 commands::record::show(index)?;
             }
@@ -135,6 +440,48 @@ fn main() -> Result<()> {
             UserCommands::Show { user_id } => {
                 user_show(&user_id)?;
             }
+            UserCommands::ChangePassphrase { user_id } => {
+                user_store::UserStore::open()?.change_passphrase(&user_id)?;
+            }
+            UserCommands::RotateKey { user_id, signers } => {
+                user_rotate_key(&user_id, signers)?;
+            }
+            UserCommands::RevokeKey {
+                user_id,
+                key,
+                from_index,
+                from_time,
+                signers,
+            } => {
+                let since = match (from_index, from_time) {
+                    (Some(index), _) => Compromised::Index(index),
+                    (None, Some(time)) => Compromised::Time(time),
+                    (None, None) => anyhow::bail!("Pass --from-index or --from-time"),
+                };
+                user_revoke_key(&user_id, key, since, signers)?;
+            }
+            UserCommands::Deactivate {
+                user_id,
+                reason,
+                from_index,
+                signers,
+            } => {
+                user_deactivate(&user_id, &reason, from_index, signers)?;
+            }
+            UserCommands::AddRole {
+                user_id,
+                role,
+                signers,
+            } => {
+                user_change_role(RoleChange::grant(&user_id, &role), signers)?;
+            }
+            UserCommands::RemoveRole {
+                user_id,
+                role,
+                signers,
+            } => {
+                user_change_role(RoleChange::revoke(&user_id, &role), signers)?;
+            }
         },
 
         Commands::Workflow(command) => match command {
@@ -154,6 +501,67 @@ fn main() -> Result<()> {
                 commands::workflow::delete(workflow_id)?;
             }
         },
+
+        Commands::State(command) => match command {
+            StateCommands::Current { entity_id } => {
+                commands::state::current(entity_id)?;
+            }
+
+            StateCommands::History { entity_id } => {
+                commands::state::history(entity_id)?;
+            }
+        },
+
+        Commands::Proof(command) => match command {
+            ProofCommands::Inclusion { index, out, signer } => {
+                commands::proof::inclusion(index, out, signer)?;
+            }
+
+            ProofCommands::Consistency { from, to, out } => {
+                commands::proof::consistency(from, to, out)?;
+            }
+
+            ProofCommands::Verify { file, old_root } => {
+                commands::proof::verify(file, old_root)?;
+            }
+        },
+
+        Commands::Checkpoint(command) => match command {
+            CheckpointCommands::Create { operator } => {
+                commands::checkpoint::create(operator)?;
+            }
+
+            CheckpointCommands::Designate { operator } => {
+                commands::checkpoint::designate(operator)?;
+            }
+
+            CheckpointCommands::Cosign { witness, size } => {
+                commands::checkpoint::cosign(witness, size)?;
+            }
+
+            CheckpointCommands::Show { out } => {
+                commands::checkpoint::show(out)?;
+            }
+        },
+
+        Commands::Db(command) => match command {
+            DbCommands::Inspect { path } => {
+                commands::db::inspect(path)?;
+            }
+            DbCommands::Repair { path, discard } => {
+                commands::db::repair(path, discard)?;
+            }
+            DbCommands::Archive { path, store, evict } => {
+                commands::db::archive(path, store, evict)?;
+            }
+            DbCommands::Bench {
+                records,
+                threads,
+                durability,
+            } => {
+                commands::db::bench(records, threads, durability)?;
+            }
+        },
     }
     Ok(())
 }
@@ -161,11 +569,11 @@ fn main() -> Result<()> {
 fn user_create(user_id: &str) -> Result<()> {
     use crate::user_store::UserStore;
 
-    if UserStore::user_exists(user_id)? {
+    if UserStore::open()?.user_exists(user_id) {
         anyhow::bail!("User '{}' already exists", user_id);
     }
 
-    UserStore::create_user(user_id)?;
+    UserStore::open()?.create_user(user_id)?;
 
     println!("\nUser '{}' can now sign records", user_id);
     println!("   Add roles with: ukweli user add-role {} <role>", user_id);
@@ -174,17 +582,33 @@ fn user_create(user_id: &str) -> Result<()> {
 }
 
 fn user_list() -> Result<()> {
+    use crate::ledger_manager::LedgerManager;
     use crate::user_store::UserStore;
 
-    let users = UserStore::list_users()?;
+    let users = UserStore::open()?.list_users()?;
 
     if users.is_empty() {
         println!("No users found.");
         println!("Create one with: ukweli user create <username>");
-    } else {
-        println!("Users:");
-        for user in users {
-            println!("  • {}", user);
+        return Ok(());
+    }
+
+    // users are listed without a status when there is no ledger yet
+    let ledger_mgr = LedgerManager::load_read_only().ok();
+    let ledger = ledger_mgr.as_ref().map(|mgr| mgr.ledger()).transpose()?;
+    let keys = ledger.as_ref().map(|ledger| ledger.keys());
+
+    println!("Users:");
+    for user in users {
+        match (&ledger, &keys) {
+            (Some(ledger), Some(keys)) => {
+                println!(
+                    "  • {} ({})",
+                    user,
+                    user_status(keys, ledger.length(), &user)
+                )
+            }
+            _ => println!("  • {}", user),
         }
     }
 
@@ -196,6 +620,10 @@ fn user_delete(user_id: &str) -> Result<()> {
 
     println!("Are you sure you want to delete user '{}'?", user_id);
     println!("This will permanently delete their private key.");
+    println!(
+        "It doesn't stop a copy of it signing, for that run: ukweli user deactivate {} --reason <why>",
+        user_id
+    );
     println!("Type 'yes (y)' to confirm:");
 
     let mut input = String::new();
@@ -206,33 +634,301 @@ fn user_delete(user_id: &str) -> Result<()> {
         return Ok(());
     }
 
-    UserStore::delete_user(user_id)?;
+    UserStore::open()?.delete_user(user_id)?;
 
     Ok(())
 }
 
 fn user_show(user_id: &str) -> Result<()> {
+    use crate::ledger_manager::LedgerManager;
     use crate::user_store::UserStore;
 
-    let user = UserStore::load_user(user_id)?;
+    let user = UserStore::open()?.load_info(user_id)?;
+    // roles only count once the ledger holds them
+    let ledger_mgr = LedgerManager::load_read_only()?;
+    let ledger = ledger_mgr.ledger()?;
+    let mut roles: Vec<String> = ledger.roles().of(user_id).into_iter().collect();
+    roles.sort();
 
     println!("User: {}", user.user_id);
+    println!("Verifying key: {}", hex::encode(&user.verifying_key));
+    match ledger.keys().key(user_id) {
+        None => println!("Ledger: not registered yet"),
+        Some(key) if key.to_bytes().as_slice() == user.verifying_key => {
+            println!("Ledger: registered, key is current")
+        }
+        Some(key) => println!(
+            "Ledger: registered with another key, {}",
+            hex::encode(key.to_bytes())
+        ),
+    }
     println!(
-        "Verifying key: {}",
-        hex::encode(user.verifying_key.to_bytes())
+        "Status: {}",
+        user_status(ledger.keys(), ledger.length(), user_id)
     );
     println!(
         "Roles: {}",
-        if user.roles.is_empty() {
+        if roles.is_empty() {
             "none".to_string()
         } else {
-            user.roles
-                .iter()
-                .map(|s| s.as_str())
-                .collect::<Vec<_>>()
-                .join(", ")
+            roles.join(", ")
+        }
+    );
+    println!(
+        "Signing key: {}",
+        if user.encrypted {
+            "encrypted"
+        } else {
+            "UNENCRYPTED, encrypted the next time it signs"
         }
     );
 
     Ok(())
 }
+
+// whether the user can sign the next record
+fn user_status(keys: &Keys, next_index: usize, user_id: &str) -> String {
+    if keys.key(user_id).is_none() {
+        return "not registered".to_string();
+    }
+
+    match keys.deactivation(user_id) {
+        None => "active".to_string(),
+        Some(deactivation) if keys.is_active(user_id, next_index) => format!(
+            "active until record #{}: {}",
+            deactivation.effective_index, deactivation.reason
+        ),
+        Some(deactivation) => format!(
+            "inactive since record #{}: {}",
+            deactivation.effective_index, deactivation.reason
+        ),
+    }
+}
+
+fn user_change_role(change: RoleChange, signer_ids: Vec<String>) -> Result<()> {
+    use crate::ledger_manager::LedgerManager;
+    use crate::user_store::UserStore;
+    use anyhow::Context;
+
+    if signer_ids.is_empty() {
+        anyhow::bail!("Role changes must be signed by an admin, pass them with --signers");
+    }
+
+    let ledger_mgr = LedgerManager::load()?;
+    let users = UserStore::open()?;
+
+    // the first admin comes from the operator, a ledger without one has no roles yet
+    if ledger_mgr.ledger()?.operator_id().is_none() {
+        anyhow::bail!(
+            "This ledger names no operator to grant the first admin, name one with: ukweli checkpoint designate --operator <user>"
+        );
+    }
+
+    // the role is held on the ledger, so its holder must be registered there
+    if !ledger_mgr
+        .ledger()?
+        .verify_registry
+        .contains_key(&change.user_id)
+    {
+        ledger_mgr.register_user(users.load_public(&change.user_id)?)?;
+    }
+
+    let mut signers = Vec::new();
+    for signer_id in &signer_ids {
+        let user = users
+            .load_user(signer_id)
+            .with_context(|| format!("Failed to load signer '{}'", signer_id))?;
+        if !ledger_mgr.ledger()?.verify_registry.contains_key(signer_id) {
+            ledger_mgr.register_user(user.clone())?;
+        }
+        signers.push(user);
+    }
+
+    let index = ledger_mgr.append_record(change.to_payload(), signers)?;
+
+    match change.action {
+        RoleAction::Grant => println!(
+            "\n'{}' holds '{}' from record #{} on",
+            change.user_id, change.role, index
+        ),
+        RoleAction::Revoke => println!(
+            "\n'{}' no longer holds '{}' from record #{} on",
+            change.user_id, change.role, index
+        ),
+    }
+
+    Ok(())
+}
+
+fn user_rotate_key(user_id: &str, signer_ids: Vec<String>) -> Result<()> {
+    use crate::ledger_manager::LedgerManager;
+    use crate::user_store::UserStore;
+    use anyhow::Context;
+
+    let ledger_mgr = LedgerManager::load()?;
+    if !ledger_mgr.ledger()?.verify_registry.contains_key(user_id) {
+        anyhow::bail!(
+            "User '{}' is not registered in the ledger, their next record registers the current key",
+            user_id
+        );
+    }
+
+    // the old key endorses the new one, unless admins sign for it
+    let users = UserStore::open()?;
+    let signers = if signer_ids.is_empty() {
+        vec![
+            users
+                .load_user(user_id)
+                .with_context(|| format!("Failed to load the old key of '{}'", user_id))?,
+        ]
+    } else {
+        signer_ids
+            .iter()
+            .map(|signer_id| {
+                users
+                    .load_user(signer_id)
+                    .with_context(|| format!("Failed to load signer '{}'", signer_id))
+            })
+            .collect::<Result<Vec<_>>>()?
+    };
+
+    let new_key = users.stage_key(user_id)?;
+    let index = match ledger_mgr.append_record(KeyRotation::new(&new_key).to_payload(), signers) {
+        Ok(index) => index,
+        Err(e) => {
+            users.discard_key(user_id)?;
+            return Err(e);
+        }
+    };
+    users.commit_key(user_id)?;
+
+    println!(
+        "\n'{}' signs with the new key from record #{} on",
+        user_id, index
+    );
+    println!(
+        "   Verifying key: {}",
+        hex::encode(new_key.verifying_key.to_bytes())
+    );
+
+    Ok(())
+}
+
+fn user_revoke_key(
+    user_id: &str,
+    key: Option<String>,
+    since: Compromised,
+    signer_ids: Vec<String>,
+) -> Result<()> {
+    use crate::ledger_manager::LedgerManager;
+    use crate::user_store::UserStore;
+    use anyhow::Context;
+
+    let ledger_mgr = LedgerManager::load()?;
+
+    let key = match key {
+        Some(key) => {
+            let bytes: [u8; 32] = hex::decode(&key)
+                .ok()
+                .and_then(|bytes| bytes.try_into().ok())
+                .context("--key must be a 32 byte hex verifying key")?;
+            ukweli_db::core::User::from_verifying_key(user_id, &bytes, Default::default())
+                .context("Invalid verifying key")?
+                .verifying_key
+        }
+        None => *ledger_mgr
+            .ledger()?
+            .keys()
+            .key(user_id)
+            .with_context(|| format!("User '{}' is not registered in the ledger", user_id))?,
+    };
+
+    let signer_ids = if signer_ids.is_empty() {
+        vec![user_id.to_string()]
+    } else {
+        signer_ids
+    };
+    let users = UserStore::open()?;
+    let signers = signer_ids
+        .iter()
+        .map(|signer_id| {
+            users
+                .load_user(signer_id)
+                .with_context(|| format!("Failed to load signer '{}'", signer_id))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let index = ledger_mgr.append_record(
+        KeyRevocation::new(user_id, &key, since).to_payload(),
+        signers,
+    )?;
+
+    println!(
+        "\nKey {} of '{}' revoked by record #{}, compromised from {}",
+        hex::encode(key.to_bytes()),
+        user_id,
+        index,
+        since
+    );
+    let affected = ledger_mgr
+        .ledger()?
+        .compromised_records()
+        .iter()
+        .filter(|record| record.user_id == user_id)
+        .count();
+    println!(
+        "   {} records signed with it since, see: ukweli record compromised",
+        affected
+    );
+    println!(
+        "   Give '{}' a new key with: ukweli user rotate-key {} --signers <admins>",
+        user_id, user_id
+    );
+
+    Ok(())
+}
+
+fn user_deactivate(
+    user_id: &str,
+    reason: &str,
+    from_index: Option<usize>,
+    signer_ids: Vec<String>,
+) -> Result<()> {
+    use crate::ledger_manager::LedgerManager;
+    use crate::user_store::UserStore;
+    use anyhow::Context;
+
+    let ledger_mgr = LedgerManager::load()?;
+    let effective_index = match from_index {
+        Some(index) => index,
+        None => ledger_mgr.ledger()?.length(),
+    };
+
+    let signer_ids = if signer_ids.is_empty() {
+        vec![user_id.to_string()]
+    } else {
+        signer_ids
+    };
+    let users = UserStore::open()?;
+    let signers = signer_ids
+        .iter()
+        .map(|signer_id| {
+            users
+                .load_user(signer_id)
+                .with_context(|| format!("Failed to load signer '{}'", signer_id))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let index = ledger_mgr.append_record(
+        Deactivation::new(user_id, reason, effective_index).to_payload(),
+        signers,
+    )?;
+
+    println!(
+        "\n'{}' deactivated by record #{}, signs nothing from record #{} on",
+        user_id, index, effective_index
+    );
+    println!("   Records they signed before still verify");
+
+    Ok(())
+}
@@ -1,53 +1,171 @@
 use anyhow::{Context, Result, bail};
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
+use std::fs::OpenOptions;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use ukweli_db::core::User;
+use zeroize::Zeroizing;
 
 use crate::config::Config;
+use crate::keystore::{
+    EncryptedKey, Interactive, KEYSTORE_VERSION, NEW_PASSPHRASE_ENV, PASSPHRASE_ENV,
+    PassphraseSource,
+};
 
+/// What `~/.ukweli/users/<id>.json` holds. The signing key is sealed under the
+/// user's passphrase, see `keystore`. The rest stays readable, so listing and
+/// showing users never asks for a passphrase.
 #[derive(Debug, Serialize, Deserialize)]
 struct StoredUser {
+    /// missing from the plaintext files written before there was a keystore
+    #[serde(default = "plaintext_version")]
+    version: u32,
     user_id: String,
-
-    /// TODO: Encrypt this in production
-    /// also prolly have a different way of handling users
-    signing_key_bytes: Vec<u8>,
     verifying_key_bytes: Vec<u8>,
     roles: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key: Option<EncryptedKey>,
+
+    /// only in plaintext files, gone once they are migrated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signing_key_bytes: Option<Vec<u8>>,
+}
+
+fn plaintext_version() -> u32 {
+    1
+}
+
+// makes a rename in `dir` survive a crash
+fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    std::fs::File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
 }
 
-pub struct UserStore;
+impl StoredUser {
+    // ties the sealed key to this user and public key
+    fn key_context(&self) -> String {
+        format!(
+            "ukweli-keystore:v{}:{}:{}",
+            self.version,
+            self.user_id,
+            hex::encode(&self.verifying_key_bytes)
+        )
+    }
+}
+
+/// The public half of a stored user, readable without the passphrase.
+pub struct UserInfo {
+    pub user_id: String,
+    pub verifying_key: Vec<u8>,
+    pub encrypted: bool,
+}
+
+// set by --read-only, plaintext keys are then used as they are
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// The user files in one directory and where their passphrases come from.
+pub struct UserStore {
+    dir: PathBuf,
+    passphrases: Box<dyn PassphraseSource>,
+    read_only: bool,
+}
 
 impl UserStore {
-    pub fn create_user(user_id: &str) -> Result<User> {
+    pub fn set_read_only(read_only: bool) {
+        READ_ONLY.store(read_only, Ordering::Relaxed);
+    }
+
+    /// `~/.ukweli/users`, passphrases from the environment or the terminal.
+    pub fn open() -> Result<Self> {
+        Ok(Self::new(Config::users_dir()?, Box::new(Interactive))
+            .with_read_only(READ_ONLY.load(Ordering::Relaxed)))
+    }
+
+    pub fn new(dir: PathBuf, passphrases: Box<dyn PassphraseSource>) -> Self {
+        Self {
+            dir,
+            passphrases,
+            read_only: false,
+        }
+    }
+
+    /// Plaintext keys are used as they are instead of being migrated.
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    pub fn create_user(&self, user_id: &str) -> Result<User> {
         let user = User::new(user_id);
-        Self::save_user(&user)?;
+        let passphrase = self.passphrases.new_passphrase(user_id, PASSPHRASE_ENV)?;
+        self.save_user(&user, &passphrase)?;
         println!("Created user: {}", user_id);
         Ok(user)
     }
 
-    pub fn save_user(user: &User) -> Result<()> {
-        let users_dir = Config::users_dir()?;
-        std::fs::create_dir_all(&users_dir).context("Failed to create users directory")?;
+    fn user_file(&self, user_id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", user_id))
+    }
+
+    fn save_user(&self, user: &User, passphrase: &str) -> Result<()> {
+        self.write_user(user, passphrase, &self.user_file(&user.user_id))
+    }
 
-        let user_file = users_dir.join(format!("{}.json", user.user_id));
+    fn write_user(&self, user: &User, passphrase: &str, user_file: &Path) -> Result<()> {
+        let users_dir = &self.dir;
+        std::fs::create_dir_all(users_dir).context("Failed to create users directory")?;
 
-        let stored = StoredUser {
+        let mut stored = StoredUser {
+            version: KEYSTORE_VERSION,
             user_id: user.user_id.clone(),
-            signing_key_bytes: user.signing_key_bytes().to_vec(),
             verifying_key_bytes: user.verifying_key.to_bytes().to_vec(),
             roles: user.roles.iter().cloned().collect(),
+            key: None,
+            signing_key_bytes: None,
         };
+        let signing_key = Zeroizing::new(user.signing_key_bytes());
+        stored.key = Some(EncryptedKey::seal(
+            &signing_key,
+            passphrase,
+            &stored.key_context(),
+        )?);
 
         let content = serde_json::to_string_pretty(&stored)?;
-        std::fs::write(&user_file, content).context("Failed to write user file")?;
+
+        // swapped in whole, a plaintext file being migrated is never half overwritten
+        let tmp_file = user_file.with_extension("json.tmp");
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+
+        let written = options
+            .open(&tmp_file)
+            .and_then(|mut file| {
+                file.write_all(content.as_bytes())?;
+                file.sync_all()
+            })
+            .and_then(|_| std::fs::rename(&tmp_file, user_file))
+            .and_then(|_| sync_dir(users_dir));
+        if written.is_err() {
+            // the sealed key isn't left lying around half written
+            let _ = std::fs::remove_file(&tmp_file);
+        }
+        written.context("Failed to write user file")?;
 
         Ok(())
     }
 
-    pub fn load_user(user_id: &str) -> Result<User> {
-        let users_dir = Config::users_dir()?;
-        let user_file = users_dir.join(format!("{}.json", user_id));
+    fn read_stored(&self, user_id: &str) -> Result<StoredUser> {
+        let user_file = self.user_file(user_id);
 
         if !user_file.exists() {
             bail!(
@@ -61,30 +179,155 @@ impl UserStore {
 
         let stored: StoredUser =
             serde_json::from_str(&content).context("Failed to parse user file")?;
+        if stored.version > KEYSTORE_VERSION {
+            bail!(
+                "User file for '{}' has format version {}, this build reads up to {}",
+                user_id,
+                stored.version,
+                KEYSTORE_VERSION
+            );
+        }
+
+        Ok(stored)
+    }
+
+    // decrypts the signing key, asking for the passphrase if there is one
+    fn unlock(&self, stored: &StoredUser) -> Result<User> {
+        let roles: HashSet<String> = stored.roles.iter().cloned().collect();
+
+        let signing_key = match (&stored.key, &stored.signing_key_bytes) {
+            (Some(key), _) => {
+                let passphrase = self.passphrases.passphrase(&stored.user_id)?;
+                key.open(&passphrase, &stored.key_context())
+                    .with_context(|| format!("Failed to unlock '{}'", stored.user_id))?
+            }
+            (None, Some(bytes)) => Zeroizing::new(
+                bytes
+                    .as_slice()
+                    .try_into()
+                    .map_err(|_| anyhow::anyhow!("Invalid signing key length"))?,
+            ),
+            (None, None) => bail!("User file for '{}' holds no signing key", stored.user_id),
+        };
+
+        let user = User::from_key_bytes(&stored.user_id, &signing_key, roles);
+        if user.verifying_key.to_bytes().as_slice() != stored.verifying_key_bytes {
+            bail!(
+                "Signing key of '{}' doesn't match its verifying key",
+                stored.user_id
+            );
+        }
+
+        Ok(user)
+    }
+
+    /// Loads a user to sign with. A plaintext key from before the keystore is
+    /// encrypted on the spot, unless running with --read-only.
+    pub fn load_user(&self, user_id: &str) -> Result<User> {
+        let stored = self.read_stored(user_id)?;
+        let user = self.unlock(&stored)?;
+
+        if stored.key.is_none() {
+            if self.read_only {
+                eprintln!(
+                    "Warning: the key of '{}' is stored unencrypted, run without --read-only to encrypt it",
+                    user_id
+                );
+            } else {
+                println!(
+                    "The key of '{}' is stored unencrypted, choose a passphrase to encrypt it",
+                    user_id
+                );
+                let passphrase = self.passphrases.new_passphrase(user_id, PASSPHRASE_ENV)?;
+                self.save_user(&user, &passphrase)?;
+                println!("Key of '{}' is now encrypted", user_id);
+            }
+        }
+
+        Ok(user)
+    }
+
+    pub fn load_info(&self, user_id: &str) -> Result<UserInfo> {
+        let stored = self.read_stored(user_id)?;
 
-        let signing_key_bytes: [u8; 32] = stored
-            .signing_key_bytes
+        Ok(UserInfo {
+            encrypted: stored.key.is_some(),
+            user_id: stored.user_id,
+            verifying_key: stored.verifying_key_bytes,
+        })
+    }
+
+    /// The user with just their public key, enough to register them.
+    pub fn load_public(&self, user_id: &str) -> Result<User> {
+        let info = self.load_info(user_id)?;
+        let verifying_key: [u8; 32] = info
+            .verifying_key
+            .as_slice()
             .try_into()
-            .map_err(|_| anyhow::anyhow!("Invalid signing key length"))?;
+            .context("Invalid verifying key length")?;
+
+        User::from_verifying_key(&info.user_id, &verifying_key, HashSet::new())
+            .context("Invalid verifying key")
+    }
+
+    /// A new key for `user_id`, kept aside in `<id>.json.next` until
+    /// `commit_key`, so the current key stays if the ledger refuses the rotation.
+    /// It is on disk for good before the rotation is appended.
+    pub fn stage_key(&self, user_id: &str) -> Result<User> {
+        let user = User::new(user_id);
+        let passphrase = self.passphrases.new_passphrase(user_id, PASSPHRASE_ENV)?;
+        self.write_user(&user, &passphrase, &self.next_file(user_id))?;
+        Ok(user)
+    }
+
+    /// Swaps in the staged key once the ledger holds the rotation. If that
+    /// fails the error says how to finish it by hand.
+    pub fn commit_key(&self, user_id: &str) -> Result<()> {
+        let (next_file, user_file) = (self.next_file(user_id), self.user_file(user_id));
+        std::fs::rename(&next_file, &user_file)
+            .and_then(|_| sync_dir(&self.dir))
+            .with_context(|| {
+                format!(
+                    "The ledger already moved '{}' to the new key, but it is still in {}. Move it over {} before they sign again",
+                    user_id,
+                    next_file.display(),
+                    user_file.display()
+                )
+            })
+    }
 
-        let roles: HashSet<String> = stored.roles.into_iter().collect();
+    pub fn discard_key(&self, user_id: &str) -> Result<()> {
+        std::fs::remove_file(self.next_file(user_id)).context("Failed to remove the new key")
+    }
 
-        Ok(User::from_key_bytes(
-            &stored.user_id,
-            &signing_key_bytes,
-            roles,
-        ))
+    fn next_file(&self, user_id: &str) -> PathBuf {
+        self.user_file(user_id).with_extension("json.next")
     }
 
-    pub fn list_users() -> Result<Vec<String>> {
-        let users_dir = Config::users_dir()?;
+    /// Seals the key under a new passphrase, taken from `UKWELI_NEW_PASSPHRASE`
+    /// or asked for. Plaintext keys just get encrypted.
+    pub fn change_passphrase(&self, user_id: &str) -> Result<()> {
+        let stored = self.read_stored(user_id)?;
+        let user = self.unlock(&stored)?;
+
+        let passphrase = self
+            .passphrases
+            .new_passphrase(user_id, NEW_PASSPHRASE_ENV)?;
+        self.save_user(&user, &passphrase)?;
+
+        println!("Passphrase of '{}' changed", user_id);
+        Ok(())
+    }
+
+    pub fn list_users(&self) -> Result<Vec<String>> {
+        let users_dir = &self.dir;
 
         if !users_dir.exists() {
             return Ok(vec![]);
         }
 
         let mut users = vec![];
-        for entry in std::fs::read_dir(&users_dir)? {
+        for entry in std::fs::read_dir(users_dir)? {
             let entry = entry?;
             let path = entry.path();
 
@@ -99,9 +342,8 @@ impl UserStore {
         Ok(users)
     }
 
-    pub fn delete_user(user_id: &str) -> Result<()> {
-        let users_dir = Config::users_dir()?;
-        let user_file = users_dir.join(format!("{}.json", user_id));
+    pub fn delete_user(&self, user_id: &str) -> Result<()> {
+        let user_file = self.user_file(user_id);
 
         if !user_file.exists() {
             bail!("User '{}' not found", user_id);
@@ -113,9 +355,166 @@ impl UserStore {
         Ok(())
     }
 
-    pub fn user_exists(user_id: &str) -> Result<bool> {
-        let users_dir = Config::users_dir()?;
-        let user_file = users_dir.join(format!("{}.json", user_id));
-        Ok(user_file.exists())
+    pub fn user_exists(&self, user_id: &str) -> bool {
+        self.user_file(user_id).exists()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixed(&'static str);
+
+    impl PassphraseSource for Fixed {
+        fn passphrase(&self, _user_id: &str) -> Result<Zeroizing<String>> {
+            Ok(Zeroizing::new(self.0.to_string()))
+        }
+
+        fn new_passphrase(&self, _user_id: &str, _env: &str) -> Result<Zeroizing<String>> {
+            Ok(Zeroizing::new(self.0.to_string()))
+        }
+    }
+
+    fn store(dir: &Path, passphrase: &'static str) -> UserStore {
+        UserStore::new(dir.to_path_buf(), Box::new(Fixed(passphrase)))
+    }
+
+    fn read_json(path: &Path) -> serde_json::Value {
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    fn write_json(path: &Path, value: &serde_json::Value) {
+        std::fs::write(path, serde_json::to_string_pretty(value).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_create_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let users = store(dir.path(), "correct horse");
+        let amina = users.create_user("amina").unwrap();
+
+        let stored = read_json(&dir.path().join("amina.json"));
+        assert_eq!(stored["version"], KEYSTORE_VERSION);
+        assert!(stored.get("signing_key_bytes").is_none());
+
+        let loaded = users.load_user("amina").unwrap();
+        assert_eq!(loaded.verifying_key, amina.verifying_key);
+        assert!(users.load_info("amina").unwrap().encrypted);
+
+        assert!(
+            store(dir.path(), "battery staple")
+                .load_user("amina")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_key_moved_to_another_user_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let users = store(dir.path(), "correct horse");
+        users.create_user("amina").unwrap();
+        users.create_user("zuri").unwrap();
+
+        // amina's sealed key copied into zuri's file, with or without her public key
+        let amina = read_json(&dir.path().join("amina.json"));
+        let zuri_file = dir.path().join("zuri.json");
+        let mut zuri = read_json(&zuri_file);
+        zuri["key"] = amina["key"].clone();
+        write_json(&zuri_file, &zuri);
+        assert!(users.load_user("zuri").is_err());
+
+        zuri["verifying_key_bytes"] = amina["verifying_key_bytes"].clone();
+        write_json(&zuri_file, &zuri);
+        assert!(users.load_user("zuri").is_err());
+
+        // the version is bound in too
+        let amina_file = dir.path().join("amina.json");
+        let mut downgraded = amina.clone();
+        downgraded["version"] = 1.into();
+        write_json(&amina_file, &downgraded);
+        assert!(users.load_user("amina").is_err());
+
+        write_json(&amina_file, &amina);
+        assert!(users.load_user("amina").is_ok());
+    }
+
+    #[test]
+    fn test_unknown_version_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let users = store(dir.path(), "correct horse");
+        users.create_user("amina").unwrap();
+
+        let amina_file = dir.path().join("amina.json");
+        let mut amina = read_json(&amina_file);
+        amina["version"] = (KEYSTORE_VERSION + 1).into();
+        write_json(&amina_file, &amina);
+
+        let err = users.load_user("amina").unwrap_err();
+        assert!(err.to_string().contains("format version"), "{}", err);
+        assert!(users.load_info("amina").is_err());
+    }
+
+    #[test]
+    fn test_plaintext_key_migrated() {
+        let dir = tempfile::tempdir().unwrap();
+        let amina = User::new("amina");
+        let plaintext = serde_json::json!({
+            "user_id": "amina",
+            "verifying_key_bytes": amina.verifying_key.to_bytes().to_vec(),
+            "signing_key_bytes": amina.signing_key_bytes().to_vec(),
+            "roles": [],
+        });
+        let amina_file = dir.path().join("amina.json");
+        write_json(&amina_file, &plaintext);
+        let before = std::fs::read(&amina_file).unwrap();
+
+        // read-only runs sign with it but leave the file as it is
+        let users = store(dir.path(), "correct horse").with_read_only(true);
+        let loaded = users.load_user("amina").unwrap();
+        assert_eq!(loaded.verifying_key, amina.verifying_key);
+        assert_eq!(std::fs::read(&amina_file).unwrap(), before);
+        assert!(!users.load_info("amina").unwrap().encrypted);
+
+        let users = store(dir.path(), "correct horse");
+        users.load_user("amina").unwrap();
+        let migrated = read_json(&amina_file);
+        assert_eq!(migrated["version"], KEYSTORE_VERSION);
+        assert!(migrated.get("signing_key_bytes").is_none());
+        assert!(users.load_info("amina").unwrap().encrypted);
+
+        let loaded = users.load_user("amina").unwrap();
+        assert_eq!(loaded.verifying_key, amina.verifying_key);
+    }
+
+    #[test]
+    fn test_staged_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let users = store(dir.path(), "correct horse");
+        let old = users.create_user("amina").unwrap();
+        let next_file = dir.path().join("amina.json.next");
+
+        // a refused rotation keeps the old key
+        users.stage_key("amina").unwrap();
+        assert!(next_file.exists());
+        assert_eq!(
+            users.load_user("amina").unwrap().verifying_key,
+            old.verifying_key
+        );
+        users.discard_key("amina").unwrap();
+        assert!(!next_file.exists());
+        assert_eq!(
+            users.load_user("amina").unwrap().verifying_key,
+            old.verifying_key
+        );
+
+        let new = users.stage_key("amina").unwrap();
+        users.commit_key("amina").unwrap();
+        assert!(!next_file.exists());
+        assert_eq!(
+            users.load_user("amina").unwrap().verifying_key,
+            new.verifying_key
+        );
+        assert_eq!(users.list_users().unwrap(), vec!["amina".to_string()]);
     }
 }
@@ -0,0 +1,205 @@
+use serde::{Deserialize, Serialize};
+
+use crate::LedgerError;
+use crate::core::merkle::{SignedTreeHead, TreeHead};
+use crate::core::{Payload, PayloadBody, Record, User};
+
+/// Content type of the record naming the ledger operator.
+pub const OPERATOR_CONTENT_TYPE: &str = "application/vnd.ukweli.operator+json";
+
+/// Names the user whose checkpoints the ledger accepts. Signed by that user,
+/// and normally the first record after genesis, so whoever creates the ledger
+/// picks the operator. A ledger that never named one, made before operators
+/// existed or without `ukweli init --operator`, names one with a designation
+/// at its tip instead. Only the first designation counts, once there is an
+/// operator nobody can claim the role.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OperatorDesignation {
+    pub user_id: String,
+}
+
+impl OperatorDesignation {
+    pub fn new(user_id: &str) -> Self {
+        Self {
+            user_id: user_id.to_owned(),
+        }
+    }
+
+    // `None` for records that aren't designations, or don't parse as one
+    pub fn from_payload(payload: &Payload) -> Option<Self> {
+        if payload.content_type != OPERATOR_CONTENT_TYPE {
+            return None;
+        }
+        match &payload.body {
+            PayloadBody::Json(value) => serde_json::from_value(value.clone()).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn to_payload(&self) -> Payload {
+        let mut payload = Payload::json(serde_json::json!({ "user_id": self.user_id }));
+        payload.content_type = OPERATOR_CONTENT_TYPE.to_owned();
+        payload
+    }
+
+    /// The designation `record` makes, if it is one that counts while the
+    /// ledger names `operator`.
+    pub fn from_record(record: &Record, operator: Option<&str>) -> Option<Self> {
+        let designation = Self::from_payload(&record.payload)?;
+        designation
+            .authorize(record.index, operator, &record.signers)
+            .is_ok()
+            .then_some(designation)
+    }
+
+    /// Checks a designation may go in at `index`, signed by `signers`, while
+    /// the ledger names `operator`.
+    pub fn authorize(
+        &self,
+        index: usize,
+        operator: Option<&str>,
+        signers: &[User],
+    ) -> Result<(), LedgerError> {
+        if let Some(operator) = operator {
+            return Err(LedgerError::OperatorRejected(format!(
+                "The ledger already names {} as its operator",
+                operator
+            )));
+        }
+        if index == 0 {
+            return Err(LedgerError::OperatorRejected(
+                "Genesis can't name an operator".to_string(),
+            ));
+        }
+        if !signers.iter().any(|s| s.user_id == self.user_id) {
+            return Err(LedgerError::OperatorRejected(format!(
+                "{} must sign their own designation",
+                self.user_id
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WitnessSignature {
+    pub signer_id: String,
+    pub verifying_key: String,
+    pub signature: String,
+}
+
+/// A tree head signed by the ledger operator, plus any witness countersignatures.
+/// Witnesses sign the exact same tree head, so one checkpoint proves the same
+/// state to everyone who trusts any of the keys on it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub head: SignedTreeHead,
+    pub witnesses: Vec<WitnessSignature>,
+}
+
+impl Checkpoint {
+    pub fn new(tree_head: TreeHead, operator: &User) -> Self {
+        Self {
+            head: tree_head.sign(operator),
+            witnesses: Vec::new(),
+        }
+    }
+
+    pub fn tree_size(&self) -> usize {
+        self.head.tree_head.tree_size
+    }
+
+    pub fn root_hash(&self) -> &str {
+        &self.head.tree_head.root_hash
+    }
+
+    pub fn operator_id(&self) -> &str {
+        &self.head.signer_id
+    }
+
+    pub fn cosign(&mut self, witness: &User) {
+        let signed = self.head.tree_head.sign(witness);
+        self.add_witness(WitnessSignature {
+            signer_id: signed.signer_id,
+            verifying_key: signed.verifying_key,
+            signature: signed.signature,
+        });
+    }
+
+    // a witness signing twice replaces its old signature
+    pub fn add_witness(&mut self, witness: WitnessSignature) {
+        self.witnesses.retain(|w| w.signer_id != witness.signer_id);
+        self.witnesses.push(witness);
+    }
+
+    pub fn same_state(&self, other: &Checkpoint) -> bool {
+        self.tree_size() == other.tree_size() && self.root_hash() == other.root_hash()
+    }
+
+    /// Checks the operator signature and every witness signature.
+    /// This says nothing about whether the root matches a ledger, see
+    /// `Ledger::verify_checkpoint` for that.
+    pub fn verify_signatures(&self) -> Result<bool, LedgerError> {
+        self.head.verify(&self.head.verifying_key()?)?;
+
+        for witness in &self.witnesses {
+            let signed = SignedTreeHead {
+                tree_head: self.head.tree_head.clone(),
+                signer_id: witness.signer_id.clone(),
+                verifying_key: witness.verifying_key.clone(),
+                signature: witness.signature.clone(),
+            };
+            signed.verify(&signed.verifying_key()?).map_err(|e| {
+                LedgerError::InvalidProof(format!(
+                    "Witness {} signature invalid: {}",
+                    witness.signer_id, e
+                ))
+            })?;
+        }
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::indexing_slicing)]
+
+    use super::*;
+
+    fn head() -> TreeHead {
+        TreeHead {
+            tree_size: 3,
+            root_hash: "ab".repeat(32),
+            timestamp: 1_700_000_000,
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_cosign() {
+        let operator = User::new("operator");
+        let witness = User::new("auditor");
+
+        let mut checkpoint = Checkpoint::new(head(), &operator);
+        checkpoint.cosign(&witness);
+        checkpoint.cosign(&witness);
+
+        assert_eq!(checkpoint.witnesses.len(), 1);
+        assert_eq!(checkpoint.operator_id(), "operator");
+        assert!(checkpoint.verify_signatures().unwrap());
+    }
+
+    #[test]
+    fn test_checkpoint_forged_witness() {
+        let operator = User::new("operator");
+        let witness = User::new("auditor");
+        let impostor = User::new("impostor");
+
+        let mut checkpoint = Checkpoint::new(head(), &operator);
+        checkpoint.cosign(&witness);
+        checkpoint.witnesses[0].verifying_key = hex::encode(impostor.verifying_key.to_bytes());
+
+        assert!(checkpoint.verify_signatures().is_err());
+    }
+}
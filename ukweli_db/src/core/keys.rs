@@ -0,0 +1,479 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+
+use crate::LedgerError;
+use crate::core::roles::ADMIN_ROLE;
+use crate::core::{Payload, PayloadBody, Record, Roles, User};
+
+/// Content type of records that move a user to a new key.
+pub const KEY_ROTATION_CONTENT_TYPE: &str = "application/vnd.ukweli.key-rotation+json";
+
+/// A user's new key, carried by a record payload. `proof` is the new key
+/// signing `KeyRotation::message`, so nobody is handed a key they don't hold.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyRotation {
+    pub user_id: String,
+    pub new_key: String,
+    pub proof: String,
+}
+
+impl KeyRotation {
+    /// `user` holds the new key.
+    pub fn new(user: &User) -> Self {
+        let new_key = hex::encode(user.verifying_key.to_bytes());
+        let proof = user.sign(Self::message(&user.user_id, &new_key).as_bytes());
+
+        Self {
+            user_id: user.user_id.clone(),
+            new_key,
+            proof: hex::encode(proof.to_bytes()),
+        }
+    }
+
+    fn message(user_id: &str, new_key: &str) -> String {
+        format!("ukweli-key-rotation:{}:{}", user_id, new_key)
+    }
+
+    /// The new key, once it has proved it signed this rotation.
+    pub fn verified_key(&self) -> Result<VerifyingKey, LedgerError> {
+        let invalid =
+            |what: &str| LedgerError::KeyRejected(format!("Invalid {} for {}", what, self.user_id));
+
+        let key_bytes: [u8; 32] = hex::decode(&self.new_key)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| invalid("key"))?;
+        let proof_bytes: [u8; 64] = hex::decode(&self.proof)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| invalid("proof"))?;
+
+        let key = VerifyingKey::from_bytes(&key_bytes).map_err(|_| invalid("key"))?;
+        key.verify_strict(
+            Self::message(&self.user_id, &self.new_key).as_bytes(),
+            &Signature::from_bytes(&proof_bytes),
+        )
+        .map_err(|_| invalid("proof"))?;
+
+        Ok(key)
+    }
+
+    pub fn from_payload(payload: &Payload) -> Option<Self> {
+        if payload.content_type != KEY_ROTATION_CONTENT_TYPE {
+            return None;
+        }
+        match &payload.body {
+            PayloadBody::Json(value) => serde_json::from_value(value.clone()).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn to_payload(&self) -> Payload {
+        let mut payload = Payload::json(serde_json::json!({
+            "user_id": self.user_id,
+            "new_key": self.new_key,
+            "proof": self.proof,
+        }));
+        payload.content_type = KEY_ROTATION_CONTENT_TYPE.to_owned();
+        payload
+    }
+}
+
+/// Content type of records that mark a key compromised.
+pub const KEY_REVOCATION_CONTENT_TYPE: &str = "application/vnd.ukweli.key-revocation+json";
+
+/// From when a revoked key is no longer trusted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compromised {
+    /// from this record index on
+    Index(usize),
+    /// from this unix time on, in seconds like `Record::timestamp`. That
+    /// timestamp is picked by whoever signs the record, so whoever holds the
+    /// compromised key can backdate what they sign past it, prefer `Index`
+    /// when the first bad record is known.
+    Time(u64),
+}
+
+impl Compromised {
+    pub fn covers(&self, record: &Record) -> bool {
+        match self {
+            Compromised::Index(index) => record.index >= *index,
+            Compromised::Time(time) => record.timestamp >= *time,
+        }
+    }
+}
+
+impl fmt::Display for Compromised {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compromised::Index(index) => write!(f, "record #{}", index),
+            Compromised::Time(time) => write!(f, "time {}", time),
+        }
+    }
+}
+
+/// One of a user's keys marked compromised, carried by a record payload.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyRevocation {
+    pub user_id: String,
+    pub key: String,
+    pub since: Compromised,
+}
+
+impl KeyRevocation {
+    pub fn new(user_id: &str, key: &VerifyingKey, since: Compromised) -> Self {
+        Self {
+            user_id: user_id.to_owned(),
+            key: hex::encode(key.to_bytes()),
+            since,
+        }
+    }
+
+    pub fn revoked_key(&self) -> Result<VerifyingKey, LedgerError> {
+        hex::decode(&self.key)
+            .ok()
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+            .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+            .ok_or_else(|| LedgerError::KeyRejected(format!("Invalid key for {}", self.user_id)))
+    }
+
+    pub fn from_payload(payload: &Payload) -> Option<Self> {
+        if payload.content_type != KEY_REVOCATION_CONTENT_TYPE {
+            return None;
+        }
+        match &payload.body {
+            PayloadBody::Json(value) => serde_json::from_value(value.clone()).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn to_payload(&self) -> Payload {
+        let mut payload = Payload::json(serde_json::json!({
+            "user_id": self.user_id,
+            "key": self.key,
+            "since": self.since,
+        }));
+        payload.content_type = KEY_REVOCATION_CONTENT_TYPE.to_owned();
+        payload
+    }
+}
+
+/// Content type of records that stop a user signing.
+pub const DEACTIVATION_CONTENT_TYPE: &str = "application/vnd.ukweli.deactivation+json";
+
+/// A user who stops signing from `effective_index` on, carried by a record
+/// payload. What they signed before stays valid.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Deactivation {
+    pub user_id: String,
+    pub reason: String,
+    pub effective_index: usize,
+}
+
+impl Deactivation {
+    pub fn new(user_id: &str, reason: &str, effective_index: usize) -> Self {
+        Self {
+            user_id: user_id.to_owned(),
+            reason: reason.to_owned(),
+            effective_index,
+        }
+    }
+
+    pub fn from_payload(payload: &Payload) -> Option<Self> {
+        if payload.content_type != DEACTIVATION_CONTENT_TYPE {
+            return None;
+        }
+        match &payload.body {
+            PayloadBody::Json(value) => serde_json::from_value(value.clone()).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn to_payload(&self) -> Payload {
+        let mut payload = Payload::json(serde_json::json!({
+            "user_id": self.user_id,
+            "reason": self.reason,
+            "effective_index": self.effective_index,
+        }));
+        payload.content_type = DEACTIVATION_CONTENT_TYPE.to_owned();
+        payload
+    }
+}
+
+/// A record signed with a key that was later revoked from before it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompromisedRecord {
+    pub index: usize,
+    pub user_id: String,
+    pub since: Compromised,
+}
+
+/// Each user's key as the ledger's rotation and revocation records leave it
+/// at some index, starting from the key they registered with.
+///
+/// Both kinds of record are endorsed by the user, signing with their current
+/// key, or by a quorum of admins when that key is gone: more than half of them.
+/// A revoked key never signs again, and no key is handed out twice.
+///
+/// Deactivated users are tracked here too, as they can't sign either. The
+/// user or an admin deactivates them, an admin has to lose the role first.
+#[derive(Debug, Clone, Default)]
+pub struct Keys {
+    // every key each user has held, the current one last
+    held: HashMap<String, Vec<VerifyingKey>>,
+    revoked: HashMap<[u8; 32], Compromised>,
+    deactivated: HashMap<String, Deactivation>,
+}
+
+impl Keys {
+    pub fn new(registered: &HashMap<String, VerifyingKey>) -> Self {
+        Self {
+            held: registered
+                .iter()
+                .map(|(user_id, key)| (user_id.clone(), vec![*key]))
+                .collect(),
+            revoked: HashMap::new(),
+            deactivated: HashMap::new(),
+        }
+    }
+
+    /// Starts tracking a newly registered user at `key`.
+    pub fn register(&mut self, user_id: &str, key: VerifyingKey) {
+        self.held
+            .entry(user_id.to_owned())
+            .or_insert_with(|| vec![key]);
+    }
+
+    pub fn key(&self, user_id: &str) -> Option<&VerifyingKey> {
+        self.held.get(user_id).and_then(|keys| keys.last())
+    }
+
+    /// The user's deactivation, if one is on the ledger yet.
+    pub fn deactivation(&self, user_id: &str) -> Option<&Deactivation> {
+        self.deactivated.get(user_id)
+    }
+
+    /// Whether `user_id` may sign the record at `index`.
+    pub fn is_active(&self, user_id: &str, index: usize) -> bool {
+        self.deactivation(user_id)
+            .is_none_or(|deactivation| index < deactivation.effective_index)
+    }
+
+    /// Since when `key` is compromised, `None` while it is trusted.
+    pub fn revoked(&self, key: &VerifyingKey) -> Option<Compromised> {
+        self.revoked.get(&key.to_bytes()).copied()
+    }
+
+    fn endorsed(user_id: &str, signers: &[User], roles: &Roles) -> Result<(), LedgerError> {
+        if signers.iter().any(|s| s.user_id == user_id) {
+            return Ok(());
+        }
+
+        let admins = signers
+            .iter()
+            .filter(|s| roles.has(&s.user_id, ADMIN_ROLE))
+            .count();
+        let quorum = roles.admin_count() / 2 + 1;
+        if admins < quorum {
+            return Err(LedgerError::KeyRejected(format!(
+                "Needs {} signing with their current key, or {} of the {} {}s, got {}",
+                user_id,
+                quorum,
+                roles.admin_count(),
+                ADMIN_ROLE,
+                admins
+            )));
+        }
+        Ok(())
+    }
+
+    /// Checks `signers` may make `rotation` from here.
+    pub fn authorize(
+        &self,
+        rotation: &KeyRotation,
+        signers: &[User],
+        roles: &Roles,
+    ) -> Result<VerifyingKey, LedgerError> {
+        let held = self.held.get(&rotation.user_id).ok_or_else(|| {
+            LedgerError::KeyRejected(format!("{} is not registered", rotation.user_id))
+        })?;
+
+        if self.deactivation(&rotation.user_id).is_some() {
+            return Err(LedgerError::KeyRejected(format!(
+                "{} is deactivated",
+                rotation.user_id
+            )));
+        }
+
+        let new_key = rotation.verified_key()?;
+        if held.contains(&new_key) {
+            return Err(LedgerError::KeyRejected(format!(
+                "{} has used that key before",
+                rotation.user_id
+            )));
+        }
+
+        Self::endorsed(&rotation.user_id, signers, roles)?;
+        Ok(new_key)
+    }
+
+    /// Checks `signers` may make `revocation` from here.
+    pub fn authorize_revocation(
+        &self,
+        revocation: &KeyRevocation,
+        signers: &[User],
+        roles: &Roles,
+    ) -> Result<VerifyingKey, LedgerError> {
+        let key = revocation.revoked_key()?;
+        let held = self.held.get(&revocation.user_id).ok_or_else(|| {
+            LedgerError::KeyRejected(format!("{} is not registered", revocation.user_id))
+        })?;
+
+        if !held.contains(&key) {
+            return Err(LedgerError::KeyRejected(format!(
+                "{} never held that key",
+                revocation.user_id
+            )));
+        }
+        if self.revoked(&key).is_some() {
+            return Err(LedgerError::KeyRejected(format!(
+                "That key of {} is already revoked",
+                revocation.user_id
+            )));
+        }
+
+        Self::endorsed(&revocation.user_id, signers, roles)?;
+        Ok(key)
+    }
+
+    /// Checks `signers` may make `deactivation` in the record at `index`.
+    pub fn authorize_deactivation(
+        &self,
+        deactivation: &Deactivation,
+        index: usize,
+        signers: &[User],
+        roles: &Roles,
+    ) -> Result<(), LedgerError> {
+        let user_id = &deactivation.user_id;
+        let rejected = |reason: String| LedgerError::KeyRejected(reason);
+
+        if !self.held.contains_key(user_id) {
+            return Err(rejected(format!("{} is not registered", user_id)));
+        }
+        if self.deactivation(user_id).is_some() {
+            return Err(rejected(format!("{} is already deactivated", user_id)));
+        }
+        if deactivation.effective_index < index {
+            return Err(rejected(format!(
+                "Deactivation can't take effect before its own record #{}",
+                index
+            )));
+        }
+        if roles.has(user_id, ADMIN_ROLE) {
+            return Err(rejected(format!(
+                "{} is an {}, revoke that role first",
+                user_id, ADMIN_ROLE
+            )));
+        }
+        if !signers
+            .iter()
+            .any(|s| s.user_id == *user_id || roles.has(&s.user_id, ADMIN_ROLE))
+        {
+            return Err(rejected(format!(
+                "Deactivating {} needs them or an {} to sign",
+                user_id, ADMIN_ROLE
+            )));
+        }
+        Ok(())
+    }
+
+    /// Moves past `record`. `roles` must be the roles in force for it.
+    pub fn replay(&mut self, record: &Record, roles: &Roles) {
+        if let Some(rotation) = KeyRotation::from_payload(&record.payload)
+            && let Ok(new_key) = self.authorize(&rotation, &record.signers, roles)
+        {
+            self.held.entry(rotation.user_id).or_default().push(new_key);
+        }
+
+        if let Some(revocation) = KeyRevocation::from_payload(&record.payload)
+            && let Ok(key) = self.authorize_revocation(&revocation, &record.signers, roles)
+        {
+            self.revoked.insert(key.to_bytes(), revocation.since);
+        }
+
+        if let Some(deactivation) = Deactivation::from_payload(&record.payload)
+            && self
+                .authorize_deactivation(&deactivation, record.index, &record.signers, roles)
+                .is_ok()
+        {
+            self.deactivated
+                .insert(deactivation.user_id.clone(), deactivation);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::indexing_slicing)]
+
+    use super::*;
+    use crate::core::RoleChange;
+
+    #[test]
+    fn test_rotation_payload_and_proof() {
+        let user = User::new("alice");
+        let rotation = KeyRotation::new(&user);
+        let payload = rotation.to_payload();
+
+        assert_eq!(KeyRotation::from_payload(&payload).unwrap(), rotation);
+        assert_eq!(rotation.verified_key().unwrap(), user.verifying_key);
+
+        // someone else's key, without its proof
+        let mut stolen = rotation.clone();
+        stolen.new_key = hex::encode(User::new("bob").verifying_key.to_bytes());
+        assert!(stolen.verified_key().is_err());
+    }
+
+    #[test]
+    fn test_rotation_endorsement() {
+        let alice = User::new("alice");
+        let admins: Vec<User> = ["a1", "a2", "a3"].iter().map(|id| User::new(id)).collect();
+
+        let mut roles = Roles::default();
+        for admin in &admins {
+            roles.apply(&RoleChange::grant(&admin.user_id, ADMIN_ROLE));
+        }
+        let registered = HashMap::from([("alice".to_string(), alice.verifying_key)]);
+        let keys = Keys::new(&registered);
+
+        let rotation = KeyRotation::new(&User::new("alice"));
+        assert!(keys.authorize(&rotation, &[alice], &roles).is_ok());
+        assert!(keys.authorize(&rotation, &admins[..1], &roles).is_err());
+        assert!(keys.authorize(&rotation, &admins[..2], &roles).is_ok());
+
+        let stranger = KeyRotation::new(&User::new("mallory"));
+        assert!(keys.authorize(&stranger, &admins, &roles).is_err());
+    }
+
+    #[test]
+    fn test_compromised_since() {
+        let user = User::new("alice");
+        let mut record = Record::new(4, "signed", "prev", vec![user.clone()]);
+        record.timestamp = 1_000;
+
+        assert!(Compromised::Index(4).covers(&record));
+        assert!(!Compromised::Index(5).covers(&record));
+        assert!(Compromised::Time(1_000).covers(&record));
+        assert!(!Compromised::Time(1_001).covers(&record));
+
+        let revocation = KeyRevocation::new("alice", &user.verifying_key, Compromised::Time(1_000));
+        let payload = revocation.to_payload();
+        assert_eq!(KeyRevocation::from_payload(&payload).unwrap(), revocation);
+        assert_eq!(revocation.revoked_key().unwrap(), user.verifying_key);
+    }
+}
@@ -1,6 +1,20 @@
 use std::collections::HashMap;
 
-use crate::{LedgerError, core::User};
+use crate::core::checkpoint::{OPERATOR_CONTENT_TYPE, OperatorDesignation};
+use crate::core::keys::{
+    DEACTIVATION_CONTENT_TYPE, KEY_REVOCATION_CONTENT_TYPE, KEY_ROTATION_CONTENT_TYPE,
+};
+use crate::core::roles::ROLE_CONTENT_TYPE;
+use crate::error::WorkflowError;
+use crate::workflow::{Engine, EntityState, StateProjector, TransitionMetadata};
+use crate::{
+    LedgerError,
+    core::{
+        Checkpoint, Deactivation, KeyRotation, Keys, Payload, RoleChange, Roles, User,
+        keys::{CompromisedRecord, KeyRevocation},
+        merkle::{self, ConsistencyProof, InclusionProof, MerkleHash, TreeHead},
+    },
+};
 use ed25519_dalek::VerifyingKey;
 use sha256::digest;
 
@@ -13,6 +27,15 @@ pub struct Ledger {
     pub records: Vec<Record>,
     pub users: HashMap<String, User>,
     pub verify_registry: HashMap<String, VerifyingKey>, // (userid, vkey)
+    pub checkpoints: Vec<Checkpoint>,
+    // roles and keys in force for the next record, kept up to date by appends
+    // so they aren't replayed from genesis each time
+    roles: Roles,
+    keys: Keys,
+    // entity states the same way, projected on the first transition append
+    // since that needs the engine, and dropped whenever roles and keys are
+    // replayed
+    entities: Option<HashMap<String, EntityState>>,
 }
 
 impl From<ed25519_dalek::SignatureError> for LedgerError {
@@ -42,17 +65,71 @@ impl Ledger {
             .signatures
             .insert(genesis_user.user_id, signature);
 
+        let keys = Keys::new(&verify_registry);
         Self {
             records: vec![genesis_record],
             users,
             verify_registry,
+            checkpoints: Vec::new(),
+            roles: Roles::default(),
+            keys,
+            entities: None,
         }
     }
 
-    pub fn add_record(&mut self, payload: &str, signers: Vec<User>) -> Result<usize, LedgerError> {
+    /// Appends a record signed by `signers`. Payloads naming an entity are
+    /// refused, they go through `add_transition_record` so their workflow is
+    /// checked.
+    pub fn add_record(
+        &mut self,
+        payload: impl Into<Payload>,
+        signers: Vec<User>,
+    ) -> Result<usize, LedgerError> {
+        let payload = payload.into();
+        if payload.entity_id.is_some()
+            || payload.workflow_id.is_some()
+            || payload.transition.is_some()
+        {
+            return Err(WorkflowError::Validation(
+                "Payload names an entity, append it as a transition so its workflow is checked"
+                    .to_string(),
+            )
+            .into());
+        }
+
+        self.append(payload, signers, None)
+    }
+
+    // `engine` moves the entity a transition record is tagged with
+    fn append(
+        &mut self,
+        payload: Payload,
+        signers: Vec<User>,
+        engine: Option<&Engine>,
+    ) -> Result<usize, LedgerError> {
+        let (roles, keys) = (&self.roles, &self.keys);
         for signer in &signers {
-            if !self.verify_registry.contains_key(&signer.user_id) {
-                return Err(LedgerError::UnregistedUser);
+            match keys.key(&signer.user_id) {
+                None => return Err(LedgerError::UnregistedUser),
+                Some(key) if *key != signer.verifying_key => {
+                    return Err(LedgerError::KeyRejected(format!(
+                        "{} signed with a key that is no longer theirs",
+                        signer.user_id
+                    )));
+                }
+                Some(key) if keys.revoked(key).is_some() => {
+                    return Err(LedgerError::KeyRejected(format!(
+                        "{} signed with a revoked key",
+                        signer.user_id
+                    )));
+                }
+                Some(_) if !keys.is_active(&signer.user_id, self.records.len()) => {
+                    return Err(LedgerError::KeyRejected(format!(
+                        "{} is deactivated",
+                        signer.user_id
+                    )));
+                }
+                Some(_) => {}
             }
         }
         let last_record = match self.get_last_record() {
@@ -63,6 +140,33 @@ impl Ledger {
         if payload.is_empty() {
             return Err(LedgerError::EmptyPayload);
         }
+        if payload.content_type == OPERATOR_CONTENT_TYPE {
+            let designation = OperatorDesignation::from_payload(&payload).ok_or_else(|| {
+                LedgerError::OperatorRejected("Malformed operator record".to_string())
+            })?;
+            designation.authorize(self.records.len(), roles.operator(), &signers)?;
+        }
+        if payload.content_type == ROLE_CONTENT_TYPE {
+            self.check_role_change(&payload, &signers, roles)?;
+        }
+        if payload.content_type == KEY_ROTATION_CONTENT_TYPE {
+            let rotation = KeyRotation::from_payload(&payload).ok_or_else(|| {
+                LedgerError::KeyRejected("Malformed key rotation record".to_string())
+            })?;
+            keys.authorize(&rotation, &signers, roles)?;
+        }
+        if payload.content_type == KEY_REVOCATION_CONTENT_TYPE {
+            let revocation = KeyRevocation::from_payload(&payload).ok_or_else(|| {
+                LedgerError::KeyRejected("Malformed key revocation record".to_string())
+            })?;
+            keys.authorize_revocation(&revocation, &signers, roles)?;
+        }
+        if payload.content_type == DEACTIVATION_CONTENT_TYPE {
+            let deactivation = Deactivation::from_payload(&payload).ok_or_else(|| {
+                LedgerError::KeyRejected("Malformed deactivation record".to_string())
+            })?;
+            keys.authorize_deactivation(&deactivation, self.records.len(), &signers, roles)?;
+        }
         let record = Record::new(
             last_record.index + 1,
             payload,
@@ -70,11 +174,172 @@ impl Ledger {
             signers,
         );
         let ret_index = record.index;
+        self.keys.replay(&record, &self.roles);
+        if let (Some(engine), Some(entities)) = (engine, self.entities.as_mut()) {
+            StateProjector::new(engine).replay(entities, &record, &self.roles);
+        }
+        self.roles.replay(&record);
         self.records.push(record);
 
         Ok(ret_index)
     }
 
+    /// Appends a record that moves an entity through its workflow.
+    /// The record is refused unless the transition is allowed from the entity's
+    /// current state by these signers. Entity states are projected with the
+    /// first engine passed in after loading and kept up to date from then on,
+    /// call `rebuild_state` if the workflows change.
+    pub fn add_transition_record(
+        &mut self,
+        engine: &Engine,
+        payload: Payload,
+        signers: Vec<User>,
+    ) -> Result<usize, LedgerError> {
+        let metadata = TransitionMetadata::from_payload(&payload).ok_or_else(|| {
+            WorkflowError::Validation(
+                "Payload needs an entity, workflow and transition".to_string(),
+            )
+        })?;
+
+        if self.entities.is_none() {
+            self.entities = Some(StateProjector::new(engine).project(self));
+        }
+        let entity = self
+            .entities
+            .as_ref()
+            .and_then(|entities| entities.get(&metadata.entity_id));
+
+        let current_state = match entity {
+            Some(state) if state.workflow_id != metadata.workflow_id => {
+                return Err(WorkflowError::Validation(format!(
+                    "Entity {} follows workflow {}, not {}",
+                    metadata.entity_id, state.workflow_id, metadata.workflow_id
+                ))
+                .into());
+            }
+            Some(state) => state.current_state.clone(),
+            None => engine
+                .workflows
+                .get(&metadata.workflow_id)
+                .map(|w| w.initial_state.clone())
+                .ok_or_else(|| {
+                    WorkflowError::Parsing(format!("Unknown workflow {}", metadata.workflow_id))
+                })?,
+        };
+
+        let transition =
+            engine.get_transition(&metadata.workflow_id, &current_state, &metadata.transition)?;
+
+        let signer_ids: Vec<String> = signers.iter().map(|s| s.user_id.clone()).collect();
+        engine.check_roles(&transition, &signer_ids, &self.roles)?;
+
+        self.append(payload, signers, Some(engine))
+    }
+
+    fn check_role_change(
+        &self,
+        payload: &Payload,
+        signers: &[User],
+        roles: &Roles,
+    ) -> Result<(), LedgerError> {
+        let change = RoleChange::from_payload(payload)
+            .ok_or_else(|| LedgerError::RoleRejected("Malformed role record".to_string()))?;
+
+        if !self.verify_registry.contains_key(&change.user_id) {
+            return Err(LedgerError::RoleRejected(format!(
+                "{} is not registered",
+                change.user_id
+            )));
+        }
+
+        roles.authorize(&change, signers)
+    }
+
+    /// Roles in force for the record at `index`, replayed from the role
+    /// records before it.
+    pub fn roles_at(&self, index: usize) -> Roles {
+        let mut roles = Roles::default();
+        for record in self.records.iter().take(index) {
+            roles.replay(record);
+        }
+        roles
+    }
+
+    /// Roles in force for the next record.
+    pub fn roles(&self) -> &Roles {
+        &self.roles
+    }
+
+    /// Keys in force for the record at `index`: the registered ones, moved
+    /// on by the rotation records before it.
+    pub fn keys_at(&self, index: usize) -> Keys {
+        self.replay_to(index).1
+    }
+
+    /// Keys new records are signed with.
+    pub fn keys(&self) -> &Keys {
+        &self.keys
+    }
+
+    /// Replays the roles and keys new records are checked against and drops
+    /// the entity states. Needed after changing `records` or `verify_registry`
+    /// other than through `add_record` and `register_user`, as storage does
+    /// when loading.
+    pub fn rebuild_state(&mut self) {
+        (self.roles, self.keys) = self.replay_to(self.records.len());
+        self.entities = None;
+    }
+
+    /// Drops every record from `len` on.
+    pub fn truncate(&mut self, len: usize) {
+        self.records.truncate(len);
+        self.rebuild_state();
+    }
+
+    /// Records signed with a key that has since been revoked from before
+    /// them. Their signatures still verify, but can't be trusted. The owner
+    /// signing the revocation of their own key isn't one of them.
+    pub fn compromised_records(&self) -> Vec<CompromisedRecord> {
+        let revoked = self.keys();
+        let mut roles = Roles::default();
+        let mut keys = Keys::new(&self.verify_registry);
+        let mut compromised = Vec::new();
+
+        for record in &self.records {
+            let revokes = KeyRevocation::from_payload(&record.payload)
+                .and_then(|revocation| revocation.revoked_key().ok());
+
+            for signer in &record.signers {
+                if let Some(key) = keys.key(&signer.user_id)
+                    && revokes.as_ref() != Some(key)
+                    && let Some(since) = revoked.revoked(key)
+                    && since.covers(record)
+                {
+                    compromised.push(CompromisedRecord {
+                        index: record.index,
+                        user_id: signer.user_id.clone(),
+                        since,
+                    });
+                }
+            }
+            keys.replay(record, &roles);
+            roles.replay(record);
+        }
+
+        compromised
+    }
+
+    // rotations by admin quorum need the roles at the same point
+    fn replay_to(&self, index: usize) -> (Roles, Keys) {
+        let mut roles = Roles::default();
+        let mut keys = Keys::new(&self.verify_registry);
+        for record in self.records.iter().take(index) {
+            keys.replay(record, &roles);
+            roles.replay(record);
+        }
+        (roles, keys)
+    }
+
     fn get_last_record(&self) -> Option<&Record> {
         self.records.last()
     }
@@ -84,7 +349,12 @@ impl Ledger {
         let verifying_key = user.verifying_key;
 
         self.users.insert(user_id.clone(), user);
-        self.verify_registry.insert(user_id, verifying_key);
+        match self.verify_registry.insert(user_id.clone(), verifying_key) {
+            None => self.keys.register(&user_id, verifying_key),
+            // everything they signed is checked against the new key from the start
+            Some(previous) if previous != verifying_key => self.rebuild_state(),
+            Some(_) => {}
+        }
     }
 
     pub fn length(&self) -> usize {
@@ -99,15 +369,205 @@ impl Ledger {
         self.users.iter()
     }
 
-    fn verify_signatures(&self, record: &Record) -> Result<bool, LedgerError> {
-        for signer in &record.signers {
-            let verify_key: Result<&VerifyingKey, LedgerError> = self
-                .verify_registry
-                .get(&signer.user_id)
-                .ok_or(LedgerError::ChainValidation(format!(
-                    "Unknown signer {:?}",
-                    signer.user_id
+    fn merkle_leaves(&self) -> Vec<MerkleHash> {
+        self.records
+            .iter()
+            .map(|r| merkle::leaf_hash(r.record_hash.as_bytes()))
+            .collect()
+    }
+
+    pub fn merkle_root(&self) -> MerkleHash {
+        merkle::root(&self.merkle_leaves())
+    }
+
+    pub fn tree_head(&self) -> TreeHead {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        TreeHead {
+            tree_size: self.records.len(),
+            root_hash: hex::encode(self.merkle_root()),
+            timestamp,
+        }
+    }
+
+    pub fn inclusion_proof(&self, index: usize) -> Result<InclusionProof, LedgerError> {
+        let record = self
+            .records
+            .get(index)
+            .ok_or(LedgerError::IndexOutOfRange(index, self.records.len()))?;
+
+        InclusionProof::new(index, &record.record_hash, &self.merkle_leaves())
+    }
+
+    pub fn consistency_proof(
+        &self,
+        old_size: usize,
+        new_size: usize,
+    ) -> Result<ConsistencyProof, LedgerError> {
+        let leaves = self.merkle_leaves();
+        let new_leaves = leaves
+            .get(..new_size)
+            .ok_or(LedgerError::IndexOutOfRange(new_size, leaves.len()))?;
+
+        ConsistencyProof::new(old_size, new_leaves)
+    }
+
+    /// The user named by the first operator designation, the only one whose
+    /// checkpoints are accepted.
+    pub fn operator_id(&self) -> Option<String> {
+        self.roles.operator().map(str::to_owned)
+    }
+
+    /// Registers `operator` and names them in a record at the tip, the first
+    /// after genesis for a new ledger. Fails once the ledger has an operator.
+    pub fn designate_operator(&mut self, operator: &User) -> Result<usize, LedgerError> {
+        self.register_user(operator.clone());
+        self.add_record(
+            OperatorDesignation::new(&operator.user_id).to_payload(),
+            vec![operator.clone()],
+        )
+    }
+
+    pub fn latest_checkpoint(&self) -> Option<&Checkpoint> {
+        self.checkpoints.last()
+    }
+
+    pub fn create_checkpoint(&mut self, operator: &User) -> Result<Checkpoint, LedgerError> {
+        let checkpoint = Checkpoint::new(self.tree_head(), operator);
+        self.add_checkpoint(checkpoint.clone())?;
+        Ok(checkpoint)
+    }
+
+    pub fn cosign_checkpoint(
+        &mut self,
+        tree_size: usize,
+        witness: &User,
+    ) -> Result<Checkpoint, LedgerError> {
+        let mut checkpoint = self
+            .checkpoints
+            .iter()
+            .find(|c| c.tree_size() == tree_size)
+            .cloned()
+            .ok_or_else(|| {
+                LedgerError::InvalidProof(format!("No checkpoint at size {}", tree_size))
+            })?;
+
+        checkpoint.cosign(witness);
+        self.add_checkpoint(checkpoint.clone())?;
+        Ok(checkpoint)
+    }
+
+    /// Verifies a checkpoint against this ledger before keeping it.
+    /// A checkpoint for a state we already hold only adds its witnesses.
+    pub fn add_checkpoint(&mut self, checkpoint: Checkpoint) -> Result<(), LedgerError> {
+        self.verify_checkpoint(&checkpoint)?;
+        self.merge_checkpoint(checkpoint)
+    }
+
+    // storage replays checkpoints before the chain is verified, so no checks here
+    pub(crate) fn merge_checkpoint(&mut self, checkpoint: Checkpoint) -> Result<(), LedgerError> {
+        if let Some(existing) = self
+            .checkpoints
+            .iter_mut()
+            .find(|c| c.tree_size() == checkpoint.tree_size())
+        {
+            if !existing.same_state(&checkpoint) {
+                return Err(LedgerError::InvalidProof(format!(
+                    "Conflicting checkpoints at size {}",
+                    checkpoint.tree_size()
                 )));
+            }
+            for witness in checkpoint.witnesses {
+                existing.add_witness(witness);
+            }
+            return Ok(());
+        }
+
+        if let Some(latest) = self.latest_checkpoint()
+            && latest.tree_size() > checkpoint.tree_size()
+        {
+            return Err(LedgerError::InvalidProof(format!(
+                "Checkpoint at size {} is older than the latest at {}",
+                checkpoint.tree_size(),
+                latest.tree_size()
+            )));
+        }
+
+        self.checkpoints.push(checkpoint);
+        Ok(())
+    }
+
+    pub fn verify_checkpoint(&self, checkpoint: &Checkpoint) -> Result<bool, LedgerError> {
+        // the operator and their key when the checkpoint was taken
+        if checkpoint.tree_size() == self.records.len() {
+            return self.verify_checkpoint_with(checkpoint, &self.roles, &self.keys);
+        }
+        let (roles, keys) = self.replay_to(checkpoint.tree_size());
+        self.verify_checkpoint_with(checkpoint, &roles, &keys)
+    }
+
+    // `roles` and `keys` are the ones in force at the checkpoint's size
+    fn verify_checkpoint_with(
+        &self,
+        checkpoint: &Checkpoint,
+        roles: &Roles,
+        keys: &Keys,
+    ) -> Result<bool, LedgerError> {
+        let operator_id = roles.operator().ok_or_else(|| {
+            LedgerError::InvalidProof(
+                "The ledger names no operator to sign checkpoints".to_string(),
+            )
+        })?;
+        if operator_id != checkpoint.operator_id() {
+            return Err(LedgerError::InvalidProof(format!(
+                "Checkpoints must be signed by operator {}, not {}",
+                operator_id,
+                checkpoint.operator_id()
+            )));
+        }
+
+        let registered = keys
+            .key(checkpoint.operator_id())
+            .ok_or(LedgerError::UnregistedUser)?;
+
+        if *registered != checkpoint.head.verifying_key()? {
+            return Err(LedgerError::InvalidProof(format!(
+                "Checkpoint key does not belong to {}",
+                checkpoint.operator_id()
+            )));
+        }
+
+        checkpoint.verify_signatures()?;
+
+        let leaves = self.merkle_leaves();
+        let covered = leaves
+            .get(..checkpoint.tree_size())
+            .ok_or(LedgerError::IndexOutOfRange(
+                checkpoint.tree_size(),
+                leaves.len(),
+            ))?;
+
+        if hex::encode(merkle::root(covered)) != checkpoint.root_hash() {
+            return Err(LedgerError::InvalidProof(format!(
+                "Checkpoint at size {} does not match the ledger",
+                checkpoint.tree_size()
+            )));
+        }
+
+        Ok(true)
+    }
+
+    fn verify_signatures(record: &Record, keys: &Keys) -> Result<bool, LedgerError> {
+        for signer in &record.signers {
+            let verify_key: Result<&VerifyingKey, LedgerError> =
+                keys.key(&signer.user_id)
+                    .ok_or(LedgerError::ChainValidation(format!(
+                        "Unknown signer {:?}",
+                        signer.user_id
+                    )));
 
             let signature =
                 record
@@ -123,8 +583,19 @@ impl Ledger {
         Ok(true)
     }
 
+    /// Checks the hash chain, and each record's signatures against the keys
+    /// its signers held at that point.
     pub fn verify_chain(&self) -> Result<bool, LedgerError> {
+        let mut roles = Roles::default();
+        let mut keys = Keys::new(&self.verify_registry);
+        let mut checkpoints = self.checkpoints.iter().peekable();
+
         for (i, record) in self.records.iter().enumerate() {
+            // checkpoints taken at this size, while `keys` are the ones of their time
+            while let Some(checkpoint) = checkpoints.next_if(|c| c.tree_size() <= i) {
+                self.check_checkpoint(checkpoint, i, &roles, &keys)?;
+            }
+
             if i == 0 {
                 if record.prev_hash != GENESIS_PREV_HASH {
                     return Err(LedgerError::ChainValidation("Invalid genesis".to_string()));
@@ -142,7 +613,7 @@ impl Ledger {
                 }
             }
 
-            let computed_payload_hash = digest(&record.payload);
+            let computed_payload_hash = record.payload.hash();
             if computed_payload_hash != record.payload_hash {
                 return Err(LedgerError::ChainValidation(format!(
                     "Payload tampered at {}",
@@ -174,12 +645,53 @@ impl Ledger {
                 )));
             }
 
-            self.verify_signatures(record).map_err(|e| {
+            Self::verify_signatures(record, &keys).map_err(|e| {
                 LedgerError::ChainValidation(format!("Signature validation failed: {}", e))
             })?;
+            // `append` refuses these, but a record can reach the ledger without it
+            if let Some(signer) = record
+                .signers
+                .iter()
+                .find(|s| !keys.is_active(&s.user_id, record.index))
+            {
+                return Err(LedgerError::ChainValidation(format!(
+                    "{} signed record {} after being deactivated",
+                    signer.user_id, i
+                )));
+            }
+
+            keys.replay(record, &roles);
+            roles.replay(record);
+        }
+
+        for checkpoint in checkpoints {
+            self.check_checkpoint(checkpoint, self.records.len(), &roles, &keys)?;
         }
         Ok(true)
     }
+
+    // for `verify_chain`, which has the keys in force at `size` at hand
+    fn check_checkpoint(
+        &self,
+        checkpoint: &Checkpoint,
+        size: usize,
+        roles: &Roles,
+        keys: &Keys,
+    ) -> Result<(), LedgerError> {
+        let verified = if checkpoint.tree_size() == size {
+            self.verify_checkpoint_with(checkpoint, roles, keys)
+        } else {
+            self.verify_checkpoint(checkpoint)
+        };
+
+        verified.map(|_| ()).map_err(|e| {
+            LedgerError::ChainValidation(format!(
+                "Checkpoint at size {} failed: {}",
+                checkpoint.tree_size(),
+                e
+            ))
+        })
+    }
 }
 
 impl Default for Ledger {
@@ -199,6 +711,9 @@ mod tests {
     #![allow(clippy::assertions_on_result_states)]
 
     use super::*;
+    use crate::core::keys::Compromised;
+    use crate::core::roles::ADMIN_ROLE;
+    use crate::test_support::{forge, grant_roles};
 
     #[test]
     fn test_ledger_init() {
@@ -221,7 +736,7 @@ mod tests {
         assert_eq!(ledger.length(), 2);
 
         let added_record = &ledger.records[1];
-        assert_eq!(added_record.payload, "test payload");
+        assert_eq!(added_record.payload.as_text(), Some("test payload"));
         assert_eq!(added_record.index, 1);
 
         // adding record with unregistered user
@@ -274,13 +789,212 @@ mod tests {
         ledger.add_record("sell 50", vec![test_signer3]).unwrap();
 
         // Tamper with data
-        ledger.records[1].payload = "evil data".to_string();
+        ledger.records[1].payload = Payload::text("evil data");
 
         // Tampered chain should fail verification
         let result = ledger.verify_chain();
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_verify_chain_metadata_tampered() {
+        let mut ledger = Ledger::new();
+        let user = User::new("user1");
+        ledger.register_user(user.clone());
+
+        // entity records only go in as checked transitions
+        let tagged = Payload::json(serde_json::json!({"bid": 1200})).with_entity(
+            "T-1",
+            "tender",
+            "submit_bid",
+        );
+        assert!(matches!(
+            ledger.add_record(tagged, vec![user.clone()]),
+            Err(LedgerError::WorkflowRejected(_))
+        ));
+
+        let payload = Payload::json(serde_json::json!({"bid": 1200})).with_tag("region", "gauteng");
+        ledger.add_record(payload, vec![user]).unwrap();
+        assert!(ledger.verify_chain().unwrap());
+
+        // body untouched, only the envelope changes
+        ledger.records[1]
+            .payload
+            .tags
+            .insert("region".to_string(), "limpopo".to_string());
+        assert!(ledger.verify_chain().is_err());
+    }
+
+    #[test]
+    fn test_inclusion_proof() {
+        let mut ledger = Ledger::new();
+        let user = User::new("user1");
+        ledger.register_user(user.clone());
+
+        for i in 0..6 {
+            ledger
+                .add_record(format!("tender {}", i).as_str(), vec![user.clone()])
+                .unwrap();
+        }
+
+        for index in 0..ledger.length() {
+            let proof = ledger.inclusion_proof(index).unwrap();
+            assert_eq!(proof.tree_size, 7);
+            assert!(merkle::verify_inclusion(&proof).unwrap());
+        }
+
+        assert!(matches!(
+            ledger.inclusion_proof(7),
+            Err(LedgerError::IndexOutOfRange(7, 7))
+        ));
+
+        // proof for a record that isn't in the ledger
+        let mut forged = ledger.inclusion_proof(3).unwrap();
+        forged.record_hash = digest("forged");
+        assert!(merkle::verify_inclusion(&forged).is_err());
+
+        // signed head must match the proof it travels with
+        let operator = User::new("operator");
+        let mut proof = ledger.inclusion_proof(2).unwrap();
+        proof.signed_tree_head = Some(ledger.tree_head().sign(&operator));
+        assert!(merkle::verify_inclusion(&proof).unwrap());
+
+        ledger.add_record("later", vec![user]).unwrap();
+        proof.signed_tree_head = Some(ledger.tree_head().sign(&operator));
+        assert!(merkle::verify_inclusion(&proof).is_err());
+    }
+
+    #[test]
+    fn test_consistency_proof() {
+        let mut ledger = Ledger::new();
+        let user = User::new("user1");
+        ledger.register_user(user.clone());
+
+        ledger.add_record("bid 1", vec![user.clone()]).unwrap();
+        ledger.add_record("bid 2", vec![user.clone()]).unwrap();
+        let seen_root = hex::encode(ledger.merkle_root());
+        let seen_size = ledger.length();
+
+        for i in 3..8 {
+            ledger
+                .add_record(format!("bid {}", i).as_str(), vec![user.clone()])
+                .unwrap();
+        }
+
+        let proof = ledger
+            .consistency_proof(seen_size, ledger.length())
+            .unwrap();
+        assert_eq!(proof.old_root, seen_root);
+        assert!(merkle::verify_consistency(&proof, Some(&seen_root)).unwrap());
+
+        // someone who saw a different history at that size
+        let other_root = hex::encode(merkle::leaf_hash(b"other"));
+        assert!(merkle::verify_consistency(&proof, Some(&other_root)).is_err());
+
+        assert!(ledger.consistency_proof(0, 3).is_err());
+        assert!(ledger.consistency_proof(4, 3).is_err());
+        assert!(ledger.consistency_proof(3, 99).is_err());
+    }
+
+    #[test]
+    fn test_checkpoints() {
+        let mut ledger = Ledger::new();
+        let operator = User::new("operator");
+        let witness = User::new("auditor");
+        let user = User::new("user1");
+        ledger.register_user(user.clone());
+        ledger.designate_operator(&operator).unwrap();
+        assert_eq!(ledger.operator_id().as_deref(), Some("operator"));
+
+        ledger.add_record("day one", vec![user.clone()]).unwrap();
+        let first = ledger.create_checkpoint(&operator).unwrap();
+        assert_eq!(first.tree_size(), 3);
+
+        ledger.add_record("day two", vec![user.clone()]).unwrap();
+        ledger.create_checkpoint(&operator).unwrap();
+
+        // witnesses don't need to be registered
+        let cosigned = ledger.cosign_checkpoint(3, &witness).unwrap();
+        assert_eq!(cosigned.witnesses.len(), 1);
+        assert_eq!(ledger.checkpoints.len(), 2);
+        assert_eq!(ledger.latest_checkpoint().unwrap().tree_size(), 4);
+        assert!(ledger.verify_chain().unwrap());
+
+        // only the designated operator can checkpoint
+        assert!(ledger.create_checkpoint(&user).is_err());
+    }
+
+    #[test]
+    fn test_operator_designation() {
+        let operator = User::new("operator");
+        let user = User::new("user1");
+
+        // without a designation even the first checkpoint is refused
+        let mut ledger = Ledger::new();
+        ledger.register_user(user.clone());
+        assert!(matches!(
+            ledger.create_checkpoint(&user),
+            Err(LedgerError::InvalidProof(_))
+        ));
+
+        // a ledger that never named one names one at its tip
+        ledger.add_record("day one", vec![user.clone()]).unwrap();
+        ledger.designate_operator(&operator).unwrap();
+        assert_eq!(ledger.operator_id().as_deref(), Some("operator"));
+        ledger.create_checkpoint(&operator).unwrap();
+        assert!(ledger.verify_chain().unwrap());
+
+        // but only once
+        assert!(matches!(
+            ledger.designate_operator(&user),
+            Err(LedgerError::OperatorRejected(_))
+        ));
+        assert_eq!(ledger.operator_id().as_deref(), Some("operator"));
+
+        // by the operator themselves
+        let mut ledger = Ledger::new();
+        ledger.register_user(user.clone());
+        ledger.register_user(operator.clone());
+        assert!(matches!(
+            ledger.add_record(
+                OperatorDesignation::new("operator").to_payload(),
+                vec![user.clone()]
+            ),
+            Err(LedgerError::OperatorRejected(_))
+        ));
+
+        // a second designation forged in doesn't count
+        ledger.designate_operator(&operator).unwrap();
+        let last = ledger.records.last().unwrap();
+        let forged = Record::new(
+            last.index + 1,
+            OperatorDesignation::new("user1").to_payload(),
+            &last.record_hash,
+            vec![user.clone()],
+        );
+        ledger.records.push(forged);
+        ledger.rebuild_state();
+        assert_eq!(ledger.operator_id().as_deref(), Some("operator"));
+        assert!(ledger.create_checkpoint(&user).is_err());
+    }
+
+    #[test]
+    fn test_checkpoint_detects_rewritten_history() {
+        let mut ledger = Ledger::new();
+        let operator = User::new("operator");
+        ledger.designate_operator(&operator).unwrap();
+        ledger
+            .add_record("original", vec![operator.clone()])
+            .unwrap();
+        ledger.create_checkpoint(&operator).unwrap();
+
+        // rebuild the record consistently, only a checkpoint can notice
+        let prev_hash = ledger.records[1].record_hash.clone();
+        ledger.records[2] = Record::new(2, "rewritten", &prev_hash, vec![operator]);
+
+        assert!(ledger.verify_chain().is_err());
+    }
+
     #[test]
     fn test_error_handling() {
         let mut ledger = Ledger::new();
@@ -350,8 +1064,507 @@ mod tests {
 
         assert_eq!(ledger.length(), 4);
 
-        ledger.records[2].payload = "HACKED!".to_string();
+        ledger.records[2].payload = Payload::text("HACKED!");
         let result = ledger.verify_chain();
         assert!(result.is_err());
     }
+
+    fn approval_engine() -> Engine {
+        let mut engine = Engine::new();
+        engine
+            .load_workflow_from_json(serde_json::json!({
+                "id": "payment",
+                "name": "Payment",
+                "description": "Payment approval",
+                "initial_state": "requested",
+                "states": [
+                    {"id": "requested", "label": "Requested"},
+                    {"id": "approved", "label": "Approved"},
+                    {"id": "paid", "label": "Paid"}
+                ],
+                "transitions": [
+                    {
+                        "from_state": "requested",
+                        "to_state": "approved",
+                        "name": "approve",
+                        "required_roles": ["approver"]
+                    },
+                    {
+                        "from_state": "approved",
+                        "to_state": "paid",
+                        "name": "pay",
+                        "required_roles": ["finance"]
+                    }
+                ]
+            }))
+            .unwrap();
+        engine
+    }
+
+    #[test]
+    fn test_add_transition_record() {
+        let engine = approval_engine();
+        let mut ledger = Ledger::new();
+
+        let approver = User::new("approver");
+        let finance = User::new("finance");
+        ledger.register_user(approver.clone());
+        ledger.register_user(finance.clone());
+        grant_roles(
+            &mut ledger,
+            &[("approver", "approver"), ("finance", "finance")],
+        );
+
+        let approve = TransitionMetadata::new("P-1", "payment", "approve")
+            .with_data(serde_json::json!({"amount": 100}));
+        let index = ledger
+            .add_transition_record(&engine, approve.to_payload(), vec![approver])
+            .unwrap();
+        assert_eq!(index, 5);
+
+        let stored = TransitionMetadata::from_payload(&ledger.records[5].payload).unwrap();
+        assert_eq!(ledger.records[5].payload.entity_id.as_deref(), Some("P-1"));
+        assert_eq!(stored.entity_id, "P-1");
+        assert_eq!(stored.data.unwrap()["amount"], 100);
+
+        let pay = TransitionMetadata::new("P-1", "payment", "pay");
+        ledger
+            .add_transition_record(&engine, pay.to_payload(), vec![finance])
+            .unwrap();
+
+        assert_eq!(ledger.length(), 7);
+        assert!(ledger.verify_chain().unwrap());
+    }
+
+    #[test]
+    fn test_entity_states_follow_appends() {
+        let engine = approval_engine();
+        let mut ledger = Ledger::new();
+
+        let approver = User::new("approver");
+        let finance = User::new("finance");
+        ledger.register_user(approver.clone());
+        ledger.register_user(finance.clone());
+        grant_roles(
+            &mut ledger,
+            &[("approver", "approver"), ("finance", "finance")],
+        );
+
+        for entity_id in ["P-1", "P-2"] {
+            let approve = TransitionMetadata::new(entity_id, "payment", "approve");
+            ledger
+                .add_transition_record(&engine, approve.to_payload(), vec![approver.clone()])
+                .unwrap();
+        }
+        ledger
+            .add_record("unrelated", vec![approver.clone()])
+            .unwrap();
+        let before_pay = ledger.length();
+        let pay = TransitionMetadata::new("P-1", "payment", "pay");
+        ledger
+            .add_transition_record(&engine, pay.to_payload(), vec![finance.clone()])
+            .unwrap();
+
+        let cached = ledger.entities.as_ref().unwrap();
+        let projected = StateProjector::new(&engine).project(&ledger);
+        assert_eq!(cached.len(), 2);
+        for (entity_id, state) in &projected {
+            assert_eq!(cached[entity_id].current_state, state.current_state);
+            assert_eq!(cached[entity_id].history.len(), state.history.len());
+        }
+        assert_eq!(cached["P-1"].current_state, "paid");
+
+        // dropping the payment puts P-1 back where it was
+        ledger.truncate(before_pay);
+        assert!(ledger.entities.is_none());
+        let approve = TransitionMetadata::new("P-1", "payment", "approve");
+        assert!(
+            ledger
+                .add_transition_record(&engine, approve.to_payload(), vec![approver])
+                .is_err()
+        );
+        ledger
+            .add_transition_record(&engine, pay.to_payload(), vec![finance])
+            .unwrap();
+    }
+
+    #[test]
+    fn test_transition_roles_checked_by_name() {
+        let mut engine = Engine::new();
+        engine
+            .load_workflow_from_json(serde_json::json!({
+                "id": "payment",
+                "name": "Payment",
+                "description": "Small payments by a clerk, large ones by a manager",
+                "initial_state": "requested",
+                "states": [
+                    {"id": "requested", "label": "Requested"},
+                    {"id": "approved", "label": "Approved"}
+                ],
+                "transitions": [
+                    {
+                        "from_state": "requested",
+                        "to_state": "approved",
+                        "name": "approve_small",
+                        "required_roles": ["clerk"]
+                    },
+                    {
+                        "from_state": "requested",
+                        "to_state": "approved",
+                        "name": "approve_large",
+                        "required_roles": ["manager"]
+                    }
+                ]
+            }))
+            .unwrap();
+        let mut ledger = Ledger::new();
+
+        let clerk = User::new("clerk");
+        let manager = User::new("manager");
+        ledger.register_user(clerk.clone());
+        ledger.register_user(manager.clone());
+        grant_roles(&mut ledger, &[("clerk", "clerk"), ("manager", "manager")]);
+
+        let large = TransitionMetadata::new("P-1", "payment", "approve_large");
+        let result = ledger.add_transition_record(&engine, large.to_payload(), vec![clerk.clone()]);
+        assert!(matches!(result, Err(LedgerError::WorkflowRejected(_))));
+
+        ledger
+            .add_transition_record(&engine, large.to_payload(), vec![manager])
+            .unwrap();
+        let small = TransitionMetadata::new("P-2", "payment", "approve_small");
+        ledger
+            .add_transition_record(&engine, small.to_payload(), vec![clerk])
+            .unwrap();
+    }
+
+    #[test]
+    fn test_add_transition_record_rejected() {
+        let engine = approval_engine();
+        let mut ledger = Ledger::new();
+
+        let approver = User::new("approver");
+        // claims the role locally, the ledger never granted it
+        let mut clerk = User::new("clerk");
+        clerk.add_role("approver");
+        ledger.register_user(approver.clone());
+        ledger.register_user(clerk.clone());
+        grant_roles(&mut ledger, &[("approver", "approver")]);
+
+        // not allowed from the initial state
+        let result = ledger.add_transition_record(
+            &engine,
+            TransitionMetadata::new("P-1", "payment", "pay").to_payload(),
+            vec![approver.clone()],
+        );
+        assert!(matches!(result, Err(LedgerError::WorkflowRejected(_))));
+
+        // signer lacks the approver role
+        let result = ledger.add_transition_record(
+            &engine,
+            TransitionMetadata::new("P-1", "payment", "approve").to_payload(),
+            vec![clerk],
+        );
+        assert!(matches!(result, Err(LedgerError::WorkflowRejected(_))));
+
+        // untagged payload
+        let result =
+            ledger.add_transition_record(&engine, Payload::text("hi"), vec![approver.clone()]);
+        assert!(matches!(result, Err(LedgerError::WorkflowRejected(_))));
+
+        // unknown workflow
+        let result = ledger.add_transition_record(
+            &engine,
+            TransitionMetadata::new("P-1", "missing", "approve").to_payload(),
+            vec![approver],
+        );
+        assert!(matches!(result, Err(LedgerError::WorkflowRejected(_))));
+
+        assert_eq!(ledger.length(), 4);
+    }
+
+    #[test]
+    fn test_role_records() {
+        let mut ledger = Ledger::new();
+        let approver = User::new("approver");
+        ledger.register_user(approver.clone());
+        let admin = grant_roles(&mut ledger, &[("approver", "approver")]);
+
+        // only admins hand out roles, and only to registered users
+        let result = ledger.add_record(
+            RoleChange::grant("approver", "finance").to_payload(),
+            vec![approver.clone()],
+        );
+        assert!(matches!(result, Err(LedgerError::RoleRejected(_))));
+        let result = ledger.add_record(
+            RoleChange::grant("stranger", "finance").to_payload(),
+            vec![admin.clone()],
+        );
+        assert!(matches!(result, Err(LedgerError::RoleRejected(_))));
+
+        let mut malformed = Payload::json(serde_json::json!({"role": "finance"}));
+        malformed.content_type = ROLE_CONTENT_TYPE.to_string();
+        let result = ledger.add_record(malformed, vec![admin.clone()]);
+        assert!(matches!(result, Err(LedgerError::RoleRejected(_))));
+
+        let revoked = ledger
+            .add_record(
+                RoleChange::revoke("approver", "approver").to_payload(),
+                vec![admin],
+            )
+            .unwrap();
+
+        // roles at each index only count the records before it
+        assert!(ledger.roles_at(3).of("approver").is_empty());
+        assert!(ledger.roles_at(4).has("approver", "approver"));
+        assert!(ledger.roles_at(revoked).has("approver", "approver"));
+        assert!(!ledger.roles().has("approver", "approver"));
+        assert!(ledger.roles().has("admin", ADMIN_ROLE));
+        assert!(ledger.verify_chain().unwrap());
+    }
+
+    #[test]
+    fn test_roles_bootstrap_without_operator() {
+        let mut ledger = Ledger::new();
+        let admin = User::new("admin");
+        ledger.register_user(admin.clone());
+        ledger.add_record("day one", vec![admin.clone()]).unwrap();
+
+        // nobody can grant the first admin until an operator is named
+        let grant = RoleChange::grant("admin", ADMIN_ROLE).to_payload();
+        let result = ledger.add_record(grant.clone(), vec![admin.clone()]);
+        assert!(matches!(result, Err(LedgerError::RoleRejected(_))));
+
+        ledger.designate_operator(&admin).unwrap();
+        ledger.add_record(grant, vec![admin]).unwrap();
+        assert!(ledger.roles().has("admin", ADMIN_ROLE));
+        assert!(ledger.verify_chain().unwrap());
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let mut ledger = Ledger::new();
+        let old = User::new("clerk");
+        ledger.designate_operator(&old).unwrap();
+        ledger
+            .add_record("signed with the old key", vec![old.clone()])
+            .unwrap();
+        ledger.create_checkpoint(&old).unwrap();
+
+        // the new key has to be endorsed, by the old one here
+        let new = User::new("clerk");
+        let result = ledger.add_record(KeyRotation::new(&new).to_payload(), vec![new.clone()]);
+        assert!(matches!(result, Err(LedgerError::KeyRejected(_))));
+        let rotated = ledger
+            .add_record(KeyRotation::new(&new).to_payload(), vec![old.clone()])
+            .unwrap();
+
+        let result = ledger.add_record("old key again", vec![old.clone()]);
+        assert!(matches!(result, Err(LedgerError::KeyRejected(_))));
+        ledger
+            .add_record("signed with the new key", vec![new.clone()])
+            .unwrap();
+        ledger.create_checkpoint(&new).unwrap();
+
+        assert_eq!(
+            ledger.keys_at(rotated).key("clerk"),
+            Some(&old.verifying_key)
+        );
+        assert_eq!(ledger.keys().key("clerk"), Some(&new.verifying_key));
+        // history still verifies, each record against the key of its time
+        assert!(ledger.verify_chain().unwrap());
+    }
+
+    #[test]
+    fn test_appends_keep_state_in_sync() {
+        let in_sync = |ledger: &Ledger| {
+            let (roles, keys) = ledger.replay_to(ledger.length());
+            for user_id in ledger.verify_registry.keys() {
+                assert_eq!(ledger.roles().of(user_id), roles.of(user_id));
+                assert_eq!(ledger.keys().key(user_id), keys.key(user_id));
+            }
+        };
+
+        let mut ledger = Ledger::new();
+        let clerk = User::new("clerk");
+        ledger.register_user(clerk.clone());
+        grant_roles(&mut ledger, &[("clerk", "approver")]);
+        in_sync(&ledger);
+        assert!(ledger.roles().has("clerk", "approver"));
+
+        let rotated = User::new("clerk");
+        ledger
+            .add_record(KeyRotation::new(&rotated).to_payload(), vec![clerk.clone()])
+            .unwrap();
+        in_sync(&ledger);
+        assert_eq!(ledger.keys().key("clerk"), Some(&rotated.verifying_key));
+
+        // rolled back, as a failed batch does
+        ledger.truncate(ledger.length() - 1);
+        in_sync(&ledger);
+        assert_eq!(ledger.keys().key("clerk"), Some(&clerk.verifying_key));
+
+        // registered again under another key, history is checked against it
+        let rekeyed = User::new("clerk");
+        ledger.register_user(rekeyed.clone());
+        in_sync(&ledger);
+        ledger.add_record("rekeyed", vec![rekeyed]).unwrap();
+        assert!(ledger.add_record("old key", vec![clerk]).is_err());
+    }
+
+    #[test]
+    fn test_key_rotation_by_admin_quorum() {
+        let mut ledger = Ledger::new();
+        let clerk = User::new("clerk");
+        let second = User::new("second");
+        ledger.register_user(clerk.clone());
+        ledger.register_user(second.clone());
+        let admin = grant_roles(&mut ledger, &[("second", ADMIN_ROLE)]);
+
+        // the old key is lost, both admins vouch for the new one
+        let new = User::new("clerk");
+        let result = ledger.add_record(KeyRotation::new(&new).to_payload(), vec![admin.clone()]);
+        assert!(matches!(result, Err(LedgerError::KeyRejected(_))));
+        ledger
+            .add_record(KeyRotation::new(&new).to_payload(), vec![admin, second])
+            .unwrap();
+
+        ledger.add_record("back in", vec![new]).unwrap();
+        assert!(ledger.verify_chain().unwrap());
+    }
+
+    #[test]
+    fn test_key_revocation() {
+        let mut ledger = Ledger::new();
+        let clerk = User::new("clerk");
+        ledger.register_user(clerk.clone());
+        let admin = grant_roles(&mut ledger, &[]);
+
+        ledger
+            .add_record("before the leak", vec![clerk.clone()])
+            .unwrap();
+        let leaked = ledger
+            .add_record("after the leak", vec![clerk.clone()])
+            .unwrap();
+        ledger
+            .add_record("cosigned", vec![admin.clone(), clerk.clone()])
+            .unwrap();
+
+        // only the owner or the admins revoke a key
+        let revocation =
+            KeyRevocation::new("clerk", &clerk.verifying_key, Compromised::Index(leaked));
+        let stranger = User::new("stranger");
+        ledger.register_user(stranger.clone());
+        let result = ledger.add_record(revocation.to_payload(), vec![stranger]);
+        assert!(matches!(result, Err(LedgerError::KeyRejected(_))));
+        ledger
+            .add_record(revocation.to_payload(), vec![admin.clone()])
+            .unwrap();
+
+        let result = ledger.add_record("still signing", vec![clerk.clone()]);
+        assert!(matches!(result, Err(LedgerError::KeyRejected(_))));
+
+        // the admins hand out a new key, it signs as usual
+        let new = User::new("clerk");
+        ledger
+            .add_record(KeyRotation::new(&new).to_payload(), vec![admin])
+            .unwrap();
+        ledger.add_record("new key", vec![new]).unwrap();
+
+        // the owner revoking their own key doesn't count as a use of it
+        let courier = User::new("courier");
+        ledger.register_user(courier.clone());
+        let leaked_too = ledger
+            .add_record("courier leak", vec![courier.clone()])
+            .unwrap();
+        let revocation = KeyRevocation::new(
+            "courier",
+            &courier.verifying_key,
+            Compromised::Index(leaked_too),
+        );
+        ledger
+            .add_record(revocation.to_payload(), vec![courier])
+            .unwrap();
+
+        let compromised = ledger.compromised_records();
+        let indices: Vec<usize> = compromised.iter().map(|c| c.index).collect();
+        assert_eq!(indices, vec![leaked, leaked + 1, leaked_too]);
+        assert!(compromised[..2].iter().all(|c| c.user_id == "clerk"));
+        assert!(ledger.verify_chain().unwrap());
+    }
+
+    #[test]
+    fn test_deactivation() {
+        let mut ledger = Ledger::new();
+        let clerk = User::new("clerk");
+        ledger.register_user(clerk.clone());
+        let admin = grant_roles(&mut ledger, &[]);
+        ledger
+            .add_record("still here", vec![clerk.clone()])
+            .unwrap();
+
+        // no backdating, and admins give up the role first
+        let next = ledger.length();
+        let backdated = Deactivation::new("clerk", "left", next - 1);
+        let result = ledger.add_record(backdated.to_payload(), vec![admin.clone()]);
+        assert!(matches!(result, Err(LedgerError::KeyRejected(_))));
+        let result = ledger.add_record(
+            Deactivation::new("admin", "left", next).to_payload(),
+            vec![admin.clone()],
+        );
+        assert!(matches!(result, Err(LedgerError::KeyRejected(_))));
+
+        // a last record before it takes effect
+        ledger
+            .add_record(
+                Deactivation::new("clerk", "left the company", next + 2).to_payload(),
+                vec![admin.clone()],
+            )
+            .unwrap();
+        ledger
+            .add_record("handing over", vec![clerk.clone()])
+            .unwrap();
+
+        let result = ledger.add_record("gone", vec![clerk.clone()]);
+        assert!(matches!(result, Err(LedgerError::KeyRejected(_))));
+        let result = ledger.add_record(
+            KeyRotation::new(&User::new("clerk")).to_payload(),
+            vec![admin],
+        );
+        assert!(matches!(result, Err(LedgerError::KeyRejected(_))));
+
+        let keys = ledger.keys();
+        assert_eq!(
+            keys.deactivation("clerk").unwrap().reason,
+            "left the company"
+        );
+        assert!(keys.is_active("clerk", next + 1));
+        assert!(!keys.is_active("clerk", next + 2));
+        assert!(ledger.verify_chain().unwrap());
+    }
+
+    #[test]
+    fn test_deactivated_signer_fails_verification() {
+        let mut ledger = Ledger::new();
+        let clerk = User::new("clerk");
+        ledger.register_user(clerk.clone());
+        let admin = grant_roles(&mut ledger, &[]);
+
+        let next = ledger.length();
+        ledger
+            .add_record(
+                Deactivation::new("clerk", "left", next + 2).to_payload(),
+                vec![admin],
+            )
+            .unwrap();
+        ledger.add_record("last one", vec![clerk.clone()]).unwrap();
+        assert!(ledger.verify_chain().unwrap());
+
+        // as if restored from an old file or written by another writer
+        forge(&mut ledger, Payload::text("gone"), vec![clerk]);
+        assert!(matches!(
+            ledger.verify_chain(),
+            Err(LedgerError::ChainValidation(_))
+        ));
+    }
 }
@@ -0,0 +1,518 @@
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+
+use crate::LedgerError;
+use crate::core::User;
+
+// RFC 6962 domain separation, so a leaf can never be passed off as a node
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+pub type MerkleHash = [u8; 32];
+
+fn sha256_raw(data: &[u8]) -> MerkleHash {
+    let mut out = [0u8; 32];
+    // digest always returns 64 hex chars so this can't fail
+    let _ = hex::decode_to_slice(sha256::digest(data), &mut out);
+    out
+}
+
+pub fn leaf_hash(data: &[u8]) -> MerkleHash {
+    let mut input = Vec::with_capacity(data.len() + 1);
+    input.push(LEAF_PREFIX);
+    input.extend_from_slice(data);
+    sha256_raw(&input)
+}
+
+pub fn node_hash(left: &MerkleHash, right: &MerkleHash) -> MerkleHash {
+    let mut input = Vec::with_capacity(65);
+    input.push(NODE_PREFIX);
+    input.extend_from_slice(left);
+    input.extend_from_slice(right);
+    sha256_raw(&input)
+}
+
+// largest power of two smaller than n, n must be > 1
+fn split_point(n: usize) -> usize {
+    let mut k = 1;
+    while k << 1 < n {
+        k <<= 1;
+    }
+    k
+}
+
+/// MTH(D[n]) over already hashed leaves.
+pub fn root(leaves: &[MerkleHash]) -> MerkleHash {
+    match leaves {
+        [] => sha256_raw(&[]),
+        [leaf] => *leaf,
+        _ => {
+            let (left, right) = leaves.split_at(split_point(leaves.len()));
+            node_hash(&root(left), &root(right))
+        }
+    }
+}
+
+/// PATH(m, D[n]), the audit path for the leaf at `index`.
+pub fn inclusion_path(index: usize, leaves: &[MerkleHash]) -> Vec<MerkleHash> {
+    if leaves.len() <= 1 {
+        return Vec::new();
+    }
+
+    let k = split_point(leaves.len());
+    let (left, right) = leaves.split_at(k);
+
+    let mut path;
+    if index < k {
+        path = inclusion_path(index, left);
+        path.push(root(right));
+    } else {
+        path = inclusion_path(index - k, right);
+        path.push(root(left));
+    }
+    path
+}
+
+/// Recomputes the root from a leaf and its audit path (RFC 9162 2.1.3.2).
+pub fn verify_inclusion_path(
+    index: usize,
+    tree_size: usize,
+    leaf: &MerkleHash,
+    path: &[MerkleHash],
+    expected_root: &MerkleHash,
+) -> bool {
+    if index >= tree_size {
+        return false;
+    }
+
+    let mut fnode = index;
+    let mut snode = tree_size - 1;
+    let mut hash = *leaf;
+
+    for sibling in path {
+        if snode == 0 {
+            return false;
+        }
+
+        if fnode & 1 == 1 || fnode == snode {
+            hash = node_hash(sibling, &hash);
+            while fnode & 1 == 0 && fnode != 0 {
+                fnode >>= 1;
+                snode >>= 1;
+            }
+        } else {
+            hash = node_hash(&hash, sibling);
+        }
+
+        fnode >>= 1;
+        snode >>= 1;
+    }
+
+    snode == 0 && hash == *expected_root
+}
+
+fn subproof(old_size: usize, leaves: &[MerkleHash], complete: bool) -> Vec<MerkleHash> {
+    let n = leaves.len();
+    if old_size == n {
+        return if complete {
+            Vec::new()
+        } else {
+            vec![root(leaves)]
+        };
+    }
+
+    let k = split_point(n);
+    let (left, right) = leaves.split_at(k);
+
+    let mut path;
+    if old_size <= k {
+        path = subproof(old_size, left, complete);
+        path.push(root(right));
+    } else {
+        path = subproof(old_size - k, right, false);
+        path.push(root(left));
+    }
+    path
+}
+
+/// PROOF(m, D[n]), shows the first `old_size` leaves are a prefix of `leaves`.
+pub fn consistency_path(old_size: usize, leaves: &[MerkleHash]) -> Vec<MerkleHash> {
+    if old_size == 0 || old_size > leaves.len() {
+        return Vec::new();
+    }
+    subproof(old_size, leaves, true)
+}
+
+/// Checks that the tree with `old_root` is a prefix of the tree with
+/// `new_root`, using nothing but the two roots and the path (RFC 9162 2.1.4.2).
+pub fn verify_consistency_path(
+    old_size: usize,
+    new_size: usize,
+    old_root: &MerkleHash,
+    new_root: &MerkleHash,
+    path: &[MerkleHash],
+) -> bool {
+    if old_size == 0 || old_size > new_size {
+        return false;
+    }
+
+    if old_size == new_size {
+        return path.is_empty() && old_root == new_root;
+    }
+
+    // a power of two old tree is a full subtree, its root starts the path
+    let mut nodes = Vec::with_capacity(path.len() + 1);
+    if old_size.is_power_of_two() {
+        nodes.push(*old_root);
+    }
+    nodes.extend_from_slice(path);
+
+    let Some((first, rest)) = nodes.split_first() else {
+        return false;
+    };
+
+    let mut fnode = old_size - 1;
+    let mut snode = new_size - 1;
+    while fnode & 1 == 1 {
+        fnode >>= 1;
+        snode >>= 1;
+    }
+
+    let mut old_hash = *first;
+    let mut new_hash = *first;
+
+    for node in rest {
+        if snode == 0 {
+            return false;
+        }
+
+        if fnode & 1 == 1 || fnode == snode {
+            old_hash = node_hash(node, &old_hash);
+            new_hash = node_hash(node, &new_hash);
+            while fnode & 1 == 0 && fnode != 0 {
+                fnode >>= 1;
+                snode >>= 1;
+            }
+        } else {
+            new_hash = node_hash(&new_hash, node);
+        }
+
+        fnode >>= 1;
+        snode >>= 1;
+    }
+
+    snode == 0 && old_hash == *old_root && new_hash == *new_root
+}
+
+fn decode_32(hex_str: &str) -> Result<MerkleHash, LedgerError> {
+    let mut out = [0u8; 32];
+    hex::decode_to_slice(hex_str, &mut out)
+        .map_err(|e| LedgerError::InvalidProof(format!("Bad hash {}: {}", hex_str, e)))?;
+    Ok(out)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TreeHead {
+    pub tree_size: usize,
+    pub root_hash: String,
+    pub timestamp: u64,
+}
+
+impl TreeHead {
+    fn material(&self) -> String {
+        format!("{} {} {}", self.tree_size, self.root_hash, self.timestamp)
+    }
+
+    pub fn sign(&self, signer: &User) -> SignedTreeHead {
+        let signature = signer.sign(self.material().as_bytes());
+        SignedTreeHead {
+            tree_head: self.clone(),
+            signer_id: signer.user_id.clone(),
+            verifying_key: hex::encode(signer.verifying_key.to_bytes()),
+            signature: hex::encode(signature.to_bytes()),
+        }
+    }
+}
+
+/// A tree head vouched for by one key.
+/// The key is carried along so the head can be checked offline, callers still
+/// have to decide whether they trust that key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedTreeHead {
+    pub tree_head: TreeHead,
+    pub signer_id: String,
+    pub verifying_key: String,
+    pub signature: String,
+}
+
+impl SignedTreeHead {
+    pub fn verifying_key(&self) -> Result<VerifyingKey, LedgerError> {
+        let key_bytes = decode_32(&self.verifying_key)?;
+        Ok(VerifyingKey::from_bytes(&key_bytes)?)
+    }
+
+    pub fn verify(&self, key: &VerifyingKey) -> Result<bool, LedgerError> {
+        let sig_bytes: [u8; 64] = hex::decode(&self.signature)
+            .map_err(|e| LedgerError::InvalidProof(format!("Bad signature: {}", e)))?
+            .try_into()
+            .map_err(|_| LedgerError::InvalidProof("Bad signature length".to_string()))?;
+
+        key.verify_strict(
+            self.tree_head.material().as_bytes(),
+            &Signature::from_bytes(&sig_bytes),
+        )?;
+        Ok(true)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InclusionProof {
+    pub index: usize,
+    pub tree_size: usize,
+    pub record_hash: String,
+    pub path: Vec<String>,
+    pub root_hash: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signed_tree_head: Option<SignedTreeHead>,
+}
+
+impl InclusionProof {
+    pub fn new(
+        index: usize,
+        record_hash: &str,
+        leaves: &[MerkleHash],
+    ) -> Result<Self, LedgerError> {
+        if index >= leaves.len() {
+            return Err(LedgerError::IndexOutOfRange(index, leaves.len()));
+        }
+
+        Ok(Self {
+            index,
+            tree_size: leaves.len(),
+            record_hash: record_hash.to_owned(),
+            path: inclusion_path(index, leaves)
+                .iter()
+                .map(hex::encode)
+                .collect(),
+            root_hash: hex::encode(root(leaves)),
+            signed_tree_head: None,
+        })
+    }
+}
+
+/// Checks a proof on its own, no ledger needed.
+/// When the proof carries a signed tree head, the head has to match the proof
+/// and its signature has to verify too.
+pub fn verify_inclusion(proof: &InclusionProof) -> Result<bool, LedgerError> {
+    let leaf = leaf_hash(proof.record_hash.as_bytes());
+    let root_hash = decode_32(&proof.root_hash)?;
+    let path = proof
+        .path
+        .iter()
+        .map(|h| decode_32(h))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if !verify_inclusion_path(proof.index, proof.tree_size, &leaf, &path, &root_hash) {
+        return Err(LedgerError::InvalidProof(format!(
+            "Record {} is not included in tree of size {}",
+            proof.index, proof.tree_size
+        )));
+    }
+
+    if let Some(sth) = &proof.signed_tree_head {
+        if sth.tree_head.tree_size != proof.tree_size || sth.tree_head.root_hash != proof.root_hash
+        {
+            return Err(LedgerError::InvalidProof(
+                "Signed tree head does not match proof".to_string(),
+            ));
+        }
+        sth.verify(&sth.verifying_key()?)?;
+    }
+
+    Ok(true)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConsistencyProof {
+    pub old_size: usize,
+    pub new_size: usize,
+    pub old_root: String,
+    pub new_root: String,
+    pub path: Vec<String>,
+}
+
+impl ConsistencyProof {
+    pub fn new(old_size: usize, leaves: &[MerkleHash]) -> Result<Self, LedgerError> {
+        if old_size == 0 || old_size > leaves.len() {
+            return Err(LedgerError::InvalidProof(format!(
+                "Cannot prove consistency from size {} to {}",
+                old_size,
+                leaves.len()
+            )));
+        }
+
+        let old_leaves = leaves.get(..old_size).unwrap_or_default();
+
+        Ok(Self {
+            old_size,
+            new_size: leaves.len(),
+            old_root: hex::encode(root(old_leaves)),
+            new_root: hex::encode(root(leaves)),
+            path: consistency_path(old_size, leaves)
+                .iter()
+                .map(hex::encode)
+                .collect(),
+        })
+    }
+}
+
+/// Checks a consistency proof on its own.
+/// A monitor should compare `old_root` against the root it saw earlier, pass
+/// it as `trusted_old_root` to have that checked here too.
+pub fn verify_consistency(
+    proof: &ConsistencyProof,
+    trusted_old_root: Option<&str>,
+) -> Result<bool, LedgerError> {
+    if let Some(trusted) = trusted_old_root
+        && !trusted.eq_ignore_ascii_case(&proof.old_root)
+    {
+        return Err(LedgerError::InvalidProof(format!(
+            "Old root {} does not match trusted root {}",
+            proof.old_root, trusted
+        )));
+    }
+
+    let old_root = decode_32(&proof.old_root)?;
+    let new_root = decode_32(&proof.new_root)?;
+    let path = proof
+        .path
+        .iter()
+        .map(|h| decode_32(h))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if !verify_consistency_path(proof.old_size, proof.new_size, &old_root, &new_root, &path) {
+        return Err(LedgerError::InvalidProof(format!(
+            "Tree of size {} is not an extension of tree of size {}",
+            proof.new_size, proof.old_size
+        )));
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::indexing_slicing)]
+
+    use super::*;
+
+    fn leaves(n: usize) -> Vec<MerkleHash> {
+        (0..n)
+            .map(|i| leaf_hash(format!("record {}", i).as_bytes()))
+            .collect()
+    }
+
+    #[test]
+    fn test_root_small_trees() {
+        let l = leaves(3);
+
+        assert_eq!(root(&l[..1]), l[0]);
+        assert_eq!(root(&l[..2]), node_hash(&l[0], &l[1]));
+        assert_eq!(
+            root(&l),
+            node_hash(&node_hash(&l[0], &l[1]), &l[2]) // unbalanced right side
+        );
+    }
+
+    #[test]
+    fn test_empty_root() {
+        // RFC 6962: MTH({}) = SHA-256()
+        assert_eq!(
+            hex::encode(root(&[])),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    #[test]
+    fn test_inclusion_all_sizes() {
+        for n in 1..=17 {
+            let l = leaves(n);
+            let r = root(&l);
+            for i in 0..n {
+                let path = inclusion_path(i, &l);
+                assert!(verify_inclusion_path(i, n, &l[i], &path, &r), "n={n} i={i}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_inclusion_rejects_wrong_leaf_or_index() {
+        let l = leaves(7);
+        let r = root(&l);
+        let path = inclusion_path(3, &l);
+
+        assert!(!verify_inclusion_path(3, 7, &l[4], &path, &r));
+        assert!(!verify_inclusion_path(4, 7, &l[3], &path, &r));
+        assert!(!verify_inclusion_path(3, 4, &l[3], &path, &r));
+        assert!(!verify_inclusion_path(7, 7, &l[3], &path, &r));
+    }
+
+    #[test]
+    fn test_consistency_all_sizes() {
+        let l = leaves(17);
+        for new_size in 1..=17 {
+            let new_root = root(&l[..new_size]);
+            for old_size in 1..=new_size {
+                let old_root = root(&l[..old_size]);
+                let path = consistency_path(old_size, &l[..new_size]);
+                assert!(
+                    verify_consistency_path(old_size, new_size, &old_root, &new_root, &path),
+                    "old={old_size} new={new_size}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_consistency_rejects_rewritten_history() {
+        let l = leaves(9);
+        let path = consistency_path(5, &l);
+        let new_root = root(&l);
+
+        let mut rewritten = l.clone();
+        rewritten[2] = leaf_hash(b"rewritten");
+        let forged_old_root = root(&rewritten[..5]);
+        assert!(!verify_consistency_path(
+            5,
+            9,
+            &forged_old_root,
+            &new_root,
+            &path
+        ));
+
+        // right roots, wrong sizes
+        let old_root = root(&l[..5]);
+        assert!(verify_consistency_path(5, 9, &old_root, &new_root, &path));
+        assert!(!verify_consistency_path(4, 9, &old_root, &new_root, &path));
+        assert!(!verify_consistency_path(5, 8, &old_root, &new_root, &path));
+        assert!(!verify_consistency_path(0, 9, &old_root, &new_root, &path));
+    }
+
+    #[test]
+    fn test_signed_tree_head() {
+        let operator = User::new("operator");
+        let head = TreeHead {
+            tree_size: 4,
+            root_hash: hex::encode(root(&leaves(4))),
+            timestamp: 1,
+        };
+
+        let mut signed = head.sign(&operator);
+        assert!(signed.verify(&operator.verifying_key).unwrap());
+
+        signed.tree_head.tree_size = 5;
+        assert!(signed.verify(&operator.verifying_key).is_err());
+    }
+}
@@ -1,7 +1,16 @@
+pub mod checkpoint;
+pub mod keys;
 pub mod ledger;
+pub mod merkle;
+pub mod payload;
 pub mod record;
+pub mod roles;
 pub mod user;
 
+pub use checkpoint::{Checkpoint, OperatorDesignation};
+pub use keys::{Deactivation, KeyRevocation, KeyRotation, Keys};
 pub use ledger::Ledger;
+pub use payload::{Payload, PayloadBody};
 pub use record::Record;
+pub use roles::{RoleChange, Roles};
 pub use user::User;
@@ -0,0 +1,235 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde_json::{Value, json};
+use sha256::digest;
+
+pub const TEXT_CONTENT_TYPE: &str = "text/plain";
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+pub const BINARY_CONTENT_TYPE: &str = "application/octet-stream";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PayloadBody {
+    Text(String),
+    Json(Value),
+    Binary(Vec<u8>),
+}
+
+impl PayloadBody {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PayloadBody::Text(_) => "text",
+            PayloadBody::Json(_) => "json",
+            PayloadBody::Binary(_) => "binary",
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match self {
+            PayloadBody::Text(text) => text.is_empty(),
+            PayloadBody::Json(value) => value.is_null(),
+            PayloadBody::Binary(bytes) => bytes.is_empty(),
+        }
+    }
+}
+
+/// Record contents plus the metadata that travels with them.
+/// Everything in here is covered by `payload_hash`, see `canonical_bytes`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Payload {
+    pub content_type: String,
+    pub entity_id: Option<String>,
+    pub workflow_id: Option<String>,
+    pub transition: Option<String>,
+    pub tags: BTreeMap<String, String>,
+    pub body: PayloadBody,
+    /// A plain string from before payloads had an envelope (1.x files). Those
+    /// were hashed on their own and keep that hash, see `legacy_text`.
+    pub legacy: bool,
+}
+
+impl Payload {
+    fn with_body(content_type: &str, body: PayloadBody) -> Self {
+        Self {
+            content_type: content_type.to_owned(),
+            entity_id: None,
+            workflow_id: None,
+            transition: None,
+            tags: BTreeMap::new(),
+            body,
+            legacy: false,
+        }
+    }
+
+    pub fn text(text: &str) -> Self {
+        Self::with_body(TEXT_CONTENT_TYPE, PayloadBody::Text(text.to_owned()))
+    }
+
+    /// A payload as 1.x stored it, text hashed with a bare `digest(text)`.
+    /// Only for reading old records, new ones always get the envelope.
+    pub fn legacy_text(text: &str) -> Self {
+        Self {
+            legacy: true,
+            ..Self::text(text)
+        }
+    }
+
+    pub fn json(value: Value) -> Self {
+        Self::with_body(JSON_CONTENT_TYPE, PayloadBody::Json(value))
+    }
+
+    pub fn binary(content_type: &str, bytes: Vec<u8>) -> Self {
+        Self::with_body(content_type, PayloadBody::Binary(bytes))
+    }
+
+    pub fn with_entity(mut self, entity_id: &str, workflow_id: &str, transition: &str) -> Self {
+        self.entity_id = Some(entity_id.to_owned());
+        self.workflow_id = Some(workflow_id.to_owned());
+        self.transition = Some(transition.to_owned());
+        self
+    }
+
+    pub fn with_tag(mut self, key: &str, value: &str) -> Self {
+        self.tags.insert(key.to_owned(), value.to_owned());
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.body.is_empty()
+    }
+
+    pub fn as_text(&self) -> Option<&str> {
+        match &self.body {
+            PayloadBody::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// Compact JSON with keys in sorted order and binary bodies hex encoded.
+    /// Two payloads that only differ in JSON whitespace or key order encode to
+    /// the same bytes, so they hash the same.
+    pub fn canonical_bytes(&self) -> Vec<u8> {
+        let body = match &self.body {
+            PayloadBody::Text(text) => Value::String(text.clone()),
+            PayloadBody::Json(value) => sorted(value),
+            PayloadBody::Binary(bytes) => Value::String(hex::encode(bytes)),
+        };
+
+        // keys are listed in sorted order here too
+        let envelope = json!({
+            "body": body,
+            "body_kind": self.body.kind(),
+            "content_type": self.content_type,
+            "entity_id": self.entity_id,
+            "tags": self.tags,
+            "transition": self.transition,
+            "workflow_id": self.workflow_id,
+        });
+
+        envelope.to_string().into_bytes()
+    }
+
+    pub fn hash(&self) -> String {
+        if self.legacy
+            && let PayloadBody::Text(text) = &self.body
+        {
+            return digest(text.as_str());
+        }
+        digest(self.canonical_bytes())
+    }
+}
+
+// rebuilds objects with keys inserted in order, so the output is sorted even
+// when serde_json is built with `preserve_order`
+fn sorted(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<(&String, &Value)> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key.clone(), sorted(value)))
+                    .collect(),
+            )
+        }
+        Value::Array(items) => Value::Array(items.iter().map(sorted).collect()),
+        other => other.clone(),
+    }
+}
+
+impl From<&str> for Payload {
+    fn from(text: &str) -> Self {
+        Payload::text(text)
+    }
+}
+
+impl From<Value> for Payload {
+    fn from(value: Value) -> Self {
+        Payload::json(value)
+    }
+}
+
+impl fmt::Display for Payload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.body {
+            PayloadBody::Text(text) => write!(f, "{}", text),
+            PayloadBody::Json(value) => write!(f, "{}", value),
+            PayloadBody::Binary(bytes) => {
+                write!(f, "<{} bytes of {}>", bytes.len(), self.content_type)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    #[test]
+    fn test_canonical_json_ignores_formatting() {
+        let a: Value = serde_json::from_str(r#"{"amount": 100, "currency": "ZAR"}"#).unwrap();
+        let b: Value = serde_json::from_str(r#"{ "currency":"ZAR","amount":100 }"#).unwrap();
+
+        assert_eq!(Payload::json(a).hash(), Payload::json(b).hash());
+    }
+
+    #[test]
+    fn test_metadata_changes_hash() {
+        let plain = Payload::text("bid received");
+        let tagged = Payload::text("bid received").with_tag("tender", "T-1");
+        let entity = Payload::text("bid received").with_entity("T-1", "tender", "receive_bid");
+
+        assert_ne!(plain.hash(), tagged.hash());
+        assert_ne!(plain.hash(), entity.hash());
+        assert_ne!(tagged.hash(), entity.hash());
+    }
+
+    #[test]
+    fn test_body_kind_changes_hash() {
+        // same characters, different meaning
+        let text = Payload::text("6869");
+        let binary = Payload::binary(TEXT_CONTENT_TYPE, b"hi".to_vec());
+
+        assert_ne!(text.hash(), binary.hash());
+    }
+
+    #[test]
+    fn test_legacy_text_keeps_bare_hash() {
+        let legacy = Payload::legacy_text("sell 50");
+
+        assert_eq!(legacy.hash(), digest("sell 50"));
+        assert_ne!(legacy.hash(), Payload::text("sell 50").hash());
+        assert_eq!(legacy.as_text(), Some("sell 50"));
+    }
+
+    #[test]
+    fn test_empty_bodies() {
+        assert!(Payload::text("").is_empty());
+        assert!(Payload::json(Value::Null).is_empty());
+        assert!(Payload::binary(BINARY_CONTENT_TYPE, vec![]).is_empty());
+        assert!(!Payload::json(json!({})).is_empty());
+    }
+}
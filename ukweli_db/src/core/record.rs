@@ -6,12 +6,12 @@ use std::{
 use ed25519_dalek::Signature;
 use sha256::digest;
 
-use crate::core::User;
+use crate::core::{Payload, User};
 
 #[derive(Clone, Debug)]
 pub struct Record {
     pub index: usize,
-    pub payload: String,
+    pub payload: Payload,
     pub payload_hash: String,
 
     pub signers: Vec<User>,
@@ -28,7 +28,13 @@ impl Record {
     #[allow(clippy::expect_used)]
     // Getting timestamp returns a result meaning I would have to propagate the error an errror I can't meaningfully handle
     // if this panics you have bigger issues than a panic hence why i'm using expect here
-    pub fn new(index: usize, payload: &str, prev_hash: &str, signers: Vec<User>) -> Self {
+    pub fn new(
+        index: usize,
+        payload: impl Into<Payload>,
+        prev_hash: &str,
+        signers: Vec<User>,
+    ) -> Self {
+        let payload = payload.into();
         let timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .expect("System clock is set before UNIX epoch")
@@ -36,7 +42,7 @@ impl Record {
 
         let nonce = rand::random();
 
-        let payload_hash = digest(payload);
+        let payload_hash = payload.hash();
         let joined_signers = signers
             .iter()
             .map(|u| u.user_id.clone())
@@ -58,7 +64,7 @@ impl Record {
 
         Self {
             index,
-            payload: payload.to_string(),
+            payload,
             payload_hash,
 
             signatures: record_signatures,
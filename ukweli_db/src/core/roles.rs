@@ -0,0 +1,292 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::LedgerError;
+use crate::core::{OperatorDesignation, Payload, PayloadBody, Record, User};
+
+/// Content type of records that grant or revoke a role.
+pub const ROLE_CONTENT_TYPE: &str = "application/vnd.ukweli.role+json";
+
+/// Holders of this role sign role changes.
+pub const ADMIN_ROLE: &str = "admin";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RoleAction {
+    Grant,
+    Revoke,
+}
+
+/// A role granted to or revoked from a user, carried by a record payload.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoleChange {
+    pub action: RoleAction,
+    pub user_id: String,
+    pub role: String,
+}
+
+impl RoleChange {
+    pub fn grant(user_id: &str, role: &str) -> Self {
+        Self {
+            action: RoleAction::Grant,
+            user_id: user_id.to_owned(),
+            role: role.to_owned(),
+        }
+    }
+
+    pub fn revoke(user_id: &str, role: &str) -> Self {
+        Self {
+            action: RoleAction::Revoke,
+            user_id: user_id.to_owned(),
+            role: role.to_owned(),
+        }
+    }
+
+    // `None` for records that aren't role changes, or don't parse as one
+    pub fn from_payload(payload: &Payload) -> Option<Self> {
+        if payload.content_type != ROLE_CONTENT_TYPE {
+            return None;
+        }
+        match &payload.body {
+            PayloadBody::Json(value) => serde_json::from_value(value.clone()).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn to_payload(&self) -> Payload {
+        let mut payload = Payload::json(serde_json::json!({
+            "action": self.action,
+            "user_id": self.user_id,
+            "role": self.role,
+        }));
+        payload.content_type = ROLE_CONTENT_TYPE.to_owned();
+        payload
+    }
+}
+
+/// Who holds which role, as the ledger's role records leave it at some index.
+///
+/// Nobody holds anything until the ledger operator, see `OperatorDesignation`,
+/// grants the first admin, to themselves or someone else. From then on every
+/// change needs an admin's signature, and the last admin can't be revoked, so
+/// the ledger can't be bootstrapped twice.
+#[derive(Debug, Clone, Default)]
+pub struct Roles {
+    held: HashMap<String, HashSet<String>>,
+    bootstrapped: bool,
+    operator: Option<String>,
+}
+
+impl Roles {
+    pub fn of(&self, user_id: &str) -> HashSet<String> {
+        self.held.get(user_id).cloned().unwrap_or_default()
+    }
+
+    pub fn has(&self, user_id: &str, role: &str) -> bool {
+        self.held
+            .get(user_id)
+            .is_some_and(|roles| roles.contains(role))
+    }
+
+    /// Every user holding at least one role.
+    pub fn users(&self) -> impl Iterator<Item = (&String, &HashSet<String>)> {
+        self.held.iter().filter(|(_, roles)| !roles.is_empty())
+    }
+
+    /// The user named by the first designation that counts, see
+    /// `OperatorDesignation`.
+    pub fn operator(&self) -> Option<&str> {
+        self.operator.as_deref()
+    }
+
+    pub fn admin_count(&self) -> usize {
+        self.held
+            .values()
+            .filter(|roles| roles.contains(ADMIN_ROLE))
+            .count()
+    }
+
+    /// Checks `signers` may make `change` from here.
+    pub fn authorize(&self, change: &RoleChange, signers: &[User]) -> Result<(), LedgerError> {
+        if signers.is_empty() {
+            return Err(LedgerError::NoSigners);
+        }
+
+        if !self.bootstrapped {
+            let Some(operator) = &self.operator else {
+                return Err(LedgerError::RoleRejected(format!(
+                    "The ledger names no operator to grant the first {}, designate one first",
+                    ADMIN_ROLE
+                )));
+            };
+            let by_operator = signers.iter().any(|s| s.user_id == *operator);
+            if change.action != RoleAction::Grant || change.role != ADMIN_ROLE || !by_operator {
+                return Err(LedgerError::RoleRejected(format!(
+                    "No admin yet, the first role record must be the operator {} granting {}",
+                    operator, ADMIN_ROLE
+                )));
+            }
+            return Ok(());
+        }
+
+        if !signers.iter().any(|s| self.has(&s.user_id, ADMIN_ROLE)) {
+            return Err(LedgerError::RoleRejected(format!(
+                "Role changes must be signed by an {}",
+                ADMIN_ROLE
+            )));
+        }
+
+        let holds = self.has(&change.user_id, &change.role);
+        match change.action {
+            RoleAction::Grant if holds => Err(LedgerError::RoleRejected(format!(
+                "{} already holds {}",
+                change.user_id, change.role
+            ))),
+            RoleAction::Revoke if !holds => Err(LedgerError::RoleRejected(format!(
+                "{} doesn't hold {}",
+                change.user_id, change.role
+            ))),
+            RoleAction::Revoke if change.role == ADMIN_ROLE && self.admin_count() == 1 => Err(
+                LedgerError::RoleRejected(format!("{} is the last {}", change.user_id, ADMIN_ROLE)),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Applies `change` without asking who signed it.
+    pub fn apply(&mut self, change: &RoleChange) {
+        match change.action {
+            RoleAction::Grant => {
+                if change.role == ADMIN_ROLE {
+                    self.bootstrapped = true;
+                }
+                self.held
+                    .entry(change.user_id.clone())
+                    .or_default()
+                    .insert(change.role.clone());
+            }
+            RoleAction::Revoke => {
+                if let Some(roles) = self.held.get_mut(&change.user_id) {
+                    roles.remove(&change.role);
+                }
+            }
+        }
+    }
+
+    /// Moves past `record`. Role changes its signers weren't allowed to make
+    /// are skipped, they can only get here by bypassing `Ledger::add_record`.
+    pub fn replay(&mut self, record: &Record) {
+        if let Some(designation) = OperatorDesignation::from_record(record, self.operator()) {
+            self.operator = Some(designation.user_id);
+        }
+        if let Some(change) = RoleChange::from_payload(&record.payload)
+            && self.authorize(&change, &record.signers).is_ok()
+        {
+            self.apply(&change);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    #[test]
+    fn test_role_change_payload() {
+        let change = RoleChange::grant("alice", "approver");
+        let payload = change.to_payload();
+
+        assert_eq!(payload.content_type, ROLE_CONTENT_TYPE);
+        assert_eq!(RoleChange::from_payload(&payload).unwrap(), change);
+        assert!(RoleChange::from_payload(&Payload::text("approver")).is_none());
+    }
+
+    // roles after the record naming `operator`
+    fn operated_by(operator: &User) -> Roles {
+        let designation = OperatorDesignation::new(&operator.user_id).to_payload();
+        let mut roles = Roles::default();
+        roles.replay(&Record::new(1, designation, "00", vec![operator.clone()]));
+        roles
+    }
+
+    #[test]
+    fn test_bootstrap_and_admin_rules() {
+        // signer lists
+        let alice = [User::new("alice")];
+        let bob = [User::new("bob")];
+
+        // without an operator nobody can bootstrap
+        assert!(
+            Roles::default()
+                .authorize(&RoleChange::grant("alice", ADMIN_ROLE), &alice)
+                .is_err()
+        );
+
+        // the operator grants the first admin and nothing else
+        let mut roles = operated_by(&alice[0]);
+        assert!(
+            roles
+                .authorize(&RoleChange::grant("alice", "approver"), &alice)
+                .is_err()
+        );
+
+        let bootstrap = RoleChange::grant("alice", ADMIN_ROLE);
+        roles.authorize(&bootstrap, &alice).unwrap();
+        roles.apply(&bootstrap);
+
+        // and nobody can bootstrap a second time
+        assert!(
+            roles
+                .authorize(&RoleChange::grant("bob", ADMIN_ROLE), &bob)
+                .is_err()
+        );
+
+        let grant = RoleChange::grant("bob", "approver");
+        roles.authorize(&grant, &alice).unwrap();
+        roles.apply(&grant);
+        assert!(roles.has("bob", "approver"));
+        assert!(roles.authorize(&grant, &alice).is_err());
+
+        assert!(
+            roles
+                .authorize(&RoleChange::revoke("alice", ADMIN_ROLE), &alice)
+                .is_err()
+        );
+
+        let revoke = RoleChange::revoke("bob", "approver");
+        roles.authorize(&revoke, &alice).unwrap();
+        roles.apply(&revoke);
+        assert!(roles.of("bob").is_empty());
+        assert_eq!(roles.users().count(), 1);
+    }
+
+    #[test]
+    fn test_only_operator_bootstraps() {
+        let operator = [User::new("operator")];
+        let mallory = [User::new("mallory")];
+        let roles = operated_by(&operator[0]);
+
+        // an ordinary user can't take admin before there is one
+        assert!(matches!(
+            roles.authorize(&RoleChange::grant("mallory", ADMIN_ROLE), &mallory),
+            Err(LedgerError::RoleRejected(_))
+        ));
+        roles
+            .authorize(&RoleChange::grant("mallory", ADMIN_ROLE), &operator)
+            .unwrap();
+
+        // once there is an operator, a later designation names nobody
+        let late = OperatorDesignation::new("mallory").to_payload();
+        let mut roles = operated_by(&operator[0]);
+        roles.replay(&Record::new(5, late, "00", mallory.to_vec()));
+        assert_eq!(roles.operator(), Some("operator"));
+        assert!(
+            roles
+                .authorize(&RoleChange::grant("mallory", ADMIN_ROLE), &mallory)
+                .is_err()
+        );
+    }
+}
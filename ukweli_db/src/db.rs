@@ -0,0 +1,1132 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::core::keys::CompromisedRecord;
+use crate::core::{Checkpoint, Ledger, Payload, Record, Roles, User};
+use crate::error::StorageError;
+use crate::storage::append::{AppendLog, WalTail};
+use crate::storage::backend::{self, Backend};
+use crate::storage::compaction::CompactionPolicy;
+use crate::storage::durability::{AppendStats, Durability, GroupCommit};
+use crate::storage::lock::DatabaseLock;
+use crate::storage::recovery::RecoveryManager;
+use crate::storage::writer::DatabaseWriter;
+use crate::workflow::Engine;
+
+// how often the compactor looks at the WAL's age when nothing is written
+const AGE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Shareable handle on one database, `Arc<UkweliDb>` it across threads.
+///
+/// Readers get the in-memory ledger behind an `RwLock` and never touch the disk.
+/// Writes are serialized twice: a mutex between threads of this handle and the
+/// exclusive `DatabaseLock` between handles and processes. Each write first
+/// catches up with the chain tip on disk, then changes the ledger and the WAL
+/// together. Readers only wait for that last step. The writer mutex also keeps
+/// where the WAL ended, so appends don't reread the whole log every time.
+///
+/// How durable an acknowledged write is depends on `with_durability`, the
+/// default flushes but doesn't fsync. With `Durability::None` the handle holds
+/// the exclusive lock from the first buffered write until `flush`, a batch or
+/// compaction writes the buffer out, or the handle is dropped. Once the WAL outgrows the
+/// `CompactionPolicy`, a background thread seals it into a new segment.
+///
+/// The plain constructors work on the filesystem, the `_in` ones on any
+/// `StorageBackend`, `in_memory` on a fresh memory backend.
+pub struct UkweliDb {
+    shared: Arc<Shared>,
+    durability: Durability,
+    group: Option<GroupCommit>,
+    stats: Mutex<AppendStats>,
+    policy: CompactionPolicy,
+    compactor: OnceLock<Compactor>,
+}
+
+// the part the background compactor works on too
+struct Shared {
+    backend: Backend,
+    path: PathBuf,
+    read_only: bool,
+    ledger: RwLock<Ledger>,
+    writer: Mutex<Writer>,
+    compaction_error: Mutex<Option<StorageError>>,
+}
+
+// what one write leaves the next, behind `Shared::writer`
+#[derive(Default)]
+struct Writer {
+    tail: Option<WalTail>,
+    // entries `Durability::None` buffered, and the lock that keeps everyone
+    // else off the WAL until they are written. Both are empty or neither is.
+    pending: Vec<u8>,
+    held: Option<Arc<DatabaseLock>>,
+}
+
+// the writer mutex and the exclusive lock, see `Shared::lock_for_write`
+struct WriteLock<'a> {
+    writer: MutexGuard<'a, Writer>,
+    // an `Arc` so dropping the guard can hand it back to the writer
+    lock: Arc<DatabaseLock>,
+}
+
+struct Compactor {
+    wake: SyncSender<()>,
+    thread: JoinHandle<()>,
+}
+
+impl UkweliDb {
+    /// Opens an existing database. A torn WAL tail is cut, but the WAL is only
+    /// compacted once writes through this handle make the policy ask for it.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StorageError> {
+        Self::open_in(&backend::filesystem(), path)
+    }
+
+    pub fn open_in<P: AsRef<Path>>(backend: &Backend, path: P) -> Result<Self, StorageError> {
+        let path = path.as_ref().to_path_buf();
+        let ledger = RecoveryManager::load_ledger_in(backend, &path)?;
+
+        Ok(Self::with_ledger(backend, path, ledger, false))
+    }
+
+    /// Opens an existing database without changing anything on disk, see
+    /// `RecoveryManager::read_ledger`. Writes through it fail with `ReadOnly`.
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<Self, StorageError> {
+        Self::open_read_only_in(&backend::filesystem(), path)
+    }
+
+    pub fn open_read_only_in<P: AsRef<Path>>(
+        backend: &Backend,
+        path: P,
+    ) -> Result<Self, StorageError> {
+        let path = path.as_ref().to_path_buf();
+        let ledger = RecoveryManager::read_ledger_in(backend, &path)?;
+
+        Ok(Self::with_ledger(backend, path, ledger, true)
+            .with_compaction(CompactionPolicy::manual()))
+    }
+
+    /// Writes a new database holding only the genesis record.
+    /// Fails if something already exists at `path`.
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, StorageError> {
+        Self::create_in(&backend::filesystem(), path)
+    }
+
+    pub fn create_in<P: AsRef<Path>>(backend: &Backend, path: P) -> Result<Self, StorageError> {
+        let path = path.as_ref().to_path_buf();
+
+        let lock = DatabaseLock::exclusive_in(backend, &path)?;
+        if backend.exists(&path)? {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("Database already exists at {}", path.display()),
+            )
+            .into());
+        }
+
+        let ledger = Ledger::new();
+        DatabaseWriter::new_in(backend, &path)?.write_ledger(&ledger)?;
+        drop(lock);
+
+        Ok(Self::with_ledger(backend, path, ledger, false))
+    }
+
+    /// A new database that only lives as long as the handle, for tests and
+    /// throwaway tools.
+    pub fn in_memory() -> Result<Self, StorageError> {
+        Self::create_in(&backend::memory(), "memory.ukweli")
+    }
+
+    fn with_ledger(backend: &Backend, path: PathBuf, ledger: Ledger, read_only: bool) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                backend: Backend::clone(backend),
+                path,
+                read_only,
+                ledger: RwLock::new(ledger),
+                writer: Mutex::new(Writer::default()),
+                compaction_error: Mutex::new(None),
+            }),
+            durability: Durability::default(),
+            group: None,
+            stats: Mutex::new(AppendStats::default()),
+            policy: CompactionPolicy::default(),
+            compactor: OnceLock::new(),
+        }
+    }
+
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.group = match durability {
+            Durability::GroupCommit { window, max_batch } => {
+                Some(GroupCommit::new(window, max_batch))
+            }
+            _ => None,
+        };
+        self.durability = durability;
+        self
+    }
+
+    pub fn durability(&self) -> Durability {
+        self.durability
+    }
+
+    pub fn with_compaction(mut self, policy: CompactionPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn compaction_policy(&self) -> CompactionPolicy {
+        self.policy
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.shared.read_only
+    }
+
+    /// Counts and timings of the writes made through this handle so far.
+    pub fn stats(&self) -> Result<AppendStats, StorageError> {
+        let mut stats = self
+            .stats
+            .lock()
+            .map_err(|_| StorageError::Poisoned)?
+            .clone();
+        if let Some(group) = &self.group {
+            stats.fsyncs += group.fsyncs()?;
+        }
+        Ok(stats)
+    }
+
+    /// Why the last background compaction failed, if it did. The WAL is left
+    /// as it was, the next write that finds compaction due tries again.
+    pub fn take_compaction_error(&self) -> Result<Option<StorageError>, StorageError> {
+        Ok(self
+            .shared
+            .compaction_error
+            .lock()
+            .map_err(|_| StorageError::Poisoned)?
+            .take())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.shared.path
+    }
+
+    pub fn backend(&self) -> &Backend {
+        &self.shared.backend
+    }
+
+    /// Read access to the whole ledger. Holding the guard holds up writers of
+    /// this handle, so don't keep it across slow work.
+    pub fn read(&self) -> Result<RwLockReadGuard<'_, Ledger>, StorageError> {
+        self.shared.read()
+    }
+
+    pub fn length(&self) -> Result<usize, StorageError> {
+        Ok(self.read()?.length())
+    }
+
+    pub fn record(&self, index: usize) -> Result<Option<Record>, StorageError> {
+        Ok(self.read()?.records.get(index).cloned())
+    }
+
+    pub fn user(&self, user_id: &str) -> Result<Option<User>, StorageError> {
+        Ok(self.read()?.users.get(user_id).cloned())
+    }
+
+    /// Roles as the ledger's role records leave them, see `Ledger::roles`.
+    pub fn roles(&self) -> Result<Roles, StorageError> {
+        Ok(self.read()?.roles().clone())
+    }
+
+    /// Records signed with keys revoked since, see `Ledger::compromised_records`.
+    pub fn compromised_records(&self) -> Result<Vec<CompromisedRecord>, StorageError> {
+        Ok(self.read()?.compromised_records())
+    }
+
+    pub fn latest_checkpoint(&self) -> Result<Option<Checkpoint>, StorageError> {
+        Ok(self.read()?.latest_checkpoint().cloned())
+    }
+
+    pub fn verify_chain(&self) -> Result<bool, StorageError> {
+        Ok(self.read()?.verify_chain()?)
+    }
+
+    pub fn register_user(&self, user: User) -> Result<(), StorageError> {
+        self.acknowledged(1, || {
+            let mut write = self.shared.lock_for_write()?;
+            let mut ledger = self.shared.write_ledger()?;
+
+            if ledger.verify_registry.contains_key(&user.user_id) {
+                return Err(StorageError::ValidationFailed(format!(
+                    "User '{}' is already registered",
+                    user.user_id
+                )));
+            }
+
+            ledger.register_user(user.clone());
+            self.persist(&mut write, &mut ledger, |log, _| log.append_user(&user))
+        })
+    }
+
+    pub fn append_record(
+        &self,
+        payload: impl Into<Payload>,
+        signers: Vec<User>,
+    ) -> Result<usize, StorageError> {
+        self.acknowledged(1, || {
+            let mut write = self.shared.lock_for_write()?;
+            let mut ledger = self.shared.write_ledger()?;
+
+            let index = ledger.add_record(payload, signers)?;
+            self.persist_record(&mut write, &mut ledger, index)
+        })
+    }
+
+    pub fn append_transition(
+        &self,
+        engine: &Engine,
+        payload: Payload,
+        signers: Vec<User>,
+    ) -> Result<usize, StorageError> {
+        self.acknowledged(1, || {
+            let mut write = self.shared.lock_for_write()?;
+            let mut ledger = self.shared.write_ledger()?;
+
+            let index = ledger.add_transition_record(engine, payload, signers)?;
+            self.persist_record(&mut write, &mut ledger, index)
+        })
+    }
+
+    pub fn create_checkpoint(&self, operator: &User) -> Result<Checkpoint, StorageError> {
+        self.acknowledged(1, || {
+            let mut write = self.shared.lock_for_write()?;
+            let mut ledger = self.shared.write_ledger()?;
+
+            let checkpoint = ledger.create_checkpoint(operator)?;
+            self.persist(&mut write, &mut ledger, |log, _| {
+                log.append_checkpoint(&checkpoint)
+            })?;
+            Ok(checkpoint)
+        })
+    }
+
+    pub fn cosign_checkpoint(
+        &self,
+        tree_size: usize,
+        witness: &User,
+    ) -> Result<Checkpoint, StorageError> {
+        self.acknowledged(1, || {
+            let mut write = self.shared.lock_for_write()?;
+            let mut ledger = self.shared.write_ledger()?;
+
+            let checkpoint = ledger.cosign_checkpoint(tree_size, witness)?;
+            self.persist(&mut write, &mut ledger, |log, _| {
+                log.append_checkpoint(&checkpoint)
+            })?;
+            Ok(checkpoint)
+        })
+    }
+
+    /// Starts a batch, nothing is written until `Batch::commit`.
+    pub fn begin(&self) -> Batch<'_> {
+        Batch {
+            db: self,
+            pending: Vec::new(),
+        }
+    }
+
+    fn commit_batch(&self, pending: Vec<PendingRecord<'_>>) -> Result<Vec<usize>, StorageError> {
+        if pending.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        self.acknowledged(pending.len(), || {
+            let mut write = self.shared.lock_for_write()?;
+            let mut ledger = self.shared.write_ledger()?;
+
+            let start = ledger.records.len();
+            let mut indices = Vec::with_capacity(pending.len());
+            for record in pending {
+                let added = match record {
+                    PendingRecord::Record(payload, signers) => ledger.add_record(payload, signers),
+                    PendingRecord::Transition(engine, payload, signers) => {
+                        ledger.add_transition_record(engine, payload, signers)
+                    }
+                };
+
+                match added {
+                    Ok(index) => indices.push(index),
+                    Err(e) => {
+                        // nothing is on disk yet, dropping the records is enough
+                        ledger.truncate(start);
+                        return Err(e.into());
+                    }
+                }
+            }
+
+            let records = ledger.records.get(start..).unwrap_or_default().to_vec();
+            self.persist(&mut write, &mut ledger, |log, lock| {
+                log.append_batch(&records, lock)
+            })?;
+            Ok(indices)
+        })
+    }
+
+    /// Seals the WAL into a new segment now, whatever the policy says. Readers
+    /// carry on meanwhile, compaction only needs to read the ledger.
+    pub fn compact(&self) -> Result<(), StorageError> {
+        self.shared.compact()
+    }
+
+    /// Writes out what `Durability::None` buffered and lets other writers
+    /// back in. The other modes write before acknowledging, for them it's a no-op.
+    pub fn flush(&self) -> Result<(), StorageError> {
+        self.shared.flush()
+    }
+
+    /// Reloads from disk, picking up whatever other handles or processes wrote.
+    pub fn refresh(&self) -> Result<(), StorageError> {
+        if self.shared.read_only {
+            let _writer = self
+                .shared
+                .writer
+                .lock()
+                .map_err(|_| StorageError::Poisoned)?;
+            *self.shared.write_ledger()? =
+                RecoveryManager::read_ledger_in(&self.shared.backend, &self.shared.path)?;
+            return Ok(());
+        }
+
+        let mut write = self.shared.lock_for_write()?;
+        self.shared.write_out(&mut write)?;
+        write.writer.tail = None;
+        *self.shared.write_ledger()? =
+            RecoveryManager::load_ledger_locked(&self.shared.path, &write.lock)?;
+        Ok(())
+    }
+
+    fn persist_record(
+        &self,
+        write: &mut WriteLock<'_>,
+        ledger: &mut Ledger,
+        index: usize,
+    ) -> Result<usize, StorageError> {
+        let record =
+            ledger.records.get(index).cloned().ok_or_else(|| {
+                StorageError::ValidationFailed(format!("Record {} missing", index))
+            })?;
+
+        self.persist(write, ledger, |log, lock| {
+            log.append_record_at_tip(&record, lock)
+        })?;
+        Ok(index)
+    }
+
+    // runs a write, which lets go of the locks when it returns, then waits for
+    // the group fsync if there is one so other writers can join it meanwhile
+    fn acknowledged<T>(
+        &self,
+        entries: usize,
+        write: impl FnOnce() -> Result<T, StorageError>,
+    ) -> Result<T, StorageError> {
+        let started = Instant::now();
+        if let Some(group) = &self.group {
+            group.begin()?;
+        }
+
+        let value = match write() {
+            Ok(value) => value,
+            Err(e) => {
+                if let Some(group) = &self.group {
+                    group.cancel()?;
+                }
+                return Err(e);
+            }
+        };
+
+        if let Some(group) = &self.group {
+            group.wait(&self.shared.backend, AppendLog::path_for(&self.shared.path))?;
+        }
+
+        {
+            let mut stats = self.stats.lock().map_err(|_| StorageError::Poisoned)?;
+            stats.record(entries as u64, started.elapsed());
+            if self.durability == Durability::Fsync {
+                stats.fsyncs += 1;
+            }
+        }
+
+        self.compact_if_due()?;
+        Ok(value)
+    }
+
+    // the ledger already holds the change, if the WAL write fails the disk is
+    // the truth and the ledger is reloaded from it
+    fn persist(
+        &self,
+        write: &mut WriteLock<'_>,
+        ledger: &mut Ledger,
+        append: impl FnOnce(&mut AppendLog, &DatabaseLock) -> Result<(), StorageError>,
+    ) -> Result<(), StorageError> {
+        let WriteLock { writer, lock } = write;
+        let result = self.shared.log(writer).and_then(|log| {
+            let mut log = log.with_durability(self.durability);
+            let result = append(&mut log, lock);
+            writer.keep(&mut log);
+            result
+        });
+
+        if let Err(e) = result {
+            // what was buffered before this write is still good
+            let written = self.shared.write_pending(writer);
+            *ledger = RecoveryManager::load_ledger_locked(&self.shared.path, lock)?;
+            written?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // hands the compaction to the background thread, the write that made it due
+    // has been acknowledged already
+    fn compact_if_due(&self) -> Result<(), StorageError> {
+        if self.policy.is_manual() || !self.shared.compaction_due(&self.policy)? {
+            return Ok(());
+        }
+
+        let compactor = self
+            .compactor
+            .get_or_init(|| Compactor::spawn(Arc::clone(&self.shared), self.policy));
+        // a full channel means a wake up is pending already
+        let _ = compactor.wake.try_send(());
+        Ok(())
+    }
+}
+
+impl Drop for UkweliDb {
+    // lets a pending compaction finish instead of leaving it half done, then
+    // writes out what is buffered. Call `flush` first to see if that fails.
+    fn drop(&mut self) {
+        if let Some(compactor) = self.compactor.take() {
+            drop(compactor.wake);
+            let _ = compactor.thread.join();
+        }
+        let _ = self.shared.flush();
+    }
+}
+
+impl Writer {
+    // picks up the tail and the buffer a `log` left
+    fn keep(&mut self, log: &mut AppendLog) {
+        self.tail = log.tail();
+        self.pending = log.take_pending();
+    }
+}
+
+impl Drop for WriteLock<'_> {
+    // buffered entries keep the lock until they are written out
+    fn drop(&mut self) {
+        self.writer.held = if self.writer.pending.is_empty() {
+            None
+        } else {
+            Some(Arc::clone(&self.lock))
+        };
+    }
+}
+
+impl Shared {
+    fn read(&self) -> Result<RwLockReadGuard<'_, Ledger>, StorageError> {
+        self.ledger.read().map_err(|_| StorageError::Poisoned)
+    }
+
+    fn write_ledger(&self) -> Result<RwLockWriteGuard<'_, Ledger>, StorageError> {
+        self.ledger.write().map_err(|_| StorageError::Poisoned)
+    }
+
+    // takes both locks and reloads if someone else moved the chain tip
+    fn lock_for_write(&self) -> Result<WriteLock<'_>, StorageError> {
+        if self.read_only {
+            return Err(StorageError::ReadOnly);
+        }
+
+        let mut writer = self.writer.lock().map_err(|_| StorageError::Poisoned)?;
+
+        // nobody else wrote while we held on to the lock for the buffer
+        if let Some(lock) = writer.held.take() {
+            return Ok(WriteLock { writer, lock });
+        }
+        let lock = Arc::new(DatabaseLock::exclusive_in(&self.backend, &self.path)?);
+
+        // whatever another process crashing left at the end of the WAL, we must
+        // not append after it
+        let mut log = self.log(&mut writer)?;
+        log.recover_tail(&lock)?;
+        let on_disk = log.chain_tip()?;
+        writer.keep(&mut log);
+
+        let ours = self
+            .read()?
+            .records
+            .last()
+            .map(|r| (r.index, r.record_hash.clone()));
+
+        if on_disk.is_some() && on_disk != ours {
+            let fresh = RecoveryManager::load_ledger_locked(&self.path, &lock)?;
+            *self.write_ledger()? = fresh;
+        }
+
+        Ok(WriteLock { writer, lock })
+    }
+
+    // the WAL as the last write through this handle left it, buffer included
+    fn log(&self, writer: &mut Writer) -> Result<AppendLog, StorageError> {
+        Ok(AppendLog::new_in(&self.backend, &self.path)?
+            .with_tail(writer.tail.take())
+            .with_pending(std::mem::take(&mut writer.pending)))
+    }
+
+    fn write_pending(&self, writer: &mut Writer) -> Result<(), StorageError> {
+        if writer.pending.is_empty() {
+            return Ok(());
+        }
+        let mut log = self.log(writer)?;
+        let result = log.flush();
+        writer.keep(&mut log);
+        result
+    }
+
+    // like `persist`, whatever didn't make it to the WAL leaves the ledger too
+    fn write_out(&self, write: &mut WriteLock<'_>) -> Result<(), StorageError> {
+        let result = self.write_pending(&mut write.writer);
+        if result.is_err() {
+            *self.write_ledger()? = RecoveryManager::load_ledger_locked(&self.path, &write.lock)?;
+        }
+        result
+    }
+
+    // only takes the exclusive lock if it is held for a buffer already
+    fn flush(&self) -> Result<(), StorageError> {
+        let mut writer = self.writer.lock().map_err(|_| StorageError::Poisoned)?;
+        let Some(lock) = writer.held.take() else {
+            return Ok(());
+        };
+        self.write_out(&mut WriteLock { writer, lock })
+    }
+
+    fn compact(&self) -> Result<(), StorageError> {
+        let mut write = self.lock_for_write()?;
+        self.write_out(&mut write)?;
+        let ledger = self.read()?;
+        write.writer.tail = None;
+        RecoveryManager::compact_locked(&self.path, &ledger, &write.lock)
+    }
+
+    // only looks at the end of the WAL when the tail is known, which after a
+    // write through this handle it is
+    fn compaction_due(&self, policy: &CompactionPolicy) -> Result<bool, StorageError> {
+        let mut writer = self.writer.lock().map_err(|_| StorageError::Poisoned)?;
+        let mut log = self.log(&mut writer)?;
+        let wal = log.stats();
+        writer.keep(&mut log);
+
+        Ok(policy.is_due(&wal?))
+    }
+}
+
+impl Compactor {
+    fn spawn(shared: Arc<Shared>, policy: CompactionPolicy) -> Self {
+        let (wake, woken) = mpsc::sync_channel(1);
+        let thread = thread::spawn(move || Self::run(&shared, policy, woken));
+        Self { wake, thread }
+    }
+
+    // runs until the handle is dropped, a pending wake up is still served first
+    fn run(shared: &Shared, policy: CompactionPolicy, woken: Receiver<()>) {
+        let interval = policy.max_age.map(|age| age.min(AGE_CHECK_INTERVAL));
+
+        loop {
+            let wake = match interval {
+                Some(interval) => woken.recv_timeout(interval),
+                None => woken.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            if wake == Err(RecvTimeoutError::Disconnected) {
+                return;
+            }
+
+            // another handle may have compacted since we were woken
+            let result = match shared.compaction_due(&policy) {
+                Ok(true) => shared.compact(),
+                Ok(false) => Ok(()),
+                Err(e) => Err(e),
+            };
+
+            if let Err(e) = result
+                && let Ok(mut error) = shared.compaction_error.lock()
+            {
+                *error = Some(e);
+            }
+        }
+    }
+}
+
+enum PendingRecord<'a> {
+    Record(Payload, Vec<User>),
+    Transition(&'a Engine, Payload, Vec<User>),
+}
+
+/// Records that land in the ledger together or not at all.
+///
+/// They are validated in order when committed, so a transition can build on
+/// a record earlier in the same batch. If any of them is refused, or the WAL
+/// write fails, none of them are kept.
+pub struct Batch<'a> {
+    db: &'a UkweliDb,
+    pending: Vec<PendingRecord<'a>>,
+}
+
+impl<'a> Batch<'a> {
+    pub fn append(&mut self, payload: impl Into<Payload>, signers: Vec<User>) -> &mut Self {
+        self.pending
+            .push(PendingRecord::Record(payload.into(), signers));
+        self
+    }
+
+    pub fn append_transition(
+        &mut self,
+        engine: &'a Engine,
+        payload: Payload,
+        signers: Vec<User>,
+    ) -> &mut Self {
+        self.pending
+            .push(PendingRecord::Transition(engine, payload, signers));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Returns the index of each record, in the order they were appended.
+    pub fn commit(self) -> Result<Vec<usize>, StorageError> {
+        self.db.commit_batch(self.pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use crate::core::roles::ADMIN_ROLE;
+    use crate::core::{KeyRotation, OperatorDesignation, RoleChange};
+    use std::fs;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_handle_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<UkweliDb>();
+    }
+
+    #[test]
+    fn test_concurrent_writers_and_readers() {
+        let test_path = "test_db_concurrent.ukweli";
+        let backend = backend::memory();
+
+        let db = Arc::new(UkweliDb::create_in(&backend, test_path).unwrap());
+        let user = User::new("0xElvis");
+        db.register_user(user.clone()).unwrap();
+
+        let writers: Vec<_> = (0..4)
+            .map(|t| {
+                let db = Arc::clone(&db);
+                let user = user.clone();
+                thread::spawn(move || {
+                    for i in 0..5 {
+                        db.append_record(format!("{}-{}", t, i).as_str(), vec![user.clone()])
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        let reader = {
+            let db = Arc::clone(&db);
+            thread::spawn(move || {
+                for _ in 0..20 {
+                    assert!(db.verify_chain().unwrap());
+                }
+            })
+        };
+
+        for handle in writers {
+            handle.join().unwrap();
+        }
+        reader.join().unwrap();
+
+        assert_eq!(db.length().unwrap(), 21);
+        db.compact().unwrap();
+        drop(db);
+
+        let reopened = UkweliDb::open_in(&backend, test_path).unwrap();
+        assert_eq!(reopened.length().unwrap(), 21);
+        assert!(reopened.verify_chain().unwrap());
+    }
+
+    #[test]
+    fn test_durability_modes() {
+        let test_path = "test_db_durability.ukweli";
+        let backend = backend::memory();
+
+        let db = UkweliDb::create_in(&backend, test_path)
+            .unwrap()
+            .with_durability(Durability::Fsync);
+        let user = User::new("0xElvis");
+        db.register_user(user.clone()).unwrap();
+        db.append_record("synced", vec![user.clone()]).unwrap();
+
+        let stats = db.stats().unwrap();
+        assert_eq!((stats.appends, stats.entries, stats.fsyncs), (2, 2, 2));
+        assert!(stats.max_latency >= stats.mean_latency());
+        drop(db);
+
+        let db = Arc::new(
+            UkweliDb::open_in(&backend, test_path)
+                .unwrap()
+                .with_durability(Durability::GroupCommit {
+                    window: std::time::Duration::from_millis(20),
+                    max_batch: 4,
+                }),
+        );
+        let writers: Vec<_> = (0..4)
+            .map(|t| {
+                let db = Arc::clone(&db);
+                let user = user.clone();
+                thread::spawn(move || {
+                    for i in 0..5 {
+                        db.append_record(format!("{}-{}", t, i).as_str(), vec![user.clone()])
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in writers {
+            handle.join().unwrap();
+        }
+
+        // writers waiting on the same sync share it
+        let stats = db.stats().unwrap();
+        assert_eq!(stats.entries, 20);
+        assert!((1..20).contains(&stats.fsyncs));
+        drop(db);
+
+        assert_eq!(
+            UkweliDb::open_in(&backend, test_path)
+                .unwrap()
+                .length()
+                .unwrap(),
+            22
+        );
+    }
+
+    #[test]
+    fn test_none_buffers_until_flushed() {
+        let test_path = "test_db_buffered.ukweli";
+        let backend = backend::memory();
+        let wal_path = AppendLog::path_for(test_path);
+        let wal_len = || backend.size(&wal_path).unwrap().unwrap_or(0);
+
+        let db = UkweliDb::create_in(&backend, test_path)
+            .unwrap()
+            .with_durability(Durability::None);
+        let user = User::new("loader");
+        db.register_user(user.clone()).unwrap();
+        for i in 0..10 {
+            db.append_record(format!("bulk {}", i).as_str(), vec![user.clone()])
+                .unwrap();
+        }
+
+        // acknowledged but not written, and nobody else can write meanwhile
+        assert_eq!(db.length().unwrap(), 11);
+        assert_eq!(wal_len(), 0);
+        assert!(
+            DatabaseLock::try_exclusive_in(&backend, test_path)
+                .unwrap()
+                .is_none()
+        );
+
+        db.flush().unwrap();
+        let flushed = wal_len();
+        assert!(flushed > 0);
+        assert!(
+            DatabaseLock::try_exclusive_in(&backend, test_path)
+                .unwrap()
+                .is_some()
+        );
+
+        // a batch goes out with whatever was buffered before it
+        db.append_record("single", vec![user.clone()]).unwrap();
+        assert_eq!(wal_len(), flushed);
+        let mut batch = db.begin();
+        batch.append("batched", vec![user.clone()]);
+        batch.commit().unwrap();
+        let committed = wal_len();
+        assert!(committed > flushed);
+
+        // dropping the handle writes out the rest
+        db.append_record("last", vec![user.clone()]).unwrap();
+        assert_eq!(wal_len(), committed);
+        drop(db);
+        assert!(wal_len() > committed);
+
+        let db = UkweliDb::open_in(&backend, test_path)
+            .unwrap()
+            .with_durability(Durability::Flush);
+        assert_eq!(db.length().unwrap(), 14);
+        assert!(db.verify_chain().unwrap());
+
+        // unlike flush, which writes every append straight away
+        let closed = wal_len();
+        db.append_record("flushed", vec![user]).unwrap();
+        assert!(wal_len() > closed);
+    }
+
+    #[test]
+    fn test_policy_compacts_in_background() {
+        let test_path = "test_db_auto_compact.ukweli";
+        let backend = backend::memory();
+
+        let db = UkweliDb::create_in(&backend, test_path)
+            .unwrap()
+            .with_compaction(CompactionPolicy {
+                max_wal_entries: Some(5),
+                ..CompactionPolicy::manual()
+            });
+        let user = User::new("0xElvis");
+        db.register_user(user.clone()).unwrap();
+        for i in 0..6 {
+            db.append_record(format!("record {}", i).as_str(), vec![user.clone()])
+                .unwrap();
+        }
+
+        // dropping waits for a compaction that is still running
+        drop(db);
+
+        let wal = AppendLog::new_in(&backend, test_path)
+            .unwrap()
+            .stats()
+            .unwrap();
+        assert!(wal.entries < 5, "{} entries left in the WAL", wal.entries);
+        assert!(
+            crate::storage::segment::Manifest::load_in(&backend, test_path)
+                .unwrap()
+                .is_some()
+        );
+
+        let reopened = UkweliDb::open_in(&backend, test_path).unwrap();
+        assert_eq!(reopened.length().unwrap(), 7);
+        assert!(reopened.take_compaction_error().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_opening_does_not_compact() {
+        let test_path = "test_db_open_no_compact.ukweli";
+        let backend = backend::memory();
+
+        let db = UkweliDb::create_in(&backend, test_path).unwrap();
+        let user = User::new("0xChege");
+        db.register_user(user.clone()).unwrap();
+        db.append_record("in the wal", vec![user.clone()]).unwrap();
+        drop(db);
+
+        let wal_path = AppendLog::path_for(test_path);
+        let db_bytes = backend.read(Path::new(test_path)).unwrap();
+        let wal_bytes = backend.read(&wal_path).unwrap();
+
+        let reader = UkweliDb::open_read_only_in(&backend, test_path).unwrap();
+        assert_eq!(reader.length().unwrap(), 2);
+        assert!(matches!(
+            reader.append_record("refused", vec![user.clone()]),
+            Err(StorageError::ReadOnly)
+        ));
+        assert!(matches!(reader.compact(), Err(StorageError::ReadOnly)));
+        drop(reader);
+
+        let db = UkweliDb::open_in(&backend, test_path).unwrap();
+        assert_eq!(db.length().unwrap(), 2);
+        drop(db);
+
+        assert_eq!(backend.read(Path::new(test_path)).unwrap(), db_bytes);
+        assert_eq!(backend.read(&wal_path).unwrap(), wal_bytes);
+    }
+
+    #[test]
+    fn test_read_only_creates_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let test_path = dir.path().join("test_db_read_only.ukweli");
+
+        let db = UkweliDb::create(&test_path).unwrap();
+        let user = User::new("0xElvis");
+        db.register_user(user.clone()).unwrap();
+        db.append_record("sealed", vec![user]).unwrap();
+        db.compact().unwrap();
+        drop(db);
+
+        // what an archive copy looks like: no WAL and no lock file
+        fs::remove_file(AppendLog::path_for(&test_path)).unwrap();
+        fs::remove_file(DatabaseLock::path_for(&test_path)).unwrap();
+        let listing = || {
+            let mut names: Vec<_> = fs::read_dir(dir.path())
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .collect();
+            names.sort();
+            names
+        };
+        let before = listing();
+
+        let reader = UkweliDb::open_read_only(&test_path).unwrap();
+        assert!(reader.is_read_only());
+        assert_eq!(reader.length().unwrap(), 2);
+        reader.refresh().unwrap();
+        assert!(reader.verify_chain().unwrap());
+        drop(reader);
+
+        assert_eq!(listing(), before);
+    }
+
+    #[test]
+    fn test_in_memory() {
+        let db = UkweliDb::in_memory().unwrap();
+        let user = User::new("0xElvis");
+        db.register_user(user.clone()).unwrap();
+        db.append_record("never on disk", vec![user]).unwrap();
+        db.compact().unwrap();
+
+        assert!(!db.path().exists());
+        let reopened = UkweliDb::open_read_only_in(db.backend(), db.path()).unwrap();
+        assert_eq!(reopened.length().unwrap(), 2);
+        assert!(reopened.verify_chain().unwrap());
+    }
+
+    #[test]
+    fn test_batch_is_all_or_nothing() {
+        let test_path = "test_db_batch.ukweli";
+        let backend = backend::memory();
+
+        let db = UkweliDb::create_in(&backend, test_path).unwrap();
+        let user = User::new("0xElvis");
+        db.register_user(user.clone()).unwrap();
+
+        let mut batch = db.begin();
+        batch
+            .append("bid received", vec![user.clone()])
+            .append("bid document hash", vec![user.clone()]);
+        assert_eq!(batch.commit().unwrap(), vec![1, 2]);
+
+        // the stranger isn't registered, so the first record goes as well
+        let mut batch = db.begin();
+        batch
+            .append("evaluator assigned", vec![user.clone()])
+            .append("forged", vec![User::new("stranger")]);
+        assert!(batch.commit().is_err());
+        assert_eq!(db.length().unwrap(), 3);
+
+        drop(db);
+        let reopened = UkweliDb::open_in(&backend, test_path).unwrap();
+        assert_eq!(reopened.length().unwrap(), 3);
+        assert_eq!(
+            reopened.record(2).unwrap().unwrap().payload,
+            Payload::from("bid document hash")
+        );
+    }
+
+    #[test]
+    fn test_roles_survive_reopening() {
+        let test_path = "test_db_roles.ukweli";
+        let backend = backend::memory();
+
+        let db = UkweliDb::create_in(&backend, test_path).unwrap();
+        let admin = User::new("0xAdmin");
+        db.register_user(admin.clone()).unwrap();
+        db.append_record(
+            OperatorDesignation::new("0xAdmin").to_payload(),
+            vec![admin.clone()],
+        )
+        .unwrap();
+        db.append_record(
+            RoleChange::grant("0xAdmin", ADMIN_ROLE).to_payload(),
+            vec![admin.clone()],
+        )
+        .unwrap();
+        db.compact().unwrap();
+        db.append_record(
+            RoleChange::grant("0xAdmin", "auditor").to_payload(),
+            vec![admin],
+        )
+        .unwrap();
+
+        drop(db);
+        let reopened = UkweliDb::open_read_only_in(&backend, test_path).unwrap();
+        let roles = reopened.roles().unwrap();
+        assert!(roles.has("0xAdmin", ADMIN_ROLE));
+        assert!(roles.has("0xAdmin", "auditor"));
+    }
+
+    #[test]
+    fn test_rotated_keys_verify_after_reopening() {
+        let test_path = "test_db_rotation.ukweli";
+        let backend = backend::memory();
+
+        let db = UkweliDb::create_in(&backend, test_path).unwrap();
+        let old = User::new("0xElvis");
+        db.register_user(old.clone()).unwrap();
+        db.append_record("old key", vec![old.clone()]).unwrap();
+
+        let new = User::new("0xElvis");
+        db.append_record(KeyRotation::new(&new).to_payload(), vec![old])
+            .unwrap();
+        db.compact().unwrap();
+        db.append_record("new key", vec![new]).unwrap();
+
+        drop(db);
+        let reopened = UkweliDb::open_read_only_in(&backend, test_path).unwrap();
+        assert_eq!(reopened.length().unwrap(), 4);
+        assert!(reopened.verify_chain().unwrap());
+    }
+
+    #[test]
+    fn test_second_handle_catches_up() {
+        let test_path = "test_db_two_handles.ukweli";
+        let backend = backend::memory();
+
+        let first = UkweliDb::create_in(&backend, test_path).unwrap();
+        let user = User::new("0xChege");
+        first.register_user(user.clone()).unwrap();
+        first
+            .append_record("from first", vec![user.clone()])
+            .unwrap();
+
+        let second = UkweliDb::open_in(&backend, test_path).unwrap();
+        second
+            .append_record("from second", vec![user.clone()])
+            .unwrap();
+
+        // first is behind, its next write reloads instead of forking
+        let index = first.append_record("first again", vec![user]).unwrap();
+        assert_eq!(index, 3);
+
+        second.refresh().unwrap();
+        assert_eq!(
+            second.record(3).unwrap().unwrap().record_hash,
+            first.record(3).unwrap().unwrap().record_hash
+        );
+        assert!(UkweliDb::create_in(&backend, test_path).is_err());
+    }
+}
@@ -25,6 +25,24 @@ pub enum LedgerError {
 
     #[error("Timestamp out of acceptable range")]
     InvalidTimestamp,
+
+    #[error("Index {0} is outside the ledger (size {1})")]
+    IndexOutOfRange(usize, usize),
+
+    #[error("Invalid proof: {0}")]
+    InvalidProof(String),
+
+    #[error("Role change rejected: {0}")]
+    RoleRejected(String),
+
+    #[error("Key rejected: {0}")]
+    KeyRejected(String),
+
+    #[error("Operator rejected: {0}")]
+    OperatorRejected(String),
+
+    #[error("Workflow rejected record: {0}")]
+    WorkflowRejected(#[from] WorkflowError),
 }
 
 #[derive(Error, Debug)]
@@ -65,6 +83,45 @@ pub enum StorageError {
     #[error("Checksum mismatch - database file may be corrupted")]
     ChecksumMismatch,
 
+    #[error("Database footer is missing or unreadable")]
+    MissingFooter,
+
+    #[error("Integrity hash mismatch - header or body was modified")]
+    IntegrityMismatch,
+
+    #[error("File size mismatch: footer records {expected} bytes, file has {actual}")]
+    FileSizeMismatch { expected: u64, actual: u64 },
+
     #[error("Database validation failed: {0}")]
     ValidationFailed(String),
+
+    #[error("Conflicting records at index {0} - the chain was forked")]
+    ForkDetected(usize),
+
+    #[error("Ledger is stale: database tip is record {found}, expected {expected}")]
+    StaleTip { expected: usize, found: usize },
+
+    #[error("WAL entry {entry} at offset {offset} is damaged: {reason}")]
+    CorruptWal {
+        offset: u64,
+        entry: usize,
+        reason: String,
+    },
+
+    #[error(
+        "WAL entries are missing: sealed data ends at LSN {sealed}, the WAL resumes at {found}"
+    )]
+    WalGap { sealed: u64, found: u64 },
+
+    #[error("Ledger error: {0}")]
+    Ledger(#[from] LedgerError),
+
+    #[error("Database handle is unusable after a writer panicked")]
+    Poisoned,
+
+    #[error("Database was opened read-only")]
+    ReadOnly,
+
+    #[error("Object store error: {0}")]
+    ObjectStore(String),
 }
@@ -5,11 +5,16 @@
 #![deny(unused_must_use)]
 
 pub mod core;
+pub mod db;
 pub mod error;
 pub mod storage;
 pub mod workflow;
 
-pub use core::{Ledger, Record};
+#[cfg(test)]
+mod test_support;
+
+pub use core::{Ledger, Payload, Record};
+pub use db::UkweliDb;
 pub use error::LedgerError;
 pub use storage::persitence;
 pub use workflow::{Workflow, WorkflowState};
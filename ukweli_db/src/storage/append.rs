@@ -1,36 +1,161 @@
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::fmt;
 use std::path::{Path, PathBuf};
 
-use crate::core::{Record, User};
+use crate::core::{Checkpoint, Record, User};
 use crate::error::StorageError;
-use crate::storage::persitence::{SerializableRecord, SerializableUser};
+use crate::storage::backend::{self, Backend};
+use crate::storage::compaction::WalStats;
+use crate::storage::durability::Durability;
+use crate::storage::lock::DatabaseLock;
+use crate::storage::persitence::{
+    LegacySerializableRecord, SerializableCheckpoint, SerializableRecord, SerializableUser,
+};
+use crate::storage::recovery::RecoveryManager;
+
+const APPEND_MAGIC: [u8; 4] = [0x41, 0x50, 0x4E, 0x32]; // "APN2"
+const ENTRY_HEADER_SIZE: usize = 4 + 1 + 8 + 4 + 32 + 8 + 32; // 89 bytes total, no padding needed
+
+// entries written before LSNs existed, read but never written
+const LEGACY_MAGIC: [u8; 4] = [0x41, 0x50, 0x4E, 0x44]; // "APND"
+const LEGACY_HEADER_SIZE: usize = 4 + 1 + 8 + 4 + 32;
+
+// batch markers, the data of both is the number of entries in the batch (u32 LE)
+pub const BATCH_BEGIN: u8 = 4;
+pub const BATCH_COMMIT: u8 = 5;
+
+// how much `Durability::None` holds back before it writes out anyway
+const BUFFER_CAPACITY: usize = 1 << 20;
+
+pub type WalEntry = (AppendEntry, Vec<u8>);
+pub type WalEntryAt = (u64, AppendEntry, Vec<u8>);
+
+// LSN and prev hash for the next entry written
+type Link = (u64, [u8; 32]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalDamageKind {
+    TruncatedHeader,
+    TruncatedData,
+    ZeroFilled,
+    BadMagic,
+    ChecksumMismatch,
+    /// prev hash doesn't match the entry before, something was dropped or moved
+    BrokenChain,
+    /// LSN doesn't follow the entry before, something was duplicated or moved
+    LsnOutOfOrder,
+}
+
+impl WalDamageKind {
+    /// The file simply ends before the entry does.
+    pub fn is_incomplete(self) -> bool {
+        matches!(
+            self,
+            Self::TruncatedHeader | Self::TruncatedData | Self::ZeroFilled
+        )
+    }
+}
+
+impl fmt::Display for WalDamageKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            Self::TruncatedHeader => "entry header cut short",
+            Self::TruncatedData => "entry data cut short",
+            Self::ZeroFilled => "zero filled tail",
+            Self::BadMagic => "bad entry magic",
+            Self::ChecksumMismatch => "checksum mismatch",
+            Self::BrokenChain => "does not chain to the entry before",
+            Self::LsnOutOfOrder => "LSN out of order",
+        };
+        f.write_str(reason)
+    }
+}
+
+/// Where the first bad WAL entry starts. Entries before `offset` are intact.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalDamage {
+    pub offset: u64,
+    /// position of the bad entry, counting from 0
+    pub entry: usize,
+    pub kind: WalDamageKind,
+    /// whether it looks like a crash mid-write: the damage runs to the end of the file
+    pub torn: bool,
+    /// bytes from `offset` to the end of the file
+    pub tail_bytes: u64,
+}
+
+impl From<WalDamage> for StorageError {
+    fn from(damage: WalDamage) -> Self {
+        StorageError::CorruptWal {
+            offset: damage.offset,
+            entry: damage.entry,
+            reason: damage.kind.to_string(),
+        }
+    }
+}
 
-const APPEND_MAGIC: [u8; 4] = [0x41, 0x50, 0x4E, 0x44]; // "APND"
-const ENTRY_HEADER_SIZE: usize = 4 + 1 + 8 + 4 + 32; // 49 bytes total, no padding needed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WalRepair {
+    pub damage: Option<WalDamage>,
+    /// where the cut off tail was moved, if it was kept
+    pub quarantined: Option<PathBuf>,
+    pub uncommitted_discarded: bool,
+}
 
 #[derive(Debug, Clone)]
 pub struct AppendEntry {
     pub magic: [u8; 4],
-    pub entry_type: u8, // 1 = Record, 2 = User
+    pub entry_type: u8, // 1 = Record, 2 = User, 3 = Checkpoint, 4/5 = batch begin/commit
     pub timestamp: u64,
     pub data_size: u32,
     pub checksum: [u8; 32],
+    pub lsn: u64,            // counts up from 1 across compactions, 0 for legacy entries
+    pub prev_hash: [u8; 32], // sha256 of the previous entry, header and data, zero for the first
 }
 
 impl AppendEntry {
-    pub fn new(entry_type: u8, data_size: u32, checksum: [u8; 32]) -> Self {
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .map(|d| d.as_secs())
-            .unwrap_or(0);
-
+    pub fn new(
+        entry_type: u8,
+        lsn: u64,
+        prev_hash: [u8; 32],
+        data_size: u32,
+        checksum: [u8; 32],
+    ) -> Self {
         Self {
             magic: APPEND_MAGIC,
             entry_type,
-            timestamp: now,
+            timestamp: unix_now(),
             data_size,
             checksum,
+            lsn,
+            prev_hash,
+        }
+    }
+
+    /// Whether this entry has an LSN and a prev hash, i.e. isn't a legacy one.
+    pub fn is_chained(&self) -> bool {
+        self.magic == APPEND_MAGIC
+    }
+
+    /// Decodes the record a type 1 entry carries. Legacy entries hold the 1.x
+    /// layout with a string payload.
+    pub fn record(&self, data: &[u8]) -> Result<SerializableRecord, StorageError> {
+        use rkyv::rancor::Error as RkyvError;
+
+        let record = if self.is_chained() {
+            rkyv::from_bytes::<SerializableRecord, RkyvError>(data)
+        } else {
+            rkyv::from_bytes::<LegacySerializableRecord, RkyvError>(data)
+                .map(SerializableRecord::from)
+        };
+        record
+            .map_err(|e| StorageError::Deserialization(format!("Failed to read WAL record: {}", e)))
+    }
+
+    pub fn header_size(&self) -> usize {
+        if self.is_chained() {
+            ENTRY_HEADER_SIZE
+        } else {
+            LEGACY_HEADER_SIZE
         }
     }
 
@@ -52,10 +177,17 @@ impl AppendEntry {
         // Write checksum (32 bytes)
         bytes[17..49].copy_from_slice(&self.checksum);
 
+        // Write lsn (8 bytes)
+        bytes[49..57].copy_from_slice(&self.lsn.to_le_bytes());
+
+        // Write prev_hash (32 bytes)
+        bytes[57..89].copy_from_slice(&self.prev_hash);
+
         bytes
     }
 
-    pub fn from_bytes(bytes: &[u8; ENTRY_HEADER_SIZE]) -> Result<Self, StorageError> {
+    /// Parses either header layout, `bytes` has to hold at least `header_size()`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, StorageError> {
         let magic_slice = bytes.get(0..4).ok_or_else(|| {
             StorageError::Deserialization("Failed to read magic bytes".to_string())
         })?;
@@ -92,36 +224,172 @@ impl AppendEntry {
             StorageError::Deserialization("Failed to convert checksum bytes".to_string())
         })?;
 
-        Ok(Self {
+        let mut entry = Self {
             magic,
             entry_type,
             timestamp,
             data_size,
             checksum,
-        })
+            lsn: 0,
+            prev_hash: [0; 32],
+        };
+
+        if !entry.is_chained() {
+            return Ok(entry);
+        }
+
+        let lsn_slice = bytes
+            .get(49..57)
+            .ok_or_else(|| StorageError::Deserialization("Failed to read lsn bytes".to_string()))?;
+
+        entry.lsn = u64::from_le_bytes(lsn_slice.try_into().map_err(|_| {
+            StorageError::Deserialization("Failed to convert lsn bytes".to_string())
+        })?);
+
+        let prev_hash_slice = bytes.get(57..89).ok_or_else(|| {
+            StorageError::Deserialization("Failed to read prev_hash bytes".to_string())
+        })?;
+
+        entry.prev_hash = prev_hash_slice.try_into().map_err(|_| {
+            StorageError::Deserialization("Failed to convert prev_hash bytes".to_string())
+        })?;
+
+        Ok(entry)
+    }
+}
+
+/// What a writer knows about the end of the WAL, so it doesn't have to read
+/// the whole log before every append. Only trusted while the file still ends
+/// with the entry it points at, which chains back to everything before it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalTail {
+    len: u64,
+    last_at: u64,
+    next: Link,
+    // newest committed record in the WAL, None if it holds no records
+    tip: Option<(usize, String)>,
+    entries: u64,
+    oldest: Option<u64>,
+}
+
+impl WalTail {
+    pub fn stats(&self) -> WalStats {
+        WalStats {
+            bytes: self.len,
+            entries: self.entries,
+            oldest: self.oldest,
+        }
     }
 }
 
 pub struct AppendLog {
+    backend: Backend,
+    db_path: PathBuf,
     path: PathBuf,
-    file: File,
+    writable: bool,
+    durability: Durability,
+    tail: Option<WalTail>,
+    // entries `Durability::None` hasn't written yet, the tail counts them
+    pending: Vec<u8>,
 }
 
 impl AppendLog {
-    pub fn new<P: AsRef<Path>>(db_path: P) -> Result<Self, StorageError> {
+    pub fn path_for<P: AsRef<Path>>(db_path: P) -> PathBuf {
         let mut append_path = PathBuf::from(db_path.as_ref());
         append_path.set_extension("wal"); // Write-Ahead Log
+        append_path
+    }
 
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .read(true)
-            .open(&append_path)?;
+    /// Opens the WAL for reading only, `None` if there isn't one. Nothing is
+    /// created, and writing through the result fails.
+    pub fn open_existing<P: AsRef<Path>>(db_path: P) -> Result<Option<Self>, StorageError> {
+        Self::open_existing_in(&backend::filesystem(), db_path)
+    }
 
-        Ok(Self {
-            path: append_path,
-            file,
-        })
+    pub fn open_existing_in<P: AsRef<Path>>(
+        backend: &Backend,
+        db_path: P,
+    ) -> Result<Option<Self>, StorageError> {
+        if !backend.exists(&Self::path_for(&db_path))? {
+            return Ok(None);
+        }
+
+        Ok(Some(Self::with_backend(backend, db_path, false)))
+    }
+
+    pub fn new<P: AsRef<Path>>(db_path: P) -> Result<Self, StorageError> {
+        Self::new_in(&backend::filesystem(), db_path)
+    }
+
+    /// Opens the WAL for appending, creating an empty one if there is none.
+    pub fn new_in<P: AsRef<Path>>(backend: &Backend, db_path: P) -> Result<Self, StorageError> {
+        let path = Self::path_for(&db_path);
+        if !backend.exists(&path)? {
+            backend.append(&path, &[])?;
+        }
+        Ok(Self::with_backend(backend, db_path, true))
+    }
+
+    fn with_backend<P: AsRef<Path>>(backend: &Backend, db_path: P, writable: bool) -> Self {
+        Self {
+            backend: Backend::clone(backend),
+            db_path: db_path.as_ref().to_path_buf(),
+            path: Self::path_for(&db_path),
+            writable,
+            durability: Durability::default(),
+            tail: None,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Starts from what an earlier `AppendLog` on the same WAL learned, see `tail`.
+    pub fn with_tail(mut self, tail: Option<WalTail>) -> Self {
+        self.tail = tail;
+        self
+    }
+
+    /// Where the WAL ended after the last read or write, for the next writer to
+    /// pick up with `with_tail`. It is checked against the file before use.
+    pub fn tail(&self) -> Option<WalTail> {
+        self.tail.clone()
+    }
+
+    /// What each write does before returning. With `GroupCommit` only the write
+    /// happens here, the fsync is up to the caller's `GroupCommit`. With `None`
+    /// entries are only buffered until `flush`, dropping the log loses them.
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    /// Starts with entries an earlier `AppendLog` buffered, see `take_pending`.
+    /// Only sound while the caller held on to the exclusive lock since, they
+    /// follow the tail that log left.
+    pub fn with_pending(mut self, pending: Vec<u8>) -> Self {
+        self.pending = pending;
+        self
+    }
+
+    /// Entries buffered under `Durability::None` and not written yet, for the
+    /// next writer to pick up with `with_pending` along with `tail`.
+    pub fn take_pending(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.pending)
+    }
+
+    /// Writes out what `Durability::None` buffered, in one write. Every other
+    /// mode writes before returning, so there is nothing to do for them.
+    pub fn flush(&mut self) -> Result<(), StorageError> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        self.ensure_writable()?;
+
+        // if the write fails we no longer know where the WAL ends
+        let tail = self.tail.take();
+        let pending = self.take_pending();
+        self.backend.append(&self.path, &pending)?;
+        self.tail = tail;
+        Ok(())
     }
 
     pub fn append_record(&mut self, record: &Record) -> Result<(), StorageError> {
@@ -130,18 +398,85 @@ impl AppendLog {
         let data_bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&serializable)
             .map_err(|e| StorageError::Serialization(e.to_string()))?;
 
-        let checksum_str = sha256::digest(data_bytes.as_slice());
-        let checksum: [u8; 32] = hex::decode(&checksum_str)
-            .map_err(|e| StorageError::Serialization(format!("Hex decode failed: {}", e)))?
-            .try_into()
-            .map_err(|_| StorageError::Serialization("Checksum conversion failed".to_string()))?;
+        let tip = (record.index, record.record_hash.clone());
+        self.write_entry(1, &data_bytes, Some(tip))
+    }
 
-        let entry = AppendEntry::new(1, data_bytes.len() as u32, checksum);
+    /// Appends `record` only if it directly follows the newest record on disk.
+    /// Run under the exclusive lock this is what keeps two writers from both
+    /// building on the same tip: the second one gets `StaleTip` and has to reload.
+    pub fn append_record_at_tip(
+        &mut self,
+        record: &Record,
+        lock: &DatabaseLock,
+    ) -> Result<(), StorageError> {
+        lock.ensure_exclusive(&self.db_path)?;
+        self.check_follows_tip(record)?;
+
+        self.append_record(record)
+    }
 
-        self.file.write_all(&entry.to_bytes())?;
+    /// Appends consecutive records framed by begin/commit markers, in a single
+    /// write. Replay only applies the records once it has seen the commit, so a
+    /// crash part way through loses the whole batch and never half of it.
+    pub fn append_batch(
+        &mut self,
+        records: &[Record],
+        lock: &DatabaseLock,
+    ) -> Result<(), StorageError> {
+        lock.ensure_exclusive(&self.db_path)?;
+
+        let Some(first) = records.first() else {
+            return Ok(());
+        };
+        self.check_follows_tip(first)?;
+
+        let count = u32::try_from(records.len())
+            .map_err(|_| StorageError::Serialization("Batch is too large".to_string()))?
+            .to_le_bytes();
+
+        let mut link = self.next_link()?;
+        let mut bytes = Self::encode_entry(&mut link, BATCH_BEGIN, &count)?;
+        for record in records {
+            let data_bytes =
+                rkyv::to_bytes::<rkyv::rancor::Error>(&SerializableRecord::from(record))
+                    .map_err(|e| StorageError::Serialization(e.to_string()))?;
+            bytes.extend(Self::encode_entry(&mut link, 1, &data_bytes)?);
+        }
+        bytes.extend(Self::encode_entry(&mut link, BATCH_COMMIT, &count)?);
 
-        self.file.write_all(&data_bytes)?;
-        self.file.flush()?;
+        let tip = records
+            .last()
+            .map(|record| (record.index, record.record_hash.clone()));
+        self.write_bytes(&bytes, ENTRY_HEADER_SIZE + count.len(), link, tip)?;
+
+        // a commit is a boundary the caller chose, nothing buffered waits past it
+        self.flush()
+    }
+
+    /// Index and hash of the newest record on disk, like `RecoveryManager::chain_tip`
+    /// but without rereading a WAL this log already knows the end of.
+    pub fn chain_tip(&mut self) -> Result<Option<(usize, String)>, StorageError> {
+        match self.known_tail()? {
+            Some(WalTail { tip: Some(tip), .. }) => Ok(Some(tip)),
+            Some(_) => RecoveryManager::sealed_tip_in(&self.backend, &self.db_path),
+            None => RecoveryManager::chain_tip_in(&self.backend, &self.db_path),
+        }
+    }
+
+    fn check_follows_tip(&mut self, record: &Record) -> Result<(), StorageError> {
+        if let Some((index, hash)) = self.chain_tip()?
+            && (index + 1 != record.index || hash != record.prev_hash)
+        {
+            return Err(if index + 1 == record.index {
+                StorageError::ForkDetected(record.index)
+            } else {
+                StorageError::StaleTip {
+                    expected: record.index.saturating_sub(1),
+                    found: index,
+                }
+            });
+        }
 
         Ok(())
     }
@@ -152,83 +487,579 @@ impl AppendLog {
         let data_bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&serializable)
             .map_err(|e| StorageError::Serialization(e.to_string()))?;
 
-        let checksum_str = sha256::digest(data_bytes.as_slice());
-        let checksum: [u8; 32] = hex::decode(&checksum_str)
+        self.write_entry(2, &data_bytes, None)
+    }
+
+    pub fn append_checkpoint(&mut self, checkpoint: &Checkpoint) -> Result<(), StorageError> {
+        let serializable = SerializableCheckpoint::from(checkpoint);
+
+        let data_bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&serializable)
+            .map_err(|e| StorageError::Serialization(e.to_string()))?;
+
+        self.write_entry(3, &data_bytes, None)
+    }
+
+    fn write_entry(
+        &mut self,
+        entry_type: u8,
+        data_bytes: &[u8],
+        tip: Option<(usize, String)>,
+    ) -> Result<(), StorageError> {
+        let mut link = self.next_link()?;
+        let bytes = Self::encode_entry(&mut link, entry_type, data_bytes)?;
+        self.write_bytes(&bytes, bytes.len(), link, tip)
+    }
+
+    // `last_len` is the size of the last entry in `bytes`, `next` the link after it
+    fn write_bytes(
+        &mut self,
+        bytes: &[u8],
+        last_len: usize,
+        next: Link,
+        tip: Option<(usize, String)>,
+    ) -> Result<(), StorageError> {
+        self.ensure_writable()?;
+
+        // if the write fails we no longer know where the WAL ends
+        let before = self.tail.take();
+        if self.durability == Durability::None {
+            self.pending.extend_from_slice(bytes);
+        } else {
+            self.backend.append(&self.path, bytes)?;
+            self.finish_write()?;
+        }
+
+        self.tail = before.map(|before| WalTail {
+            len: before.len + bytes.len() as u64,
+            last_at: before.len + (bytes.len() - last_len) as u64,
+            entries: before.entries + (next.0 - before.next.0),
+            oldest: before.oldest.or(Some(unix_now())),
+            next,
+            tip: tip.or(before.tip),
+        });
+
+        if self.pending.len() >= BUFFER_CAPACITY {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    // what a write that reached the backend still needs before it returns
+    fn finish_write(&mut self) -> Result<(), StorageError> {
+        match self.durability {
+            Durability::None | Durability::Flush | Durability::GroupCommit { .. } => {}
+            Durability::Fsync => self.backend.sync(&self.path)?,
+        }
+        Ok(())
+    }
+
+    fn ensure_writable(&self) -> Result<(), StorageError> {
+        if !self.writable {
+            return Err(StorageError::ReadOnly);
+        }
+        Ok(())
+    }
+
+    fn wal_len(&self) -> Result<u64, StorageError> {
+        Ok(self.backend.size(&self.path)?.unwrap_or(0))
+    }
+
+    // continues from the last intact entry, or from the last compacted LSN when
+    // the WAL is empty or only has legacy entries
+    fn next_link(&mut self) -> Result<Link, StorageError> {
+        if let Some(tail) = self.known_tail()? {
+            return Ok(tail.next);
+        }
+
+        let (entries, _) = self.scan()?;
+        let watermark = RecoveryManager::last_compacted_lsn_in(&self.backend, &self.db_path)?;
+
+        let prev_hash = match entries.last() {
+            Some((_, entry, data)) => Self::entry_hash(entry, data)?,
+            None => [0; 32],
+        };
+
+        match entries
+            .iter()
+            .rev()
+            .find(|(_, entry, _)| entry.is_chained())
+        {
+            Some((_, entry, _)) if entry.lsn <= watermark => Err(StorageError::ValidationFailed(
+                "WAL still holds compacted entries, recover the database before appending"
+                    .to_string(),
+            )),
+            Some((_, entry, _)) => Ok((entry.lsn + 1, prev_hash)),
+            None => Ok((watermark + 1, prev_hash)),
+        }
+    }
+
+    fn checksum_of(data_bytes: &[u8]) -> Result<[u8; 32], StorageError> {
+        let checksum_str = sha256::digest(data_bytes);
+        hex::decode(&checksum_str)
             .map_err(|e| StorageError::Serialization(format!("Hex decode failed: {}", e)))?
             .try_into()
-            .map_err(|_| StorageError::Serialization("Checksum conversion failed".to_string()))?;
+            .map_err(|_| StorageError::Serialization("Checksum conversion failed".to_string()))
+    }
 
-        let entry = AppendEntry::new(2, data_bytes.len() as u32, checksum);
+    // what the next entry's prev_hash has to be
+    fn entry_hash(entry: &AppendEntry, data_bytes: &[u8]) -> Result<[u8; 32], StorageError> {
+        let header = entry.to_bytes();
+        let header = header.get(..entry.header_size()).ok_or_else(|| {
+            StorageError::Serialization("Entry header size out of range".to_string())
+        })?;
+        Self::checksum_of(&[header, data_bytes].concat())
+    }
 
-        self.file.write_all(&entry.to_bytes())?;
+    fn encode_entry(
+        link: &mut Link,
+        entry_type: u8,
+        data_bytes: &[u8],
+    ) -> Result<Vec<u8>, StorageError> {
+        let checksum = Self::checksum_of(data_bytes)?;
 
-        self.file.write_all(&data_bytes)?;
-        self.file.flush()?;
+        let (lsn, prev_hash) = *link;
+        let entry = AppendEntry::new(
+            entry_type,
+            lsn,
+            prev_hash,
+            data_bytes.len() as u32,
+            checksum,
+        );
 
-        Ok(())
+        let mut bytes = Vec::with_capacity(ENTRY_HEADER_SIZE + data_bytes.len());
+        bytes.extend_from_slice(&entry.to_bytes());
+        bytes.extend_from_slice(data_bytes);
+
+        *link = (lsn + 1, Self::checksum_of(&bytes)?);
+        Ok(bytes)
     }
 
-    pub fn read_all_entries(&mut self) -> Result<Vec<(AppendEntry, Vec<u8>)>, StorageError> {
-        let mut entries = Vec::new();
+    pub fn read_all_entries(&mut self) -> Result<Vec<WalEntry>, StorageError> {
+        Ok(self
+            .read_entries_with_offsets()?
+            .into_iter()
+            .map(|(_, entry, data)| (entry, data))
+            .collect())
+    }
 
-        // Seek to beginning
-        self.file.seek(SeekFrom::Start(0))?;
+    /// First and last LSN in the WAL, `None` if it has no chained entries. The
+    /// scan already checked everything in between is there and in order.
+    pub fn lsn_range(&mut self) -> Result<Option<(u64, u64)>, StorageError> {
+        let entries = self.read_all_entries()?;
+        let mut chained = entries.iter().filter(|(entry, _)| entry.is_chained());
 
-        loop {
-            // Read fixed-size entry header
-            let mut header_buf = [0u8; ENTRY_HEADER_SIZE];
-            match self.file.read_exact(&mut header_buf) {
-                Ok(()) => {
-                    let entry = AppendEntry::from_bytes(&header_buf)?;
+        let first = chained.next().map(|(entry, _)| entry.lsn);
+        let last = chained.next_back().map(|(entry, _)| entry.lsn);
+        Ok(first.map(|first| (first, last.unwrap_or(first))))
+    }
 
-                    // Check magic
-                    if entry.magic != APPEND_MAGIC {
-                        // Might be padding or EOF, break
-                        break;
-                    }
+    /// How big and how old the WAL is, for `CompactionPolicy`. Cheap when the
+    /// tail is known, otherwise the WAL is read once to find it.
+    pub fn stats(&mut self) -> Result<WalStats, StorageError> {
+        if let Some(tail) = self.known_tail()? {
+            return Ok(tail.stats());
+        }
+
+        let (entries, _) = self.scan()?;
+        Ok(WalStats {
+            bytes: self.wal_len()?,
+            entries: entries.len() as u64,
+            oldest: entries.first().map(|(_, entry, _)| entry.timestamp),
+        })
+    }
 
-                    // Read data
-                    let mut data_buf = vec![0u8; entry.data_size as usize];
-                    self.file.read_exact(&mut data_buf)?;
-
-                    // Verify checksum with hex decode
-                    let computed = sha256::digest(&data_buf);
-                    let computed_bytes: [u8; 32] = hex::decode(&computed)
-                        .map_err(|_| {
-                            StorageError::Deserialization("Checksum conversion failed".to_string())
-                        })?
-                        .try_into()
-                        .map_err(|_| {
-                            StorageError::Deserialization("Checksum conversion failed".to_string())
-                        })?;
-
-                    if computed_bytes != entry.checksum {
-                        return Err(StorageError::ChecksumMismatch);
+    /// Every intact entry, for readers that mustn't change the file. A torn last
+    /// write is left out instead of cut off, damage anywhere else is an error.
+    pub fn peek_entries(&mut self) -> Result<Vec<WalEntry>, StorageError> {
+        let (entries, damage) = self.scan()?;
+        if let Some(damage) = damage
+            && !damage.torn
+        {
+            return Err(damage.into());
+        }
+
+        Ok(entries
+            .into_iter()
+            .map(|(_, entry, data)| (entry, data))
+            .collect())
+    }
+
+    /// Entries as replay should see them: batch markers are dropped, and so is
+    /// any batch that never got its commit marker, along with everything in it.
+    pub fn read_committed_entries(&mut self) -> Result<Vec<(AppendEntry, Vec<u8>)>, StorageError> {
+        let entries = self.read_all_entries()?;
+        Self::committed(entries)
+    }
+
+    pub(crate) fn committed(entries: Vec<WalEntry>) -> Result<Vec<WalEntry>, StorageError> {
+        let (applied, _) = Self::batch_bounds(
+            entries
+                .iter()
+                .map(|(entry, data)| (entry.entry_type, data.as_slice())),
+        )?;
+
+        let mut applied = applied.into_iter().peekable();
+        Ok(entries
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, entry)| applied.next_if_eq(&i).map(|_| entry))
+            .collect())
+    }
+
+    // the one walk over batch markers both replay and recovery go by: positions
+    // of the entries replay applies, and of the begin marker of a batch still
+    // waiting for its commit when the WAL ends
+    fn batch_bounds<'a>(
+        entries: impl IntoIterator<Item = (u8, &'a [u8])>,
+    ) -> Result<(Vec<usize>, Option<usize>), StorageError> {
+        let mut applied = Vec::new();
+        // where the open batch begins, how many entries it holds and the ones seen
+        let mut open: Option<(usize, usize, Vec<usize>)> = None;
+
+        for (position, (entry_type, data)) in entries.into_iter().enumerate() {
+            match entry_type {
+                // a begin while a batch is open abandons the open one
+                BATCH_BEGIN => open = Some((position, Self::batch_len(data)?, Vec::new())),
+                BATCH_COMMIT => {
+                    if let Some((_, expected, pending)) = open.take()
+                        && pending.len() == expected
+                    {
+                        applied.extend(pending);
                     }
+                }
+                _ => match open.as_mut() {
+                    Some((_, expected, pending)) if pending.len() < *expected => {
+                        pending.push(position);
+                    }
+                    // the batch is full but no commit came, it was torn
+                    Some(_) => {
+                        open = None;
+                        applied.push(position);
+                    }
+                    None => applied.push(position),
+                },
+            }
+        }
+
+        Ok((applied, open.map(|(begin, _, _)| begin)))
+    }
+
+    /// Cuts a batch that never got its commit marker off the end of the WAL, so
+    /// the next entries don't get mistaken for part of it. Returns whether
+    /// anything was cut.
+    pub fn discard_uncommitted(&mut self, lock: &DatabaseLock) -> Result<bool, StorageError> {
+        lock.ensure_exclusive(&self.db_path)?;
+
+        let entries = self.read_entries_with_offsets()?;
+        let Some(offset) = Self::open_batch_at(&entries)? else {
+            return Ok(false);
+        };
+
+        self.ensure_writable()?;
+        self.tail = None;
+        self.backend.truncate(&self.path, offset)?;
+        Ok(true)
+    }
+
+    // where a batch without its commit marker starts, if the WAL ends in one.
+    // One that is followed by other entries was torn and stays, see `committed`
+    fn open_batch_at(entries: &[WalEntryAt]) -> Result<Option<u64>, StorageError> {
+        let (_, open) = Self::batch_bounds(
+            entries
+                .iter()
+                .map(|(_, entry, data)| (entry.entry_type, data.as_slice())),
+        )?;
+        Ok(open
+            .and_then(|begin| entries.get(begin))
+            .map(|(offset, _, _)| *offset))
+    }
+
+    // the cached tail if the file still ends with the entry it points at,
+    // otherwise one from a full scan. None if the WAL isn't clean: damaged,
+    // ending in an uncommitted batch or holding entries that were compacted
+    fn known_tail(&mut self) -> Result<Option<WalTail>, StorageError> {
+        // buffered entries aren't in the file yet, but under the lock they were
+        // buffered with nobody else can have written since
+        if !self.pending.is_empty() {
+            if self.tail.is_some() {
+                return Ok(self.tail.clone());
+            }
+            self.flush()?;
+        }
+
+        let len = self.wal_len()?;
+
+        // an empty WAL says nothing about compactions since, so it's always rebuilt
+        let cached = match self.tail.take() {
+            Some(tail) if tail.len == len && len > 0 => {
+                let last_len = (tail.len - tail.last_at) as usize;
+                let last = self.backend.read_at(&self.path, tail.last_at, last_len)?;
+                (last.len() == last_len && Self::checksum_of(&last)? == tail.next.1).then_some(tail)
+            }
+            _ => None,
+        };
+
+        self.tail = match cached {
+            Some(tail) => Some(tail),
+            None => self.summarize()?,
+        };
+        Ok(self.tail.clone())
+    }
+
+    fn summarize(&mut self) -> Result<Option<WalTail>, StorageError> {
+        let (entries, damage) = self.scan()?;
+        if damage.is_some() || Self::open_batch_at(&entries)?.is_some() {
+            return Ok(None);
+        }
 
-                    entries.push((entry, data_buf));
+        let watermark = RecoveryManager::last_compacted_lsn_in(&self.backend, &self.db_path)?;
+        let next_lsn = match entries
+            .iter()
+            .rev()
+            .find(|(_, entry, _)| entry.is_chained())
+        {
+            Some((_, entry, _)) if entry.lsn <= watermark => return Ok(None),
+            Some((_, entry, _)) => entry.lsn + 1,
+            None => watermark + 1,
+        };
+
+        let (last_at, prev_hash) = match entries.last() {
+            Some((offset, entry, data)) => (*offset, Self::entry_hash(entry, data)?),
+            None => (0, [0; 32]),
+        };
+        let len = self.wal_len()?;
+        let count = entries.len() as u64;
+        let oldest = entries.first().map(|(_, entry, _)| entry.timestamp);
+
+        let committed = Self::committed(
+            entries
+                .into_iter()
+                .map(|(_, entry, data)| (entry, data))
+                .collect(),
+        )?;
+        let tip = match committed
+            .iter()
+            .rev()
+            .find(|(entry, _)| entry.entry_type == 1)
+        {
+            Some((entry, data)) => {
+                let record = entry.record(data)?;
+                Some((record.index, record.record_hash))
+            }
+            None => None,
+        };
+
+        Ok(Some(WalTail {
+            len,
+            last_at,
+            next: (next_lsn, prev_hash),
+            tip,
+            entries: count,
+            oldest,
+        }))
+    }
+
+    fn batch_len(data: &[u8]) -> Result<usize, StorageError> {
+        let count: [u8; 4] = data.try_into().map_err(|_| {
+            StorageError::Deserialization("Batch marker has the wrong size".to_string())
+        })?;
+        Ok(u32::from_le_bytes(count) as usize)
+    }
+
+    /// Reads the whole WAL, stopping at the first entry that doesn't check out.
+    /// Everything before the damage offset is intact. Buffered entries are
+    /// written out first.
+    pub fn scan(&mut self) -> Result<(Vec<WalEntryAt>, Option<WalDamage>), StorageError> {
+        self.flush()?;
+        let bytes = self.backend.read(&self.path)?;
+
+        let mut entries = Vec::new();
+        let mut offset = 0usize;
+
+        while let Some(rest) = bytes.get(offset..).filter(|rest| !rest.is_empty()) {
+            let position = entries.len();
+            let damage = |kind, torn| WalDamage {
+                offset: offset as u64,
+                entry: position,
+                kind,
+                torn,
+                tail_bytes: rest.len() as u64,
+            };
+
+            // filesystems can leave zeroes where a write never landed
+            if rest.iter().all(|b| *b == 0) {
+                return Ok((entries, Some(damage(WalDamageKind::ZeroFilled, true))));
+            }
+
+            let Some(magic) = rest.get(..4) else {
+                return Ok((entries, Some(damage(WalDamageKind::TruncatedHeader, true))));
+            };
+            let header_size = match magic {
+                m if m == APPEND_MAGIC => ENTRY_HEADER_SIZE,
+                m if m == LEGACY_MAGIC => LEGACY_HEADER_SIZE,
+                _ => return Ok((entries, Some(damage(WalDamageKind::BadMagic, false)))),
+            };
+
+            let Some(header) = rest.get(..header_size) else {
+                return Ok((entries, Some(damage(WalDamageKind::TruncatedHeader, true))));
+            };
+            let entry = AppendEntry::from_bytes(header)?;
+
+            let end = header_size + entry.data_size as usize;
+            let Some(data) = rest.get(header_size..end) else {
+                // a write cut short is the last thing in the file, another
+                // entry after this header means its data_size is wrong
+                let torn = !Self::holds_header(rest.get(header_size..).unwrap_or_default());
+                return Ok((entries, Some(damage(WalDamageKind::TruncatedData, torn))));
+            };
+
+            // a bad last entry is most likely a write the crash cut short
+            if Self::checksum_of(data)? != entry.checksum {
+                let torn = end == rest.len();
+                return Ok((entries, Some(damage(WalDamageKind::ChecksumMismatch, torn))));
+            }
+
+            // intact on its own but in the wrong place, no crash does that
+            if entry.is_chained() {
+                let prev = entries.last();
+                let expected_prev = match prev {
+                    Some((_, prev, prev_data)) => Self::entry_hash(prev, prev_data)?,
+                    None => [0; 32],
+                };
+                if entry.prev_hash != expected_prev {
+                    return Ok((entries, Some(damage(WalDamageKind::BrokenChain, false))));
+                }
+
+                let in_order = match prev {
+                    Some((_, prev, _)) if prev.is_chained() => entry.lsn == prev.lsn + 1,
+                    _ => entry.lsn > 0,
+                };
+                if !in_order {
+                    return Ok((entries, Some(damage(WalDamageKind::LsnOutOfOrder, false))));
                 }
-                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
-                Err(e) => return Err(e.into()),
             }
+
+            entries.push((offset as u64, entry, data.to_vec()));
+            offset += end;
+        }
+
+        Ok((entries, None))
+    }
+
+    // whether an entry header starts anywhere in `bytes`
+    fn holds_header(bytes: &[u8]) -> bool {
+        bytes
+            .windows(APPEND_MAGIC.len())
+            .any(|w| w == APPEND_MAGIC || w == LEGACY_MAGIC)
+    }
+
+    /// Cuts off what a crash mid-write left at the end of the WAL, after moving
+    /// it to `.wal.corrupt`, plus any batch that never got its commit marker.
+    /// Damage further up is not something a crash does, that is refused here
+    /// and left for `repair`.
+    pub fn recover_tail(&mut self, lock: &DatabaseLock) -> Result<WalRepair, StorageError> {
+        lock.ensure_exclusive(&self.db_path)?;
+
+        let mut repair = WalRepair::default();
+        if self.known_tail()?.is_some() {
+            return Ok(repair);
+        }
+
+        match self.scan()?.1 {
+            Some(damage) if damage.torn => {
+                repair.quarantined = self.cut(&damage, true)?;
+                repair.damage = Some(damage);
+            }
+            Some(damage) => return Err(damage.into()),
+            None => {}
+        }
+
+        repair.uncommitted_discarded = self.discard_uncommitted(lock)?;
+        Ok(repair)
+    }
+
+    /// Keeps the longest valid prefix of the WAL, whatever is wrong after it.
+    /// The rest goes to `.wal.corrupt` unless `quarantine` is false.
+    pub fn repair(
+        &mut self,
+        lock: &DatabaseLock,
+        quarantine: bool,
+    ) -> Result<WalRepair, StorageError> {
+        lock.ensure_exclusive(&self.db_path)?;
+
+        let mut repair = WalRepair::default();
+        if let Some(damage) = self.scan()?.1 {
+            repair.quarantined = self.cut(&damage, quarantine)?;
+            repair.damage = Some(damage);
+        }
+
+        repair.uncommitted_discarded = self.discard_uncommitted(lock)?;
+        Ok(repair)
+    }
+
+    pub fn corrupt_path(&self) -> PathBuf {
+        PathBuf::from(format!("{}.corrupt", self.path.display()))
+    }
+
+    fn cut(
+        &mut self,
+        damage: &WalDamage,
+        quarantine: bool,
+    ) -> Result<Option<PathBuf>, StorageError> {
+        self.ensure_writable()?;
+        let mut quarantined = None;
+
+        if quarantine {
+            let tail_len = usize::try_from(damage.tail_bytes).unwrap_or(usize::MAX);
+            let tail = self.backend.read_at(&self.path, damage.offset, tail_len)?;
+
+            // appended, an earlier quarantine stays in there too
+            let corrupt_path = self.corrupt_path();
+            self.backend.append(&corrupt_path, &tail)?;
+            self.backend.sync(&corrupt_path)?;
+
+            quarantined = Some(corrupt_path);
         }
 
-        Ok(entries)
+        self.tail = None;
+        self.backend.truncate(&self.path, damage.offset)?;
+        Ok(quarantined)
+    }
+
+    // strict read for everything but replay after recovery: a torn tail is just
+    // a write that never happened, any other damage is an error
+    fn read_entries_with_offsets(&mut self) -> Result<Vec<WalEntryAt>, StorageError> {
+        match self.scan()? {
+            (entries, None) => Ok(entries),
+            (entries, Some(damage)) if damage.torn && damage.kind.is_incomplete() => Ok(entries),
+            (_, Some(damage)) if damage.kind == WalDamageKind::ChecksumMismatch => {
+                Err(StorageError::ChecksumMismatch)
+            }
+            (_, Some(damage)) => Err(damage.into()),
+        }
     }
 
+    // only called once the WAL contents are durable somewhere else
     pub fn truncate(&mut self) -> Result<(), StorageError> {
-        self.file.set_len(0)?;
-        self.file.seek(SeekFrom::Start(0))?;
-        Ok(())
+        self.ensure_writable()?;
+        self.tail = None;
+        self.pending.clear();
+        self.backend.truncate(&self.path, 0)
     }
 
     pub fn delete(self) -> Result<(), StorageError> {
-        drop(self.file);
-        std::fs::remove_file(&self.path)?;
-        Ok(())
+        self.ensure_writable()?;
+        self.backend.remove(&self.path)
     }
 }
 
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
 #[cfg(test)]
 mod tests {
     #![allow(clippy::unwrap_used)]
@@ -239,7 +1070,8 @@ mod tests {
 
     use super::*;
     use crate::core::{Record, User};
-    use std::fs;
+    use std::fs::{self, OpenOptions};
+    use std::io::{Read, Seek, SeekFrom, Write};
 
     fn cleanup_test_files(base_path: &str) {
         let _ = fs::remove_file(base_path);
@@ -254,7 +1086,7 @@ mod tests {
     #[test]
     fn test_entry_serialization() {
         let checksum = [0u8; 32];
-        let entry = AppendEntry::new(1, 100, checksum);
+        let entry = AppendEntry::new(1, 7, [3u8; 32], 100, checksum);
 
         let bytes = entry.to_bytes();
         let entry2 = AppendEntry::from_bytes(&bytes).unwrap();
@@ -264,6 +1096,8 @@ mod tests {
         assert_eq!(entry.timestamp, entry2.timestamp);
         assert_eq!(entry.data_size, entry2.data_size);
         assert_eq!(entry.checksum, entry2.checksum);
+        assert_eq!(entry.lsn, entry2.lsn);
+        assert_eq!(entry.prev_hash, entry2.prev_hash);
     }
 
     #[test]
@@ -475,4 +1309,325 @@ mod tests {
 
         cleanup_test_files(test_path);
     }
+
+    #[test]
+    fn test_entries_are_chained() {
+        let test_path = "test_chained";
+        cleanup_test_files(test_path);
+
+        let mut append_log = AppendLog::new(test_path).unwrap();
+        append_log.append_user(&User::new("first")).unwrap();
+        append_log.append_user(&User::new("second")).unwrap();
+        drop(append_log);
+
+        // picks up where the file left off
+        let mut append_log = AppendLog::new(test_path).unwrap();
+        append_log.append_user(&User::new("third")).unwrap();
+
+        let entries = append_log.read_all_entries().unwrap();
+        let lsns: Vec<u64> = entries.iter().map(|(entry, _)| entry.lsn).collect();
+        assert_eq!(lsns, vec![1, 2, 3]);
+        assert_eq!(entries[0].0.prev_hash, [0; 32]);
+        assert_eq!(
+            entries[2].0.prev_hash,
+            AppendLog::entry_hash(&entries[1].0, &entries[1].1).unwrap()
+        );
+
+        cleanup_test_files(test_path);
+    }
+
+    #[test]
+    fn test_stale_tail_is_not_trusted() {
+        let test_path = "test_stale_tail";
+        cleanup_test_files(test_path);
+
+        let mut first = AppendLog::new(test_path).unwrap();
+        first.append_user(&User::new("first")).unwrap();
+        let tail = first.tail().unwrap();
+        drop(first);
+
+        // someone else appends behind the cached tail's back
+        let mut other = AppendLog::new(test_path).unwrap();
+        other.append_user(&User::new("second")).unwrap();
+        drop(other);
+
+        let mut append_log = AppendLog::new(test_path).unwrap().with_tail(Some(tail));
+        append_log.append_user(&User::new("third")).unwrap();
+
+        let lsns: Vec<u64> = append_log
+            .read_all_entries()
+            .unwrap()
+            .iter()
+            .map(|(entry, _)| entry.lsn)
+            .collect();
+        assert_eq!(lsns, vec![1, 2, 3]);
+
+        cleanup_test_files(test_path);
+    }
+
+    #[test]
+    fn test_moved_entries_are_caught() {
+        let test_path = "test_moved_entries";
+        cleanup_test_files(test_path);
+
+        let mut append_log = AppendLog::new(test_path).unwrap();
+        for i in 0..3 {
+            append_log
+                .append_user(&User::new(&format!("user_{}", i)))
+                .unwrap();
+        }
+        let (entries, _) = append_log.scan().unwrap();
+        drop(append_log);
+
+        let wal_path = format!("{}.wal", test_path);
+        let bytes = fs::read(&wal_path).unwrap();
+        let (first, second, third) = (entries[0].0, entries[1].0, entries[2].0);
+        let entry = |from: u64, to: u64| &bytes[from as usize..to as usize];
+        let len = bytes.len() as u64;
+
+        let expect = |wal: Vec<u8>, at: usize, kind: WalDamageKind| {
+            fs::write(&wal_path, wal).unwrap();
+            let mut append_log = AppendLog::new(test_path).unwrap();
+            let damage = append_log.scan().unwrap().1.unwrap();
+            assert_eq!((damage.entry, damage.kind), (at, kind));
+            assert!(!damage.torn);
+        };
+
+        // dropped, swapped and replayed entries all check out on their own
+        expect(
+            [entry(first, second), entry(third, len)].concat(),
+            1,
+            WalDamageKind::BrokenChain,
+        );
+        expect(
+            [
+                entry(first, second),
+                entry(third, len),
+                entry(second, third),
+            ]
+            .concat(),
+            1,
+            WalDamageKind::BrokenChain,
+        );
+        expect(
+            [&bytes[..], entry(third, len)].concat(),
+            3,
+            WalDamageKind::BrokenChain,
+        );
+
+        // a rewritten LSN on the last entry, nothing chains to it yet
+        let mut renumbered = bytes.clone();
+        renumbered[third as usize + 49] = 7;
+        expect(renumbered, 2, WalDamageKind::LsnOutOfOrder);
+
+        cleanup_test_files(test_path);
+    }
+
+    #[test]
+    fn test_torn_batch_is_discarded() {
+        use crate::core::Ledger;
+        use crate::storage::writer::DatabaseWriter;
+
+        let test_path = "test_torn_batch";
+        let backend = backend::memory();
+
+        let mut ledger = Ledger::new();
+        let user = User::new("batch_user");
+        ledger.register_user(user.clone());
+        DatabaseWriter::new_in(&backend, test_path)
+            .unwrap()
+            .write_ledger(&ledger)
+            .unwrap();
+
+        let lock = DatabaseLock::exclusive_in(&backend, test_path).unwrap();
+        let mut append_log = AppendLog::new_in(&backend, test_path).unwrap();
+        ledger
+            .add_record("bid received", vec![user.clone()])
+            .unwrap();
+        ledger.add_record("bid document", vec![user]).unwrap();
+        append_log
+            .append_batch(&ledger.records[1..], &lock)
+            .unwrap();
+        assert_eq!(append_log.read_committed_entries().unwrap().len(), 2);
+
+        // crash before the commit marker made it to disk
+        let wal_path = AppendLog::path_for(test_path);
+        let wal_len = backend.size(&wal_path).unwrap().unwrap();
+        backend
+            .truncate(&wal_path, wal_len - (ENTRY_HEADER_SIZE + 4) as u64)
+            .unwrap();
+        assert_eq!(append_log.read_all_entries().unwrap().len(), 3);
+        assert!(append_log.read_committed_entries().unwrap().is_empty());
+
+        assert!(append_log.discard_uncommitted(&lock).unwrap());
+        assert!(append_log.read_all_entries().unwrap().is_empty());
+        drop(lock);
+
+        let recovered = RecoveryManager::recover_ledger_in(&backend, test_path).unwrap();
+        assert_eq!(recovered.length(), 1);
+    }
+
+    #[test]
+    fn test_unterminated_batch_then_single_append() {
+        use crate::core::Ledger;
+        use crate::storage::writer::DatabaseWriter;
+
+        let test_path = "test_unterminated_batch";
+        let backend = backend::memory();
+
+        let mut ledger = Ledger::new();
+        let user = User::new("batch_user");
+        ledger.register_user(user.clone());
+        DatabaseWriter::new_in(&backend, test_path)
+            .unwrap()
+            .write_ledger(&ledger)
+            .unwrap();
+
+        let lock = DatabaseLock::exclusive_in(&backend, test_path).unwrap();
+        let mut append_log = AppendLog::new_in(&backend, test_path).unwrap();
+        ledger
+            .add_record("bid received", vec![user.clone()])
+            .unwrap();
+        ledger
+            .add_record("bid document", vec![user.clone()])
+            .unwrap();
+        append_log
+            .append_batch(&ledger.records[1..], &lock)
+            .unwrap();
+
+        // the commit marker never made it, and a writer that didn't recover
+        // first appended after the batch
+        let wal_path = AppendLog::path_for(test_path);
+        let wal_len = backend.size(&wal_path).unwrap().unwrap();
+        backend
+            .truncate(&wal_path, wal_len - (ENTRY_HEADER_SIZE + 4) as u64)
+            .unwrap();
+        ledger.add_record("late bid", vec![user]).unwrap();
+        append_log.append_record(&ledger.records[3]).unwrap();
+
+        // replay and recovery agree the batch was torn and the append stands
+        let committed = append_log.read_committed_entries().unwrap();
+        assert_eq!(committed.len(), 1);
+        assert_eq!(committed[0].0.record(&committed[0].1).unwrap().index, 3);
+        assert!(!append_log.discard_uncommitted(&lock).unwrap());
+        assert_eq!(append_log.read_all_entries().unwrap().len(), 4);
+        assert_eq!(append_log.read_committed_entries().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_torn_tail_is_cut() {
+        let test_path = "test_torn_tail";
+        let backend = backend::memory();
+
+        let mut append_log = AppendLog::new_in(&backend, test_path).unwrap();
+        append_log.append_user(&User::new("kept")).unwrap();
+        append_log.append_user(&User::new("torn")).unwrap();
+        let (entries, _) = append_log.scan().unwrap();
+        let torn_at = entries[1].0;
+
+        let wal_path = AppendLog::path_for(test_path);
+        let wal_len = backend.size(&wal_path).unwrap().unwrap();
+        backend.truncate(&wal_path, wal_len - 10).unwrap();
+
+        // reads stop at the torn entry instead of failing
+        assert_eq!(append_log.read_all_entries().unwrap().len(), 1);
+
+        let lock = DatabaseLock::exclusive_in(&backend, test_path).unwrap();
+        let repair = append_log.recover_tail(&lock).unwrap();
+        let damage = repair.damage.unwrap();
+        assert_eq!(damage.entry, 1);
+        assert_eq!(damage.offset, torn_at);
+        assert_eq!(damage.kind, WalDamageKind::TruncatedData);
+        assert!(damage.torn);
+
+        let corrupt_path = repair.quarantined.unwrap();
+        assert_eq!(
+            backend.size(&corrupt_path).unwrap(),
+            Some(damage.tail_bytes)
+        );
+        assert_eq!(backend.size(&wal_path).unwrap(), Some(torn_at));
+    }
+
+    #[test]
+    fn test_damage_mid_log_needs_repair() {
+        let test_path = "test_mid_log_damage";
+        cleanup_test_files(test_path);
+
+        let mut append_log = AppendLog::new(test_path).unwrap();
+        for i in 0..3 {
+            append_log
+                .append_user(&User::new(&format!("user_{}", i)))
+                .unwrap();
+        }
+        let (entries, _) = append_log.scan().unwrap();
+        let bad_at = entries[1].0;
+        drop(append_log);
+
+        let wal_path = format!("{}.wal", test_path);
+        let mut bytes = fs::read(&wal_path).unwrap();
+        bytes[bad_at as usize + ENTRY_HEADER_SIZE + 2] ^= 0xFF;
+        fs::write(&wal_path, bytes).unwrap();
+
+        let lock = DatabaseLock::exclusive(test_path).unwrap();
+        let mut append_log = AppendLog::new(test_path).unwrap();
+        assert!(matches!(
+            append_log.recover_tail(&lock),
+            Err(StorageError::CorruptWal { entry: 1, offset, .. }) if offset == bad_at
+        ));
+        assert!(matches!(
+            append_log.read_all_entries(),
+            Err(StorageError::ChecksumMismatch)
+        ));
+
+        let repair = append_log.repair(&lock, false).unwrap();
+        assert_eq!(repair.damage.unwrap().kind, WalDamageKind::ChecksumMismatch);
+        assert!(repair.quarantined.is_none());
+        assert!(!append_log.corrupt_path().exists());
+        assert_eq!(append_log.read_all_entries().unwrap().len(), 1);
+
+        drop(lock);
+        cleanup_test_files(test_path);
+        let _ = fs::remove_file(DatabaseLock::path_for(test_path));
+    }
+
+    #[test]
+    fn test_bad_data_size_mid_log_is_not_torn() {
+        let test_path = "test_bad_data_size";
+        let backend = backend::memory();
+
+        let mut append_log = AppendLog::new_in(&backend, test_path).unwrap();
+        for i in 0..3 {
+            append_log
+                .append_user(&User::new(&format!("user_{}", i)))
+                .unwrap();
+        }
+        let (entries, _) = append_log.scan().unwrap();
+        let bad_at = entries[1].0 as usize;
+
+        // data_size now runs past the end of the file
+        let wal_path = AppendLog::path_for(test_path);
+        let mut bytes = backend.read(&wal_path).unwrap();
+        bytes[bad_at + 13..bad_at + 17].copy_from_slice(&u32::MAX.to_le_bytes());
+        backend.replace(&wal_path, &bytes).unwrap();
+
+        let mut append_log = AppendLog::new_in(&backend, test_path).unwrap();
+        let damage = append_log.scan().unwrap().1.unwrap();
+        assert_eq!(damage.kind, WalDamageKind::TruncatedData);
+        assert!(!damage.torn);
+        assert!(matches!(
+            append_log.read_all_entries(),
+            Err(StorageError::CorruptWal { entry: 1, .. })
+        ));
+
+        // the entries after it aren't cut on open, only by repair
+        let lock = DatabaseLock::exclusive_in(&backend, test_path).unwrap();
+        assert!(matches!(
+            append_log.recover_tail(&lock),
+            Err(StorageError::CorruptWal { entry: 1, offset, .. }) if offset == bad_at as u64
+        ));
+        assert_eq!(backend.read(&wal_path).unwrap(), bytes);
+
+        append_log.repair(&lock, false).unwrap();
+        assert_eq!(append_log.read_all_entries().unwrap().len(), 1);
+    }
 }
@@ -0,0 +1,370 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::error::StorageError;
+use crate::storage::backend::{Backend, FileVersion, LockGuard, SegmentBytes, StorageBackend};
+use crate::storage::lock::{DatabaseLock, LockMode};
+use crate::storage::object_store::{Store, check_object, object_key};
+use crate::storage::reader::verify_layout;
+use crate::storage::segment::{Manifest, SegmentInfo};
+
+/// What `ArchiveBackend::push` did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PushReport {
+    pub uploaded: usize,
+    /// already in the store, only their keys were recorded
+    pub already_stored: usize,
+    pub bytes: u64,
+}
+
+/// A backend whose sealed segments also live in an object store.
+///
+/// Everything is written to `local` as usual. `push` copies sealed segments to
+/// the store and records their keys in the manifest, and `evict` then drops
+/// the local copies. A segment read while it is missing locally is fetched
+/// and cached in `local` again, so the local segments are just a cache.
+///
+/// The store isn't trusted: a fetched segment must hash to its key and match
+/// its manifest entry before it is cached, and readers verify it again like
+/// any other segment.
+#[derive(Debug)]
+pub struct ArchiveBackend {
+    local: Backend,
+    store: Store,
+    // one fetch at a time, so readers don't race to cache the same segment
+    fetching: Mutex<()>,
+}
+
+impl ArchiveBackend {
+    pub fn new(local: Backend, store: Store) -> Self {
+        Self {
+            local,
+            store,
+            fetching: Mutex::new(()),
+        }
+    }
+
+    pub fn local(&self) -> &Backend {
+        &self.local
+    }
+
+    pub fn store(&self) -> &Store {
+        &self.store
+    }
+
+    /// Uploads every sealed segment the store doesn't have yet and records its
+    /// key in the manifest. Single-file ledgers have nothing sealed to push.
+    pub fn push<P: AsRef<Path>>(&self, db_path: P) -> Result<PushReport, StorageError> {
+        let db_path = db_path.as_ref();
+        let _lock = DatabaseLock::exclusive_in(&self.local, db_path)?;
+        let Some(mut manifest) = Manifest::load_in(&self.local, db_path)? else {
+            return Ok(PushReport::default());
+        };
+
+        let paths: Vec<PathBuf> = manifest
+            .segments
+            .iter()
+            .map(|segment| manifest.segment_path(db_path, segment))
+            .collect();
+        let mut report = PushReport::default();
+
+        for (segment, path) in manifest.segments.iter_mut().zip(paths) {
+            if segment.object.is_some() {
+                continue;
+            }
+
+            // a damaged segment is better left where someone will notice
+            let bytes = self.local.read(&path)?;
+            Self::check_segment(segment, &bytes)?;
+
+            let key = object_key(&bytes);
+            if self.store.head(&key)? == Some(bytes.len() as u64) {
+                report.already_stored += 1;
+            } else {
+                self.store.put(&key, &bytes)?;
+                report.uploaded += 1;
+                report.bytes += bytes.len() as u64;
+            }
+            segment.object = Some(key);
+        }
+
+        if report.uploaded + report.already_stored > 0 {
+            manifest.save(&self.local, db_path)?;
+        }
+
+        Ok(report)
+    }
+
+    /// Removes the local copies of pushed segments, once the store confirms it
+    /// holds them. The database file itself always stays. Returns how many went.
+    pub fn evict<P: AsRef<Path>>(&self, db_path: P) -> Result<usize, StorageError> {
+        let db_path = db_path.as_ref();
+        let _lock = DatabaseLock::exclusive_in(&self.local, db_path)?;
+        let Some(manifest) = Manifest::load_in(&self.local, db_path)? else {
+            return Ok(0);
+        };
+
+        let segments_dir = Manifest::segments_dir(db_path);
+        let mut evicted = 0;
+
+        for segment in &manifest.segments {
+            let Some(key) = &segment.object else {
+                continue;
+            };
+            let path = manifest.segment_path(db_path, segment);
+            if path.parent() != Some(segments_dir.as_path()) || !self.local.exists(&path)? {
+                continue;
+            }
+
+            if self.store.head(key)? != Some(segment.size_bytes) {
+                return Err(StorageError::ObjectStore(format!(
+                    "Segment {} is not in the object store as {}",
+                    segment.file, key
+                )));
+            }
+
+            self.local.remove(&path)?;
+            evicted += 1;
+        }
+
+        Ok(evicted)
+    }
+
+    fn check_segment(segment: &SegmentInfo, bytes: &[u8]) -> Result<(), StorageError> {
+        let (header, _body) = verify_layout(bytes)?;
+        Manifest::check_segment(
+            segment,
+            header.first_record_index,
+            header.record_count,
+            &header.checksum,
+        )
+    }
+
+    // `<dir>/<db>.segments/<file>` belongs to the database `<dir>/<db>`
+    fn database_for(path: &Path) -> Option<PathBuf> {
+        let dir = path.parent()?;
+        let name = dir.file_name()?.to_str()?.strip_suffix(".segments")?;
+        Some(dir.with_file_name(name))
+    }
+
+    fn fetch_if_missing(&self, path: &Path) -> Result<(), StorageError> {
+        let Some(db_path) = Self::database_for(path) else {
+            return Ok(());
+        };
+        if self.local.exists(path)? {
+            return Ok(());
+        }
+
+        let _fetching = self.fetching.lock().map_err(|_| StorageError::Poisoned)?;
+        if self.local.exists(path)? {
+            return Ok(());
+        }
+
+        let Some(manifest) = Manifest::load_in(&self.local, &db_path)? else {
+            return Ok(());
+        };
+        let Some(segment) = manifest
+            .segments
+            .iter()
+            .find(|segment| manifest.segment_path(&db_path, segment) == path)
+        else {
+            return Ok(());
+        };
+        let Some(key) = &segment.object else {
+            return Ok(());
+        };
+
+        let bytes = self.store.get(key)?.ok_or_else(|| {
+            StorageError::ObjectStore(format!(
+                "Segment {} is neither cached nor in the object store",
+                segment.file
+            ))
+        })?;
+        check_object(key, &bytes)?;
+        Self::check_segment(segment, &bytes)?;
+
+        self.local.replace(path, &bytes)
+    }
+}
+
+impl StorageBackend for ArchiveBackend {
+    fn size(&self, path: &Path) -> Result<Option<u64>, StorageError> {
+        self.fetch_if_missing(path)?;
+        self.local.size(path)
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>, StorageError> {
+        self.fetch_if_missing(path)?;
+        self.local.read(path)
+    }
+
+    fn read_at(&self, path: &Path, offset: u64, len: usize) -> Result<Vec<u8>, StorageError> {
+        self.fetch_if_missing(path)?;
+        self.local.read_at(path, offset, len)
+    }
+
+    fn read_segment(&self, path: &Path) -> Result<SegmentBytes, StorageError> {
+        self.fetch_if_missing(path)?;
+        self.local.read_segment(path)
+    }
+
+    fn version(&self, path: &Path) -> Result<Option<FileVersion>, StorageError> {
+        self.fetch_if_missing(path)?;
+        self.local.version(path)
+    }
+
+    fn append(&self, path: &Path, bytes: &[u8]) -> Result<(), StorageError> {
+        self.local.append(path, bytes)
+    }
+
+    fn sync(&self, path: &Path) -> Result<(), StorageError> {
+        self.local.sync(path)
+    }
+
+    fn truncate(&self, path: &Path, len: u64) -> Result<(), StorageError> {
+        self.local.truncate(path, len)
+    }
+
+    fn replace(&self, path: &Path, contents: &[u8]) -> Result<(), StorageError> {
+        self.local.replace(path, contents)
+    }
+
+    fn restore_backup(&self, path: &Path) -> Result<(), StorageError> {
+        self.local.restore_backup(path)
+    }
+
+    fn remove(&self, path: &Path) -> Result<(), StorageError> {
+        self.local.remove(path)
+    }
+
+    fn list(&self, dir: &Path) -> Result<Vec<PathBuf>, StorageError> {
+        self.local.list(dir)
+    }
+
+    fn lock(&self, path: &Path, mode: LockMode) -> Result<Box<dyn LockGuard>, StorageError> {
+        self.local.lock(path, mode)
+    }
+
+    fn try_lock(
+        &self,
+        path: &Path,
+        mode: LockMode,
+    ) -> Result<Option<Box<dyn LockGuard>>, StorageError> {
+        self.local.try_lock(path, mode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::indexing_slicing)]
+
+    use super::*;
+    use crate::UkweliDb;
+    use crate::core::User;
+    use crate::storage::backend;
+    use crate::storage::object_store::DirStore;
+    use crate::storage::segment::read_sealed_record_in;
+    use std::fs;
+    use std::sync::Arc;
+
+    fn archived_db(dir: &Path) -> (Arc<ArchiveBackend>, Backend, &'static str) {
+        let archive = Arc::new(ArchiveBackend::new(
+            backend::memory(),
+            Arc::new(DirStore::new(dir)),
+        ));
+        let as_backend: Backend = archive.clone();
+        let db_path = "test_archive.ukweli";
+
+        let db = UkweliDb::create_in(&as_backend, db_path).unwrap();
+        let user = User::new("0xElvis");
+        db.register_user(user.clone()).unwrap();
+        db.append_record("sealed and shipped", vec![user.clone()])
+            .unwrap();
+        db.compact().unwrap();
+        db.append_record("sealed later", vec![user]).unwrap();
+        db.compact().unwrap();
+
+        (archive, as_backend, db_path)
+    }
+
+    fn segment_paths(backend: &Backend, db_path: &str) -> Vec<PathBuf> {
+        let manifest = Manifest::load_in(backend, db_path).unwrap().unwrap();
+        manifest
+            .segments
+            .iter()
+            .skip(1)
+            .map(|segment| manifest.segment_path(db_path, segment))
+            .collect()
+    }
+
+    #[test]
+    fn test_push_evict_and_fetch() {
+        let dir = tempfile::tempdir().unwrap();
+        let (archive, backend, db_path) = archived_db(dir.path());
+
+        let report = archive.push(db_path).unwrap();
+        assert_eq!(report.uploaded, 3);
+        assert_eq!(archive.push(db_path).unwrap(), PushReport::default());
+        assert_eq!(archive.store().list("segments/").unwrap().len(), 3);
+
+        // the database file stays, both sealed segments go
+        assert_eq!(archive.evict(db_path).unwrap(), 2);
+        let segments = segment_paths(archive.local(), db_path);
+        assert!(
+            segments
+                .iter()
+                .all(|path| !archive.local().exists(path).unwrap())
+        );
+
+        // one record fetches just the segments it needs
+        let record = read_sealed_record_in(&backend, db_path, 1)
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.index, 1);
+        assert!(archive.local().exists(&segments[0]).unwrap());
+        assert!(!archive.local().exists(&segments[1]).unwrap());
+
+        let reopened = UkweliDb::open_read_only_in(&backend, db_path).unwrap();
+        assert_eq!(reopened.length().unwrap(), 3);
+        assert!(reopened.verify_chain().unwrap());
+        assert!(archive.local().exists(&segments[1]).unwrap());
+    }
+
+    #[test]
+    fn test_tampered_object_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let (archive, backend, db_path) = archived_db(dir.path());
+        archive.push(db_path).unwrap();
+        archive.evict(db_path).unwrap();
+
+        let manifest = Manifest::load_in(archive.local(), db_path)
+            .unwrap()
+            .unwrap();
+        let keys: Vec<String> = manifest
+            .segments
+            .iter()
+            .filter_map(|segment| segment.object.clone())
+            .collect();
+        let object = |key: &str| dir.path().join(key);
+
+        // a valid segment, just not the one asked for
+        fs::copy(object(&keys[0]), object(&keys[1])).unwrap();
+        assert!(UkweliDb::open_read_only_in(&backend, db_path).is_err());
+
+        // the right segment, changed on the way
+        let mut bytes = fs::read(object(&keys[2])).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        fs::write(object(&keys[2]), &bytes).unwrap();
+        assert!(read_sealed_record_in(&backend, db_path, 2).is_err());
+
+        // nothing bad was cached
+        let segments = segment_paths(archive.local(), db_path);
+        assert!(
+            segments
+                .iter()
+                .all(|path| !archive.local().exists(path).unwrap())
+        );
+    }
+}
@@ -0,0 +1,133 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::error::StorageError;
+
+pub fn tmp_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    PathBuf::from(format!("{}.tmp", path.as_ref().display()))
+}
+
+pub fn backup_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    PathBuf::from(format!("{}.backup", path.as_ref().display()))
+}
+
+/// Replaces `path` with `contents` so a crash at any point leaves either the old
+/// file or the new one, never a mix.
+///
+/// The new contents go to `<path>.tmp` and are fsynced, the current file is kept as
+/// `<path>.backup`, then the temp file is renamed over `path` and the directory is
+/// fsynced so the rename itself survives a power cut.
+pub fn replace_file<P: AsRef<Path>>(path: P, contents: &[u8]) -> Result<(), StorageError> {
+    let path = path.as_ref();
+    let tmp = tmp_path(path);
+
+    write_synced(&tmp, contents)?;
+
+    // empty files are placeholders, there is nothing worth keeping
+    if fs::metadata(path).map(|m| m.len() > 0).unwrap_or(false) {
+        copy_synced(path, &backup_path(path))?;
+    }
+
+    fs::rename(&tmp, path)?;
+    sync_parent_dir(path)
+}
+
+/// Puts `<path>.backup` back in place of a damaged `path`, same crash guarantees
+/// as `replace_file`. The damaged file is not kept as the new backup.
+pub fn restore_backup<P: AsRef<Path>>(path: P) -> Result<(), StorageError> {
+    let path = path.as_ref();
+    let tmp = tmp_path(path);
+
+    copy_synced(&backup_path(path), &tmp)?;
+    fs::rename(&tmp, path)?;
+    sync_parent_dir(path)
+}
+
+fn write_synced(path: &Path, contents: &[u8]) -> Result<(), StorageError> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(path)?;
+
+    file.write_all(contents)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+// goes through a temp file as well, so a crash never leaves half a backup
+fn copy_synced(from: &Path, to: &Path) -> Result<(), StorageError> {
+    let tmp = tmp_path(to);
+    fs::copy(from, &tmp)?;
+    File::open(&tmp)?.sync_all()?;
+    fs::rename(&tmp, to)?;
+    Ok(())
+}
+
+/// fsync on the directory makes renames and new files in it durable.
+pub fn sync_parent_dir<P: AsRef<Path>>(path: P) -> Result<(), StorageError> {
+    let dir = match path.as_ref().parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    sync_dir(&dir)
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> Result<(), StorageError> {
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+// windows can't open directories as files, NTFS journals renames anyway
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> Result<(), StorageError> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    fn cleanup(path: &str) {
+        let _ = fs::remove_file(path);
+        let _ = fs::remove_file(backup_path(path));
+        let _ = fs::remove_file(tmp_path(path));
+    }
+
+    #[test]
+    fn test_replace_keeps_backup() {
+        let path = "test_atomic_replace.bin";
+        cleanup(path);
+
+        replace_file(path, b"first").unwrap();
+        assert!(!backup_path(path).exists());
+
+        replace_file(path, b"second").unwrap();
+        assert_eq!(fs::read(path).unwrap(), b"second");
+        assert_eq!(fs::read(backup_path(path)).unwrap(), b"first");
+        assert!(!tmp_path(path).exists());
+
+        cleanup(path);
+    }
+
+    #[test]
+    fn test_restore_backup() {
+        let path = "test_atomic_restore.bin";
+        cleanup(path);
+
+        replace_file(path, b"good").unwrap();
+        replace_file(path, b"newer").unwrap();
+        fs::write(path, b"dam").unwrap();
+
+        restore_backup(path).unwrap();
+        assert_eq!(fs::read(path).unwrap(), b"good");
+        assert_eq!(fs::read(backup_path(path)).unwrap(), b"good");
+
+        cleanup(path);
+    }
+}
@@ -0,0 +1,632 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File, Metadata, OpenOptions, TryLockError};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::time::SystemTime;
+
+use memmap2::Mmap;
+use rkyv::util::AlignedVec;
+
+use crate::error::StorageError;
+use crate::storage::atomic;
+use crate::storage::lock::LockMode;
+
+pub type Backend = Arc<dyn StorageBackend>;
+
+/// Where a database keeps its files: the database file, segments, manifest,
+/// WAL and lock. Paths are the ones the filesystem would see, other backends
+/// just use them as names.
+///
+/// Functions in `storage` that take a plain path use the filesystem, their
+/// `_in` variants take a backend.
+pub trait StorageBackend: Send + Sync + fmt::Debug {
+    /// Size of the file, `None` if there isn't one.
+    fn size(&self, path: &Path) -> Result<Option<u64>, StorageError>;
+
+    fn exists(&self, path: &Path) -> Result<bool, StorageError> {
+        Ok(self.size(path)?.is_some())
+    }
+
+    /// The whole file. A missing one is an `Io` error of kind `NotFound`.
+    fn read(&self, path: &Path) -> Result<Vec<u8>, StorageError>;
+
+    /// Up to `len` bytes from `offset`, fewer if the file ends first.
+    fn read_at(&self, path: &Path, offset: u64, len: usize) -> Result<Vec<u8>, StorageError>;
+
+    /// A sealed file to read records from in place. Those are never written
+    /// again, so a backend may map them instead of copying.
+    fn read_segment(&self, path: &Path) -> Result<SegmentBytes, StorageError>;
+
+    /// Changes whenever the file is written or replaced, `None` if there is none.
+    fn version(&self, path: &Path) -> Result<Option<FileVersion>, StorageError>;
+
+    /// Adds to the end of the file, creating it and its parent directories if
+    /// needed. Only durable after `sync`.
+    fn append(&self, path: &Path, bytes: &[u8]) -> Result<(), StorageError>;
+
+    fn sync(&self, path: &Path) -> Result<(), StorageError>;
+
+    /// Cuts the file down to `len` bytes and syncs it.
+    fn truncate(&self, path: &Path, len: u64) -> Result<(), StorageError>;
+
+    /// Swaps in `contents` all at once, the current file is kept as
+    /// `<path>.backup`. Missing parent directories are created.
+    fn replace(&self, path: &Path, contents: &[u8]) -> Result<(), StorageError>;
+
+    /// Puts `<path>.backup` back in place of `path`.
+    fn restore_backup(&self, path: &Path) -> Result<(), StorageError>;
+
+    fn remove(&self, path: &Path) -> Result<(), StorageError>;
+
+    /// Files directly in `dir`, in no particular order. Empty if there is no `dir`.
+    fn list(&self, dir: &Path) -> Result<Vec<PathBuf>, StorageError>;
+
+    /// Blocks until it has the lock kept at `path`, see `DatabaseLock`. Shared
+    /// locks never create anything.
+    fn lock(&self, path: &Path, mode: LockMode) -> Result<Box<dyn LockGuard>, StorageError>;
+
+    /// Like `lock` but gives up straight away, `None` if someone else has it.
+    fn try_lock(
+        &self,
+        path: &Path,
+        mode: LockMode,
+    ) -> Result<Option<Box<dyn LockGuard>>, StorageError>;
+}
+
+/// Holds a lock from `StorageBackend::lock` until it is dropped.
+pub trait LockGuard: Send + Sync + fmt::Debug {}
+
+/// The filesystem backend, what every plain path function uses.
+pub fn filesystem() -> Backend {
+    static FILESYSTEM: OnceLock<Backend> = OnceLock::new();
+    Arc::clone(FILESYSTEM.get_or_init(|| Arc::new(FsBackend)))
+}
+
+/// A fresh, empty in-memory backend.
+pub fn memory() -> Backend {
+    Arc::new(MemoryBackend::default())
+}
+
+/// Bytes of a sealed file, see `StorageBackend::read_segment`.
+pub enum SegmentBytes {
+    Mapped(Mmap),
+    Owned(AlignedVec),
+}
+
+impl Deref for SegmentBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Mapped(mmap) => mmap,
+            Self::Owned(bytes) => bytes,
+        }
+    }
+}
+
+/// Enough to notice a file was replaced or rewritten.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileVersion {
+    Disk {
+        len: u64,
+        modified: Option<SystemTime>,
+        #[cfg(unix)]
+        inode: u64,
+    },
+    Generation(u64),
+}
+
+impl FileVersion {
+    fn of(metadata: &Metadata) -> Self {
+        #[cfg(unix)]
+        use std::os::unix::fs::MetadataExt;
+
+        Self::Disk {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            #[cfg(unix)]
+            inode: metadata.ino(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FsBackend;
+
+#[derive(Debug)]
+struct FsLock(Option<File>);
+
+impl LockGuard for FsLock {}
+
+impl Drop for FsLock {
+    // closing the file releases it too, this just doesn't wait for that
+    fn drop(&mut self) {
+        if let Some(file) = &self.0 {
+            let _ = file.unlock();
+        }
+    }
+}
+
+impl FsBackend {
+    // new files may go in a directory nothing was written to yet, like the
+    // segments directory on the first compaction
+    fn ensure_parent(path: &Path) -> Result<(), StorageError> {
+        if let Some(dir) = path.parent()
+            && !dir.as_os_str().is_empty()
+            && !dir.exists()
+        {
+            fs::create_dir_all(dir)?;
+            atomic::sync_parent_dir(dir)?;
+        }
+        Ok(())
+    }
+
+    // only writers create the lock file, a reader without one goes unlocked
+    fn open_lock(path: &Path, mode: LockMode) -> Result<Option<File>, StorageError> {
+        match mode {
+            LockMode::Shared => match File::open(path) {
+                Ok(file) => Ok(Some(file)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.into()),
+            },
+            LockMode::Exclusive => Ok(Some(
+                OpenOptions::new()
+                    .create(true)
+                    .truncate(false)
+                    .write(true)
+                    .open(path)?,
+            )),
+        }
+    }
+}
+
+impl StorageBackend for FsBackend {
+    fn size(&self, path: &Path) -> Result<Option<u64>, StorageError> {
+        match fs::metadata(path) {
+            Ok(metadata) => Ok(Some(metadata.len())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>, StorageError> {
+        Ok(fs::read(path)?)
+    }
+
+    fn read_at(&self, path: &Path, offset: u64, len: usize) -> Result<Vec<u8>, StorageError> {
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(offset))?;
+
+        let mut bytes = Vec::with_capacity(len);
+        file.take(len as u64).read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn read_segment(&self, path: &Path) -> Result<SegmentBytes, StorageError> {
+        let file = File::open(path)?;
+
+        // SAFETY: the map is read only and ukweli replaces database files instead of
+        // editing them, so the mapped bytes don't change while we hold them.
+        let mmap = unsafe { Mmap::map(&file)? };
+        Ok(SegmentBytes::Mapped(mmap))
+    }
+
+    fn version(&self, path: &Path) -> Result<Option<FileVersion>, StorageError> {
+        match fs::metadata(path) {
+            Ok(metadata) => Ok(Some(FileVersion::of(&metadata))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn append(&self, path: &Path, bytes: &[u8]) -> Result<(), StorageError> {
+        let mut file = match OpenOptions::new().append(true).open(path) {
+            Ok(file) => file,
+            // a new file is only there for good once its directory is synced
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Self::ensure_parent(path)?;
+                let file = OpenOptions::new().create(true).append(true).open(path)?;
+                atomic::sync_parent_dir(path)?;
+                file
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        file.write_all(bytes)?;
+        Ok(())
+    }
+
+    fn sync(&self, path: &Path) -> Result<(), StorageError> {
+        File::open(path)?.sync_data()?;
+        Ok(())
+    }
+
+    fn truncate(&self, path: &Path, len: u64) -> Result<(), StorageError> {
+        let file = OpenOptions::new().write(true).open(path)?;
+        file.set_len(len)?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    fn replace(&self, path: &Path, contents: &[u8]) -> Result<(), StorageError> {
+        Self::ensure_parent(path)?;
+        atomic::replace_file(path, contents)
+    }
+
+    fn restore_backup(&self, path: &Path) -> Result<(), StorageError> {
+        atomic::restore_backup(path)
+    }
+
+    fn remove(&self, path: &Path) -> Result<(), StorageError> {
+        Ok(fs::remove_file(path)?)
+    }
+
+    fn list(&self, dir: &Path) -> Result<Vec<PathBuf>, StorageError> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut files = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                files.push(entry.path());
+            }
+        }
+        Ok(files)
+    }
+
+    fn lock(&self, path: &Path, mode: LockMode) -> Result<Box<dyn LockGuard>, StorageError> {
+        let file = Self::open_lock(path, mode)?;
+        if let Some(file) = &file {
+            match mode {
+                LockMode::Shared => file.lock_shared()?,
+                LockMode::Exclusive => file.lock()?,
+            }
+        }
+        Ok(Box::new(FsLock(file)))
+    }
+
+    fn try_lock(
+        &self,
+        path: &Path,
+        mode: LockMode,
+    ) -> Result<Option<Box<dyn LockGuard>>, StorageError> {
+        let file = Self::open_lock(path, mode)?;
+        if let Some(file) = &file {
+            let attempt = match mode {
+                LockMode::Shared => file.try_lock_shared(),
+                LockMode::Exclusive => file.try_lock(),
+            };
+            match attempt {
+                Ok(()) => {}
+                Err(TryLockError::WouldBlock) => return Ok(None),
+                Err(TryLockError::Error(e)) => return Err(e.into()),
+            }
+        }
+        Ok(Some(Box::new(FsLock(file))))
+    }
+}
+
+/// Keeps every file in a map, for tests and tools that want a ledger without
+/// touching the disk. Nothing survives the backend being dropped.
+///
+/// Locks work like file locks between everyone sharing the same backend,
+/// including one thread asking twice blocking itself.
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+    files: Mutex<MemoryFiles>,
+    locks: Arc<LockTable>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryFiles {
+    files: HashMap<PathBuf, MemoryFile>,
+    generation: u64,
+}
+
+#[derive(Debug)]
+struct MemoryFile {
+    bytes: Vec<u8>,
+    generation: u64,
+}
+
+#[derive(Debug, Default)]
+struct LockTable {
+    held: Mutex<HashMap<PathBuf, Holders>>,
+    released: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct Holders {
+    readers: usize,
+    writer: bool,
+}
+
+#[derive(Debug)]
+struct MemoryLock {
+    table: Arc<LockTable>,
+    path: PathBuf,
+    mode: LockMode,
+}
+
+impl LockGuard for MemoryLock {}
+
+impl Drop for MemoryLock {
+    fn drop(&mut self) {
+        let Ok(mut held) = self.table.held.lock() else {
+            return;
+        };
+
+        if let Some(holders) = held.get_mut(&self.path) {
+            match self.mode {
+                LockMode::Shared => holders.readers = holders.readers.saturating_sub(1),
+                LockMode::Exclusive => holders.writer = false,
+            }
+            if holders.readers == 0 && !holders.writer {
+                held.remove(&self.path);
+            }
+        }
+        self.table.released.notify_all();
+    }
+}
+
+impl MemoryBackend {
+    fn files(&self) -> Result<std::sync::MutexGuard<'_, MemoryFiles>, StorageError> {
+        self.files.lock().map_err(|_| StorageError::Poisoned)
+    }
+
+    fn not_found(path: &Path) -> StorageError {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("{} not found", path.display()),
+        )
+        .into()
+    }
+
+    // takes the lock if it is free, true if it did
+    fn acquire(holders: &mut Holders, mode: LockMode) -> bool {
+        match mode {
+            LockMode::Shared if !holders.writer => holders.readers += 1,
+            LockMode::Exclusive if !holders.writer && holders.readers == 0 => holders.writer = true,
+            _ => return false,
+        }
+        true
+    }
+
+    fn guard(&self, path: &Path, mode: LockMode) -> Box<dyn LockGuard> {
+        Box::new(MemoryLock {
+            table: Arc::clone(&self.locks),
+            path: path.to_path_buf(),
+            mode,
+        })
+    }
+}
+
+impl MemoryFiles {
+    fn write(&mut self, path: &Path, change: impl FnOnce(&mut Vec<u8>)) {
+        self.generation += 1;
+        let file = self
+            .files
+            .entry(path.to_path_buf())
+            .or_insert_with(|| MemoryFile {
+                bytes: Vec::new(),
+                generation: 0,
+            });
+        change(&mut file.bytes);
+        file.generation = self.generation;
+    }
+
+    fn get(&self, path: &Path) -> Result<&MemoryFile, StorageError> {
+        self.files
+            .get(path)
+            .ok_or_else(|| MemoryBackend::not_found(path))
+    }
+}
+
+impl StorageBackend for MemoryBackend {
+    fn size(&self, path: &Path) -> Result<Option<u64>, StorageError> {
+        Ok(self
+            .files()?
+            .files
+            .get(path)
+            .map(|file| file.bytes.len() as u64))
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>, StorageError> {
+        Ok(self.files()?.get(path)?.bytes.clone())
+    }
+
+    fn read_at(&self, path: &Path, offset: u64, len: usize) -> Result<Vec<u8>, StorageError> {
+        let files = self.files()?;
+        let bytes = &files.get(path)?.bytes;
+
+        let start = usize::try_from(offset)
+            .unwrap_or(usize::MAX)
+            .min(bytes.len());
+        let end = start.saturating_add(len).min(bytes.len());
+        Ok(bytes.get(start..end).unwrap_or_default().to_vec())
+    }
+
+    fn read_segment(&self, path: &Path) -> Result<SegmentBytes, StorageError> {
+        let files = self.files()?;
+        let bytes = &files.get(path)?.bytes;
+
+        // copied into an aligned buffer, rkyv reads archived data in place
+        let mut aligned = AlignedVec::with_capacity(bytes.len());
+        aligned.extend_from_slice(bytes);
+        Ok(SegmentBytes::Owned(aligned))
+    }
+
+    fn version(&self, path: &Path) -> Result<Option<FileVersion>, StorageError> {
+        Ok(self
+            .files()?
+            .files
+            .get(path)
+            .map(|file| FileVersion::Generation(file.generation)))
+    }
+
+    fn append(&self, path: &Path, bytes: &[u8]) -> Result<(), StorageError> {
+        self.files()?
+            .write(path, |file| file.extend_from_slice(bytes));
+        Ok(())
+    }
+
+    fn sync(&self, path: &Path) -> Result<(), StorageError> {
+        self.files()?.get(path).map(|_| ())
+    }
+
+    fn truncate(&self, path: &Path, len: u64) -> Result<(), StorageError> {
+        let mut files = self.files()?;
+        files.get(path)?;
+
+        let len = usize::try_from(len)
+            .map_err(|_| StorageError::ValidationFailed("File length out of range".into()))?;
+        files.write(path, |file| file.resize(len, 0));
+        Ok(())
+    }
+
+    fn replace(&self, path: &Path, contents: &[u8]) -> Result<(), StorageError> {
+        let mut files = self.files()?;
+
+        // empty files are placeholders, same as on disk
+        if let Some(current) = files.files.get(path).filter(|file| !file.bytes.is_empty()) {
+            let current = current.bytes.clone();
+            files.write(&atomic::backup_path(path), |backup| *backup = current);
+        }
+
+        files.write(path, |file| *file = contents.to_vec());
+        Ok(())
+    }
+
+    fn restore_backup(&self, path: &Path) -> Result<(), StorageError> {
+        let mut files = self.files()?;
+        let backup = files.get(&atomic::backup_path(path))?.bytes.clone();
+
+        files.write(path, |file| *file = backup);
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> Result<(), StorageError> {
+        let mut files = self.files()?;
+        files
+            .files
+            .remove(path)
+            .ok_or_else(|| Self::not_found(path))?;
+        files.generation += 1;
+        Ok(())
+    }
+
+    fn list(&self, dir: &Path) -> Result<Vec<PathBuf>, StorageError> {
+        Ok(self
+            .files()?
+            .files
+            .keys()
+            .filter(|path| path.parent() == Some(dir))
+            .cloned()
+            .collect())
+    }
+
+    fn lock(&self, path: &Path, mode: LockMode) -> Result<Box<dyn LockGuard>, StorageError> {
+        let mut held = self.locks.held.lock().map_err(|_| StorageError::Poisoned)?;
+
+        while !Self::acquire(held.entry(path.to_path_buf()).or_default(), mode) {
+            held = self
+                .locks
+                .released
+                .wait(held)
+                .map_err(|_| StorageError::Poisoned)?;
+        }
+        Ok(self.guard(path, mode))
+    }
+
+    fn try_lock(
+        &self,
+        path: &Path,
+        mode: LockMode,
+    ) -> Result<Option<Box<dyn LockGuard>>, StorageError> {
+        let mut held = self.locks.held.lock().map_err(|_| StorageError::Poisoned)?;
+
+        if !Self::acquire(held.entry(path.to_path_buf()).or_default(), mode) {
+            return Ok(None);
+        }
+        Ok(Some(self.guard(path, mode)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    // what every backend has to do the same way
+    fn exercise(backend: &Backend, dir: &Path) {
+        let wal = dir.join("ledger.wal");
+        assert_eq!(backend.size(&wal).unwrap(), None);
+        assert!(backend.read(&wal).is_err());
+
+        backend.append(&wal, b"first ").unwrap();
+        backend.append(&wal, b"second").unwrap();
+        backend.sync(&wal).unwrap();
+        assert_eq!(backend.read(&wal).unwrap(), b"first second");
+        assert_eq!(backend.read_at(&wal, 6, 100).unwrap(), b"second");
+
+        let before = backend.version(&wal).unwrap();
+        backend.truncate(&wal, 5).unwrap();
+        assert_eq!(backend.read(&wal).unwrap(), b"first");
+        assert_ne!(backend.version(&wal).unwrap(), before);
+
+        let segment = dir.join("ledger.segments").join("segment-000001.ukweli");
+        backend.replace(&segment, b"old").unwrap();
+        backend.replace(&segment, b"new").unwrap();
+        assert_eq!(&*backend.read_segment(&segment).unwrap(), b"new");
+        assert_eq!(
+            backend.read(&atomic::backup_path(&segment)).unwrap(),
+            b"old"
+        );
+
+        backend.restore_backup(&segment).unwrap();
+        assert_eq!(backend.read(&segment).unwrap(), b"old");
+
+        let mut listed = backend.list(&dir.join("ledger.segments")).unwrap();
+        listed.sort();
+        assert_eq!(listed, vec![segment.clone(), atomic::backup_path(&segment)]);
+        assert!(backend.list(&dir.join("nowhere")).unwrap().is_empty());
+
+        backend.remove(&wal).unwrap();
+        assert!(!backend.exists(&wal).unwrap());
+
+        let lock = dir.join("ledger.lock");
+        let reader = backend.lock(&lock, LockMode::Shared).unwrap();
+        assert!(backend.try_lock(&lock, LockMode::Shared).unwrap().is_some());
+        drop(reader);
+        let writer = backend.lock(&lock, LockMode::Exclusive).unwrap();
+        assert!(backend.try_lock(&lock, LockMode::Shared).unwrap().is_none());
+        drop(writer);
+        assert!(
+            backend
+                .try_lock(&lock, LockMode::Exclusive)
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_memory_backend() {
+        let backend = memory();
+        exercise(&backend, Path::new("db"));
+        assert!(!Path::new("db").exists());
+    }
+
+    #[test]
+    fn test_filesystem_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        exercise(&filesystem(), dir.path());
+    }
+}
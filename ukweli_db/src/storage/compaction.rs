@@ -0,0 +1,109 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How much the WAL holds right now, see `AppendLog::stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WalStats {
+    pub bytes: u64,
+    pub entries: u64,
+    /// unix seconds of the oldest entry, `None` for an empty WAL
+    pub oldest: Option<u64>,
+}
+
+impl WalStats {
+    pub fn is_empty(&self) -> bool {
+        self.entries == 0
+    }
+
+    /// How long the oldest entry has been waiting to be sealed.
+    pub fn age(&self) -> Duration {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        match self.oldest {
+            Some(oldest) => Duration::from_secs(now.saturating_sub(oldest)),
+            None => Duration::ZERO,
+        }
+    }
+}
+
+/// When a `UkweliDb` handle seals its WAL into a new segment on its own.
+/// Whichever limit is hit first triggers it, `None` turns a limit off.
+///
+/// Only handles that write compact. Opening a database or reading from it
+/// never does, however long the WAL has got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionPolicy {
+    pub max_wal_bytes: Option<u64>,
+    pub max_wal_entries: Option<u64>,
+    /// counted from the oldest entry still in the WAL
+    pub max_age: Option<Duration>,
+}
+
+impl Default for CompactionPolicy {
+    fn default() -> Self {
+        Self {
+            max_wal_bytes: Some(16 * 1024 * 1024),
+            max_wal_entries: Some(10_000),
+            max_age: Some(Duration::from_secs(60 * 60)),
+        }
+    }
+}
+
+impl CompactionPolicy {
+    /// Nothing is sealed until someone calls `compact`.
+    pub fn manual() -> Self {
+        Self {
+            max_wal_bytes: None,
+            max_wal_entries: None,
+            max_age: None,
+        }
+    }
+
+    pub fn is_manual(&self) -> bool {
+        *self == Self::manual()
+    }
+
+    pub fn is_due(&self, wal: &WalStats) -> bool {
+        if wal.is_empty() {
+            return false;
+        }
+
+        self.max_wal_bytes.is_some_and(|max| wal.bytes >= max)
+            || self.max_wal_entries.is_some_and(|max| wal.entries >= max)
+            || self.max_age.is_some_and(|max| wal.age() >= max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_limit_hit_triggers() {
+        let policy = CompactionPolicy {
+            max_wal_bytes: Some(1000),
+            max_wal_entries: Some(10),
+            max_age: None,
+        };
+
+        let wal = |bytes, entries| WalStats {
+            bytes,
+            entries,
+            oldest: Some(0),
+        };
+        assert!(!policy.is_due(&wal(999, 9)));
+        assert!(policy.is_due(&wal(1000, 1)));
+        assert!(policy.is_due(&wal(10, 10)));
+        assert!(!policy.is_due(&WalStats::default()));
+
+        // the epoch was a while ago
+        let aged = CompactionPolicy {
+            max_age: Some(Duration::from_secs(60)),
+            ..CompactionPolicy::manual()
+        };
+        assert!(aged.is_due(&wal(1, 1)));
+        assert!(!CompactionPolicy::manual().is_due(&wal(u64::MAX, u64::MAX)));
+    }
+}
@@ -1,12 +1,16 @@
 use rkyv::bytecheck::CheckBytes;
 use rkyv::{Archive, Deserialize, Serialize};
 
-use crate::storage::persitence::{SerializableRecord, SerializableUser};
+use crate::storage::persitence::{
+    LegacySerializableRecord, SerializableCheckpoint, SerializableRecord, SerializableUser,
+};
 
 pub const MAGIC_NUMBER: [u8; 4] = [0x55, 0x4B, 0x57, 0x4C]; // "UKWL"
-pub const VERSION_MAJOR: u8 = 1;
-pub const VERSION_MINOR: u8 = 0; // might be redundant but good to keep for now
+// 2.0 stores payload envelopes, 1.x bodies are `LegacyDatabaseBody` and still read
+pub const VERSION_MAJOR: u8 = 2;
+pub const VERSION_MINOR: u8 = 0;
 pub const HEADER_SIZE: usize = 120;
+pub const FOOTER_SIZE: usize = 40;
 
 // TODO
 // https://github.com/elviscgn/UkweliDB/issues/1#issuecomment-3734544932
@@ -29,9 +33,11 @@ pub struct DatabaseHeader {
     pub body_offset: u64, // where the body and footer start
     pub footer_offset: u64,
 
-    pub checksum: [u8; 32], // hash of body content
-    pub reserved: [u8; 40],
-} // Total: 6 + 8 + 16 + 16 + 32 + 40 = 118 bytes
+    pub checksum: [u8; 32],      // hash of body content
+    pub first_record_index: u64, // 0 unless this file is a later segment
+    pub last_lsn: u64,           // newest WAL entry sealed in here, 0 in older files
+    pub reserved: [u8; 24],
+} // Total: 6 + 8 + 16 + 16 + 32 + 8 + 8 + 24 = 118 bytes
 
 impl DatabaseHeader {
     pub fn new(record_count: u64, body_offset: u64, footer_offset: u64) -> Self {
@@ -50,7 +56,9 @@ impl DatabaseHeader {
             body_offset,
             footer_offset,
             checksum: [0; 32],
-            reserved: [0; 40],
+            first_record_index: 0,
+            last_lsn: 0,
+            reserved: [0; 24],
         }
     }
 }
@@ -59,6 +67,28 @@ impl DatabaseHeader {
 pub struct DatabaseBody {
     pub records: Vec<SerializableRecord>,
     pub users: Vec<SerializableUser>,
+    pub checkpoints: Vec<SerializableCheckpoint>,
+}
+
+/// The body 1.x writers produced: string payloads and no checkpoints.
+#[derive(Archive, Serialize, Deserialize, Debug, CheckBytes)]
+pub struct LegacyDatabaseBody {
+    pub records: Vec<LegacySerializableRecord>,
+    pub users: Vec<SerializableUser>,
+}
+
+impl From<LegacyDatabaseBody> for DatabaseBody {
+    fn from(legacy: LegacyDatabaseBody) -> Self {
+        Self {
+            records: legacy
+                .records
+                .into_iter()
+                .map(SerializableRecord::from)
+                .collect(),
+            users: legacy.users,
+            checkpoints: Vec::new(),
+        }
+    }
 }
 
 #[derive(Archive, Serialize, Deserialize, Debug, CheckBytes)]
@@ -0,0 +1,268 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::error::StorageError;
+use crate::storage::backend::Backend;
+
+/// How far a WAL append gets before it returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Durability {
+    /// Buffered in the process and written out in one go when the buffer
+    /// fills, a batch commits or the handle flushes or closes, for bulk loads
+    /// that can be redone. The handle keeps the database locked while anything
+    /// is buffered, so other processes wait, readers included.
+    None,
+    /// Each write goes straight to the OS before returning, nothing is
+    /// buffered in the process. Survives the process dying but not the machine.
+    #[default]
+    Flush,
+    /// fsync after every write, nothing acknowledged is lost to a power cut.
+    Fsync,
+    /// Same guarantee as `Fsync`, but concurrent appends share one fsync. The
+    /// first writer to arrive waits up to `window` for the others still writing,
+    /// or until `max_batch` writes piled up, then syncs for all of them.
+    GroupCommit { window: Duration, max_batch: usize },
+}
+
+impl Durability {
+    pub const DEFAULT_WINDOW: Duration = Duration::from_millis(5);
+    pub const DEFAULT_MAX_BATCH: usize = 64;
+
+    pub fn group_commit() -> Self {
+        Self::GroupCommit {
+            window: Self::DEFAULT_WINDOW,
+            max_batch: Self::DEFAULT_MAX_BATCH,
+        }
+    }
+
+    /// Whether an acknowledged append survives a power cut.
+    pub fn is_synced(self) -> bool {
+        matches!(self, Self::Fsync | Self::GroupCommit { .. })
+    }
+}
+
+impl fmt::Display for Durability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => f.write_str("none"),
+            Self::Flush => f.write_str("flush"),
+            Self::Fsync => f.write_str("fsync"),
+            Self::GroupCommit { window, max_batch } => {
+                write!(f, "group ({:?} / {} writes)", window, max_batch)
+            }
+        }
+    }
+}
+
+impl FromStr for Durability {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "flush" => Ok(Self::Flush),
+            "fsync" => Ok(Self::Fsync),
+            "group" | "group-commit" => Ok(Self::group_commit()),
+            other => Err(format!(
+                "unknown durability '{}', expected none, flush, fsync or group",
+                other
+            )),
+        }
+    }
+}
+
+/// What appends through one handle cost so far.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AppendStats {
+    /// append calls that returned successfully
+    pub appends: u64,
+    /// WAL entries they wrote, a batch counts each record
+    pub entries: u64,
+    pub fsyncs: u64,
+    /// time spent inside appends, waiting for locks and syncs included
+    pub busy: Duration,
+    pub max_latency: Duration,
+}
+
+impl AppendStats {
+    pub fn record(&mut self, entries: u64, latency: Duration) {
+        self.appends += 1;
+        self.entries += entries;
+        self.busy += latency;
+        self.max_latency = self.max_latency.max(latency);
+    }
+
+    pub fn mean_latency(&self) -> Duration {
+        match u32::try_from(self.appends) {
+            Ok(0) => Duration::ZERO,
+            Ok(appends) => self.busy / appends,
+            Err(_) => Duration::from_secs_f64(self.busy.as_secs_f64() / self.appends as f64),
+        }
+    }
+
+    /// Entries per second over `elapsed` of wall clock time.
+    pub fn throughput(&self, elapsed: Duration) -> f64 {
+        if elapsed.is_zero() {
+            return 0.0;
+        }
+        self.entries as f64 / elapsed.as_secs_f64()
+    }
+}
+
+#[derive(Debug, Default)]
+struct GroupState {
+    // writers between `begin` and `wait`
+    writing: u64,
+    written: u64,
+    synced: u64,
+    leader: bool,
+    fsyncs: u64,
+}
+
+/// Shares fsyncs between writers of one handle, see `Durability::GroupCommit`.
+///
+/// Writers call `begin` before they take the database lock and `wait` once their
+/// write is done and the lock is let go, so others can write while they wait.
+/// The sync doesn't wait any longer once nobody is left writing. Every write
+/// that finished before a sync started is covered by it.
+#[derive(Debug)]
+pub struct GroupCommit {
+    window: Duration,
+    max_batch: u64,
+    state: Mutex<GroupState>,
+    changed: Condvar,
+}
+
+impl GroupCommit {
+    pub fn new(window: Duration, max_batch: usize) -> Self {
+        Self {
+            window,
+            max_batch: max_batch.max(1) as u64,
+            state: Mutex::new(GroupState::default()),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Announces a write, it must be followed by `wait` or `cancel`.
+    pub fn begin(&self) -> Result<(), StorageError> {
+        let mut state = self.state.lock().map_err(|_| StorageError::Poisoned)?;
+        state.writing += 1;
+        Ok(())
+    }
+
+    /// For a write that failed, so the sync doesn't wait for it.
+    pub fn cancel(&self) -> Result<(), StorageError> {
+        let mut state = self.state.lock().map_err(|_| StorageError::Poisoned)?;
+        state.writing = state.writing.saturating_sub(1);
+        self.changed.notify_all();
+        Ok(())
+    }
+
+    /// Blocks until a sync of `wal_path` covers the write the caller just did.
+    pub fn wait<P: AsRef<Path>>(&self, backend: &Backend, wal_path: P) -> Result<(), StorageError> {
+        let mut state = self.state.lock().map_err(|_| StorageError::Poisoned)?;
+        state.writing = state.writing.saturating_sub(1);
+        state.written += 1;
+        let ticket = state.written;
+        self.changed.notify_all();
+
+        while state.synced < ticket {
+            if state.leader {
+                state = self
+                    .changed
+                    .wait(state)
+                    .map_err(|_| StorageError::Poisoned)?;
+                continue;
+            }
+
+            // nobody is syncing, so we do it for everyone who turns up meanwhile
+            state.leader = true;
+            let deadline = Instant::now() + self.window;
+            while state.writing > 0 && state.written - state.synced < self.max_batch {
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                state = self
+                    .changed
+                    .wait_timeout(state, deadline - now)
+                    .map_err(|_| StorageError::Poisoned)?
+                    .0;
+            }
+
+            let target = state.written;
+            drop(state);
+            let result = backend.sync(wal_path.as_ref());
+
+            state = self.state.lock().map_err(|_| StorageError::Poisoned)?;
+            state.leader = false;
+            if result.is_ok() {
+                state.synced = state.synced.max(target);
+                state.fsyncs += 1;
+            }
+            self.changed.notify_all();
+
+            // whoever wakes up next takes another shot at it
+            result?;
+        }
+
+        Ok(())
+    }
+
+    pub fn fsyncs(&self) -> Result<u64, StorageError> {
+        Ok(self
+            .state
+            .lock()
+            .map_err(|_| StorageError::Poisoned)?
+            .fsyncs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use crate::storage::backend;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_parse_modes() {
+        assert_eq!("fsync".parse::<Durability>().unwrap(), Durability::Fsync);
+        assert_eq!("None".parse::<Durability>().unwrap(), Durability::None);
+        assert_eq!("Flush".parse::<Durability>().unwrap(), Durability::Flush);
+        assert_eq!(
+            "group".parse::<Durability>().unwrap(),
+            Durability::group_commit()
+        );
+        assert!("sometimes".parse::<Durability>().is_err());
+        assert!(!Durability::default().is_synced());
+    }
+
+    #[test]
+    fn test_group_commit_shares_fsyncs() {
+        let backend = backend::memory();
+        let wal_path = Path::new("test_group_commit.wal");
+        backend.append(wal_path, b"entries").unwrap();
+
+        let group = Arc::new(GroupCommit::new(Duration::from_millis(50), 8));
+        let writers: Vec<_> = (0..8)
+            .map(|_| {
+                let group = Arc::clone(&group);
+                let backend = Backend::clone(&backend);
+                group.begin().unwrap();
+                thread::spawn(move || group.wait(&backend, wal_path).unwrap())
+            })
+            .collect();
+        for handle in writers {
+            handle.join().unwrap();
+        }
+
+        let fsyncs = group.fsyncs().unwrap();
+        assert!((1..8).contains(&fsyncs), "{} fsyncs for 8 writes", fsyncs);
+    }
+}
@@ -0,0 +1,154 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::StorageError;
+use crate::storage::backend::{self, Backend, LockGuard};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    Shared,
+    Exclusive,
+}
+
+/// Advisory lock on `<db>.lock`, shared by readers and held exclusively by writers.
+/// It covers the database file, the manifest, the segments and the WAL together.
+///
+/// Locks are per open file, so one process asking twice blocks itself. Functions
+/// that run under a lock the caller already holds take it as `&DatabaseLock`,
+/// and find the backend the database lives in through it.
+///
+/// Only writers create the lock file. Readers open it read-only, and without one
+/// they go ahead unlocked, so copies on read-only media can still be read.
+#[derive(Debug)]
+pub struct DatabaseLock {
+    _guard: Box<dyn LockGuard>,
+    mode: LockMode,
+    path: PathBuf,
+    backend: Backend,
+}
+
+impl DatabaseLock {
+    pub fn path_for<P: AsRef<Path>>(db_path: P) -> PathBuf {
+        PathBuf::from(format!("{}.lock", db_path.as_ref().display()))
+    }
+
+    /// Blocks until no writer holds the lock. If there is no lock file no writer
+    /// ever took it, and it isn't created just to read.
+    pub fn shared<P: AsRef<Path>>(db_path: P) -> Result<Self, StorageError> {
+        Self::shared_in(&backend::filesystem(), db_path)
+    }
+
+    pub fn shared_in<P: AsRef<Path>>(backend: &Backend, db_path: P) -> Result<Self, StorageError> {
+        let path = Self::path_for(db_path);
+        let guard = backend.lock(&path, LockMode::Shared)?;
+        Ok(Self::held(backend, guard, LockMode::Shared, path))
+    }
+
+    /// Blocks until every other reader and writer has let go.
+    pub fn exclusive<P: AsRef<Path>>(db_path: P) -> Result<Self, StorageError> {
+        Self::exclusive_in(&backend::filesystem(), db_path)
+    }
+
+    pub fn exclusive_in<P: AsRef<Path>>(
+        backend: &Backend,
+        db_path: P,
+    ) -> Result<Self, StorageError> {
+        let path = Self::path_for(db_path);
+        let guard = backend.lock(&path, LockMode::Exclusive)?;
+        Ok(Self::held(backend, guard, LockMode::Exclusive, path))
+    }
+
+    /// Like `exclusive` but gives up straight away, `None` if someone else has it.
+    pub fn try_exclusive<P: AsRef<Path>>(db_path: P) -> Result<Option<Self>, StorageError> {
+        Self::try_exclusive_in(&backend::filesystem(), db_path)
+    }
+
+    pub fn try_exclusive_in<P: AsRef<Path>>(
+        backend: &Backend,
+        db_path: P,
+    ) -> Result<Option<Self>, StorageError> {
+        let path = Self::path_for(db_path);
+        Ok(backend
+            .try_lock(&path, LockMode::Exclusive)?
+            .map(|guard| Self::held(backend, guard, LockMode::Exclusive, path)))
+    }
+
+    fn held(backend: &Backend, guard: Box<dyn LockGuard>, mode: LockMode, path: PathBuf) -> Self {
+        Self {
+            _guard: guard,
+            mode,
+            path,
+            backend: Backend::clone(backend),
+        }
+    }
+
+    pub fn mode(&self) -> LockMode {
+        self.mode
+    }
+
+    /// Where the locked database lives.
+    pub fn backend(&self) -> &Backend {
+        &self.backend
+    }
+
+    /// Checks this is an exclusive lock on `db_path`, for functions that write
+    /// under a lock the caller passes in.
+    pub fn ensure_exclusive<P: AsRef<Path>>(&self, db_path: P) -> Result<(), StorageError> {
+        if self.mode != LockMode::Exclusive || self.path != Self::path_for(db_path) {
+            return Err(StorageError::ValidationFailed(
+                "Writing needs an exclusive lock on this database".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_exclusive_excludes_everyone() {
+        let db_path = "test_lock_exclusive.ukweli";
+
+        let writer = DatabaseLock::exclusive(db_path).unwrap();
+        assert!(DatabaseLock::try_exclusive(db_path).unwrap().is_none());
+        assert!(writer.ensure_exclusive(db_path).is_ok());
+        assert!(writer.ensure_exclusive("other.ukweli").is_err());
+
+        drop(writer);
+        assert!(DatabaseLock::try_exclusive(db_path).unwrap().is_some());
+
+        let _ = fs::remove_file(DatabaseLock::path_for(db_path));
+    }
+
+    #[test]
+    fn test_readers_share() {
+        let db_path = "test_lock_shared.ukweli";
+        drop(DatabaseLock::exclusive(db_path).unwrap());
+
+        let first = DatabaseLock::shared(db_path).unwrap();
+        let second = DatabaseLock::shared(db_path).unwrap();
+        assert_eq!(second.mode(), LockMode::Shared);
+        assert!(first.ensure_exclusive(db_path).is_err());
+        assert!(DatabaseLock::try_exclusive(db_path).unwrap().is_none());
+
+        drop(first);
+        drop(second);
+        assert!(DatabaseLock::try_exclusive(db_path).unwrap().is_some());
+
+        let _ = fs::remove_file(DatabaseLock::path_for(db_path));
+    }
+
+    #[test]
+    fn test_readers_dont_create_the_lock_file() {
+        let db_path = "test_lock_no_file.ukweli";
+        let _ = fs::remove_file(DatabaseLock::path_for(db_path));
+
+        let reader = DatabaseLock::shared(db_path).unwrap();
+        assert_eq!(reader.mode(), LockMode::Shared);
+        assert!(!DatabaseLock::path_for(db_path).exists());
+    }
+}
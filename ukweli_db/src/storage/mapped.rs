@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use rkyv::rancor::Error as RkyvError;
+
+use crate::core::{Record, User};
+use crate::error::StorageError;
+use crate::storage::backend::{self, Backend, FileVersion, SegmentBytes};
+use crate::storage::database::{ArchivedDatabaseBody, DatabaseBody, DatabaseHeader};
+use crate::storage::persitence::{
+    ArchivedSerializableRecord, SerializableRecord, SerializableUser,
+};
+use crate::storage::reader::{decode_body, verify_layout};
+use crate::storage::recovery::RecoveryManager;
+
+/// Read-only view of a database file that serves records straight out of the mapping.
+///
+/// The checksum and bytecheck validation run once in `open`, after that records are
+/// read in place and only the ones asked for get deserialized. On the filesystem
+/// the file is mapped, other backends hand over a copy.
+///
+/// `DatabaseWriter` never writes into an existing file, it writes a new one and
+/// renames it over the old path. The inode we mapped keeps its contents, so a
+/// compaction running underneath us just makes the mapping stale, see `is_stale`.
+/// Something outside ukweli truncating the file in place is not covered.
+pub struct MappedDatabase {
+    path: PathBuf,
+    backend: Backend,
+    bytes: SegmentBytes,
+    header: DatabaseHeader,
+    body: Range<usize>,
+    version: Option<FileVersion>,
+}
+
+impl MappedDatabase {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StorageError> {
+        Self::open_in(&backend::filesystem(), path)
+    }
+
+    pub fn open_in<P: AsRef<Path>>(backend: &Backend, path: P) -> Result<Self, StorageError> {
+        let path = path.as_ref().to_path_buf();
+        // taken first, a change after this shows up as stale rather than slipping by
+        let version = backend.version(&path)?;
+        let bytes = backend.read_segment(&path)?;
+
+        let (header, body) = verify_layout(&bytes)?;
+
+        let body_bytes = bytes.get(body.clone()).ok_or_else(|| {
+            StorageError::Serialization("Header offsets point outside file boundaries".to_string())
+        })?;
+
+        // a 1.x file has a different layout, it is converted once and served
+        // from memory until compaction rewrites it
+        if header.version_major == 1 {
+            let converted = rkyv::to_bytes::<RkyvError>(&decode_body(&header, body_bytes)?)
+                .map_err(|e| StorageError::Serialization(e.to_string()))?;
+            let body = 0..converted.len();
+
+            return Ok(Self {
+                path,
+                backend: Backend::clone(backend),
+                bytes: SegmentBytes::Owned(converted),
+                header,
+                body,
+                version,
+            });
+        }
+
+        // full bytecheck once, `body()` relies on it afterwards
+        rkyv::access::<ArchivedDatabaseBody, RkyvError>(body_bytes)
+            .map_err(|e| StorageError::Deserialization(format!("Body corruption: {}", e)))?;
+
+        Ok(Self {
+            path,
+            backend: Backend::clone(backend),
+            bytes,
+            header,
+            body,
+            version,
+        })
+    }
+
+    pub fn header(&self) -> &DatabaseHeader {
+        &self.header
+    }
+
+    pub fn body(&self) -> Result<&ArchivedDatabaseBody, StorageError> {
+        let body_bytes = self.bytes.get(self.body.clone()).ok_or_else(|| {
+            StorageError::Serialization("Header offsets point outside file boundaries".to_string())
+        })?;
+
+        // SAFETY: these exact bytes passed `rkyv::access` in `open` and they are
+        // immutable for as long as `self` lives.
+        Ok(unsafe { rkyv::access_unchecked::<ArchivedDatabaseBody>(body_bytes) })
+    }
+
+    pub fn record_count(&self) -> Result<usize, StorageError> {
+        Ok(self.body()?.records.len())
+    }
+
+    pub fn archived_record(
+        &self,
+        index: usize,
+    ) -> Result<Option<&ArchivedSerializableRecord>, StorageError> {
+        let records = &self.body()?.records;
+
+        // records are sealed in index order, so this is almost always a direct hit
+        if let Some(record) = records.get(index)
+            && record.index.to_native() as usize == index
+        {
+            return Ok(Some(record));
+        }
+
+        Ok(records
+            .iter()
+            .find(|record| record.index.to_native() as usize == index))
+    }
+
+    pub fn serialized_record(
+        &self,
+        index: usize,
+    ) -> Result<Option<SerializableRecord>, StorageError> {
+        let Some(archived) = self.archived_record(index)? else {
+            return Ok(None);
+        };
+
+        rkyv::deserialize::<SerializableRecord, RkyvError>(archived)
+            .map(Some)
+            .map_err(|e| StorageError::Deserialization(format!("Record map error: {}", e)))
+    }
+
+    pub fn user(&self, user_id: &str) -> Result<Option<User>, StorageError> {
+        let Some(archived) = self
+            .body()?
+            .users
+            .iter()
+            .find(|user| user.user_id.as_str() == user_id)
+        else {
+            return Ok(None);
+        };
+
+        let ser_user = rkyv::deserialize::<SerializableUser, RkyvError>(archived)
+            .map_err(|e| StorageError::Deserialization(format!("User map error: {}", e)))?;
+
+        RecoveryManager::user_from_serialized(ser_user).map(Some)
+    }
+
+    /// Deserializes a single record and the users that signed it.
+    /// Signers have to be sealed in this same file, see `segment::read_sealed_record`
+    /// for records whose signers live in earlier segments.
+    pub fn read_record(&self, index: usize) -> Result<Option<Record>, StorageError> {
+        let Some(ser_record) = self.serialized_record(index)? else {
+            return Ok(None);
+        };
+
+        let mut signers: HashMap<String, User> = HashMap::new();
+        for signer_id in &ser_record.signer_ids {
+            if let Some(user) = self.user(signer_id)? {
+                signers.insert(signer_id.clone(), user);
+            }
+        }
+
+        RecoveryManager::record_from_serialized(ser_record, &signers).map(Some)
+    }
+
+    /// Copies the whole body out, same result as `DatabaseReader::read_and_verify`.
+    pub fn to_owned_body(&self) -> Result<DatabaseBody, StorageError> {
+        rkyv::deserialize::<DatabaseBody, RkyvError>(self.body()?)
+            .map_err(|e| StorageError::Deserialization(format!("Body map error: {}", e)))
+    }
+
+    /// True once the path points at a different or changed file than the one mapped.
+    /// The mapping itself stays valid, it just no longer shows the latest state.
+    pub fn is_stale(&self) -> Result<bool, StorageError> {
+        match self.backend.version(&self.path)? {
+            Some(version) => Ok(Some(version) != self.version),
+            None => Ok(true),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::indexing_slicing)]
+
+    use super::*;
+    use crate::core::Ledger;
+    use crate::storage::writer::DatabaseWriter;
+    use std::fs;
+
+    fn write_ledger(path: &str, records: usize) -> Ledger {
+        let mut ledger = Ledger::new();
+        let user = User::new("0xElvis");
+        ledger.register_user(user.clone());
+        for i in 0..records {
+            ledger
+                .add_record(format!("record {}", i).as_str(), vec![user.clone()])
+                .unwrap();
+        }
+
+        let mut writer = DatabaseWriter::new(path).unwrap();
+        writer.write_ledger(&ledger).unwrap();
+        ledger
+    }
+
+    #[test]
+    fn test_mapped_read_record() {
+        let test_path = "test_mapped_read.ukweli";
+        let _ = fs::remove_file(test_path);
+
+        let ledger = write_ledger(test_path, 5);
+        let mapped = MappedDatabase::open(test_path).unwrap();
+
+        assert_eq!(mapped.record_count().unwrap(), ledger.records.len());
+
+        let record = mapped.read_record(3).unwrap().unwrap();
+        assert_eq!(record.record_hash, ledger.records[3].record_hash);
+        assert_eq!(record.payload, ledger.records[3].payload);
+        assert_eq!(record.signers[0].user_id, "0xElvis");
+        assert!(mapped.read_record(42).unwrap().is_none());
+
+        fs::remove_file(test_path).unwrap();
+    }
+
+    #[test]
+    fn test_mapped_rejects_corrupted_body() {
+        let test_path = "test_mapped_corrupt.ukweli";
+        let _ = fs::remove_file(test_path);
+
+        write_ledger(test_path, 2);
+
+        let mut bytes = fs::read(test_path).unwrap();
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 0xFF;
+        fs::write(test_path, bytes).unwrap();
+
+        assert!(matches!(
+            MappedDatabase::open(test_path),
+            Err(StorageError::ChecksumMismatch)
+        ));
+
+        fs::remove_file(test_path).unwrap();
+    }
+
+    #[test]
+    fn test_mapping_survives_rewrite() {
+        let test_path = "test_mapped_rewrite.ukweli";
+        let _ = fs::remove_file(test_path);
+
+        let ledger = write_ledger(test_path, 2);
+        let mapped = MappedDatabase::open(test_path).unwrap();
+        assert!(!mapped.is_stale().unwrap());
+
+        write_ledger(test_path, 6);
+
+        // old view is intact, it just knows it is behind
+        assert!(mapped.is_stale().unwrap());
+        assert_eq!(mapped.record_count().unwrap(), ledger.records.len());
+        let record = mapped.read_record(2).unwrap().unwrap();
+        assert_eq!(record.record_hash, ledger.records[2].record_hash);
+
+        let fresh = MappedDatabase::open(test_path).unwrap();
+        assert_eq!(fresh.record_count().unwrap(), 7);
+
+        fs::remove_file(test_path).unwrap();
+        let _ = fs::remove_file(format!("{}.backup", test_path));
+    }
+}
@@ -1,6 +1,17 @@
 pub mod append;
+pub mod archive;
+pub mod atomic;
+pub mod backend;
+pub mod compaction;
 pub mod database;
+pub mod durability;
+pub mod lock;
+pub mod mapped;
+pub mod object_store;
 pub mod persitence;
 pub mod reader;
 pub mod recovery;
+#[cfg(feature = "s3")]
+pub mod s3;
+pub mod segment;
 pub mod writer;
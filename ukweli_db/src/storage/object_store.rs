@@ -0,0 +1,190 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::error::StorageError;
+
+pub type Store = Arc<dyn ObjectStore>;
+
+/// Long-term home for sealed segments: an S3 bucket, or anything that speaks
+/// enough of its API. Objects are written once under content-addressed keys,
+/// see `object_key`, so nothing is ever overwritten and no locking is needed.
+///
+/// Stores aren't trusted. Whatever comes back is checked against the key it
+/// was asked for and the checksums the manifest already holds.
+pub trait ObjectStore: Send + Sync + fmt::Debug {
+    fn put(&self, key: &str, bytes: &[u8]) -> Result<(), StorageError>;
+
+    /// The whole object, `None` if there isn't one.
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StorageError>;
+
+    /// Size of the object, `None` if there isn't one.
+    fn head(&self, key: &str) -> Result<Option<u64>, StorageError>;
+
+    /// Keys starting with `prefix`, in no particular order.
+    fn list(&self, prefix: &str) -> Result<Vec<String>, StorageError>;
+}
+
+/// The key an object holding exactly `bytes` is stored under.
+pub fn object_key(bytes: &[u8]) -> String {
+    format!("segments/{}.ukweli", sha256::digest(bytes))
+}
+
+/// Checks `bytes` really are the object `key` names.
+pub fn check_object(key: &str, bytes: &[u8]) -> Result<(), StorageError> {
+    if object_key(bytes) != key {
+        return Err(StorageError::ObjectStore(format!(
+            "Object {} does not match its key",
+            key
+        )));
+    }
+    Ok(())
+}
+
+/// Opens the store `url` points at: `s3://bucket/prefix`, see `S3Store::from_env`,
+/// or a directory given as a path or a `file://` URL.
+pub fn open(url: &str) -> Result<Store, StorageError> {
+    #[cfg(feature = "s3")]
+    if let Some(location) = url.strip_prefix("s3://") {
+        return Ok(Arc::new(crate::storage::s3::S3Store::from_env(location)?));
+    }
+
+    #[cfg(not(feature = "s3"))]
+    if url.starts_with("s3://") {
+        return Err(StorageError::ObjectStore(format!(
+            "{} needs ukweli_db built with the s3 feature",
+            url
+        )));
+    }
+
+    let root = url.strip_prefix("file://").unwrap_or(url);
+    Ok(Arc::new(DirStore::new(root)))
+}
+
+/// A directory standing in for a bucket, each key a file under it. For tests,
+/// and for archives kept on a mounted share.
+#[derive(Debug, Clone)]
+pub struct DirStore {
+    root: PathBuf,
+}
+
+impl DirStore {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    // keys are relative and slash separated, none of them climbs out of the root
+    fn path_for(&self, key: &str) -> Result<PathBuf, StorageError> {
+        if key
+            .split('/')
+            .any(|part| part.is_empty() || part == "." || part == ".." || part.contains('\\'))
+        {
+            return Err(StorageError::ObjectStore(format!("Invalid key '{}'", key)));
+        }
+        Ok(self.root.join(key))
+    }
+
+    fn collect(&self, dir: &Path, prefix: &str, keys: &mut Vec<String>) -> io::Result<()> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // uploads still being written
+            if name.starts_with('.') {
+                continue;
+            }
+
+            let key = if prefix.is_empty() {
+                name
+            } else {
+                format!("{}/{}", prefix, name)
+            };
+            if entry.file_type()?.is_dir() {
+                self.collect(&entry.path(), &key, keys)?;
+            } else {
+                keys.push(key);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl ObjectStore for DirStore {
+    fn put(&self, key: &str, bytes: &[u8]) -> Result<(), StorageError> {
+        let path = self.path_for(key)?;
+        let dir = path.parent().unwrap_or(&self.root);
+        fs::create_dir_all(dir)?;
+
+        // written aside and renamed, so a key is either absent or complete
+        let mut upload = tempfile::NamedTempFile::new_in(dir)?;
+        upload.write_all(bytes)?;
+        upload.as_file().sync_all()?;
+        upload.persist(&path).map_err(|e| e.error)?;
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StorageError> {
+        match fs::read(self.path_for(key)?) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn head(&self, key: &str) -> Result<Option<u64>, StorageError> {
+        match fs::metadata(self.path_for(key)?) {
+            Ok(metadata) => Ok(Some(metadata.len())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>, StorageError> {
+        let mut keys = Vec::new();
+        self.collect(&self.root, "", &mut keys)?;
+        keys.retain(|key| key.starts_with(prefix));
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    #[test]
+    fn test_dir_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = open(&format!("file://{}", dir.path().display())).unwrap();
+
+        let key = object_key(b"sealed");
+        assert!(store.get(&key).unwrap().is_none());
+        assert!(store.head(&key).unwrap().is_none());
+
+        store.put(&key, b"sealed").unwrap();
+        store.put("notes/readme", b"hello").unwrap();
+        assert_eq!(store.get(&key).unwrap().unwrap(), b"sealed");
+        assert_eq!(store.head(&key).unwrap(), Some(6));
+        assert_eq!(store.list("segments/").unwrap(), vec![key.clone()]);
+        assert_eq!(store.list("").unwrap().len(), 2);
+
+        assert!(check_object(&key, b"sealed").is_ok());
+        assert!(check_object(&key, b"tampered").is_err());
+        assert!(store.put("../outside", b"nope").is_err());
+    }
+}
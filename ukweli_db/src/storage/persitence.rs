@@ -1,15 +1,96 @@
-use crate::core::User;
+use crate::core::checkpoint::WitnessSignature;
+use crate::core::merkle::{SignedTreeHead, TreeHead};
+use crate::core::{Checkpoint, Payload, PayloadBody, User};
+use crate::error::StorageError;
 use rkyv::bytecheck::CheckBytes;
 use rkyv::{Archive, Deserialize, Serialize};
 
 // use std::io::Write;
 use crate::core::Record;
 
+const BODY_TEXT: u8 = 1;
+const BODY_JSON: u8 = 2;
+const BODY_BINARY: u8 = 3;
+const BODY_LEGACY_TEXT: u8 = 4; // a 1.x string payload, see `Payload::legacy`
+
+#[derive(Archive, Serialize, Deserialize, Debug, Clone, CheckBytes)]
+#[rkyv(derive(Debug))]
+pub struct SerializablePayload {
+    pub content_type: String,
+    pub entity_id: Option<String>,
+    pub workflow_id: Option<String>,
+    pub transition: Option<String>,
+    pub tags: Vec<(String, String)>,
+    pub body_kind: u8, // 1 = Text, 2 = Json, 3 = Binary, 4 = legacy Text
+    pub body: Vec<u8>,
+}
+
+impl From<&Payload> for SerializablePayload {
+    fn from(payload: &Payload) -> Self {
+        let (body_kind, body) = match &payload.body {
+            PayloadBody::Text(text) if payload.legacy => {
+                (BODY_LEGACY_TEXT, text.as_bytes().to_vec())
+            }
+            PayloadBody::Text(text) => (BODY_TEXT, text.as_bytes().to_vec()),
+            PayloadBody::Json(value) => (BODY_JSON, value.to_string().into_bytes()),
+            PayloadBody::Binary(bytes) => (BODY_BINARY, bytes.clone()),
+        };
+
+        Self {
+            content_type: payload.content_type.clone(),
+            entity_id: payload.entity_id.clone(),
+            workflow_id: payload.workflow_id.clone(),
+            transition: payload.transition.clone(),
+            tags: payload
+                .tags
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            body_kind,
+            body,
+        }
+    }
+}
+
+impl TryFrom<SerializablePayload> for Payload {
+    type Error = StorageError;
+
+    fn try_from(ser: SerializablePayload) -> Result<Self, Self::Error> {
+        let body = match ser.body_kind {
+            BODY_TEXT | BODY_LEGACY_TEXT => {
+                PayloadBody::Text(String::from_utf8(ser.body).map_err(|e| {
+                    StorageError::Deserialization(format!("Invalid text payload: {}", e))
+                })?)
+            }
+            BODY_JSON => PayloadBody::Json(serde_json::from_slice(&ser.body).map_err(|e| {
+                StorageError::Deserialization(format!("Invalid JSON payload: {}", e))
+            })?),
+            BODY_BINARY => PayloadBody::Binary(ser.body),
+            kind => {
+                return Err(StorageError::Deserialization(format!(
+                    "Unknown payload body kind: {}",
+                    kind
+                )));
+            }
+        };
+
+        Ok(Payload {
+            content_type: ser.content_type,
+            entity_id: ser.entity_id,
+            workflow_id: ser.workflow_id,
+            transition: ser.transition,
+            tags: ser.tags.into_iter().collect(),
+            body,
+            legacy: ser.body_kind == BODY_LEGACY_TEXT,
+        })
+    }
+}
+
 #[derive(Archive, Serialize, Deserialize, Debug, Clone, CheckBytes)]
 #[rkyv(derive(Debug))]
 pub struct SerializableRecord {
     pub index: usize,
-    pub payload: String,
+    pub payload: SerializablePayload,
     pub payload_hash: String,
     pub signer_ids: Vec<String>,
     pub signatures: Vec<(String, Vec<u8>)>, // (user_id, signature_bytes)
@@ -23,7 +104,7 @@ impl From<&Record> for SerializableRecord {
     fn from(record: &Record) -> Self {
         Self {
             index: record.index,
-            payload: record.payload.clone(),
+            payload: SerializablePayload::from(&record.payload),
             payload_hash: record.payload_hash.clone(),
 
             signer_ids: record.signers.iter().map(|u| u.user_id.clone()).collect(),
@@ -42,6 +123,37 @@ impl From<&Record> for SerializableRecord {
     }
 }
 
+/// A record as 1.x files and unchained WAL entries hold it, the payload is a
+/// plain string. Only read, never written.
+#[derive(Archive, Serialize, Deserialize, Debug, Clone, CheckBytes)]
+pub struct LegacySerializableRecord {
+    pub index: usize,
+    pub payload: String,
+    pub payload_hash: String,
+    pub signer_ids: Vec<String>,
+    pub signatures: Vec<(String, Vec<u8>)>,
+    pub prev_hash: String,
+    pub record_hash: String,
+    pub timestamp: u64,
+    pub nonce: u64,
+}
+
+impl From<LegacySerializableRecord> for SerializableRecord {
+    fn from(legacy: LegacySerializableRecord) -> Self {
+        Self {
+            index: legacy.index,
+            payload: SerializablePayload::from(&Payload::legacy_text(&legacy.payload)),
+            payload_hash: legacy.payload_hash,
+            signer_ids: legacy.signer_ids,
+            signatures: legacy.signatures,
+            prev_hash: legacy.prev_hash,
+            record_hash: legacy.record_hash,
+            timestamp: legacy.timestamp,
+            nonce: legacy.nonce,
+        }
+    }
+}
+
 #[derive(Archive, Serialize, Deserialize, Debug, Clone, CheckBytes)]
 pub struct SerializableUser {
     pub user_id: String,
@@ -59,6 +171,68 @@ impl From<&User> for SerializableUser {
     }
 }
 
+#[derive(Archive, Serialize, Deserialize, Debug, Clone, CheckBytes)]
+#[rkyv(derive(Debug))]
+pub struct SerializableCheckpoint {
+    pub tree_size: u64,
+    pub root_hash: String,
+    pub timestamp: u64,
+    pub operator_id: String,
+    pub operator_key: String,
+    pub operator_signature: String,
+    pub witnesses: Vec<(String, String, String)>, // (signer_id, key, signature)
+}
+
+impl From<&Checkpoint> for SerializableCheckpoint {
+    fn from(checkpoint: &Checkpoint) -> Self {
+        Self {
+            tree_size: checkpoint.tree_size() as u64,
+            root_hash: checkpoint.root_hash().to_string(),
+            timestamp: checkpoint.head.tree_head.timestamp,
+            operator_id: checkpoint.head.signer_id.clone(),
+            operator_key: checkpoint.head.verifying_key.clone(),
+            operator_signature: checkpoint.head.signature.clone(),
+            witnesses: checkpoint
+                .witnesses
+                .iter()
+                .map(|w| {
+                    (
+                        w.signer_id.clone(),
+                        w.verifying_key.clone(),
+                        w.signature.clone(),
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<SerializableCheckpoint> for Checkpoint {
+    fn from(ser: SerializableCheckpoint) -> Self {
+        Checkpoint {
+            head: SignedTreeHead {
+                tree_head: TreeHead {
+                    tree_size: ser.tree_size as usize,
+                    root_hash: ser.root_hash,
+                    timestamp: ser.timestamp,
+                },
+                signer_id: ser.operator_id,
+                verifying_key: ser.operator_key,
+                signature: ser.operator_signature,
+            },
+            witnesses: ser
+                .witnesses
+                .into_iter()
+                .map(|(signer_id, verifying_key, signature)| WitnessSignature {
+                    signer_id,
+                    verifying_key,
+                    signature,
+                })
+                .collect(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #![allow(clippy::unwrap_used)]
@@ -67,6 +241,7 @@ mod tests {
     use super::*;
 
     use crate::core::{Ledger, User};
+    use crate::storage::backend;
     use crate::storage::database::{DatabaseHeader, MAGIC_NUMBER};
     use crate::storage::reader::DatabaseReader;
     use crate::storage::writer::DatabaseWriter;
@@ -81,12 +256,14 @@ mod tests {
         let header = DatabaseHeader::new(100, 128, 5000);
 
         assert_eq!(header.magic, MAGIC_NUMBER);
-        assert_eq!(header.version_major, 1);
+        assert_eq!(header.version_major, 2);
         assert_eq!(header.version_minor, 0);
         assert_eq!(header.record_count, 100);
         assert_eq!(header.body_offset, 128);
         assert_eq!(header.footer_offset, 5000);
-        assert_eq!(header.reserved.len(), 40);
+        assert_eq!(header.first_record_index, 0);
+        assert_eq!(header.last_lsn, 0);
+        assert_eq!(header.reserved.len(), 24);
 
         // all reserved bytes should be zero
         assert!(header.reserved.iter().all(|&b| b == 0));
@@ -138,13 +315,39 @@ mod tests {
         let serializable = SerializableRecord::from(record);
 
         assert_eq!(serializable.index, record.index);
-        assert_eq!(serializable.payload, record.payload);
+        assert_eq!(
+            Payload::try_from(serializable.payload.clone()).unwrap(),
+            record.payload
+        );
         assert_eq!(serializable.payload_hash, record.payload_hash);
         assert_eq!(serializable.record_hash, record.record_hash);
         assert_eq!(serializable.signer_ids.len(), 1);
         assert_eq!(serializable.signatures.len(), 1);
     }
 
+    #[test]
+    fn test_serializable_payload_roundtrip() {
+        let payloads = [
+            Payload::text("plain"),
+            Payload::json(serde_json::json!({"amount": 250, "items": ["a", "b"]}))
+                .with_entity("T-9", "tender", "submit_bid")
+                .with_tag("region", "gauteng"),
+            Payload::binary("application/pdf", vec![0x25, 0x50, 0x44, 0x46]),
+            Payload::legacy_text("from a 1.x file"),
+        ];
+
+        for payload in payloads {
+            let serializable = SerializablePayload::from(&payload);
+            let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&serializable).unwrap();
+            let restored =
+                rkyv::from_bytes::<SerializablePayload, rkyv::rancor::Error>(&bytes).unwrap();
+            let restored = Payload::try_from(restored).unwrap();
+
+            assert_eq!(restored, payload);
+            assert_eq!(restored.hash(), payload.hash());
+        }
+    }
+
     #[test]
     fn test_serializable_record_multi_signer() {
         let mut ledger = Ledger::new();
@@ -191,6 +394,30 @@ mod tests {
         assert_eq!(serializable.roles.len(), 0);
     }
 
+    #[test]
+    fn test_checkpoint_in_database_file() {
+        let mut ledger = Ledger::new();
+        let operator = User::new("operator");
+        ledger.designate_operator(&operator).unwrap();
+        ledger
+            .add_record("closing", vec![operator.clone()])
+            .unwrap();
+        ledger.create_checkpoint(&operator).unwrap();
+        ledger.cosign_checkpoint(3, &User::new("witness")).unwrap();
+
+        let test_path = "test_checkpoint_db.ukweli";
+        let mut writer = DatabaseWriter::new(test_path).unwrap();
+        writer.write_ledger(&ledger).unwrap();
+
+        let reader = DatabaseReader::new(test_path).unwrap();
+        let checkpoint = reader.latest_checkpoint().unwrap().unwrap();
+
+        assert_eq!(&checkpoint, ledger.latest_checkpoint().unwrap());
+        assert!(checkpoint.verify_signatures().unwrap());
+
+        fs::remove_file(test_path).unwrap();
+    }
+
     #[test]
     fn test_writer_creates_file() {
         let test_path = "test_writer_creates.ukweli";
@@ -222,22 +449,21 @@ mod tests {
             .unwrap();
 
         let test_path = "test_db.ukweli";
+        let backend = backend::memory();
 
         // Write
-        let mut writer = DatabaseWriter::new(test_path).unwrap();
+        let mut writer = DatabaseWriter::new_in(&backend, test_path).unwrap();
         writer.write_ledger(&ledger).unwrap();
 
         // read
-        let reader = DatabaseReader::new(test_path).unwrap();
+        let reader = DatabaseReader::new_in(&backend, test_path).unwrap();
         let (header, body) = reader.read_and_verify().unwrap();
 
         assert_eq!(header.magic, MAGIC_NUMBER);
-        assert_eq!(header.version_major, 1);
+        assert_eq!(header.version_major, 2);
         assert_eq!(header.version_minor, 0);
         assert_eq!(header.record_count, 3); // Genesis + 2 records
         assert_eq!(body.records.len(), 3);
-
-        // cleanup
-        // fs::remove_file(test_path).unwrap();
+        assert!(reader.latest_checkpoint().unwrap().is_none());
     }
 }
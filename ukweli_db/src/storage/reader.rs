@@ -1,7 +1,13 @@
+use crate::core::Checkpoint;
 use crate::error::StorageError;
-use crate::storage::database::{DatabaseBody, DatabaseHeader, HEADER_SIZE, MAGIC_NUMBER};
+use crate::storage::backend::{self, Backend};
+use crate::storage::database::{
+    DatabaseBody, DatabaseFooter, DatabaseHeader, FOOTER_SIZE, HEADER_SIZE, LegacyDatabaseBody,
+    MAGIC_NUMBER, VERSION_MAJOR,
+};
 use rkyv::rancor::Error as RkyvError;
-use std::fs;
+use rkyv::util::AlignedVec;
+use std::ops::Range;
 use std::path::Path;
 
 pub struct DatabaseReader {
@@ -10,58 +16,316 @@ pub struct DatabaseReader {
 
 impl DatabaseReader {
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, StorageError> {
+        Self::new_in(&backend::filesystem(), path)
+    }
+
+    pub fn new_in<P: AsRef<Path>>(backend: &Backend, path: P) -> Result<Self, StorageError> {
         // snapshot the file into memory to avoid Undefined Behavior if disk state changes during read.
-        let buffer = fs::read(path)?;
+        let buffer = backend.read(path.as_ref())?;
         Ok(Self { buffer })
     }
 
     pub fn read_and_verify(&self) -> Result<(DatabaseHeader, DatabaseBody), StorageError> {
-        let header_slice = self.buffer.get(..HEADER_SIZE).ok_or_else(|| {
-            StorageError::Serialization("File truncated: missing header".to_string())
+        let (header, body_range) = verify_layout(&self.buffer)?;
+
+        let body_bytes = self.buffer.get(body_range).ok_or_else(|| {
+            StorageError::Serialization("Header offsets point outside file boundaries".to_string())
         })?;
 
-        let archived_header =
-            rkyv::access::<rkyv::Archived<DatabaseHeader>, RkyvError>(header_slice)
-                .map_err(|e| StorageError::Deserialization(format!("Header validation: {}", e)))?;
+        let body = decode_body(&header, body_bytes)?;
+        Ok((header, body))
+    }
+
+    /// Decodes as much as it can without giving up on the first failed check, so a
+    /// damaged file can still be looked at. Only fails if the header is unreadable.
+    pub fn inspect(&self) -> Result<Inspection, StorageError> {
+        let header = parse_header(&self.buffer)?;
+        let footer = decode_footer(&self.buffer, header.footer_offset as usize).ok();
+
+        let body = self
+            .buffer
+            .get(header.body_offset as usize..header.footer_offset as usize)
+            .and_then(|bytes| decode_body(&header, bytes).ok())
+            .map(|body| BodySummary {
+                records: body.records.len(),
+                users: body.users.len(),
+                checkpoints: body.checkpoints.len(),
+                first_index: body.records.first().map(|r| r.index),
+                last_index: body.records.last().map(|r| r.index),
+            });
+
+        Ok(Inspection {
+            file_size: self.buffer.len() as u64,
+            header,
+            footer,
+            body,
+            verification: verify_layout(&self.buffer).map(|_| ()),
+        })
+    }
 
-        if archived_header.magic != MAGIC_NUMBER {
-            return Err(StorageError::InvalidMagic);
-        }
+    /// The most recent signed state sealed into this file, if any.
+    pub fn latest_checkpoint(&self) -> Result<Option<Checkpoint>, StorageError> {
+        let (_header, body) = self.read_and_verify()?;
+        Ok(body.checkpoints.into_iter().last().map(Checkpoint::from))
+    }
+}
 
-        if archived_header.version_major != 1 {
-            return Err(StorageError::UnsupportedVersion(
-                archived_header.version_major,
-                archived_header.version_minor,
-            ));
-        }
+/// Reads just the header, without touching the body. Nothing past the header is verified.
+pub fn read_header<P: AsRef<Path>>(path: P) -> Result<DatabaseHeader, StorageError> {
+    read_header_in(&backend::filesystem(), path)
+}
 
-        let header: DatabaseHeader =
-            rkyv::deserialize::<DatabaseHeader, RkyvError>(archived_header)
-                .map_err(|e| StorageError::Deserialization(format!("Header map error: {}", e)))?;
+pub fn read_header_in<P: AsRef<Path>>(
+    backend: &Backend,
+    path: P,
+) -> Result<DatabaseHeader, StorageError> {
+    // a short read is a truncated file, `parse_header` says so
+    let header_bytes = backend.read_at(path.as_ref(), 0, HEADER_SIZE)?;
+    parse_header(&header_bytes)
+}
 
-        let body_start = header.body_offset as usize;
-        let body_end = header.footer_offset as usize;
+fn parse_header(buffer: &[u8]) -> Result<DatabaseHeader, StorageError> {
+    let header_slice = buffer
+        .get(..HEADER_SIZE)
+        .ok_or_else(|| StorageError::Serialization("File truncated: missing header".to_string()))?;
 
-        let body_bytes = self.buffer.get(body_start..body_end).ok_or_else(|| {
-            StorageError::Serialization("Header offsets point outside file boundaries".to_string())
-        })?;
+    let archived_header =
+        rkyv::access::<rkyv::Archived<DatabaseHeader>, RkyvError>(header_slice)
+            .map_err(|e| StorageError::Deserialization(format!("Header validation: {}", e)))?;
 
-        let computed_checksum = sha256::digest(body_bytes);
-        let computed_bytes: [u8; 32] = hex::decode(&computed_checksum)
-            .map_err(|_| StorageError::Deserialization("Hash conversion error".to_string()))?
-            .try_into()
-            .map_err(|_| StorageError::Deserialization("Hash conversion error".to_string()))?;
+    if archived_header.magic != MAGIC_NUMBER {
+        return Err(StorageError::InvalidMagic);
+    }
 
-        if computed_bytes != header.checksum {
-            return Err(StorageError::ChecksumMismatch);
-        }
+    if !(1..=VERSION_MAJOR).contains(&archived_header.version_major) {
+        return Err(StorageError::UnsupportedVersion(
+            archived_header.version_major,
+            archived_header.version_minor,
+        ));
+    }
 
-        let archived_body = rkyv::access::<rkyv::Archived<DatabaseBody>, RkyvError>(body_bytes)
+    rkyv::deserialize::<DatabaseHeader, RkyvError>(archived_header)
+        .map_err(|e| StorageError::Deserialization(format!("Header map error: {}", e)))
+}
+
+/// Bytechecks and copies out the body, converting the 1.x layout on the way.
+pub(crate) fn decode_body(
+    header: &DatabaseHeader,
+    body_bytes: &[u8],
+) -> Result<DatabaseBody, StorageError> {
+    if header.version_major == 1 {
+        let legacy = rkyv::from_bytes::<LegacyDatabaseBody, RkyvError>(body_bytes)
             .map_err(|e| StorageError::Deserialization(format!("Body corruption: {}", e)))?;
+        return Ok(DatabaseBody::from(legacy));
+    }
 
-        let body: DatabaseBody = rkyv::deserialize::<DatabaseBody, RkyvError>(archived_body)
-            .map_err(|e| StorageError::Deserialization(format!("Body map error: {}", e)))?;
+    let archived_body = rkyv::access::<rkyv::Archived<DatabaseBody>, RkyvError>(body_bytes)
+        .map_err(|e| StorageError::Deserialization(format!("Body corruption: {}", e)))?;
 
-        Ok((header, body))
+    rkyv::deserialize::<DatabaseBody, RkyvError>(archived_body)
+        .map_err(|e| StorageError::Deserialization(format!("Body map error: {}", e)))
+}
+
+// 1.x writers filled in total_file_size as if the footer took 64 bytes, 2.0
+// fixed that
+const LEGACY_FOOTER_SIZE: u64 = 64;
+
+fn sha256_bytes(data: &[u8]) -> Result<[u8; 32], StorageError> {
+    hex::decode(sha256::digest(data))
+        .map_err(|_| StorageError::Deserialization("Hash conversion error".to_string()))?
+        .try_into()
+        .map_err(|_| StorageError::Deserialization("Hash conversion error".to_string()))
+}
+
+fn decode_footer(buffer: &[u8], footer_offset: usize) -> Result<DatabaseFooter, StorageError> {
+    let footer_bytes = footer_offset
+        .checked_add(FOOTER_SIZE)
+        .and_then(|footer_end| buffer.get(footer_offset..footer_end))
+        .ok_or(StorageError::MissingFooter)?;
+
+    // the footer follows the body directly, so it is not necessarily aligned
+    let mut aligned = AlignedVec::<16>::with_capacity(FOOTER_SIZE);
+    aligned.extend_from_slice(footer_bytes);
+
+    rkyv::from_bytes::<DatabaseFooter, RkyvError>(&aligned).map_err(|_| StorageError::MissingFooter)
+}
+
+/// Full-file verification, returns the header and where the body sits.
+/// Checks the body checksum from the header, then the footer's integrity hash over
+/// everything before it (so the header is covered too), then the recorded file size.
+/// Shared by the owned reader and the mapped one so both refuse the same files.
+pub(crate) fn verify_layout(buffer: &[u8]) -> Result<(DatabaseHeader, Range<usize>), StorageError> {
+    let header = parse_header(buffer)?;
+
+    let body_start = header.body_offset as usize;
+    let body_end = header.footer_offset as usize;
+
+    let body_bytes = buffer.get(body_start..body_end).ok_or_else(|| {
+        StorageError::Serialization("Header offsets point outside file boundaries".to_string())
+    })?;
+
+    if sha256_bytes(body_bytes)? != header.checksum {
+        return Err(StorageError::ChecksumMismatch);
+    }
+
+    let footer = decode_footer(buffer, body_end)?;
+
+    let pre_footer = buffer.get(..body_end).ok_or(StorageError::MissingFooter)?;
+    if sha256_bytes(pre_footer)? != footer.integrity_hash {
+        return Err(StorageError::IntegrityMismatch);
+    }
+
+    let recorded_size = if header.version_major == 1 {
+        footer
+            .total_file_size
+            .saturating_sub(LEGACY_FOOTER_SIZE - FOOTER_SIZE as u64)
+    } else {
+        footer.total_file_size
+    };
+
+    if recorded_size != buffer.len() as u64 {
+        return Err(StorageError::FileSizeMismatch {
+            expected: recorded_size,
+            actual: buffer.len() as u64,
+        });
+    }
+
+    Ok((header, body_start..body_end))
+}
+
+#[derive(Debug)]
+pub struct BodySummary {
+    pub records: usize,
+    pub users: usize,
+    pub checkpoints: usize,
+    pub first_index: Option<usize>,
+    pub last_index: Option<usize>,
+}
+
+/// Everything `DatabaseReader::inspect` could decode, plus the verification result.
+#[derive(Debug)]
+pub struct Inspection {
+    pub file_size: u64,
+    pub header: DatabaseHeader,
+    pub footer: Option<DatabaseFooter>,
+    pub body: Option<BodySummary>,
+    pub verification: Result<(), StorageError>,
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::indexing_slicing)]
+
+    use super::*;
+    use crate::core::{Ledger, User};
+    use crate::storage::database::VERSION_MINOR;
+    use crate::storage::writer::DatabaseWriter;
+
+    fn write_test_db(backend: &Backend, path: &str) -> Vec<u8> {
+        let mut ledger = Ledger::new();
+        let user = User::new("0xElvis");
+        ledger.register_user(user.clone());
+        ledger.add_record("footer test", vec![user]).unwrap();
+
+        let mut writer = DatabaseWriter::new_in(backend, path).unwrap();
+        writer.write_ledger(&ledger).unwrap();
+        backend.read(Path::new(path)).unwrap()
+    }
+
+    fn verify(path: &str, bytes: Vec<u8>) -> Result<(), StorageError> {
+        let backend = backend::memory();
+        backend.append(Path::new(path), &bytes).unwrap();
+        let result = DatabaseReader::new_in(&backend, path)
+            .unwrap()
+            .read_and_verify();
+        result.map(|_| ())
+    }
+
+    #[test]
+    fn test_footer_roundtrip() {
+        let path = "test_footer_roundtrip.ukweli";
+        let backend = backend::memory();
+        let bytes = write_test_db(&backend, path);
+
+        let inspection = DatabaseReader::new_in(&backend, path)
+            .unwrap()
+            .inspect()
+            .unwrap();
+        assert_eq!(
+            (
+                inspection.header.version_major,
+                inspection.header.version_minor
+            ),
+            (VERSION_MAJOR, VERSION_MINOR)
+        );
+        let footer = inspection.footer.unwrap();
+        assert_eq!(footer.total_file_size, bytes.len() as u64);
+        assert_eq!(
+            inspection.header.footer_offset as usize + FOOTER_SIZE,
+            bytes.len()
+        );
+        assert_eq!(inspection.body.unwrap().records, 2);
+        assert!(inspection.verification.is_ok());
+    }
+
+    #[test]
+    fn test_edited_header_detected() {
+        let path = "test_footer_header.ukweli";
+        let mut bytes = write_test_db(&backend::memory(), path);
+
+        // record_count sits right after magic, versions and padding
+        bytes[8] = 99;
+
+        assert!(matches!(
+            verify(path, bytes),
+            Err(StorageError::IntegrityMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_trailing_bytes_detected() {
+        let path = "test_footer_trailing.ukweli";
+        let mut bytes = write_test_db(&backend::memory(), path);
+        bytes.extend_from_slice(b"extra");
+
+        assert!(matches!(
+            verify(path, bytes),
+            Err(StorageError::FileSizeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_truncated_footer_detected() {
+        let path = "test_footer_truncated.ukweli";
+        let mut bytes = write_test_db(&backend::memory(), path);
+        bytes.truncate(bytes.len() - 8);
+
+        assert!(matches!(
+            verify(path, bytes),
+            Err(StorageError::MissingFooter)
+        ));
+    }
+
+    #[test]
+    fn test_legacy_footer_size_accepted() {
+        // written by the 1.0 CLI, with the 64 byte footer size every 1.x writer used
+        let bytes = include_bytes!("../../tests/fixtures/baseline.ukweli").to_vec();
+        let header = parse_header(&bytes).unwrap();
+        let footer = decode_footer(&bytes, header.footer_offset as usize).unwrap();
+
+        assert_eq!((header.version_major, header.version_minor), (1, 0));
+        assert_eq!(
+            footer.total_file_size,
+            bytes.len() as u64 + LEGACY_FOOTER_SIZE - FOOTER_SIZE as u64
+        );
+        assert!(verify("test_footer_legacy.ukweli", bytes.clone()).is_ok());
+
+        let body = DatabaseReader { buffer: bytes }
+            .read_and_verify()
+            .unwrap()
+            .1;
+        assert_eq!(body.records.len(), 3);
+        assert!(body.checkpoints.is_empty());
     }
 }
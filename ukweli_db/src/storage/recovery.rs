@@ -1,125 +1,398 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::path::Path;
 
 use ed25519_dalek::{Signature, VerifyingKey};
 
-use crate::core::{Ledger, Record, User};
+use crate::core::{Checkpoint, Ledger, Payload, Record, User};
 use crate::error::{LedgerError, StorageError};
-use crate::storage::append::AppendLog;
+use crate::storage::append::{AppendLog, WalEntry, WalRepair};
+use crate::storage::atomic;
+use crate::storage::backend::{self, Backend};
 use crate::storage::database::DatabaseBody;
-use crate::storage::persitence::{SerializableRecord, SerializableUser};
+use crate::storage::lock::DatabaseLock;
+use crate::storage::mapped::MappedDatabase;
+use crate::storage::persitence::{SerializableCheckpoint, SerializableRecord, SerializableUser};
 use crate::storage::reader::DatabaseReader;
+use crate::storage::segment::Manifest;
 use crate::storage::writer::DatabaseWriter;
 
 pub struct RecoveryManager;
 
 impl RecoveryManager {
     pub fn recover_ledger<P: AsRef<Path>>(db_path: P) -> Result<Ledger, StorageError> {
-        let reader = DatabaseReader::new(&db_path)?;
+        Self::recover_ledger_in(&backend::filesystem(), db_path)
+    }
 
-        match reader.read_and_verify() {
-            Ok((_header, body)) => {
-                let mut ledger = Self::reconstruct_from_body(body)?;
+    pub fn recover_ledger_in<P: AsRef<Path>>(
+        backend: &Backend,
+        db_path: P,
+    ) -> Result<Ledger, StorageError> {
+        // recovery can restore backups and compact, so it writes
+        let lock = DatabaseLock::exclusive_in(backend, &db_path)?;
+        Self::recover_ledger_locked(&db_path, &lock)
+    }
 
-                if let Ok(mut append_log) = AppendLog::new(&db_path) {
-                    match append_log.read_all_entries() {
-                        Ok(entries) if !entries.is_empty() => {
-                            Self::replay_wal(&mut ledger, entries)?;
-                            Self::compact(&db_path, &ledger)?;
-                        }
-                        _ => {}
-                    }
-                }
+    /// `recover_ledger` for callers already holding the exclusive lock. Whatever
+    /// the WAL held is sealed into a new segment afterwards.
+    pub fn recover_ledger_locked<P: AsRef<Path>>(
+        db_path: P,
+        lock: &DatabaseLock,
+    ) -> Result<Ledger, StorageError> {
+        let (ledger, replayed) = Self::load_locked(&db_path, lock)?;
+        if replayed {
+            Self::compact_locked(&db_path, &ledger, lock)?;
+        }
+        Ok(ledger)
+    }
+
+    /// Like `recover_ledger` but leaves the WAL as it is, when to compact is up
+    /// to the caller. Still a writer: a torn WAL tail is cut and damaged files
+    /// are swapped for their backups.
+    pub fn load_ledger<P: AsRef<Path>>(db_path: P) -> Result<Ledger, StorageError> {
+        Self::load_ledger_in(&backend::filesystem(), db_path)
+    }
+
+    pub fn load_ledger_in<P: AsRef<Path>>(
+        backend: &Backend,
+        db_path: P,
+    ) -> Result<Ledger, StorageError> {
+        let lock = DatabaseLock::exclusive_in(backend, &db_path)?;
+        Self::load_ledger_locked(&db_path, &lock)
+    }
+
+    /// `load_ledger` under a lock the caller holds, e.g. a writer that found the
+    /// chain tip moved and needs to catch up.
+    pub fn load_ledger_locked<P: AsRef<Path>>(
+        db_path: P,
+        lock: &DatabaseLock,
+    ) -> Result<Ledger, StorageError> {
+        Ok(Self::load_locked(&db_path, lock)?.0)
+    }
+
+    /// Loads the database and its WAL without changing anything on disk. A torn
+    /// last write or an unfinished batch is left out instead of cut off, anything
+    /// else a writer would have to fix first, like a damaged file, is an error.
+    pub fn read_ledger<P: AsRef<Path>>(db_path: P) -> Result<Ledger, StorageError> {
+        Self::read_ledger_in(&backend::filesystem(), db_path)
+    }
+
+    pub fn read_ledger_in<P: AsRef<Path>>(
+        backend: &Backend,
+        db_path: P,
+    ) -> Result<Ledger, StorageError> {
+        let _lock = DatabaseLock::shared_in(backend, &db_path)?;
+
+        let (mut ledger, watermark) = Self::load_sealed(backend, &db_path)?;
+
+        if let Some(mut append_log) = AppendLog::open_existing_in(backend, &db_path)? {
+            let entries = append_log.peek_entries()?;
+            Self::check_wal_start(&entries, watermark)?;
+            Self::replay_wal(&mut ledger, AppendLog::committed(entries)?, watermark)?;
+        }
 
-                ledger.verify_chain().map_err(|e| match e {
-                    LedgerError::ChainValidation(msg) => StorageError::ValidationFailed(msg),
-                    _ => StorageError::ValidationFailed(format!("Ledger error: {:?}", e)),
-                })?;
+        Self::verify_loaded(&mut ledger)?;
+        Ok(ledger)
+    }
 
-                Ok(ledger)
+    // the ledger plus whether the WAL added anything to it
+    fn load_locked<P: AsRef<Path>>(
+        db_path: P,
+        lock: &DatabaseLock,
+    ) -> Result<(Ledger, bool), StorageError> {
+        lock.ensure_exclusive(&db_path)?;
+        let backend = lock.backend();
+
+        let (mut ledger, watermark) = match Self::load_sealed(backend, &db_path) {
+            Ok(sealed) => sealed,
+            Err(e) if Self::is_damage(&e) && Self::restore_from_backups(backend, &db_path)? => {
+                Self::load_sealed(backend, &db_path)?
+            }
+            Err(StorageError::ChecksumMismatch) => {
+                return Ok((Self::recover_from_wal(backend, &db_path)?, false));
+            }
+            Err(e) => return Err(e),
+        };
+
+        let mut replayed = false;
+        if let Ok(mut append_log) = AppendLog::new_in(backend, &db_path) {
+            // a torn last write or a batch cut short by a crash never happened,
+            // damage anywhere else stops us here until someone runs `repair`
+            append_log.recover_tail(lock)?;
+
+            let entries = append_log.read_all_entries()?;
+            Self::check_wal_start(&entries, watermark)?;
+
+            let entries = AppendLog::committed(entries)?;
+            if !entries.is_empty() {
+                Self::replay_wal(&mut ledger, entries, watermark)?;
+                replayed = true;
             }
-            Err(StorageError::ChecksumMismatch) => Self::recover_from_wal(&db_path),
-            Err(e) => Err(e),
         }
+
+        Self::verify_loaded(&mut ledger)?;
+        Ok((ledger, replayed))
     }
 
-    fn reconstruct_from_body(body: DatabaseBody) -> Result<Ledger, StorageError> {
+    // storage fills the ledger in directly, so its roles and keys are replayed here
+    fn verify_loaded(ledger: &mut Ledger) -> Result<(), StorageError> {
+        ledger.rebuild_state();
+        ledger.verify_chain().map_err(|e| match e {
+            LedgerError::ChainValidation(msg) => StorageError::ValidationFailed(msg),
+            _ => StorageError::ValidationFailed(format!("Ledger error: {:?}", e)),
+        })?;
+        Ok(())
+    }
+
+    // the WAL has to pick up right after what was sealed, a later start means
+    // entries were lost along with a sealed file
+    fn check_wal_start(entries: &[WalEntry], watermark: u64) -> Result<(), StorageError> {
+        if let Some((first, _)) = entries.iter().find(|(entry, _)| entry.is_chained())
+            && first.lsn > watermark + 1
+        {
+            return Err(StorageError::WalGap {
+                sealed: watermark,
+                found: first.lsn,
+            });
+        }
+        Ok(())
+    }
+
+    /// Cuts the WAL back to its longest valid prefix, wherever the damage is,
+    /// then recovers as usual. The cut off tail goes to `.wal.corrupt` unless
+    /// `quarantine` is false.
+    pub fn repair<P: AsRef<Path>>(
+        db_path: P,
+        quarantine: bool,
+    ) -> Result<(WalRepair, Ledger), StorageError> {
+        Self::repair_in(&backend::filesystem(), db_path, quarantine)
+    }
+
+    pub fn repair_in<P: AsRef<Path>>(
+        backend: &Backend,
+        db_path: P,
+        quarantine: bool,
+    ) -> Result<(WalRepair, Ledger), StorageError> {
+        let lock = DatabaseLock::exclusive_in(backend, &db_path)?;
+
+        let repair = AppendLog::new_in(backend, &db_path)?.repair(&lock, quarantine)?;
+        let ledger = Self::recover_ledger_locked(&db_path, &lock)?;
+
+        Ok((repair, ledger))
+    }
+
+    // errors that mean a file on disk is damaged, as opposed to e.g. permissions
+    fn is_damage(error: &StorageError) -> bool {
+        match error {
+            StorageError::Io(e) => e.kind() == std::io::ErrorKind::NotFound,
+            StorageError::InvalidMagic
+            | StorageError::Serialization(_)
+            | StorageError::Deserialization(_)
+            | StorageError::ChecksumMismatch
+            | StorageError::MissingFooter
+            | StorageError::IntegrityMismatch
+            | StorageError::FileSizeMismatch { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// Swaps damaged files for their `.backup` copies when the backup itself checks
+    /// out, then lists any segments the restored manifest didn't know about yet.
+    /// Returns whether anything changed on disk.
+    fn restore_from_backups<P: AsRef<Path>>(
+        backend: &Backend,
+        db_path: P,
+    ) -> Result<bool, StorageError> {
+        let mut restored = false;
+
+        let manifest_path = Manifest::path_for(&db_path);
+        if backend.exists(&manifest_path)?
+            && Manifest::load_in(backend, &db_path).is_err()
+            && Manifest::from_file(backend, atomic::backup_path(&manifest_path)).is_ok()
+        {
+            backend.restore_backup(&manifest_path)?;
+            restored = true;
+        }
+
+        let db_backup = atomic::backup_path(&db_path);
+        if Self::verify_single(backend, &db_path).is_err()
+            && Self::verify_single(backend, &db_backup).is_ok()
+        {
+            backend.restore_backup(db_path.as_ref())?;
+            restored = true;
+        }
+
+        if let Ok(Some(mut manifest)) = Manifest::load_in(backend, &db_path)
+            && manifest.adopt_sealed_segments(backend, &db_path)? > 0
+        {
+            manifest.save(backend, &db_path)?;
+            restored = true;
+        }
+
+        Ok(restored)
+    }
+
+    fn verify_single<P: AsRef<Path>>(backend: &Backend, path: P) -> Result<(), StorageError> {
+        DatabaseReader::new_in(backend, path)?
+            .read_and_verify()
+            .map(|_| ())
+    }
+
+    // everything sealed so far, either the single database file or every segment,
+    // plus the last WAL entry that went into it
+    fn load_sealed<P: AsRef<Path>>(
+        backend: &Backend,
+        db_path: P,
+    ) -> Result<(Ledger, u64), StorageError> {
+        let Some(manifest) = Manifest::load_in(backend, &db_path)? else {
+            let reader = DatabaseReader::new_in(backend, &db_path)?;
+            let (header, body) = reader.read_and_verify()?;
+            return Ok((Self::reconstruct_from_body(body)?, header.last_lsn));
+        };
+
+        let mut ledger = Self::empty_ledger();
+        let mut last_lsn = 0;
+
+        for segment in &manifest.segments {
+            let reader = DatabaseReader::new_in(backend, manifest.segment_path(&db_path, segment))?;
+            let (header, body) = reader.read_and_verify()?;
+
+            Manifest::check_segment(
+                segment,
+                header.first_record_index,
+                header.record_count,
+                &header.checksum,
+            )?;
+
+            if segment.first_index != ledger.records.len() {
+                return Err(StorageError::ValidationFailed(format!(
+                    "Segment {} starts at record {}, expected {}",
+                    segment.file,
+                    segment.first_index,
+                    ledger.records.len()
+                )));
+            }
+
+            last_lsn = last_lsn.max(header.last_lsn);
+            Self::apply_body(&mut ledger, body)?;
+        }
+
+        Ok((ledger, last_lsn))
+    }
+
+    /// LSN of the newest WAL entry sealed so far, 0 if there is no database yet
+    /// or it was last compacted before WAL entries had LSNs.
+    pub fn last_compacted_lsn<P: AsRef<Path>>(db_path: P) -> Result<u64, StorageError> {
+        Self::last_compacted_lsn_in(&backend::filesystem(), db_path)
+    }
+
+    pub fn last_compacted_lsn_in<P: AsRef<Path>>(
+        backend: &Backend,
+        db_path: P,
+    ) -> Result<u64, StorageError> {
+        if !backend.exists(db_path.as_ref())? {
+            return Ok(0);
+        }
+
+        Manifest::load_or_single_file_in(backend, &db_path)?.last_lsn(backend, &db_path)
+    }
+
+    fn empty_ledger() -> Ledger {
         let mut ledger = Ledger::new();
         ledger.records.clear();
         ledger.users.clear();
         ledger.verify_registry.clear();
+        ledger
+    }
 
-        for ser_user in body.users {
-            let verifying_key_bytes: [u8; 32] =
-                ser_user.verifying_key_bytes.try_into().map_err(|_| {
-                    StorageError::Deserialization("Invalid verifying key length".to_string())
-                })?;
-
-            let verifying_key = VerifyingKey::from_bytes(&verifying_key_bytes).map_err(|e| {
-                StorageError::Deserialization(format!("Invalid verifying key: {}", e))
-            })?;
+    fn reconstruct_from_body(body: DatabaseBody) -> Result<Ledger, StorageError> {
+        let mut ledger = Self::empty_ledger();
+        Self::apply_body(&mut ledger, body)?;
+        Ok(ledger)
+    }
 
-            let user = User::from_verifying_key(
-                &ser_user.user_id,
-                &verifying_key_bytes,
-                ser_user.roles.into_iter().collect(),
-            )
-            .map_err(|err| {
-                StorageError::Deserialization(format!("Failed to create user: {}", err))
-            })?;
+    fn apply_body(ledger: &mut Ledger, body: DatabaseBody) -> Result<(), StorageError> {
+        for ser_user in body.users {
+            let user = Self::user_from_serialized(ser_user)?;
 
-            ledger.users.insert(ser_user.user_id.clone(), user);
             ledger
                 .verify_registry
-                .insert(ser_user.user_id.clone(), verifying_key);
+                .insert(user.user_id.clone(), user.verifying_key);
+            ledger.users.insert(user.user_id.clone(), user);
         }
 
         for ser_record in body.records {
-            let signers: Vec<User> = ser_record
-                .signer_ids
-                .iter()
-                .filter_map(|user_id| ledger.users.get(user_id).cloned())
-                .collect();
-
-            if signers.len() != ser_record.signer_ids.len() {
-                return Err(StorageError::Deserialization(format!(
-                    "Missing signers for record {}",
-                    ser_record.index
-                )));
-            }
+            let record = Self::record_from_serialized(ser_record, &ledger.users)?;
+            ledger.records.push(record);
+        }
 
-            let mut signatures = HashMap::new();
-            for (user_id, sig_bytes) in &ser_record.signatures {
-                if let Some(sig) = Self::try_parse_signature(sig_bytes) {
-                    signatures.insert(user_id.clone(), sig);
-                }
-            }
+        ledger.records.sort_by_key(|a| a.index);
 
-            if signatures.len() != ser_record.signatures.len() {
-                return Err(StorageError::Deserialization(format!(
-                    "Invalid signatures for record {}",
-                    ser_record.index
-                )));
-            }
+        for ser_checkpoint in body.checkpoints {
+            ledger
+                .merge_checkpoint(Checkpoint::from(ser_checkpoint))
+                .map_err(|e| StorageError::ValidationFailed(e.to_string()))?;
+        }
 
-            let record = Record {
-                index: ser_record.index,
-                payload: ser_record.payload,
-                payload_hash: ser_record.payload_hash,
-                signers,
-                signatures,
-                prev_hash: ser_record.prev_hash,
-                record_hash: ser_record.record_hash,
-                timestamp: ser_record.timestamp,
-                nonce: ser_record.nonce,
-            };
+        Ok(())
+    }
 
-            ledger.records.push(record);
+    pub(crate) fn user_from_serialized(ser_user: SerializableUser) -> Result<User, StorageError> {
+        let verifying_key_bytes: [u8; 32] =
+            ser_user.verifying_key_bytes.try_into().map_err(|_| {
+                StorageError::Deserialization("Invalid verifying key length".to_string())
+            })?;
+
+        User::from_verifying_key(
+            &ser_user.user_id,
+            &verifying_key_bytes,
+            ser_user.roles.into_iter().collect(),
+        )
+        .map_err(|err| StorageError::Deserialization(format!("Failed to create user: {}", err)))
+    }
+
+    // sealed records must resolve every signer and signature, unlike the WAL
+    pub(crate) fn record_from_serialized(
+        ser_record: SerializableRecord,
+        users: &HashMap<String, User>,
+    ) -> Result<Record, StorageError> {
+        let signers: Vec<User> = ser_record
+            .signer_ids
+            .iter()
+            .filter_map(|user_id| users.get(user_id).cloned())
+            .collect();
+
+        if signers.len() != ser_record.signer_ids.len() {
+            return Err(StorageError::Deserialization(format!(
+                "Missing signers for record {}",
+                ser_record.index
+            )));
         }
 
-        ledger.records.sort_by(|a, b| a.index.cmp(&b.index));
+        let mut signatures = HashMap::new();
+        for (user_id, sig_bytes) in &ser_record.signatures {
+            if let Some(sig) = Self::try_parse_signature(sig_bytes) {
+                signatures.insert(user_id.clone(), sig);
+            }
+        }
 
-        Ok(ledger)
+        if signatures.len() != ser_record.signatures.len() {
+            return Err(StorageError::Deserialization(format!(
+                "Invalid signatures for record {}",
+                ser_record.index
+            )));
+        }
+
+        Ok(Record {
+            index: ser_record.index,
+            payload: Payload::try_from(ser_record.payload)?,
+            payload_hash: ser_record.payload_hash,
+            signers,
+            signatures,
+            prev_hash: ser_record.prev_hash,
+            record_hash: ser_record.record_hash,
+            timestamp: ser_record.timestamp,
+            nonce: ser_record.nonce,
+        })
     }
 
     fn try_parse_signature(sig_bytes: &[u8]) -> Option<Signature> {
@@ -130,30 +403,19 @@ impl RecoveryManager {
     fn replay_wal(
         ledger: &mut Ledger,
         entries: Vec<(crate::storage::append::AppendEntry, Vec<u8>)>,
+        watermark: u64,
     ) -> Result<(), StorageError> {
         use rkyv::rancor::Error as RkyvError;
 
         for (entry, data) in entries {
+            // sealed already, a compaction crashed before it could clear the WAL
+            if entry.is_chained() && entry.lsn <= watermark {
+                continue;
+            }
+
             match entry.entry_type {
                 1 => {
-                    let archived =
-                        rkyv::access::<rkyv::Archived<SerializableRecord>, RkyvError>(&data)
-                            .map_err(|e| {
-                                StorageError::Deserialization(format!(
-                                    "Failed to access WAL record: {}",
-                                    e
-                                ))
-                            })?;
-
-                    let ser_record: SerializableRecord =
-                        rkyv::deserialize::<SerializableRecord, RkyvError>(archived).map_err(
-                            |e| {
-                                StorageError::Deserialization(format!(
-                                    "Failed to deserialize WAL record: {}",
-                                    e
-                                ))
-                            },
-                        )?;
+                    let ser_record = entry.record(&data)?;
 
                     let signers: Vec<User> = ser_record
                         .signer_ids
@@ -176,7 +438,7 @@ impl RecoveryManager {
 
                     let record = Record {
                         index: ser_record.index,
-                        payload: ser_record.payload,
+                        payload: Payload::try_from(ser_record.payload)?,
                         payload_hash: ser_record.payload_hash,
                         signers,
                         signatures,
@@ -186,8 +448,12 @@ impl RecoveryManager {
                         nonce: ser_record.nonce,
                     };
 
-                    if !ledger.records.iter().any(|r| r.index == record.index) {
-                        ledger.records.push(record);
+                    // the same record twice is harmless, two different ones means
+                    // two writers built on the same tip
+                    match ledger.records.get(record.index) {
+                        Some(existing) if existing.record_hash == record.record_hash => {}
+                        Some(_) => return Err(StorageError::ForkDetected(record.index)),
+                        None => ledger.records.push(record),
                     }
                 }
                 2 => {
@@ -238,6 +504,21 @@ impl RecoveryManager {
                             .insert(ser_user.user_id.clone(), verifying_key);
                     }
                 }
+                3 => {
+                    let ser_checkpoint = rkyv::from_bytes::<SerializableCheckpoint, RkyvError>(
+                        &data,
+                    )
+                    .map_err(|e| {
+                        StorageError::Deserialization(format!(
+                            "Failed to read WAL checkpoint: {}",
+                            e
+                        ))
+                    })?;
+
+                    ledger
+                        .merge_checkpoint(Checkpoint::from(ser_checkpoint))
+                        .map_err(|e| StorageError::ValidationFailed(e.to_string()))?;
+                }
                 _ => {
                     return Err(StorageError::Deserialization(format!(
                         "Unknown entry type: {}",
@@ -250,9 +531,24 @@ impl RecoveryManager {
         Ok(())
     }
 
-    fn recover_from_wal<P: AsRef<Path>>(db_path: P) -> Result<Ledger, StorageError> {
-        let mut append_log = AppendLog::new(&db_path)?;
-        let entries = append_log.read_all_entries()?;
+    fn recover_from_wal<P: AsRef<Path>>(
+        backend: &Backend,
+        db_path: P,
+    ) -> Result<Ledger, StorageError> {
+        let mut append_log = AppendLog::new_in(backend, &db_path)?;
+
+        // anything compacted before the WAL's first entry only lived in the
+        // damaged file, replaying the rest would give a ledger with a hole in it
+        if let Some((first, _)) = append_log.lsn_range()?
+            && first > 1
+        {
+            return Err(StorageError::WalGap {
+                sealed: 0,
+                found: first,
+            });
+        }
+
+        let entries = append_log.read_committed_entries()?;
 
         if entries.is_empty() {
             return Err(StorageError::ValidationFailed(
@@ -263,31 +559,209 @@ impl RecoveryManager {
         let mut ledger = Ledger::new();
         ledger.records.clear();
 
-        Self::replay_wal(&mut ledger, entries)?;
+        Self::replay_wal(&mut ledger, entries, 0)?;
 
-        ledger.records.sort_by(|a, b| a.index.cmp(&b.index));
+        ledger.records.sort_by_key(|a| a.index);
+        ledger.rebuild_state();
 
         Ok(ledger)
     }
 
+    /// Seals whatever the WAL added since the last compaction into new segments,
+    /// then clears the WAL. Existing files are never rewritten, so the cost only
+    /// depends on how much is new.
     pub fn compact<P: AsRef<Path>>(db_path: P, ledger: &Ledger) -> Result<(), StorageError> {
-        let backup_path = format!("{}.backup", db_path.as_ref().display());
-        if db_path.as_ref().exists() {
-            std::fs::copy(&db_path, &backup_path)?;
+        Self::compact_in(&backend::filesystem(), db_path, ledger)
+    }
+
+    pub fn compact_in<P: AsRef<Path>>(
+        backend: &Backend,
+        db_path: P,
+        ledger: &Ledger,
+    ) -> Result<(), StorageError> {
+        let lock = DatabaseLock::exclusive_in(backend, &db_path)?;
+        Self::compact_locked(&db_path, ledger, &lock)
+    }
+
+    /// `compact` under a lock the caller holds. The ledger has to be at the chain
+    /// tip, otherwise clearing the WAL would throw away what other writers added.
+    pub fn compact_locked<P: AsRef<Path>>(
+        db_path: P,
+        ledger: &Ledger,
+        lock: &DatabaseLock,
+    ) -> Result<(), StorageError> {
+        lock.ensure_exclusive(&db_path)?;
+        let backend = lock.backend();
+        Self::ensure_at_tip_in(backend, &db_path, ledger)?;
+
+        let wal_lsn = match AppendLog::new_in(backend, &db_path) {
+            Ok(mut append_log) => append_log.lsn_range()?.map(|(_, last)| last),
+            Err(_) => None,
+        };
+
+        if !backend.exists(db_path.as_ref())? {
+            // nothing sealed yet, the whole ledger becomes the first file
+            let mut writer = DatabaseWriter::new_in(backend, &db_path)?;
+            writer.write_ledger_at(ledger, wal_lsn.unwrap_or(0))?;
+            return Self::clear_wal(backend, &db_path);
         }
 
-        let mut writer = DatabaseWriter::new(&db_path)?;
-        writer.write_ledger(ledger)?;
+        let mut manifest = Manifest::load_or_single_file_in(backend, &db_path)?;
+        let last_lsn = manifest
+            .last_lsn(backend, &db_path)?
+            .max(wal_lsn.unwrap_or(0));
+        let sealed_segments = manifest.segments.len();
+        let next_index = manifest.next_index();
+
+        let (wal_users, wal_checkpoints) = Self::wal_contents(backend, &db_path)?;
+
+        let records: Vec<SerializableRecord> = ledger
+            .records
+            .iter()
+            .filter(|record| record.index >= next_index)
+            .map(SerializableRecord::from)
+            .collect();
+
+        // signers of new records go in too, sealing a user twice is harmless
+        let mut user_ids: BTreeSet<String> = wal_users;
+        for record in ledger.records.iter().filter(|r| r.index >= next_index) {
+            user_ids.extend(record.signers.iter().map(|s| s.user_id.clone()));
+        }
+
+        let users: Vec<SerializableUser> = user_ids
+            .iter()
+            .filter_map(|id| ledger.users.get(id))
+            .map(SerializableUser::from)
+            .collect();
 
-        if let Ok(mut append_log) = AppendLog::new(&db_path) {
-            let _ = append_log.truncate();
+        // the ledger copy has every witness merged in
+        let checkpoints: Vec<SerializableCheckpoint> = ledger
+            .checkpoints
+            .iter()
+            .filter(|c| c.tree_size() > next_index || wal_checkpoints.contains(&c.tree_size()))
+            .map(SerializableCheckpoint::from)
+            .collect();
+
+        manifest.seal(backend, &db_path, records, users, checkpoints, last_lsn)?;
+
+        if manifest.segments.len() != sealed_segments {
+            manifest.save(backend, &db_path)?;
         }
 
-        if Path::new(&backup_path).exists() {
-            std::fs::remove_file(&backup_path)?;
+        Self::clear_wal(backend, &db_path)
+    }
+
+    /// Index and hash of the newest record on disk, from the WAL if it has any
+    /// records and otherwise from the last sealed segment.
+    pub fn chain_tip<P: AsRef<Path>>(db_path: P) -> Result<Option<(usize, String)>, StorageError> {
+        Self::chain_tip_in(&backend::filesystem(), db_path)
+    }
+
+    pub fn chain_tip_in<P: AsRef<Path>>(
+        backend: &Backend,
+        db_path: P,
+    ) -> Result<Option<(usize, String)>, StorageError> {
+        let mut tip = None;
+        if let Ok(mut append_log) = AppendLog::new_in(backend, &db_path) {
+            for (entry, data) in append_log.read_committed_entries()? {
+                if entry.entry_type == 1 {
+                    let record = entry.record(&data)?;
+                    tip = Some((record.index, record.record_hash));
+                }
+            }
         }
 
-        Ok(())
+        if tip.is_some() {
+            return Ok(tip);
+        }
+        Self::sealed_tip_in(backend, &db_path)
+    }
+
+    /// Index and hash of the newest sealed record, ignoring the WAL.
+    pub fn sealed_tip<P: AsRef<Path>>(db_path: P) -> Result<Option<(usize, String)>, StorageError> {
+        Self::sealed_tip_in(&backend::filesystem(), db_path)
+    }
+
+    pub fn sealed_tip_in<P: AsRef<Path>>(
+        backend: &Backend,
+        db_path: P,
+    ) -> Result<Option<(usize, String)>, StorageError> {
+        if !backend.exists(db_path.as_ref())? {
+            return Ok(None);
+        }
+
+        let manifest = Manifest::load_or_single_file_in(backend, &db_path)?;
+        let Some(segment) = manifest.segments.iter().rev().find(|s| s.record_count > 0) else {
+            return Ok(None);
+        };
+
+        let mapped = MappedDatabase::open_in(backend, manifest.segment_path(&db_path, segment))?;
+        Ok(mapped
+            .serialized_record(segment.end_index() - 1)?
+            .map(|record| (record.index, record.record_hash)))
+    }
+
+    /// Checks the ledger's last record is also the last one on disk.
+    pub fn ensure_at_tip<P: AsRef<Path>>(db_path: P, ledger: &Ledger) -> Result<(), StorageError> {
+        Self::ensure_at_tip_in(&backend::filesystem(), db_path, ledger)
+    }
+
+    pub fn ensure_at_tip_in<P: AsRef<Path>>(
+        backend: &Backend,
+        db_path: P,
+        ledger: &Ledger,
+    ) -> Result<(), StorageError> {
+        let Some((index, hash)) = Self::chain_tip_in(backend, &db_path)? else {
+            return Ok(());
+        };
+
+        match ledger.records.last() {
+            Some(last) if last.index == index && last.record_hash == hash => Ok(()),
+            Some(last) if last.index == index => Err(StorageError::ForkDetected(index)),
+            last => Err(StorageError::StaleTip {
+                expected: last.map(|r| r.index).unwrap_or(0),
+                found: index,
+            }),
+        }
+    }
+
+    // the sealed files hold everything by now, but appends refuse a WAL that
+    // still holds compacted entries, so the caller has to hear it wasn't cleared
+    fn clear_wal<P: AsRef<Path>>(backend: &Backend, db_path: P) -> Result<(), StorageError> {
+        AppendLog::new_in(backend, &db_path)?.truncate()
+    }
+
+    // user ids and checkpoint sizes written to the WAL since the last compaction
+    fn wal_contents<P: AsRef<Path>>(
+        backend: &Backend,
+        db_path: P,
+    ) -> Result<(BTreeSet<String>, BTreeSet<usize>), StorageError> {
+        use rkyv::rancor::Error as RkyvError;
+
+        let mut users = BTreeSet::new();
+        let mut checkpoints = BTreeSet::new();
+
+        let Ok(mut append_log) = AppendLog::new_in(backend, &db_path) else {
+            return Ok((users, checkpoints));
+        };
+
+        for (entry, data) in append_log.read_committed_entries()? {
+            match entry.entry_type {
+                2 => {
+                    let user = rkyv::from_bytes::<SerializableUser, RkyvError>(&data)
+                        .map_err(|e| StorageError::Deserialization(e.to_string()))?;
+                    users.insert(user.user_id);
+                }
+                3 => {
+                    let checkpoint = rkyv::from_bytes::<SerializableCheckpoint, RkyvError>(&data)
+                        .map_err(|e| StorageError::Deserialization(e.to_string()))?;
+                    checkpoints.insert(checkpoint.tree_size as usize);
+                }
+                _ => {}
+            }
+        }
+
+        Ok((users, checkpoints))
     }
 
     pub fn create_snapshot<P: AsRef<Path>>(
@@ -300,8 +774,32 @@ impl RecoveryManager {
     }
 
     pub fn verify_file<P: AsRef<Path>>(db_path: P) -> Result<bool, StorageError> {
-        let reader = DatabaseReader::new(db_path)?;
-        reader.read_and_verify()?;
+        Self::verify_file_in(&backend::filesystem(), db_path)
+    }
+
+    pub fn verify_file_in<P: AsRef<Path>>(
+        backend: &Backend,
+        db_path: P,
+    ) -> Result<bool, StorageError> {
+        let _lock = DatabaseLock::shared_in(backend, &db_path)?;
+
+        let Some(manifest) = Manifest::load_in(backend, &db_path)? else {
+            let reader = DatabaseReader::new_in(backend, db_path)?;
+            reader.read_and_verify()?;
+            return Ok(true);
+        };
+
+        for segment in &manifest.segments {
+            let reader = DatabaseReader::new_in(backend, manifest.segment_path(&db_path, segment))?;
+            let (header, _body) = reader.read_and_verify()?;
+            Manifest::check_segment(
+                segment,
+                header.first_record_index,
+                header.record_count,
+                &header.checksum,
+            )?;
+        }
+
         Ok(true)
     }
 }
@@ -321,27 +819,456 @@ mod tests {
     #[test]
     fn test_recovery_and_compact() {
         let test_path = "test_recovery.ukweli";
-
-        let _ = fs::remove_file(test_path);
-        let _ = fs::remove_file(format!("{}.wal", test_path));
-        let _ = fs::remove_file(format!("{}.backup", test_path));
+        let backend = backend::memory();
 
         let mut ledger = Ledger::new();
         let user1 = User::new("recovery_user");
         ledger.register_user(user1.clone());
         ledger.add_record("test transaction", vec![user1]).unwrap();
 
-        let mut writer = DatabaseWriter::new(test_path).unwrap();
+        let mut writer = DatabaseWriter::new_in(&backend, test_path).unwrap();
         writer.write_ledger(&ledger).unwrap();
 
-        let recovered = RecoveryManager::recover_ledger(test_path).unwrap();
+        let recovered = RecoveryManager::recover_ledger_in(&backend, test_path).unwrap();
         assert_eq!(recovered.length(), ledger.length());
 
+        assert!(RecoveryManager::verify_file_in(&backend, test_path).unwrap());
+    }
+
+    #[test]
+    fn test_checkpoint_survives_wal_and_compaction() {
+        let test_path = "test_recovery_checkpoint.ukweli";
+        let backend = backend::memory();
+
+        let mut ledger = Ledger::new();
+        let operator = User::new("operator");
+        ledger.designate_operator(&operator).unwrap();
+        ledger.add_record("eod", vec![operator.clone()]).unwrap();
+
+        let mut writer = DatabaseWriter::new_in(&backend, test_path).unwrap();
+        writer.write_ledger(&ledger).unwrap();
+
+        let checkpoint = ledger.create_checkpoint(&operator).unwrap();
+        let mut append_log = AppendLog::new_in(&backend, test_path).unwrap();
+        append_log.append_checkpoint(&checkpoint).unwrap();
+        drop(append_log);
+
+        // replays the WAL entry and seals it into a new segment
+        let recovered = RecoveryManager::recover_ledger_in(&backend, test_path).unwrap();
+        assert_eq!(recovered.latest_checkpoint(), Some(&checkpoint));
+
+        let manifest = Manifest::load_in(&backend, test_path).unwrap().unwrap();
+        let segment_path = manifest.segment_path(test_path, &manifest.segments[1]);
+        let reader = DatabaseReader::new_in(&backend, segment_path).unwrap();
+        assert_eq!(
+            reader.latest_checkpoint().unwrap(),
+            Some(checkpoint.clone())
+        );
+
+        let reloaded = RecoveryManager::recover_ledger_in(&backend, test_path).unwrap();
+        assert_eq!(reloaded.latest_checkpoint(), Some(&checkpoint));
+    }
+
+    #[test]
+    fn test_backup_used_when_main_damaged() {
+        let test_path = "test_recovery_backup.ukweli";
+        let cleanup = || {
+            let _ = fs::remove_file(test_path);
+            let _ = fs::remove_file(atomic::backup_path(test_path));
+            let _ = fs::remove_file("test_recovery_backup.wal");
+            let _ = fs::remove_file(DatabaseLock::path_for(test_path));
+        };
+        cleanup();
+
+        let mut ledger = Ledger::new();
+        let user = User::new("backup_user");
+        ledger.register_user(user.clone());
+        ledger.add_record("first", vec![user.clone()]).unwrap();
+
+        let mut writer = DatabaseWriter::new(test_path).unwrap();
+        writer.write_ledger(&ledger).unwrap();
+
+        ledger.add_record("second", vec![user]).unwrap();
+        let mut writer = DatabaseWriter::new(test_path).unwrap();
+        writer.write_ledger(&ledger).unwrap();
+
+        // torn write in the middle of the body
+        let mut bytes = fs::read(test_path).unwrap();
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 0xFF;
+        fs::write(test_path, bytes).unwrap();
+
+        let recovered = RecoveryManager::recover_ledger(test_path).unwrap();
+        assert_eq!(recovered.length(), 2);
         assert!(RecoveryManager::verify_file(test_path).unwrap());
 
-        fs::remove_file(test_path).unwrap();
-        let _ = fs::remove_file(format!("{}.wal", test_path));
-        let _ = fs::remove_file(format!("{}.backup", test_path));
+        cleanup();
+    }
+
+    #[test]
+    fn test_damaged_manifest_restored() {
+        let test_path = "test_recovery_manifest.ukweli";
+        let cleanup = || {
+            let _ = fs::remove_file(test_path);
+            let _ = fs::remove_file("test_recovery_manifest.wal");
+            let _ = fs::remove_file(format!("{}.manifest", test_path));
+            let _ = fs::remove_file(format!("{}.manifest.backup", test_path));
+            let _ = fs::remove_dir_all(format!("{}.segments", test_path));
+            let _ = fs::remove_file(DatabaseLock::path_for(test_path));
+        };
+        cleanup();
+
+        let mut ledger = Ledger::new();
+        let user = User::new("manifest_user");
+        ledger.register_user(user.clone());
+        let mut writer = DatabaseWriter::new(test_path).unwrap();
+        writer.write_ledger(&ledger).unwrap();
+
+        // two compactions, so the manifest has a backup one segment behind
+        for payload in ["one", "two"] {
+            let mut append_log = AppendLog::new(test_path).unwrap();
+            append_log.append_user(&user).unwrap();
+            let index = ledger.add_record(payload, vec![user.clone()]).unwrap();
+            append_log.append_record(&ledger.records[index]).unwrap();
+            drop(append_log);
+            RecoveryManager::recover_ledger(test_path).unwrap();
+        }
+
+        fs::write(format!("{}.manifest", test_path), b"{ not json").unwrap();
+
+        let recovered = RecoveryManager::recover_ledger(test_path).unwrap();
+        assert_eq!(recovered.length(), 3);
+        assert_eq!(
+            Manifest::load(test_path).unwrap().unwrap().segments.len(),
+            3
+        );
+
+        cleanup();
+    }
+
+    #[test]
+    fn test_fork_in_wal_detected() {
+        let test_path = "test_recovery_fork.ukweli";
+        let backend = backend::memory();
+
+        let mut ledger = Ledger::new();
+        let user = User::new("fork_user");
+        ledger.register_user(user.clone());
+        let mut writer = DatabaseWriter::new_in(&backend, test_path).unwrap();
+        writer.write_ledger(&ledger).unwrap();
+
+        // two writers that both saw the same tip
+        let mut other = RecoveryManager::recover_ledger_in(&backend, test_path).unwrap();
+        let mut append_log = AppendLog::new_in(&backend, test_path).unwrap();
+        append_log.append_user(&user).unwrap();
+        for (ledger, payload) in [(&mut ledger, "mine"), (&mut other, "theirs")] {
+            let index = ledger.add_record(payload, vec![user.clone()]).unwrap();
+            append_log.append_record(&ledger.records[index]).unwrap();
+        }
+        drop(append_log);
+
+        assert!(matches!(
+            RecoveryManager::recover_ledger_in(&backend, test_path),
+            Err(StorageError::ForkDetected(1))
+        ));
+    }
+
+    #[test]
+    fn test_stale_writer_has_to_reload() {
+        let test_path = "test_recovery_stale.ukweli";
+        let cleanup = || {
+            let _ = fs::remove_file(test_path);
+            let _ = fs::remove_file("test_recovery_stale.wal");
+            let _ = fs::remove_file(format!("{}.manifest", test_path));
+            let _ = fs::remove_file(format!("{}.manifest.backup", test_path));
+            let _ = fs::remove_dir_all(format!("{}.segments", test_path));
+            let _ = fs::remove_file(DatabaseLock::path_for(test_path));
+        };
+        cleanup();
+
+        let mut first = Ledger::new();
+        let user = User::new("stale_user");
+        first.register_user(user.clone());
+        let mut writer = DatabaseWriter::new(test_path).unwrap();
+        writer.write_ledger(&first).unwrap();
+        let mut second = RecoveryManager::recover_ledger(test_path).unwrap();
+        let stale = RecoveryManager::recover_ledger(test_path).unwrap();
+
+        let lock = DatabaseLock::exclusive(test_path).unwrap();
+        let mut append_log = AppendLog::new(test_path).unwrap();
+
+        let index = first.add_record("first", vec![user.clone()]).unwrap();
+        append_log
+            .append_record_at_tip(&first.records[index], &lock)
+            .unwrap();
+
+        let index = second.add_record("second", vec![user.clone()]).unwrap();
+        assert!(matches!(
+            append_log.append_record_at_tip(&second.records[index], &lock),
+            Err(StorageError::StaleTip {
+                expected: 0,
+                found: 1
+            })
+        ));
+
+        // catching up compacts the WAL, the tip now comes from the segment
+        let mut second = RecoveryManager::recover_ledger_locked(test_path, &lock).unwrap();
+        let index = second.add_record("second", vec![user]).unwrap();
+        append_log
+            .append_record_at_tip(&second.records[index], &lock)
+            .unwrap();
+
+        assert!(matches!(
+            RecoveryManager::compact_locked(test_path, &stale, &lock),
+            Err(StorageError::StaleTip { .. })
+        ));
+        RecoveryManager::compact_locked(test_path, &second, &lock).unwrap();
+        drop(lock);
+
+        let recovered = RecoveryManager::recover_ledger(test_path).unwrap();
+        assert_eq!(recovered.length(), 3);
+
+        cleanup();
+    }
+
+    #[test]
+    fn test_torn_wal_tail_recovered() {
+        let test_path = "test_recovery_torn.ukweli";
+        let cleanup = || {
+            let _ = fs::remove_file(test_path);
+            let _ = fs::remove_file("test_recovery_torn.wal");
+            let _ = fs::remove_file("test_recovery_torn.wal.corrupt");
+            let _ = fs::remove_file(format!("{}.manifest", test_path));
+            let _ = fs::remove_dir_all(format!("{}.segments", test_path));
+            let _ = fs::remove_file(DatabaseLock::path_for(test_path));
+        };
+        cleanup();
+
+        let mut ledger = Ledger::new();
+        let user = User::new("torn_user");
+        ledger.register_user(user.clone());
+        let mut writer = DatabaseWriter::new(test_path).unwrap();
+        writer.write_ledger(&ledger).unwrap();
+
+        let mut append_log = AppendLog::new(test_path).unwrap();
+        for payload in ["landed", "torn"] {
+            let index = ledger.add_record(payload, vec![user.clone()]).unwrap();
+            append_log.append_record(&ledger.records[index]).unwrap();
+        }
+        drop(append_log);
+
+        let wal = fs::read("test_recovery_torn.wal").unwrap();
+        let torn = &wal[..wal.len() - 7];
+        fs::write("test_recovery_torn.wal", torn).unwrap();
+
+        // a reader leaves the torn write out but doesn't cut it
+        let read = RecoveryManager::read_ledger(test_path).unwrap();
+        assert_eq!(read.length(), 2);
+        assert_eq!(fs::read("test_recovery_torn.wal").unwrap(), torn);
+        assert!(!Path::new("test_recovery_torn.wal.corrupt").exists());
+
+        let recovered = RecoveryManager::recover_ledger(test_path).unwrap();
+        assert_eq!(recovered.length(), 2);
+        assert_eq!(
+            recovered.records[1].record_hash,
+            ledger.records[1].record_hash
+        );
+        assert!(Path::new("test_recovery_torn.wal.corrupt").exists());
+
+        cleanup();
+    }
+
+    #[test]
+    fn test_repair_keeps_valid_prefix() {
+        let test_path = "test_recovery_repair.ukweli";
+        let cleanup = || {
+            let _ = fs::remove_file(test_path);
+            let _ = fs::remove_file("test_recovery_repair.wal");
+            let _ = fs::remove_file("test_recovery_repair.wal.corrupt");
+            let _ = fs::remove_file(format!("{}.manifest", test_path));
+            let _ = fs::remove_dir_all(format!("{}.segments", test_path));
+            let _ = fs::remove_file(DatabaseLock::path_for(test_path));
+        };
+        cleanup();
+
+        let mut ledger = Ledger::new();
+        let user = User::new("repair_user");
+        ledger.register_user(user.clone());
+        let mut writer = DatabaseWriter::new(test_path).unwrap();
+        writer.write_ledger(&ledger).unwrap();
+
+        let mut append_log = AppendLog::new(test_path).unwrap();
+        for payload in ["one", "two", "three"] {
+            let index = ledger.add_record(payload, vec![user.clone()]).unwrap();
+            append_log.append_record(&ledger.records[index]).unwrap();
+        }
+        let second_at = append_log.scan().unwrap().0[1].0 as usize;
+        drop(append_log);
+
+        // one bad byte in the middle entry, with a good one after it
+        let mut wal = fs::read("test_recovery_repair.wal").unwrap();
+        wal[second_at + 60] ^= 0xFF;
+        fs::write("test_recovery_repair.wal", &wal).unwrap();
+
+        assert!(matches!(
+            RecoveryManager::recover_ledger(test_path),
+            Err(StorageError::CorruptWal { entry: 1, .. })
+        ));
+
+        let (repair, recovered) = RecoveryManager::repair(test_path, true).unwrap();
+        assert_eq!(repair.damage.unwrap().offset, second_at as u64);
+        assert_eq!(recovered.length(), 2);
+        assert_eq!(
+            fs::read("test_recovery_repair.wal.corrupt").unwrap(),
+            wal[second_at..]
+        );
+
+        cleanup();
+    }
+
+    #[test]
+    fn test_replay_after_partial_compaction() {
+        let test_path = "test_recovery_partial.ukweli";
+        let cleanup = || {
+            let _ = fs::remove_file(test_path);
+            let _ = fs::remove_file("test_recovery_partial.wal");
+            let _ = fs::remove_file(format!("{}.manifest", test_path));
+            let _ = fs::remove_file(format!("{}.manifest.backup", test_path));
+            let _ = fs::remove_dir_all(format!("{}.segments", test_path));
+            let _ = fs::remove_file(DatabaseLock::path_for(test_path));
+        };
+        cleanup();
+
+        let mut ledger = Ledger::new();
+        let user = User::new("partial_user");
+        ledger.register_user(user.clone());
+        let mut writer = DatabaseWriter::new(test_path).unwrap();
+        writer.write_ledger(&ledger).unwrap();
+
+        let mut append_log = AppendLog::new(test_path).unwrap();
+        let index = ledger
+            .add_record("sealed once", vec![user.clone()])
+            .unwrap();
+        append_log.append_record(&ledger.records[index]).unwrap();
+        append_log.append_user(&User::new("late_user")).unwrap();
+        drop(append_log);
+        let wal = fs::read("test_recovery_partial.wal").unwrap();
+
+        let compacted = RecoveryManager::recover_ledger(test_path).unwrap();
+        assert_eq!(RecoveryManager::last_compacted_lsn(test_path).unwrap(), 2);
+
+        // crash between sealing and clearing the WAL
+        fs::write("test_recovery_partial.wal", &wal).unwrap();
+
+        let recovered = RecoveryManager::recover_ledger(test_path).unwrap();
+        assert_eq!(recovered.length(), 2);
+        assert_eq!(recovered.users.len(), compacted.users.len());
+        assert!(recovered.users.contains_key("late_user"));
+        assert_eq!(fs::metadata("test_recovery_partial.wal").unwrap().len(), 0);
+
+        // numbering carries on from the header
+        let mut append_log = AppendLog::new(test_path).unwrap();
+        append_log.append_user(&User::new("after_user")).unwrap();
+        assert_eq!(append_log.lsn_range().unwrap(), Some((3, 3)));
+
+        cleanup();
+    }
+
+    #[test]
+    fn test_wal_gap_detected() {
+        let test_path = "test_recovery_gap.ukweli";
+        let cleanup = || {
+            let _ = fs::remove_file(test_path);
+            let _ = fs::remove_file("test_recovery_gap.wal");
+            let _ = fs::remove_file(format!("{}.manifest", test_path));
+            let _ = fs::remove_file(format!("{}.manifest.backup", test_path));
+            let _ = fs::remove_dir_all(format!("{}.segments", test_path));
+            let _ = fs::remove_file(DatabaseLock::path_for(test_path));
+        };
+        cleanup();
+
+        let mut ledger = Ledger::new();
+        let user = User::new("gap_user");
+        ledger.register_user(user.clone());
+        let mut writer = DatabaseWriter::new(test_path).unwrap();
+        writer.write_ledger(&ledger).unwrap();
+
+        let index = ledger.add_record("first", vec![user.clone()]).unwrap();
+        AppendLog::new(test_path)
+            .unwrap()
+            .append_record(&ledger.records[index])
+            .unwrap();
+        RecoveryManager::recover_ledger(test_path).unwrap();
+
+        let index = ledger.add_record("second", vec![user]).unwrap();
+        AppendLog::new(test_path)
+            .unwrap()
+            .append_record(&ledger.records[index])
+            .unwrap();
+
+        // the segment holding LSN 1 goes missing
+        fs::remove_file(format!("{}.manifest", test_path)).unwrap();
+        fs::remove_dir_all(format!("{}.segments", test_path)).unwrap();
+
+        assert!(matches!(
+            RecoveryManager::recover_ledger(test_path),
+            Err(StorageError::WalGap {
+                sealed: 0,
+                found: 2
+            })
+        ));
+
+        cleanup();
+    }
+
+    #[test]
+    fn test_baseline_database_opens() {
+        // written by the 1.0 CLI: genesis and two records sealed, one more in the WAL
+        let test_path = "test_baseline.ukweli";
+        let backend = backend::memory();
+        backend
+            .append(
+                Path::new(test_path),
+                include_bytes!("../../tests/fixtures/baseline.ukweli"),
+            )
+            .unwrap();
+        backend
+            .append(
+                &AppendLog::path_for(test_path),
+                include_bytes!("../../tests/fixtures/baseline.wal"),
+            )
+            .unwrap();
+
+        let ledger = RecoveryManager::read_ledger_in(&backend, test_path).unwrap();
+        assert_eq!(ledger.length(), 4);
+        assert_eq!(ledger.records[1].payload.as_text(), Some("first bid"));
+        assert_eq!(ledger.records[3].payload.as_text(), Some("third bid"));
+        assert!(ledger.records[3].payload.legacy);
+
+        // sealing the WAL writes the old records into a 2.0 segment, they keep
+        // their old hashes there
+        let mut ledger = RecoveryManager::recover_ledger_in(&backend, test_path).unwrap();
+        let lindiwe = User::new("lindiwe");
+        ledger.register_user(lindiwe.clone());
+        let mut append_log = AppendLog::new_in(&backend, test_path).unwrap();
+        append_log.append_user(&lindiwe).unwrap();
+        let index = ledger.add_record("new style", vec![lindiwe]).unwrap();
+        append_log.append_record(&ledger.records[index]).unwrap();
+        drop(append_log);
+
+        let reloaded = RecoveryManager::recover_ledger_in(&backend, test_path).unwrap();
+        assert_eq!(reloaded.length(), 5);
+        assert!(reloaded.records[3].payload.legacy);
+        assert!(!reloaded.records[4].payload.legacy);
+        assert!(reloaded.verify_chain().unwrap());
+
+        let manifest = Manifest::load_in(&backend, test_path).unwrap().unwrap();
+        let sealed = manifest.segment_path(test_path, manifest.segments.last().unwrap());
+        let header = crate::storage::reader::read_header_in(&backend, sealed).unwrap();
+        assert_eq!(
+            header.version_major,
+            crate::storage::database::VERSION_MAJOR
+        );
     }
 
     #[test]
@@ -364,6 +1291,7 @@ mod tests {
         assert!(RecoveryManager::verify_file(snapshot_path).unwrap());
 
         fs::remove_file(snapshot_path).unwrap();
+        let _ = fs::remove_file(DatabaseLock::path_for(snapshot_path));
     }
 
     #[test]
@@ -383,5 +1311,6 @@ mod tests {
         assert!(RecoveryManager::verify_file(test_path).unwrap());
 
         fs::remove_file(test_path).unwrap();
+        let _ = fs::remove_file(DatabaseLock::path_for(test_path));
     }
 }
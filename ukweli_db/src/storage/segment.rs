@@ -0,0 +1,545 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::core::{Record, User};
+use crate::error::StorageError;
+use crate::storage::backend::{self, Backend};
+use crate::storage::database::{DatabaseBody, DatabaseHeader};
+use crate::storage::lock::DatabaseLock;
+use crate::storage::mapped::MappedDatabase;
+use crate::storage::persitence::{SerializableCheckpoint, SerializableRecord, SerializableUser};
+use crate::storage::reader::{DatabaseReader, read_header_in};
+use crate::storage::recovery::RecoveryManager;
+use crate::storage::writer::DatabaseWriter;
+
+pub const MANIFEST_VERSION: u32 = 1;
+
+// compaction starts a new segment once the current one reaches roughly this size
+pub const SEGMENT_TARGET_BYTES: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SegmentInfo {
+    pub file: String, // relative to the directory holding the database file
+    pub first_index: usize,
+    pub record_count: usize,
+    pub checksum: String, // body checksum from the segment header, hex
+    pub size_bytes: u64,
+    /// key of the copy in the object store, once one was pushed there
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub object: Option<String>,
+}
+
+impl SegmentInfo {
+    pub fn end_index(&self) -> usize {
+        self.first_index + self.record_count
+    }
+
+    pub fn contains(&self, index: usize) -> bool {
+        index >= self.first_index && index < self.end_index()
+    }
+}
+
+/// Ordered list of sealed segments, stored as `<db>.manifest` next to the database file.
+///
+/// The original database file is always the first segment. Segments are never
+/// rewritten once listed, compaction only appends new ones, so older segments can
+/// be archived or copied to read-only media. Ledgers without a manifest are a
+/// single file, the first compaction writes one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub segments: Vec<SegmentInfo>,
+}
+
+impl Manifest {
+    pub fn path_for<P: AsRef<Path>>(db_path: P) -> PathBuf {
+        PathBuf::from(format!("{}.manifest", db_path.as_ref().display()))
+    }
+
+    pub fn segments_dir<P: AsRef<Path>>(db_path: P) -> PathBuf {
+        PathBuf::from(format!("{}.segments", db_path.as_ref().display()))
+    }
+
+    pub fn load<P: AsRef<Path>>(db_path: P) -> Result<Option<Self>, StorageError> {
+        Self::load_in(&backend::filesystem(), db_path)
+    }
+
+    pub fn load_in<P: AsRef<Path>>(
+        backend: &Backend,
+        db_path: P,
+    ) -> Result<Option<Self>, StorageError> {
+        let path = Self::path_for(&db_path);
+        if !backend.exists(&path)? {
+            return Ok(None);
+        }
+
+        Self::from_file(backend, path).map(Some)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(backend: &Backend, path: P) -> Result<Self, StorageError> {
+        let manifest: Manifest = serde_json::from_slice(&backend.read(path.as_ref())?)
+            .map_err(|e| StorageError::Deserialization(format!("Manifest: {}", e)))?;
+
+        if manifest.version != MANIFEST_VERSION {
+            return Err(StorageError::ValidationFailed(format!(
+                "Unsupported manifest version {}",
+                manifest.version
+            )));
+        }
+
+        Ok(manifest)
+    }
+
+    /// The manifest a single-file ledger would have, built from its header alone.
+    pub fn for_single_file<P: AsRef<Path>>(
+        backend: &Backend,
+        db_path: P,
+    ) -> Result<Self, StorageError> {
+        let db_path = db_path.as_ref();
+        let header = read_header_in(backend, db_path)?;
+
+        let file = db_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| {
+                StorageError::ValidationFailed("Database path has no file name".into())
+            })?;
+
+        Ok(Self {
+            version: MANIFEST_VERSION,
+            segments: vec![SegmentInfo {
+                file,
+                first_index: header.first_record_index as usize,
+                record_count: header.record_count as usize,
+                checksum: hex::encode(header.checksum),
+                size_bytes: backend.size(db_path)?.unwrap_or(0),
+                object: None,
+            }],
+        })
+    }
+
+    /// Manifest if there is one, otherwise the database file as the only segment.
+    pub fn load_or_single_file<P: AsRef<Path>>(db_path: P) -> Result<Self, StorageError> {
+        Self::load_or_single_file_in(&backend::filesystem(), db_path)
+    }
+
+    pub fn load_or_single_file_in<P: AsRef<Path>>(
+        backend: &Backend,
+        db_path: P,
+    ) -> Result<Self, StorageError> {
+        match Self::load_in(backend, &db_path)? {
+            Some(manifest) => Ok(manifest),
+            None => Self::for_single_file(backend, &db_path),
+        }
+    }
+
+    // swapped in atomically like database files, the previous one stays as a backup
+    pub fn save<P: AsRef<Path>>(&self, backend: &Backend, db_path: P) -> Result<(), StorageError> {
+        let json = serde_json::to_vec_pretty(self)
+            .map_err(|e| StorageError::Serialization(format!("Manifest: {}", e)))?;
+        backend.replace(&Self::path_for(&db_path), &json)
+    }
+
+    pub fn next_index(&self) -> usize {
+        self.segments.last().map(|s| s.end_index()).unwrap_or(0)
+    }
+
+    /// Newest WAL entry that made it into a segment, from the last segment's header.
+    /// Only the header is read, `load_sealed` verifies the rest.
+    pub fn last_lsn<P: AsRef<Path>>(
+        &self,
+        backend: &Backend,
+        db_path: P,
+    ) -> Result<u64, StorageError> {
+        match self.segments.last() {
+            Some(segment) => {
+                Ok(read_header_in(backend, self.segment_path(&db_path, segment))?.last_lsn)
+            }
+            None => Ok(0),
+        }
+    }
+
+    pub fn segment_path<P: AsRef<Path>>(&self, db_path: P, segment: &SegmentInfo) -> PathBuf {
+        match db_path.as_ref().parent() {
+            Some(dir) => dir.join(&segment.file),
+            None => PathBuf::from(&segment.file),
+        }
+    }
+
+    pub fn segment_for(&self, index: usize) -> Option<&SegmentInfo> {
+        self.segments.iter().find(|s| s.contains(index))
+    }
+
+    /// Checks a segment's header against what the manifest recorded for it.
+    pub fn check_segment(
+        segment: &SegmentInfo,
+        first_record_index: u64,
+        record_count: u64,
+        checksum: &[u8; 32],
+    ) -> Result<(), StorageError> {
+        if first_record_index as usize != segment.first_index
+            || record_count as usize != segment.record_count
+            || hex::encode(checksum) != segment.checksum
+        {
+            return Err(StorageError::ValidationFailed(format!(
+                "Segment {} does not match the manifest",
+                segment.file
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Writes new data into fresh segments after the current last one and lists them.
+    /// Users go into the first new segment so every record can resolve its signers,
+    /// checkpoints go into the last one. Every new header records `last_lsn`, the
+    /// newest WAL entry they cover. The manifest itself is not saved here.
+    pub fn seal<P: AsRef<Path>>(
+        &mut self,
+        backend: &Backend,
+        db_path: P,
+        records: Vec<SerializableRecord>,
+        users: Vec<SerializableUser>,
+        checkpoints: Vec<SerializableCheckpoint>,
+        last_lsn: u64,
+    ) -> Result<(), StorageError> {
+        if records.is_empty() && users.is_empty() && checkpoints.is_empty() {
+            return Ok(());
+        }
+
+        // the backend creates the directory with the first one
+        let segments_dir = Self::segments_dir(&db_path);
+
+        let mut chunks = split_records(records);
+        let mut users = Some(users);
+        let last = chunks.len().saturating_sub(1);
+        let mut checkpoints = Some(checkpoints);
+
+        for (i, records) in chunks.drain(..).enumerate() {
+            let body = DatabaseBody {
+                records,
+                users: users.take().unwrap_or_default(),
+                checkpoints: if i == last {
+                    checkpoints.take().unwrap_or_default()
+                } else {
+                    Vec::new()
+                },
+            };
+
+            let first_index = self.next_index();
+            let path = segments_dir.join(Self::segment_file_name(self.segments.len()));
+
+            let mut writer = DatabaseWriter::new_in(backend, &path)?;
+            let header = writer.write_body(&body, first_index as u64, last_lsn)?;
+
+            self.segments
+                .push(Self::segment_info(backend, &db_path, &path, &header)?);
+        }
+
+        Ok(())
+    }
+
+    /// Lists segments that were sealed after this manifest was written, as found
+    /// after restoring an older manifest from its backup. Stops at the first file
+    /// that is missing, fails verification or doesn't continue the record range.
+    pub fn adopt_sealed_segments<P: AsRef<Path>>(
+        &mut self,
+        backend: &Backend,
+        db_path: P,
+    ) -> Result<usize, StorageError> {
+        let segments_dir = Self::segments_dir(&db_path);
+        let present = backend.list(&segments_dir)?;
+        let mut adopted = 0;
+
+        loop {
+            let path = segments_dir.join(Self::segment_file_name(self.segments.len()));
+            if !present.contains(&path) {
+                break;
+            }
+
+            let Ok((header, _body)) =
+                DatabaseReader::new_in(backend, &path).and_then(|r| r.read_and_verify())
+            else {
+                break;
+            };
+
+            if header.first_record_index as usize != self.next_index() {
+                break;
+            }
+
+            self.segments
+                .push(Self::segment_info(backend, &db_path, &path, &header)?);
+            adopted += 1;
+        }
+
+        Ok(adopted)
+    }
+
+    fn segment_file_name(sequence: usize) -> String {
+        format!("segment-{:06}.ukweli", sequence)
+    }
+
+    fn segment_info<P: AsRef<Path>>(
+        backend: &Backend,
+        db_path: P,
+        path: &Path,
+        header: &DatabaseHeader,
+    ) -> Result<SegmentInfo, StorageError> {
+        let dir_name = Self::segments_dir(&db_path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        Ok(SegmentInfo {
+            file: format!("{}/{}", dir_name, file_name),
+            first_index: header.first_record_index as usize,
+            record_count: header.record_count as usize,
+            checksum: hex::encode(header.checksum),
+            size_bytes: backend.size(path)?.unwrap_or(0),
+            object: None,
+        })
+    }
+}
+
+// always at least one chunk, so users and checkpoints have somewhere to go
+fn split_records(records: Vec<SerializableRecord>) -> Vec<Vec<SerializableRecord>> {
+    let mut chunks = vec![Vec::new()];
+    let mut current_size = 0;
+
+    for record in records {
+        let size = estimated_size(&record);
+        if current_size + size > SEGMENT_TARGET_BYTES
+            && chunks.last().is_some_and(|chunk| !chunk.is_empty())
+        {
+            chunks.push(Vec::new());
+            current_size = 0;
+        }
+
+        current_size += size;
+        if let Some(chunk) = chunks.last_mut() {
+            chunk.push(record);
+        }
+    }
+
+    chunks
+}
+
+// close enough to the archived size without serializing twice
+fn estimated_size(record: &SerializableRecord) -> usize {
+    let payload = &record.payload;
+    let signatures: usize = record
+        .signatures
+        .iter()
+        .map(|(id, sig)| id.len() + sig.len() + 16)
+        .sum();
+    let tags: usize = payload
+        .tags
+        .iter()
+        .map(|(k, v)| k.len() + v.len() + 16)
+        .sum();
+
+    payload.body.len()
+        + payload.content_type.len()
+        + tags
+        + signatures
+        + record
+            .signer_ids
+            .iter()
+            .map(|id| id.len() + 8)
+            .sum::<usize>()
+        + record.payload_hash.len()
+        + record.prev_hash.len()
+        + record.record_hash.len()
+        + 128
+}
+
+/// Reads one sealed record by mapping only the segments it needs.
+/// Returns `None` for records that are still only in the WAL.
+pub fn read_sealed_record<P: AsRef<Path>>(
+    db_path: P,
+    index: usize,
+) -> Result<Option<Record>, StorageError> {
+    read_sealed_record_in(&backend::filesystem(), db_path, index)
+}
+
+pub fn read_sealed_record_in<P: AsRef<Path>>(
+    backend: &Backend,
+    db_path: P,
+    index: usize,
+) -> Result<Option<Record>, StorageError> {
+    let _lock = DatabaseLock::shared_in(backend, &db_path)?;
+    let manifest = Manifest::load_or_single_file_in(backend, &db_path)?;
+
+    let Some(position) = manifest.segments.iter().position(|s| s.contains(index)) else {
+        return Ok(None);
+    };
+
+    let mut ser_record = None;
+    let mut users: HashMap<String, User> = HashMap::new();
+
+    // signers were sealed in this segment or an earlier one
+    for segment in manifest.segments.iter().take(position + 1).rev() {
+        let segment_db =
+            MappedDatabase::open_in(backend, manifest.segment_path(&db_path, segment))?;
+        let header = segment_db.header();
+        Manifest::check_segment(
+            segment,
+            header.first_record_index,
+            header.record_count,
+            &header.checksum,
+        )?;
+
+        if ser_record.is_none() {
+            ser_record = segment_db.serialized_record(index)?;
+        }
+
+        let Some(record) = &ser_record else {
+            return Ok(None);
+        };
+
+        for signer_id in &record.signer_ids {
+            if !users.contains_key(signer_id)
+                && let Some(user) = segment_db.user(signer_id)?
+            {
+                users.insert(signer_id.clone(), user);
+            }
+        }
+
+        if record.signer_ids.iter().all(|id| users.contains_key(id)) {
+            break;
+        }
+    }
+
+    match ser_record {
+        Some(record) => RecoveryManager::record_from_serialized(record, &users).map(Some),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::indexing_slicing)]
+
+    use super::*;
+    use crate::core::Ledger;
+    use crate::storage::append::AppendLog;
+    use std::fs;
+
+    fn cleanup(path: &str) {
+        let _ = fs::remove_file(path);
+        let _ = fs::remove_file(format!("{}.manifest", path));
+        let _ = fs::remove_file(format!("{}.wal", path.trim_end_matches(".ukweli")));
+        let _ = fs::remove_dir_all(format!("{}.segments", path));
+        let _ = fs::remove_file(DatabaseLock::path_for(path));
+    }
+
+    #[test]
+    fn test_compaction_only_seals_new_records() {
+        let test_path = "test_segments_compact.ukweli";
+        cleanup(test_path);
+
+        let mut ledger = Ledger::new();
+        let user = User::new("0xElvis");
+        ledger.register_user(user.clone());
+        ledger.add_record("sealed", vec![user.clone()]).unwrap();
+
+        let mut writer = DatabaseWriter::new(test_path).unwrap();
+        writer.write_ledger(&ledger).unwrap();
+        let base_bytes = fs::read(test_path).unwrap();
+
+        let late = User::new("0xChege");
+        ledger.register_user(late.clone());
+        let mut append_log = AppendLog::new(test_path).unwrap();
+        append_log.append_user(&late).unwrap();
+        for payload in ["one", "two"] {
+            let index = ledger.add_record(payload, vec![late.clone()]).unwrap();
+            append_log.append_record(&ledger.records[index]).unwrap();
+        }
+        drop(append_log);
+
+        let recovered = RecoveryManager::recover_ledger(test_path).unwrap();
+        assert_eq!(recovered.length(), 4);
+
+        // the original file was left alone, the WAL went into a new segment
+        assert_eq!(fs::read(test_path).unwrap(), base_bytes);
+        let manifest = Manifest::load(test_path).unwrap().unwrap();
+        assert_eq!(manifest.segments.len(), 2);
+        assert_eq!(manifest.segments[1].first_index, 2);
+        assert_eq!(manifest.segments[1].record_count, 2);
+
+        let reloaded = RecoveryManager::recover_ledger(test_path).unwrap();
+        assert_eq!(
+            reloaded.records[3].record_hash,
+            ledger.records[3].record_hash
+        );
+        assert!(reloaded.verify_chain().unwrap());
+
+        let record = read_sealed_record(test_path, 3).unwrap().unwrap();
+        assert_eq!(record.record_hash, ledger.records[3].record_hash);
+        assert_eq!(record.signers[0].user_id, "0xChege");
+        assert!(read_sealed_record(test_path, 4).unwrap().is_none());
+
+        cleanup(test_path);
+    }
+
+    #[test]
+    fn test_tampered_segment_is_refused() {
+        let test_path = "test_segments_tamper.ukweli";
+        cleanup(test_path);
+
+        let mut ledger = Ledger::new();
+        let user = User::new("0xElvis");
+        ledger.register_user(user.clone());
+
+        let mut writer = DatabaseWriter::new(test_path).unwrap();
+        writer.write_ledger(&ledger).unwrap();
+
+        let mut append_log = AppendLog::new(test_path).unwrap();
+        append_log.append_user(&user).unwrap();
+        let index = ledger.add_record("late", vec![user]).unwrap();
+        append_log.append_record(&ledger.records[index]).unwrap();
+        drop(append_log);
+
+        RecoveryManager::recover_ledger(test_path).unwrap();
+
+        // swap the segment for a valid file with different contents
+        let manifest = Manifest::load(test_path).unwrap().unwrap();
+        let segment_path = manifest.segment_path(test_path, &manifest.segments[1]);
+        let mut writer = DatabaseWriter::new(&segment_path).unwrap();
+        writer.write_ledger(&Ledger::new()).unwrap();
+
+        assert!(RecoveryManager::recover_ledger(test_path).is_err());
+
+        cleanup(test_path);
+    }
+
+    #[test]
+    fn test_split_records_respects_target() {
+        let big = |index| SerializableRecord {
+            index,
+            payload: (&crate::core::Payload::binary(
+                "application/octet-stream",
+                vec![0u8; SEGMENT_TARGET_BYTES / 2],
+            ))
+                .into(),
+            payload_hash: String::new(),
+            signer_ids: Vec::new(),
+            signatures: Vec::new(),
+            prev_hash: String::new(),
+            record_hash: String::new(),
+            timestamp: 0,
+            nonce: 0,
+        };
+
+        let chunks = split_records(vec![big(1), big(2), big(3)]);
+        assert_eq!(chunks.len(), 3);
+        assert!(split_records(Vec::new())[0].is_empty());
+    }
+}
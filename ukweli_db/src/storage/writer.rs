@@ -2,30 +2,47 @@ use crate::error::StorageError;
 
 use rkyv::rancor::Error as RkyvError;
 
-use std::io::Write;
-// use std::io::Write;
 use crate::core::Ledger;
-use crate::storage::database::{DatabaseBody, DatabaseFooter, DatabaseHeader, HEADER_SIZE};
-use crate::storage::persitence::{SerializableRecord, SerializableUser};
+use crate::storage::backend::{self, Backend};
+use crate::storage::database::{
+    DatabaseBody, DatabaseFooter, DatabaseHeader, FOOTER_SIZE, HEADER_SIZE,
+};
+use crate::storage::persitence::{SerializableCheckpoint, SerializableRecord, SerializableUser};
 use hex;
-use std::fs::{File, OpenOptions};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+/// Writes a whole database file and swaps it in with `StorageBackend::replace`.
+/// The old file is never modified, so readers that mapped it keep a consistent view
+/// and a crash leaves either the old or the new file.
 pub struct DatabaseWriter {
-    file: File,
+    path: PathBuf,
+    backend: Backend,
 }
 
 impl DatabaseWriter {
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, StorageError> {
-        let file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(path)?;
+        Self::new_in(&backend::filesystem(), path)
+    }
+
+    pub fn new_in<P: AsRef<Path>>(backend: &Backend, path: P) -> Result<Self, StorageError> {
+        let path = path.as_ref().to_path_buf();
+
+        // make sure the target exists without touching its contents
+        backend.append(&path, &[])?;
 
-        Ok(Self { file })
+        Ok(Self {
+            path,
+            backend: Backend::clone(backend),
+        })
     }
 
     pub fn write_ledger(&mut self, ledger: &Ledger) -> Result<(), StorageError> {
+        self.write_ledger_at(ledger, 0)
+    }
+
+    /// `write_ledger` for a compaction, recording that WAL entries up to
+    /// `last_lsn` are in this file now.
+    pub fn write_ledger_at(&mut self, ledger: &Ledger, last_lsn: u64) -> Result<(), StorageError> {
         let records: Vec<SerializableRecord> = ledger
             .records
             .iter()
@@ -35,9 +52,30 @@ impl DatabaseWriter {
         let users: Vec<SerializableUser> =
             ledger.users.values().map(SerializableUser::from).collect();
 
-        let body = DatabaseBody { records, users };
+        let checkpoints: Vec<SerializableCheckpoint> = ledger
+            .checkpoints
+            .iter()
+            .map(SerializableCheckpoint::from)
+            .collect();
+
+        let body = DatabaseBody {
+            records,
+            users,
+            checkpoints,
+        };
+
+        self.write_body(&body, 0, last_lsn)?;
+        Ok(())
+    }
 
-        let body_bytes = rkyv::to_bytes::<RkyvError>(&body)
+    /// Writes a body that may start part way through the ledger, as segments do.
+    pub fn write_body(
+        &mut self,
+        body: &DatabaseBody,
+        first_record_index: u64,
+        last_lsn: u64,
+    ) -> Result<DatabaseHeader, StorageError> {
+        let body_bytes = rkyv::to_bytes::<RkyvError>(body)
             .map_err(|e| StorageError::Serialization(e.to_string()))?;
 
         let body_checksum = sha256::digest(body_bytes.as_slice());
@@ -49,17 +87,26 @@ impl DatabaseWriter {
         let body_offset = HEADER_SIZE as u64;
         let footer_offset = body_offset + body_bytes.len() as u64;
 
-        let mut header =
-            DatabaseHeader::new(ledger.records.len() as u64, body_offset, footer_offset);
+        let mut header = DatabaseHeader::new(body.records.len() as u64, body_offset, footer_offset);
         header.checksum = checksum_bytes;
+        header.first_record_index = first_record_index;
+        header.last_lsn = last_lsn;
 
         let header_bytes = rkyv::to_bytes::<RkyvError>(&header)
             .map_err(|e| StorageError::Serialization(e.to_string()))?;
 
-        println!("Serialized header size: {}", header_bytes.len());
+        if header_bytes.len() > HEADER_SIZE {
+            return Err(StorageError::Serialization(format!(
+                "Header is {} bytes, only {} reserved",
+                header_bytes.len(),
+                HEADER_SIZE
+            )));
+        }
 
-        let mut pre_footer_data = Vec::with_capacity(header_bytes.len() + body_bytes.len());
+        // exactly the bytes that end up on disk before the footer, padding included
+        let mut pre_footer_data = Vec::with_capacity(HEADER_SIZE + body_bytes.len());
         pre_footer_data.extend_from_slice(&header_bytes);
+        pre_footer_data.resize(HEADER_SIZE, 0);
         pre_footer_data.extend_from_slice(&body_bytes);
 
         let integrity_hash = sha256::digest(&pre_footer_data);
@@ -72,24 +119,24 @@ impl DatabaseWriter {
 
         let footer = DatabaseFooter {
             integrity_hash: integrity_bytes,
-            total_file_size: (HEADER_SIZE + body_bytes.len() + 64) as u64,
+            total_file_size: (pre_footer_data.len() + FOOTER_SIZE) as u64,
         };
 
         let footer_bytes = rkyv::to_bytes::<RkyvError>(&footer)
             .map_err(|e| StorageError::Serialization(e.to_string()))?;
 
-        self.file.write_all(&header_bytes)?;
-
-        let padding_needed = HEADER_SIZE.saturating_sub(header_bytes.len());
-        if padding_needed > 0 {
-            let padding = vec![0u8; padding_needed];
-            self.file.write_all(&padding)?;
+        if footer_bytes.len() != FOOTER_SIZE {
+            return Err(StorageError::Serialization(format!(
+                "Footer is {} bytes, expected {}",
+                footer_bytes.len(),
+                FOOTER_SIZE
+            )));
         }
 
-        self.file.write_all(&body_bytes)?;
-        self.file.write_all(&footer_bytes)?;
-        self.file.flush()?;
+        let mut contents = pre_footer_data;
+        contents.extend_from_slice(&footer_bytes);
+        self.backend.replace(&self.path, &contents)?;
 
-        Ok(())
+        Ok(header)
     }
 }
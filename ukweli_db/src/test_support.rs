@@ -0,0 +1,37 @@
+//! Fixtures shared by the unit tests of several modules.
+
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::indexing_slicing)]
+
+use crate::core::roles::ADMIN_ROLE;
+use crate::core::{Ledger, Payload, Record, RoleChange, User};
+
+/// Appends without any workflow check, like records from before it existed.
+pub(crate) fn forge(ledger: &mut Ledger, payload: Payload, signers: Vec<User>) {
+    let last = ledger.records.last().unwrap();
+    let record = Record::new(last.index + 1, payload, &last.record_hash, signers);
+    ledger.records.push(record);
+    ledger.rebuild_state();
+}
+
+/// Registers an admin and has them grant each role.
+pub(crate) fn grant_roles(ledger: &mut Ledger, grants: &[(&str, &str)]) -> User {
+    let admin = User::new("admin");
+    ledger.designate_operator(&admin).unwrap();
+    ledger
+        .add_record(
+            RoleChange::grant("admin", ADMIN_ROLE).to_payload(),
+            vec![admin.clone()],
+        )
+        .unwrap();
+
+    for (user_id, role) in grants {
+        ledger
+            .add_record(
+                RoleChange::grant(user_id, role).to_payload(),
+                vec![admin.clone()],
+            )
+            .unwrap();
+    }
+    admin
+}
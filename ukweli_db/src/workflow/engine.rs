@@ -1,4 +1,4 @@
-use crate::core::User;
+use crate::core::Roles;
 use crate::error::WorkflowError;
 use crate::workflow::Transition;
 
@@ -85,12 +85,40 @@ impl Engine {
         Ok(transitions)
     }
 
+    pub fn get_transition(
+        &self,
+        workflow_id: &str,
+        from_state: &str,
+        transition_name: &str,
+    ) -> Result<Transition, WorkflowError> {
+        let workflow = self
+            .workflows
+            .get(workflow_id)
+            .ok_or_else(|| WorkflowError::Parsing(format!("Unknown workflow {}", workflow_id)))?;
+
+        workflow
+            .transitions
+            .iter()
+            .find(|t| t.from_state == from_state && t.name == transition_name)
+            .cloned()
+            .ok_or_else(|| {
+                WorkflowError::Validation(format!(
+                    "Transition '{}' is not allowed from state {}",
+                    transition_name, from_state
+                ))
+            })
+    }
+
+    /// Checks the signers between them hold every role the transition needs.
+    /// `roles` come from the ledger, see `Ledger::roles_at`, never from the
+    /// signers' own `User::roles`.
     pub fn validate_transition(
         &self,
         workflow_id: &str,
         from_state: &str,
         to_state: &str,
-        signers: Vec<User>,
+        signers: &[String],
+        roles: &Roles,
         _payload: &str,
     ) -> Result<bool, WorkflowError> {
         let workflow = self
@@ -109,10 +137,20 @@ impl Engine {
                 ))
             })?;
 
-        let signer_roles: Vec<String> = signers
-            .iter()
-            .flat_map(|s| s.roles.iter().cloned())
-            .collect();
+        self.check_roles(transition, signers, roles)?;
+        Ok(true)
+    }
+
+    /// Checks the signers between them hold every role `transition` needs.
+    /// Takes the transition a record names, `validate_transition` only knows
+    /// the states and can't tell apart two transitions between the same ones.
+    pub fn check_roles(
+        &self,
+        transition: &Transition,
+        signers: &[String],
+        roles: &Roles,
+    ) -> Result<(), WorkflowError> {
+        let signer_roles: Vec<String> = signers.iter().flat_map(|s| roles.of(s)).collect();
         let missing_roles: Vec<String> = transition
             .required_roles
             .iter()
@@ -127,7 +165,7 @@ impl Engine {
             )));
         }
 
-        Ok(true)
+        Ok(())
     }
 }
 
@@ -150,9 +188,18 @@ mod tests {
     use serde_json::json;
 
     use crate::WorkflowState;
+    use crate::core::{RoleChange, User};
 
     use super::*;
 
+    fn granted(grants: &[(&str, &str)]) -> Roles {
+        let mut roles = Roles::default();
+        for (user_id, role) in grants {
+            roles.apply(&RoleChange::grant(user_id, role));
+        }
+        roles
+    }
+
     #[test]
     fn test_workflow_empty_states() {
         let workflow = Workflow::new("test_0", "Test", "testtt", vec![], vec![], "");
@@ -285,15 +332,15 @@ mod tests {
 
         engine.load_workflow(workflow_json).unwrap();
 
-        let mut editor_user = User::new("user_editor");
-        editor_user.add_role("editor");
+        let roles = granted(&[("user_editor", "editor")]);
 
         let result = engine
             .validate_transition(
                 "test_workflow",
                 "draft",
                 "review",
-                vec![editor_user],
+                &["user_editor".to_string()],
+                &roles,
                 "hmmm",
             )
             .unwrap();
@@ -308,14 +355,17 @@ mod tests {
 
         engine.load_workflow(workflow_json).unwrap();
 
-        let editor_user = User::new("user_editor");
-        // editor_user.add_role("editor"); no role
+        // whatever the user's own file claims, the ledger never granted editor
+        let mut editor_user = User::new("user_editor");
+        editor_user.add_role("editor");
+        let roles = granted(&[("someone_else", "editor")]);
 
         let result = engine.validate_transition(
             "test_workflow",
             "draft",
             "review",
-            vec![editor_user],
+            &[editor_user.user_id],
+            &roles,
             "hmmm",
         );
 
@@ -329,14 +379,14 @@ mod tests {
 
         engine.load_workflow(workflow_json).unwrap();
 
-        let mut editor_user = User::new("user_editor");
-        editor_user.add_role("editor");
+        let roles = granted(&[("user_editor", "editor")]);
 
         let result = engine.validate_transition(
             "test_workflow",
             "draft",
             "published", // no such transition
-            vec![editor_user],
+            &["user_editor".to_string()],
+            &roles,
             "hmmm",
         );
 
@@ -350,17 +400,16 @@ mod tests {
 
         engine.load_workflow(workflow_json).unwrap();
 
-        let mut admin_user = User::new("user_admin");
-        admin_user.add_role("admin");
-
-        let mut editor_user = User::new("user_editor");
-        editor_user.add_role("editor");
+        let roles = granted(&[("user_admin", "admin"), ("user_editor", "editor")]);
+        let admin_user = "user_admin".to_string();
+        let editor_user = "user_editor".to_string();
 
         let result1 = engine.validate_transition(
             "test_workflow",
             "review",
             "published",
-            vec![admin_user.clone()],
+            &["user_admin".to_string()],
+            &roles,
             "hmmm",
         );
 
@@ -370,7 +419,8 @@ mod tests {
             "test_workflow",
             "review",
             "published",
-            vec![editor_user.clone()],
+            &["user_editor".to_string()],
+            &roles,
             "hmmm",
         );
 
@@ -381,7 +431,8 @@ mod tests {
                 "test_workflow",
                 "review",
                 "published",
-                vec![admin_user, editor_user],
+                &[admin_user, editor_user],
+                &roles,
                 "hmmm",
             )
             .unwrap();
@@ -0,0 +1,54 @@
+use serde_json::{Map, Value};
+
+use crate::core::{Payload, PayloadBody};
+
+/// Workflow tags carried by a record payload.
+/// A record only takes part in state replay when its payload envelope has all
+/// three ids set, `data` is whatever the caller attached as the body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransitionMetadata {
+    pub entity_id: String,
+    pub workflow_id: String,
+    pub transition: String,
+    pub data: Option<Value>,
+}
+
+impl TransitionMetadata {
+    pub fn new(entity_id: &str, workflow_id: &str, transition: &str) -> Self {
+        Self {
+            entity_id: entity_id.to_owned(),
+            workflow_id: workflow_id.to_owned(),
+            transition: transition.to_owned(),
+            data: None,
+        }
+    }
+
+    pub fn with_data(mut self, data: Value) -> Self {
+        self.data = Some(data);
+        self
+    }
+
+    // records without entity ids are valid, they just don't belong to an entity
+    pub fn from_payload(payload: &Payload) -> Option<Self> {
+        let data = match &payload.body {
+            PayloadBody::Json(Value::Object(map)) if map.is_empty() => None,
+            PayloadBody::Json(Value::Null) => None,
+            PayloadBody::Json(value) => Some(value.clone()),
+            PayloadBody::Text(text) if !text.is_empty() => Some(Value::String(text.clone())),
+            _ => None,
+        };
+
+        Some(Self {
+            entity_id: payload.entity_id.clone()?,
+            workflow_id: payload.workflow_id.clone()?,
+            transition: payload.transition.clone()?,
+            data,
+        })
+    }
+
+    // a transition without data still needs a non-empty body, so use `{}`
+    pub fn to_payload(&self) -> Payload {
+        let body = self.data.clone().unwrap_or(Value::Object(Map::new()));
+        Payload::json(body).with_entity(&self.entity_id, &self.workflow_id, &self.transition)
+    }
+}
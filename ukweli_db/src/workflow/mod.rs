@@ -1,9 +1,13 @@
 pub mod definition;
 pub mod engine;
+pub mod metadata;
+pub mod projector;
 pub mod state;
 pub mod transition;
 
 pub use definition::Workflow;
 pub use engine::Engine;
+pub use metadata::TransitionMetadata;
+pub use projector::{EntityState, StateProjector};
 pub use state::WorkflowState;
 pub use transition::Transition;
@@ -0,0 +1,387 @@
+use std::collections::HashMap;
+
+use crate::core::{Ledger, Record, Roles};
+use crate::error::WorkflowError;
+
+use super::engine::Engine;
+use super::metadata::TransitionMetadata;
+
+#[derive(Debug, Clone)]
+pub struct StateChange {
+    pub record_index: usize,
+    pub transition: String,
+    pub from_state: String,
+    pub to_state: String,
+    pub signers: Vec<String>,
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct RejectedTransition {
+    pub record_index: usize,
+    pub transition: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct EntityState {
+    pub entity_id: String,
+    pub workflow_id: String,
+    /// Empty while `workflow_id` isn't loaded, every record is rejected then.
+    pub current_state: String,
+    pub history: Vec<StateChange>,
+    pub rejected: Vec<RejectedTransition>,
+}
+
+/// Rebuilds entity states by replaying tagged records in ledger order.
+/// Records that break the workflow don't move the entity, they are kept in
+/// `rejected` so callers can see what the ledger holds but the rules refused.
+/// Signers are judged by the roles the ledger gave them at that point.
+pub struct StateProjector<'a> {
+    engine: &'a Engine,
+}
+
+impl<'a> StateProjector<'a> {
+    pub fn new(engine: &'a Engine) -> Self {
+        Self { engine }
+    }
+
+    pub fn project(&self, ledger: &Ledger) -> HashMap<String, EntityState> {
+        let mut entities: HashMap<String, EntityState> = HashMap::new();
+        let mut roles = Roles::default();
+
+        for record in ledger.all_records() {
+            self.replay(&mut entities, record, &roles);
+            roles.replay(record);
+        }
+
+        entities
+    }
+
+    pub fn entity(&self, ledger: &Ledger, entity_id: &str) -> Option<EntityState> {
+        self.project(ledger).remove(entity_id)
+    }
+
+    /// Moves the entity `record` is tagged with, if any, judging its signers
+    /// by `roles`, the roles in force before the record.
+    pub(crate) fn replay(
+        &self,
+        entities: &mut HashMap<String, EntityState>,
+        record: &Record,
+        roles: &Roles,
+    ) {
+        let Some(metadata) = TransitionMetadata::from_payload(&record.payload) else {
+            return;
+        };
+
+        let entity = entities
+            .entry(metadata.entity_id.clone())
+            .or_insert_with(|| EntityState {
+                entity_id: metadata.entity_id.clone(),
+                workflow_id: metadata.workflow_id.clone(),
+                current_state: self
+                    .initial_state(&metadata.workflow_id)
+                    .unwrap_or_default(),
+                history: Vec::new(),
+                rejected: Vec::new(),
+            });
+        self.apply(entity, record, &metadata, roles);
+    }
+
+    fn initial_state(&self, workflow_id: &str) -> Option<String> {
+        self.engine
+            .workflows
+            .get(workflow_id)
+            .map(|w| w.initial_state.clone())
+    }
+
+    fn apply(
+        &self,
+        entity: &mut EntityState,
+        record: &Record,
+        metadata: &TransitionMetadata,
+        roles: &Roles,
+    ) {
+        match self.check(entity, record, metadata, roles) {
+            Ok(to_state) => {
+                entity.history.push(StateChange {
+                    record_index: record.index,
+                    transition: metadata.transition.clone(),
+                    from_state: entity.current_state.clone(),
+                    to_state: to_state.clone(),
+                    signers: record.signers.iter().map(|s| s.user_id.clone()).collect(),
+                    timestamp: record.timestamp,
+                });
+                entity.current_state = to_state;
+            }
+            Err(e) => entity.rejected.push(RejectedTransition {
+                record_index: record.index,
+                transition: metadata.transition.clone(),
+                reason: e.to_string(),
+            }),
+        }
+    }
+
+    fn check(
+        &self,
+        entity: &EntityState,
+        record: &Record,
+        metadata: &TransitionMetadata,
+        roles: &Roles,
+    ) -> Result<String, WorkflowError> {
+        if metadata.workflow_id != entity.workflow_id {
+            return Err(WorkflowError::Validation(format!(
+                "Entity {} follows workflow {}, not {}",
+                entity.entity_id, entity.workflow_id, metadata.workflow_id
+            )));
+        }
+
+        let transition = self.engine.get_transition(
+            &entity.workflow_id,
+            &entity.current_state,
+            &metadata.transition,
+        )?;
+
+        self.engine.check_roles(
+            &transition,
+            &record
+                .signers
+                .iter()
+                .map(|s| s.user_id.clone())
+                .collect::<Vec<_>>(),
+            roles,
+        )?;
+
+        Ok(transition.to_state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::expect_used)]
+    #![allow(clippy::indexing_slicing)]
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::core::{Payload, RoleChange, User};
+    use crate::test_support::{forge, grant_roles};
+
+    fn tender_engine() -> Engine {
+        let mut engine = Engine::new();
+        engine
+            .load_workflow_from_json(json!({
+                "id": "tender",
+                "name": "Tender",
+                "description": "Procurement tender lifecycle",
+                "initial_state": "call_for_bids",
+                "states": [
+                    {"id": "call_for_bids", "label": "Call for bids"},
+                    {"id": "bidding_open", "label": "Bidding open"},
+                    {"id": "awarded", "label": "Awarded"}
+                ],
+                "transitions": [
+                    {
+                        "from_state": "call_for_bids",
+                        "to_state": "bidding_open",
+                        "name": "open_bidding",
+                        "required_roles": ["procuring_officer"]
+                    },
+                    {
+                        "from_state": "call_for_bids",
+                        "to_state": "bidding_open",
+                        "name": "open_without_notice",
+                        "required_roles": ["director"]
+                    },
+                    {
+                        "from_state": "bidding_open",
+                        "to_state": "awarded",
+                        "name": "award",
+                        "required_roles": ["procuring_officer", "finance_approver"]
+                    }
+                ]
+            }))
+            .unwrap();
+        engine
+    }
+
+    fn tagged(entity_id: &str, transition: &str) -> Payload {
+        TransitionMetadata::new(entity_id, "tender", transition).to_payload()
+    }
+
+    #[test]
+    fn test_replay_current_state() {
+        let engine = tender_engine();
+        let mut ledger = Ledger::new();
+
+        let officer = User::new("officer");
+        let finance = User::new("finance");
+        ledger.register_user(officer.clone());
+        ledger.register_user(finance.clone());
+        grant_roles(
+            &mut ledger,
+            &[
+                ("officer", "procuring_officer"),
+                ("finance", "finance_approver"),
+            ],
+        );
+
+        ledger
+            .add_transition_record(
+                &engine,
+                tagged("T-1", "open_bidding"),
+                vec![officer.clone()],
+            )
+            .unwrap();
+        ledger
+            .add_record("unrelated note", vec![officer.clone()])
+            .unwrap();
+        ledger
+            .add_transition_record(
+                &engine,
+                tagged("T-2", "open_bidding"),
+                vec![officer.clone()],
+            )
+            .unwrap();
+        ledger
+            .add_transition_record(&engine, tagged("T-1", "award"), vec![officer, finance])
+            .unwrap();
+
+        let projector = StateProjector::new(&engine);
+        let states = projector.project(&ledger);
+
+        assert_eq!(states.len(), 2);
+        assert_eq!(states["T-1"].current_state, "awarded");
+        assert_eq!(states["T-1"].history.len(), 2);
+        assert_eq!(states["T-1"].history[1].record_index, 8);
+        assert_eq!(states["T-2"].current_state, "bidding_open");
+    }
+
+    #[test]
+    fn test_replay_rejects_invalid_transitions() {
+        let engine = tender_engine();
+        let mut ledger = Ledger::new();
+
+        let officer = User::new("officer");
+        ledger.register_user(officer.clone());
+        grant_roles(&mut ledger, &[("officer", "procuring_officer")]);
+
+        // skips a step
+        forge(&mut ledger, tagged("T-1", "award"), vec![officer.clone()]);
+        ledger
+            .add_transition_record(
+                &engine,
+                tagged("T-1", "open_bidding"),
+                vec![officer.clone()],
+            )
+            .unwrap();
+        // missing finance_approver
+        forge(&mut ledger, tagged("T-1", "award"), vec![officer]);
+
+        let projector = StateProjector::new(&engine);
+        let state = projector.entity(&ledger, "T-1").unwrap();
+
+        assert_eq!(state.current_state, "bidding_open");
+        assert_eq!(state.history.len(), 1);
+        assert_eq!(state.rejected.len(), 2);
+        assert_eq!(state.rejected[0].record_index, 4);
+        assert_eq!(state.rejected[1].record_index, 6);
+    }
+
+    #[test]
+    fn test_replay_uses_roles_at_each_record() {
+        let engine = tender_engine();
+        let mut ledger = Ledger::new();
+
+        let officer = User::new("officer");
+        ledger.register_user(officer.clone());
+        let admin = grant_roles(&mut ledger, &[("officer", "procuring_officer")]);
+
+        ledger
+            .add_transition_record(
+                &engine,
+                tagged("T-1", "open_bidding"),
+                vec![officer.clone()],
+            )
+            .unwrap();
+        ledger
+            .add_record(
+                RoleChange::revoke("officer", "procuring_officer").to_payload(),
+                vec![admin],
+            )
+            .unwrap();
+        assert!(
+            ledger
+                .add_transition_record(
+                    &engine,
+                    tagged("T-2", "open_bidding"),
+                    vec![officer.clone()]
+                )
+                .is_err()
+        );
+        forge(&mut ledger, tagged("T-2", "open_bidding"), vec![officer]);
+
+        // the revocation doesn't undo what came before it
+        let states = StateProjector::new(&engine).project(&ledger);
+        assert_eq!(states["T-1"].current_state, "bidding_open");
+        assert_eq!(states["T-2"].current_state, "call_for_bids");
+        assert_eq!(states["T-2"].rejected.len(), 1);
+    }
+
+    #[test]
+    fn test_replay_unknown_entity_and_workflow() {
+        let engine = tender_engine();
+        let mut ledger = Ledger::new();
+        let user = User::new("user");
+        ledger.register_user(user.clone());
+
+        let projector = StateProjector::new(&engine);
+        assert!(projector.entity(&ledger, "T-404").is_none());
+
+        let payload = TransitionMetadata::new("X-1", "missing", "go").to_payload();
+        forge(&mut ledger, payload, vec![user.clone()]);
+        let payload = TransitionMetadata::new("X-1", "tender", "open_bidding").to_payload();
+        forge(&mut ledger, payload, vec![user.clone()]);
+        forge(&mut ledger, tagged("T-1", "open_bidding"), vec![user]);
+
+        // the unknown workflow only spoils its own entity
+        let states = projector.project(&ledger);
+        let unknown = &states["X-1"];
+        assert_eq!(unknown.workflow_id, "missing");
+        assert_eq!(unknown.current_state, "");
+        assert!(unknown.history.is_empty());
+        assert_eq!(unknown.rejected.len(), 2);
+        assert_eq!(unknown.rejected[0].record_index, 1);
+        assert!(
+            unknown.rejected[0]
+                .reason
+                .contains("Unknown workflow missing")
+        );
+        assert_eq!(states["T-1"].current_state, "call_for_bids");
+        assert_eq!(states["T-1"].rejected.len(), 1);
+    }
+
+    #[test]
+    fn test_replay_checks_the_named_transition() {
+        let engine = tender_engine();
+        let mut ledger = Ledger::new();
+
+        let officer = User::new("officer");
+        ledger.register_user(officer.clone());
+        grant_roles(&mut ledger, &[("officer", "procuring_officer")]);
+
+        // same states as open_bidding, but only a director may take it
+        forge(
+            &mut ledger,
+            tagged("T-1", "open_without_notice"),
+            vec![officer.clone()],
+        );
+        forge(&mut ledger, tagged("T-2", "open_bidding"), vec![officer]);
+
+        let states = StateProjector::new(&engine).project(&ledger);
+        assert_eq!(states["T-1"].current_state, "call_for_bids");
+        assert!(states["T-1"].rejected[0].reason.contains("director"));
+        assert_eq!(states["T-2"].current_state, "bidding_open");
+    }
+}